@@ -5,7 +5,9 @@ use tokio::runtime::Runtime;
 pub use substrate_cli::{VersionInfo, IntoExit, error};
 use substrate_cli::{informant, parse_and_execute, NoCustom};
 use substrate_service::{ServiceFactory, Roles as ServiceRoles};
+use crate::engine;
 use crate::genesis;
+use crate::epoch::CustomSubcommands;
 use std::ops::Deref;
 use log::info;
 
@@ -15,7 +17,7 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 	T: Into<std::ffi::OsString> + Clone,
 	E: IntoExit,
 {
-	parse_and_execute::<service::Factory, NoCustom, NoCustom, _, _, _, _, _>(
+	match parse_and_execute::<service::Factory, CustomSubcommands, NoCustom, _, _, _, _, _>(
 		load_spec, &version, "gmpc-node", args, exit,
 	 	|exit, _custom_args, config| {
 			info!("{}", version.name);
@@ -24,6 +26,8 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 			info!("Chain specification: {}", config.chain_spec.name());
 			info!("Node name: {}", config.name);
 			info!("Roles: {:?}", config.roles);
+			// before the service opens the database, chains of another engine stop here
+			engine::ensure_aura(&config.chain_spec)?;
 			let runtime = Runtime::new().map_err(|e| format!("{:?}", e))?;
 			let executor = runtime.executor();
 			match config.roles {
@@ -39,7 +43,13 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 				),
 			}.map_err(|e| format!("{:?}", e))
 		}
-	).map_err(Into::into).map(|_| ())
+	)? {
+		Some(CustomSubcommands::PowEpoch(cmd)) => {
+			cmd.run()?;
+			Ok(())
+		},
+		None => Ok(()),
+	}
 }
 
 fn load_spec(id: &str) -> Result<Option<genesis::ChainSpec>, String> {
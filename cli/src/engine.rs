@@ -0,0 +1,149 @@
+//! Consensus engine selected by the chain spec.
+//!
+//! The `engine` property of the chain spec holds an `EngineSpec` for GBFT,
+//! PoA and PoW chains. Chains without it keep Aura, the only consensus the
+//! service drives for now: it refuses to start the others, see `ensure_aura`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use consensus_engine::{Engine, EngineSpec};
+use primitives::{blake2_256, ed25519::{Pair, Public, Signature}, Pair as PairT};
+
+use crate::genesis::ChainSpec;
+
+/// Chain spec property holding the `EngineSpec`.
+pub const ENGINE_PROPERTY: &str = "engine";
+
+/// Hashing and signing with the ed25519 authority key of the node.
+#[derive(Clone)]
+pub struct AuthorityCrypto {
+	key: Option<Arc<Pair>>,
+}
+
+impl AuthorityCrypto {
+	pub fn new(key: Option<Arc<Pair>>) -> Self {
+		AuthorityCrypto { key }
+	}
+
+	pub fn address(&self) -> Option<Vec<u8>> {
+		self.key.as_ref().map(|key| key.public().0.to_vec())
+	}
+
+	fn sign(&self, hash: &[u8]) -> Result<Vec<u8>, String> {
+		match self.key {
+			Some(ref key) => Ok(key.sign(hash).0.to_vec()),
+			None => Err("the node has no authority key".to_owned()),
+		}
+	}
+
+	fn verify(&self, signature: &[u8], hash: &[u8], signer: &[u8]) -> bool {
+		if signature.len() != 64 || signer.len() != 32 {
+			return false;
+		}
+		let mut public = [0u8; 32];
+		public.copy_from_slice(signer);
+		let mut raw = [0u8; 64];
+		raw.copy_from_slice(signature);
+		<Pair as PairT>::verify(&Signature(raw), hash, &Public(public))
+	}
+}
+
+impl gbft::Crypto for AuthorityCrypto {
+	fn hash(&self, msg: &[u8]) -> gbft::Hash {
+		blake2_256(msg).to_vec()
+	}
+
+	fn sign(&self, hash: &gbft::Hash) -> gbft::Result<gbft::Signature> {
+		AuthorityCrypto::sign(self, hash).map_err(gbft::Error::Signing)
+	}
+
+	fn verify(&self, signature: &gbft::Signature, hash: &gbft::Hash, signer: &gbft::Address) -> bool {
+		AuthorityCrypto::verify(self, signature, hash, signer)
+	}
+}
+
+impl poa::Crypto for AuthorityCrypto {
+	fn hash(&self, msg: &[u8]) -> Vec<u8> {
+		blake2_256(msg).to_vec()
+	}
+
+	fn sign(&self, hash: &[u8]) -> consensus_engine::Result<Vec<u8>> {
+		AuthorityCrypto::sign(self, hash).map_err(consensus_engine::Error::Signing)
+	}
+
+	fn verify(&self, signature: &[u8], hash: &[u8], signer: &poa::Address) -> bool {
+		AuthorityCrypto::verify(self, signature, hash, signer)
+	}
+}
+
+/// Fail for chains selecting an engine, after checking its parameters: the
+/// service only authors and imports Aura blocks, such a node would run
+/// without consensus. Nothing is written to disk.
+pub fn ensure_aura(chain_spec: &ChainSpec) -> Result<(), String> {
+	match engine_spec(chain_spec)? {
+		Some(spec) => {
+			check(&spec)?;
+			Err(format!("The {} consensus of the chain spec cannot author or import blocks yet", spec.engine))
+		},
+		None => Ok(()),
+	}
+}
+
+/// Check the parameters of `spec` without opening anything on disk, unlike `build`.
+fn check(spec: &EngineSpec) -> Result<(), String> {
+	let crypto = AuthorityCrypto::new(None);
+	match spec.engine.as_str() {
+		gbft::NAME => gbft::GbftEngine::from_spec(crypto, spec).map(drop).map_err(|e| e.to_string()),
+		poa::NAME => poa::PoaEngine::from_spec(crypto, None, spec).map(drop).map_err(|e| e.to_string()),
+		pow::NAME => spec
+			.params::<pow::PowParams>(pow::NAME)
+			.and_then(|params| params.validate())
+			.map_err(|e| e.to_string()),
+		other => Err(format!("Unknown consensus engine {}", other)),
+	}
+}
+
+/// The engine selected by `chain_spec`, `None` for chains running Aura.
+pub fn engine_spec(chain_spec: &ChainSpec) -> Result<Option<EngineSpec>, String> {
+	match chain_spec.properties().get(ENGINE_PROPERTY) {
+		Some(value) => serde_json::from_value(value.clone())
+			.map(Some)
+			.map_err(|e| format!("Invalid engine in chain spec: {}", e)),
+		None => Ok(None),
+	}
+}
+
+/// Directory of the PoA snapshots, under the database directory.
+pub const POA_SNAPSHOTS_DIR: &str = "poa-snapshots";
+
+/// Directory of the Ethash light caches, under the database directory.
+pub const ETHASH_CACHE_DIR: &str = "ethash";
+
+/// Directory of the recent PoW headers, under the database directory.
+pub const POW_HEADERS_DIR: &str = "pow-headers";
+
+/// Build the engine of `spec`, signing with `key` when the node is an authority.
+/// Engines keeping state on disk keep it under `database_path`.
+pub fn build(spec: &EngineSpec, key: Option<Arc<Pair>>, database_path: &Path) -> Result<Box<dyn Engine + Send>, String> {
+	let crypto = AuthorityCrypto::new(key);
+	let engine: Box<dyn Engine + Send> = match spec.engine.as_str() {
+		gbft::NAME => Box::new(gbft::GbftEngine::from_spec(crypto, spec).map_err(|e| e.to_string())?),
+		poa::NAME => {
+			let address = crypto.address();
+			let store = poa::SnapshotStore::open(database_path.join(POA_SNAPSHOTS_DIR))
+				.map_err(|e| format!("Cannot open the PoA snapshots: {}", e))?;
+			let engine = poa::PoaEngine::from_spec(crypto, address, spec).map_err(|e| e.to_string())?;
+			Box::new(engine.with_store(store, poa::DEFAULT_SNAPSHOT_INTERVAL))
+		},
+		pow::NAME => {
+			let cache_dir = database_path.join(ETHASH_CACHE_DIR);
+			let store = pow::HeaderStore::open(database_path.join(POW_HEADERS_DIR))
+				.map_err(|e| format!("Cannot open the PoW headers: {}", e))?;
+			let engine = pow::PowEngine::from_spec(spec, &cache_dir).map_err(|e| e.to_string())?;
+			Box::new(engine.with_store(store).map_err(|e| format!("Cannot read the PoW headers: {}", e))?)
+		},
+		other => return Err(format!("Unknown consensus engine {}", other)),
+	};
+	Ok(engine)
+}
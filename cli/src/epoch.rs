@@ -0,0 +1,56 @@
+//! `pow-epoch` subcommand: the PoW epoch of a block.
+
+use std::path::PathBuf;
+
+use log::warn;
+use structopt::StructOpt;
+use substrate_cli::GetLogFilter;
+
+/// Subcommands of the node besides the substrate ones.
+#[derive(Debug, StructOpt, Clone)]
+pub enum CustomSubcommands {
+	/// Print the PoW epoch of a block: its blocks, seed hash, cache and dataset sizes.
+	#[structopt(name = "pow-epoch")]
+	PowEpoch(PowEpochCmd),
+}
+
+impl GetLogFilter for CustomSubcommands {
+	fn get_log_filter(&self) -> Option<String> {
+		None
+	}
+}
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct PowEpochCmd {
+	/// Block number
+	#[structopt(name = "BLOCK")]
+	pub block: u64,
+
+	/// Ethash cache directory of a node, to read and save the seed hashes
+	#[structopt(long = "cache-dir", value_name = "PATH", parse(from_os_str))]
+	pub cache_dir: Option<PathBuf>,
+}
+
+impl PowEpochCmd {
+	pub fn run(&self) -> Result<(), String> {
+		let seeds = match self.cache_dir {
+			Some(ref dir) => pow::SeedHashCache::open(dir).unwrap_or_else(|e| {
+				warn!("cannot open seed hashes in {:?}: {}", dir, e);
+				pow::SeedHashCache::in_memory()
+			}),
+			None => pow::SeedHashCache::in_memory(),
+		};
+		let info = pow::EpochInfo::of_block(self.block, &seeds).map_err(|e| e.to_string())?;
+		println!("block:        {}", self.block);
+		println!("epoch:        {}", info.epoch);
+		println!("blocks:       {} - {}", info.first_block, info.last_block);
+		println!("seed hash:    0x{}", hex(&info.seed_hash));
+		println!("cache size:   {} bytes", info.cache_size);
+		println!("dataset size: {} bytes", info.dataset_size);
+		Ok(())
+	}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
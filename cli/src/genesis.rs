@@ -1,7 +1,7 @@
 use primitives::{ed25519, sr25519, Pair};
 use gmpc_runtime::{
 	AccountId, GenesisConfig, ConsensusConfig, TimestampConfig, BalancesConfig,
-	SudoConfig, IndicesConfig,
+	SudoConfig, IndicesConfig, ValidatorConfig,
 };
 use substrate_service;
 
@@ -36,6 +36,11 @@ fn account_key(s: &str) -> AccountId {
 		.public()
 }
 
+/// Account and authority key of the validator `s`.
+fn validator_keys(s: &str) -> (AccountId, AuthorityId) {
+	(account_key(s), authority_key(s))
+}
+
 impl Alternative {
 	/// Get an actual chain config from one of the alternatives.
 	pub(crate) fn load(self) -> Result<ChainSpec, String> {
@@ -44,7 +49,7 @@ impl Alternative {
 				"Development",
 				"dev",
 				|| testnet_genesis(vec![
-					authority_key("Alice")
+					validator_keys("Alice")
 				], vec![
 					account_key("Alice")
 				],
@@ -60,8 +65,8 @@ impl Alternative {
 				"Local Testnet",
 				"local_testnet",
 				|| testnet_genesis(vec![
-					authority_key("Alice"),
-					authority_key("Bob"),
+					validator_keys("Alice"),
+					validator_keys("Bob"),
 				], vec![
 					account_key("Alice"),
 					account_key("Bob"),
@@ -90,11 +95,11 @@ impl Alternative {
 	}
 }
 
-fn testnet_genesis(initial_authorities: Vec<AuthorityId>, endowed_accounts: Vec<AccountId>, root_key: AccountId) -> GenesisConfig {
+fn testnet_genesis(initial_authorities: Vec<(AccountId, AuthorityId)>, endowed_accounts: Vec<AccountId>, root_key: AccountId) -> GenesisConfig {
 	GenesisConfig {
 		consensus: Some(ConsensusConfig {
 			code: include_bytes!("../../../target/wasm32-unknown-unknown/release/gmpc_runtime_wasm.wasm").to_vec(),
-			authorities: initial_authorities.clone(),
+			authorities: initial_authorities.iter().map(|x| x.1.clone()).collect(),
 		}),
 		system: None,
 		timestamp: Some(TimestampConfig {
@@ -115,5 +120,9 @@ fn testnet_genesis(initial_authorities: Vec<AuthorityId>, endowed_accounts: Vec<
 		sudo: Some(SudoConfig {
 			key: root_key,
 		}),
+		validator: Some(ValidatorConfig {
+			validators: initial_authorities.iter().map(|x| (x.0.clone(), 1)).collect(),
+			session_keys: initial_authorities.iter().map(|x| (x.0.clone(), x.1.clone())).collect(),
+		}),
 	}
 }
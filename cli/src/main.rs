@@ -2,8 +2,10 @@
 #![warn(unused_extern_crates)]
 
 mod genesis;
+mod engine;
 mod service;
 mod cli;
+mod epoch;
 
 pub use substrate_cli::{VersionInfo, IntoExit, error};
 
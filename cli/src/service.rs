@@ -19,6 +19,7 @@ use inherents::InherentDataProviders;
 use network::construct_simple_protocol;
 use substrate_executor::native_executor_instance;
 use substrate_service::construct_service_factory;
+use crate::engine;
 
 pub use substrate_executor::NativeExecutor;
 // Our native executor instance.
@@ -87,6 +88,9 @@ construct_service_factory! {
 			Self::Block,
 		>
 			{ |config: &mut FactoryFullConfiguration<Self> , client: Arc<FullClient<Self>>| {
+					// Also refused before the service is built, see `cli::run`, this
+					// covers the commands importing blocks.
+					engine::ensure_aura(&config.chain_spec)?;
 					import_queue::<_, _, _, Pair>(
 						SlotDuration::get_or_compute(&*client)?,
 						client.clone(),
@@ -101,6 +105,7 @@ construct_service_factory! {
 			Self::Block,
 		>
 			{ |config: &mut FactoryFullConfiguration<Self>, client: Arc<LightClient<Self>>| {
+					engine::ensure_aura(&config.chain_spec)?;
 					import_queue::<_, _, _, Pair>(
 						SlotDuration::get_or_compute(&*client)?,
 						client.clone(),
@@ -0,0 +1,68 @@
+//! Errors shared by the consensus engines.
+
+use std::fmt;
+
+/// Result type alias for consensus engines.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error type for consensus engines.
+#[derive(derive_more::Display)]
+pub enum Error {
+    /// The header carries no seal.
+    #[display(fmt = "Header {} is not sealed", _0)]
+    MissingSeal(u64),
+    /// The seal does not prove what the engine requires.
+    #[display(fmt = "Invalid seal: {}", _0)]
+    InvalidSeal(String),
+    /// The header does not follow its parent.
+    #[display(fmt = "Header number {} does not follow parent {}", number, parent)]
+    InvalidNumber {
+        /// Number of the header.
+        number: u64,
+        /// Number of the parent.
+        parent: u64,
+    },
+    /// The header is not later than its parent.
+    #[display(fmt = "Header timestamp {} is not after parent timestamp {}", timestamp, parent)]
+    InvalidTimestamp {
+        /// Timestamp of the header.
+        timestamp: u64,
+        /// Timestamp of the parent.
+        parent: u64,
+    },
+    /// The header difficulty is not the expected one.
+    #[display(fmt = "Difficulty {} where {} was expected", got, expected)]
+    InvalidDifficulty {
+        /// Difficulty required by the engine.
+        expected: u128,
+        /// Difficulty of the header.
+        got: u128,
+    },
+    /// The block author is not allowed to seal it.
+    #[display(fmt = "Unauthorized author {:?}", _0)]
+    UnauthorizedAuthor(Vec<u8>),
+    /// The engine data of the header cannot be decoded or is not allowed.
+    #[display(fmt = "Invalid extra data: {}", _0)]
+    InvalidExtra(String),
+    /// The engine has no state for the parent of the header.
+    #[display(fmt = "Unknown parent of header {}", _0)]
+    UnknownParent(u64),
+    /// The local key cannot sign.
+    #[display(fmt = "Cannot sign: {}", _0)]
+    Signing(String),
+    /// The chain spec names an engine this node does not know.
+    #[display(fmt = "Unknown consensus engine {}", _0)]
+    UnknownEngine(String),
+    /// The engine parameters of the chain spec cannot be used.
+    #[display(fmt = "Invalid engine parameters: {}", _0)]
+    InvalidParams(String),
+}
+
+// Make `Debug` use the `Display` implementation.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
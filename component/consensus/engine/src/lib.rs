@@ -0,0 +1,228 @@
+//! Interface shared by the consensus engines.
+//!
+//! `gbft`, `poa` and `pow` each implement `Engine`, the node picks one from
+//! the `EngineSpec` of its chain spec and drives it through the trait:
+//! `prepare` and `propose` when authoring, `verify_header`, `verify_import`
+//! and `imported` when importing, `finalized` once a block is final.
+//!
+//! Engines work on the header of the chain, `primitives::Header`. What they
+//! need beyond it, the `Fields`, is kept in its digest, and so is their seal.
+
+mod error;
+mod spec;
+
+use codec::{Decode, Encode};
+use primitives::{BlakeTwo256, Digest, DigestItem};
+
+pub use error::{Error, Result};
+pub use primitives::{ConsensusEngineId, H256};
+pub use spec::EngineSpec;
+
+/// Header of the chain, the one every engine works on.
+pub type Header = primitives::Header<u64, BlakeTwo256>;
+
+/// Id of the digest item holding the `Fields` of a header.
+pub const FIELDS_ID: ConsensusEngineId = *b"flds";
+
+/// The consensus fields of a header, a pre-runtime item of its digest.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Fields {
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    pub author: Vec<u8>,
+    pub difficulty: u128,
+    /// engine specific data, such as authority votes
+    pub extra: Vec<u8>,
+}
+
+/// An unsealed header of block `number` with `fields`, its roots left empty
+/// for the runtime.
+pub fn new_header(parent_hash: H256, number: u64, fields: Fields) -> Header {
+    let mut header = Header {
+        parent_hash,
+        number,
+        state_root: H256::default(),
+        extrinsics_root: H256::default(),
+        digest: Digest::default(),
+    };
+    header.set_fields(fields);
+    header
+}
+
+/// The consensus fields and the seal of a `Header`.
+///
+/// Engines sign or mine `unsealed`, the encoding of the header without its
+/// seal, and hash it with their own hasher.
+pub trait ConsensusHeader {
+    /// The fields of the header, the defaults when it has none.
+    fn fields(&self) -> Result<Fields>;
+
+    /// Replace the fields, kept before the seal.
+    fn set_fields(&mut self, fields: Fields);
+
+    /// Data of the seal of `engine`, `None` until sealed.
+    fn seal(&self, engine: &ConsensusEngineId) -> Option<&[u8]>;
+
+    /// Seal the header for `engine`, replacing any previous seal.
+    fn set_seal(&mut self, engine: ConsensusEngineId, seal: Vec<u8>);
+
+    /// SCALE encoding of the header without its seal.
+    fn unsealed(&self) -> Vec<u8>;
+}
+
+impl ConsensusHeader for Header {
+    fn fields(&self) -> Result<Fields> {
+        let fields = self.digest.log(|item| match item.as_pre_runtime() {
+            Some((id, data)) if *id == FIELDS_ID => Some(data),
+            _ => None,
+        });
+        match fields {
+            Some(mut data) => Fields::decode(&mut data).map_err(|e| Error::InvalidExtra(e.to_string())),
+            None => Ok(Fields::default()),
+        }
+    }
+
+    fn set_fields(&mut self, fields: Fields) {
+        let logs = &mut self.digest.logs;
+        logs.retain(|item| !matches!(item.as_pre_runtime(), Some((id, _)) if *id == FIELDS_ID));
+        let at = if logs.last().and_then(DigestItem::as_seal).is_some() { logs.len() - 1 } else { logs.len() };
+        logs.insert(at, DigestItem::PreRuntime(FIELDS_ID, fields.encode()));
+    }
+
+    fn seal(&self, engine: &ConsensusEngineId) -> Option<&[u8]> {
+        self.digest.seal(engine)
+    }
+
+    fn set_seal(&mut self, engine: ConsensusEngineId, seal: Vec<u8>) {
+        if self.digest.logs().last().and_then(DigestItem::as_seal).is_some() {
+            self.digest.pop();
+        }
+        self.digest.push(DigestItem::Seal(engine, seal));
+    }
+
+    fn unsealed(&self) -> Vec<u8> {
+        let mut unsealed = self.clone();
+        if unsealed.digest.logs().last().and_then(DigestItem::as_seal).is_some() {
+            unsealed.digest.pop();
+        }
+        unsealed.encode()
+    }
+}
+
+/// Outcome of `Engine::propose`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Proposal {
+    /// The header can be imported with this seal.
+    Sealed(Vec<u8>),
+    /// The seal is produced later, by the consensus rounds or a miner.
+    Pending,
+    /// The local node may not author this block.
+    NotAuthor,
+}
+
+/// When a block can no longer be reverted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Finality {
+    /// As soon as it is imported with a valid seal.
+    Instant,
+    /// Once buried under this many blocks.
+    Probabilistic { confirmations: u64 },
+}
+
+pub trait Engine {
+    /// Name selecting the engine in `EngineSpec`.
+    fn name(&self) -> &'static str;
+
+    /// Id of the engine in the header digest, `Proposal::Sealed` goes there.
+    fn id(&self) -> ConsensusEngineId;
+
+    fn finality(&self) -> Finality;
+
+    /// Fill the consensus fields of a header about to be built on `parent`.
+    fn prepare(&mut self, _header: &mut Header, _parent: &Header) -> Result<()> {
+        Ok(())
+    }
+
+    /// Try to seal a prepared header.
+    fn propose(&mut self, header: &Header) -> Result<Proposal>;
+
+    /// Check the seal of `header`, without looking at other blocks.
+    fn verify_header(&self, header: &Header) -> Result<()>;
+
+    /// Check `header` against its parent before importing it.
+    fn verify_import(&self, header: &Header, parent: &Header) -> Result<()> {
+        check_parent(header, parent)
+    }
+
+    /// `header` passed `verify_import` and was imported, engines record
+    /// what its children are checked against.
+    fn imported(&mut self, _header: &Header) -> Result<()> {
+        Ok(())
+    }
+
+    /// `header` became final, engines may prune what they keep for older blocks.
+    fn finalized(&mut self, _header: &Header) {}
+}
+
+/// Checks every engine needs: the header is a child of `parent` and time
+/// moves on.
+pub fn check_parent(header: &Header, parent: &Header) -> Result<()> {
+    if header.number != parent.number + 1 {
+        return Err(Error::InvalidNumber { number: header.number, parent: parent.number });
+    }
+    if header.parent_hash != parent.hash() {
+        return Err(Error::UnknownParent(header.number));
+    }
+    let (timestamp, parent_timestamp) = (header.fields()?.timestamp, parent.fields()?.timestamp);
+    if timestamp <= parent_timestamp {
+        return Err(Error::InvalidTimestamp { timestamp, parent: parent_timestamp });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsealed_ignores_seal() {
+        let header = new_header([1; 32], 1, Fields { timestamp: 10, ..Fields::default() });
+        let mut sealed = header.clone();
+        sealed.set_seal(*b"test", vec![1, 2, 3]);
+        assert_eq!(header.unsealed(), sealed.unsealed());
+        assert_eq!(sealed.seal(b"test"), Some(&[1u8, 2, 3][..]));
+        sealed.set_seal(*b"test", vec![4]);
+        assert_eq!(sealed.digest.logs().len(), 2);
+        assert_eq!(Header::decode(&mut &sealed.encode()[..]).unwrap(), sealed);
+
+        // the fields stay before the seal and are covered by it
+        sealed.set_fields(Fields { difficulty: 2, ..sealed.fields().unwrap() });
+        assert_eq!(sealed.seal(b"test"), Some(&[4u8][..]));
+        assert_eq!(sealed.fields().unwrap().timestamp, 10);
+        assert_ne!(header.unsealed(), sealed.unsealed());
+        sealed.digest.logs[0] = DigestItem::PreRuntime(FIELDS_ID, vec![1]);
+        assert!(matches!(sealed.fields(), Err(Error::InvalidExtra(_))));
+    }
+
+    #[test]
+    fn test_check_parent() {
+        let parent = new_header([0; 32], 1, Fields { timestamp: 10, ..Fields::default() });
+        let header = new_header(parent.hash(), 2, Fields { timestamp: 11, ..Fields::default() });
+        check_parent(&header, &parent).unwrap();
+        let skipped = Header { number: 3, ..header.clone() };
+        assert!(matches!(check_parent(&skipped, &parent), Err(Error::InvalidNumber { .. })));
+        let orphan = Header { parent_hash: [1; 32], ..header.clone() };
+        assert!(matches!(check_parent(&orphan, &parent), Err(Error::UnknownParent(2))));
+        let early = new_header(parent.hash(), 2, Fields { timestamp: 10, ..Fields::default() });
+        assert!(matches!(check_parent(&early, &parent), Err(Error::InvalidTimestamp { .. })));
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec: EngineSpec = serde_json::from_str(r#"{ "engine": "pow", "params": { "confirmations": 12 } }"#).unwrap();
+        let params: serde_json::Value = spec.params("pow").unwrap();
+        assert_eq!(params["confirmations"], 12);
+        assert!(spec.params::<serde_json::Value>("gbft").is_err());
+        assert_eq!(EngineSpec::new("pow", &params).unwrap(), spec);
+    }
+}
@@ -0,0 +1,33 @@
+//! Engine selection in the chain spec.
+//!
+//! The genesis names the engine and carries its parameters, for instance
+//! `{ "engine": "gbft", "params": { "validators": [...] } }`. The parameters
+//! are only decoded by the engine itself, so one node binary runs any chain.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EngineSpec {
+    /// `Engine::name` of the selected engine.
+    pub engine: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+impl EngineSpec {
+    pub fn new<P: Serialize>(engine: &str, params: &P) -> Result<Self> {
+        let params = serde_json::to_value(params).map_err(|e| Error::InvalidParams(e.to_string()))?;
+        Ok(EngineSpec { engine: engine.to_owned(), params })
+    }
+
+    /// Decode the parameters of `engine`, failing if another engine is selected.
+    pub fn params<P: DeserializeOwned>(&self, engine: &str) -> Result<P> {
+        if self.engine != engine {
+            return Err(Error::InvalidParams(format!("chain spec selects {}, not {}", self.engine, engine)));
+        }
+        serde_json::from_value(self.params.clone()).map_err(|e| Error::InvalidParams(e.to_string()))
+    }
+}
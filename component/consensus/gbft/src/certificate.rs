@@ -0,0 +1,160 @@
+//! Compact commit certificates for light clients.
+//!
+//! A `CommitCertificate` repeats the whole vote of every signer. Its
+//! aggregated form keeps a bitfield of the signers, indexed like the
+//! validator set of the height, and their signatures in the same order: the
+//! votes themselves are rebuilt from the certificate when checking it.
+//!
+//! The aggregate is sealed in the header digest as
+//! `DigestItem::Seal(GBFT_ENGINE_ID, certificate.encode())`, see `seal_header`.
+//! GBFT hashes the header without that seal, see `verify_header`.
+
+use codec::{Decode, Encode};
+use primitives::{DigestItem, Headerly};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Crypto;
+use crate::error::{Error, Result};
+use crate::message::{vote_payload, CommitCertificate, Hash, Signature, SignedVote, Vote, VoteType};
+use crate::validator_set::ValidatorSet;
+
+/// Consensus engine id of the GBFT seal.
+pub const GBFT_ENGINE_ID: [u8; 4] = *b"gbft";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct AggregatedCertificate {
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: Hash,
+    /// bit `i` (least significant first) is set if validator `i` signed
+    pub signers: Vec<u8>,
+    /// signatures of the commit votes, in signer order
+    pub signatures: Vec<Signature>,
+}
+
+impl AggregatedCertificate {
+    /// Compact `certificate`, `validators` being the set of its height.
+    pub fn aggregate(certificate: &CommitCertificate, validators: &ValidatorSet) -> Result<Self> {
+        let mut signed: Vec<Option<&Signature>> = vec![None; validators.len()];
+        for vote in &certificate.votes {
+            let index = validators
+                .validators()
+                .iter()
+                .position(|v| v.address == vote.vote.voter)
+                .ok_or_else(|| Error::UnknownValidator(vote.vote.voter.clone()))?;
+            signed[index] = Some(&vote.signature);
+        }
+
+        let mut signers = vec![0u8; (validators.len() + 7) / 8];
+        let mut signatures = vec![];
+        for (index, signature) in signed.into_iter().enumerate() {
+            if let Some(signature) = signature {
+                signers[index / 8] |= 1 << (index % 8);
+                signatures.push(signature.clone());
+            }
+        }
+        Ok(AggregatedCertificate {
+            height: certificate.height,
+            round: certificate.round,
+            block_hash: certificate.block_hash.clone(),
+            signers,
+            signatures,
+        })
+    }
+
+    /// Rebuild the full certificate.
+    pub fn expand(&self, validators: &ValidatorSet) -> Result<CommitCertificate> {
+        if self.signers.len() != (validators.len() + 7) / 8 {
+            return Err(Error::InvalidCertificate("signer bitfield does not match the validator set"));
+        }
+        let indexes: Vec<usize> = (0..self.signers.len() * 8)
+            .filter(|i| self.signers[i / 8] & (1 << (i % 8)) != 0)
+            .collect();
+        if indexes.iter().any(|i| *i >= validators.len()) {
+            return Err(Error::InvalidCertificate("signer out of the validator set"));
+        }
+        if indexes.len() != self.signatures.len() {
+            return Err(Error::InvalidCertificate("one signature per signer"));
+        }
+
+        let votes = indexes
+            .into_iter()
+            .zip(&self.signatures)
+            .map(|(index, signature)| SignedVote {
+                vote: Vote {
+                    height: self.height,
+                    round: self.round,
+                    vote_type: VoteType::Commit,
+                    block_hash: self.block_hash.clone(),
+                    voter: validators.validators()[index].address.clone(),
+                },
+                signature: signature.clone(),
+            })
+            .collect();
+        Ok(CommitCertificate {
+            height: self.height,
+            round: self.round,
+            block_hash: self.block_hash.clone(),
+            votes,
+        })
+    }
+
+    /// Check every signature and that the signers hold a quorum of `validators`.
+    pub fn verify<C: Crypto>(&self, crypto: &C, validators: &ValidatorSet) -> Result<()> {
+        let certificate = self.expand(validators)?;
+        for signed in &certificate.votes {
+            let hash = crypto.hash(&vote_payload(&signed.vote));
+            if !crypto.verify(&signed.signature, &hash, &signed.vote.voter) {
+                return Err(Error::InvalidSignature(signed.vote.voter.clone()));
+            }
+        }
+        let power = validators.power_of(certificate.votes.iter().map(|v| &v.vote.voter));
+        if power < validators.quorum_power() {
+            return Err(Error::InvalidCertificate("not enough votes"));
+        }
+        Ok(())
+    }
+}
+
+/// Check that the block hashing to `crypto.hash(unsealed)` is final, `seal`
+/// being the data of its GBFT seal and `validators` the set of its height.
+pub fn verify_finality<C: Crypto>(
+    crypto: &C,
+    validators: &ValidatorSet,
+    height: u64,
+    unsealed: &[u8],
+    seal: &[u8],
+) -> Result<AggregatedCertificate> {
+    let certificate = AggregatedCertificate::decode(&mut &seal[..])
+        .map_err(|_| Error::InvalidCertificate("malformed seal"))?;
+    if certificate.height != height {
+        return Err(Error::InvalidCertificate("wrong height"));
+    }
+    if certificate.block_hash != crypto.hash(unsealed) {
+        return Err(Error::InvalidBlockHash);
+    }
+    certificate.verify(crypto, validators)?;
+    Ok(certificate)
+}
+
+/// Seal `header` with `certificate`, the last item of its digest.
+pub fn seal_header<H: Headerly>(header: &mut H, certificate: &AggregatedCertificate) {
+    header.digest_mut().push(DigestItem::Seal(GBFT_ENGINE_ID, certificate.encode()));
+}
+
+/// Check that `header` is final from its GBFT seal, `validators` being the
+/// set of its height. GBFT agreed on the encoding of the header without it.
+pub fn verify_header<C, H>(crypto: &C, validators: &ValidatorSet, header: &H) -> Result<AggregatedCertificate>
+where
+    C: Crypto,
+    H: Headerly,
+    H::Number: Into<u64>,
+{
+    let seal = header
+        .digest()
+        .seal(&GBFT_ENGINE_ID)
+        .ok_or(Error::InvalidCertificate("no GBFT seal"))?;
+    let mut unsealed = header.clone();
+    unsealed.digest_mut().pop();
+    verify_finality(crypto, validators, (*header.number()).into(), &unsealed.encode(), seal)
+}
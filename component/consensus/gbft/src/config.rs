@@ -1,29 +1,283 @@
+//! GBFT configuration, read from a TOML or JSON file.
+//!
+//! Missing settings take their default value and every value is range
+//! checked by `GBFTConfig::validate`. Timeouts, the proposal delay and the
+//! retry counts only affect liveness and can be changed on a running node
+//! with `State::reload_config`; `version` and `max_block_size` decide which
+//! blocks are valid and need a restart.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{info, trace};
 use serde::{Deserialize, Serialize};
-use log::{trace};
+
+use crate::error::{Error, Result};
+
+const MIN_ROUND_TIMEOUT: u64 = 100;
+const MAX_ROUND_TIMEOUT: u64 = 600_000;
+const MAX_TIMEOUT_MULTIPLIER: u64 = 10;
+const MAX_BLOCK_SIZE: u64 = 128 * 1024 * 1024;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct GBFTConfig{
+#[serde(default, deny_unknown_fields)]
+pub struct GBFTConfig {
     version: String,
+    /// timeout of the first round of a height, in milliseconds
+    round_timeout_base: u64,
+    /// factor applied to the timeout at every new round
+    round_timeout_multiplier: u64,
+    /// largest block content accepted, in bytes
+    max_block_size: u64,
+    /// time the proposer leaves to fill its block, in milliseconds
+    proposal_delay: u64,
     vc_retry_times: u64,
     recovery_retry_times: u64,
 }
 
+impl Default for GBFTConfig {
+    fn default() -> Self {
+        GBFTConfig {
+            version: String::from("1.0"),
+            round_timeout_base: 3000,
+            round_timeout_multiplier: 2,
+            max_block_size: 4 * 1024 * 1024,
+            proposal_delay: 0,
+            vc_retry_times: 10,
+            recovery_retry_times: 10,
+        }
+    }
+}
+
 impl GBFTConfig {
-    pub fn get_version(&self) -> String{
+    /// Default configuration with the given version and retry counts.
+    pub fn new(version: String, vc_retry_times: u64, recovery_retry_times: u64) -> Self {
+        GBFTConfig {
+            version,
+            vc_retry_times,
+            recovery_retry_times,
+            ..GBFTConfig::default()
+        }
+    }
+
+    /// Parse and validate a TOML configuration.
+    pub fn from_toml(content: &str) -> Result<Self> {
+        let config: GBFTConfig = toml::from_str(content).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse and validate a JSON configuration.
+    pub fn from_json(content: &str) -> Result<Self> {
+        let config: GBFTConfig = serde_json::from_str(content).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load `path`, read as JSON if its extension is `json` and as TOML otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("cannot read {}: {}", path.display(), e)))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => GBFTConfig::from_json(&content),
+            _ => GBFTConfig::from_toml(&content),
+        }
+    }
+
+    /// Check that every setting is in range.
+    pub fn validate(&self) -> Result<()> {
+        if self.version.is_empty() {
+            return Err(Error::InvalidConfig("version is empty".to_owned()));
+        }
+        if !(MIN_ROUND_TIMEOUT..=MAX_ROUND_TIMEOUT).contains(&self.round_timeout_base) {
+            return Err(Error::InvalidConfig(format!(
+                "round_timeout_base must be within {} and {} ms",
+                MIN_ROUND_TIMEOUT, MAX_ROUND_TIMEOUT
+            )));
+        }
+        if !(1..=MAX_TIMEOUT_MULTIPLIER).contains(&self.round_timeout_multiplier) {
+            return Err(Error::InvalidConfig(format!(
+                "round_timeout_multiplier must be within 1 and {}",
+                MAX_TIMEOUT_MULTIPLIER
+            )));
+        }
+        if !(1..=MAX_BLOCK_SIZE).contains(&self.max_block_size) {
+            return Err(Error::InvalidConfig(format!("max_block_size must be within 1 and {} bytes", MAX_BLOCK_SIZE)));
+        }
+        if self.proposal_delay >= self.round_timeout_base {
+            return Err(Error::InvalidConfig("proposal_delay must be shorter than round_timeout_base".to_owned()));
+        }
+        if self.vc_retry_times == 0 || self.recovery_retry_times == 0 {
+            return Err(Error::InvalidConfig("retry counts must be at least 1".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Check that a running node can switch to `other` without restarting.
+    pub fn check_reload(&self, other: &GBFTConfig) -> Result<()> {
+        other.validate()?;
+        if self.version != other.version {
+            return Err(Error::InvalidConfig("version cannot change at runtime".to_owned()));
+        }
+        if self.max_block_size != other.max_block_size {
+            return Err(Error::InvalidConfig("max_block_size cannot change at runtime".to_owned()));
+        }
+        Ok(())
+    }
+
+    pub fn get_version(&self) -> String {
         trace!("config version is {:?}", self.version);
-        return self.version.clone();
+        self.version.clone()
+    }
+
+    pub fn round_timeout_base(&self) -> u64 {
+        self.round_timeout_base
+    }
+
+    pub fn round_timeout_multiplier(&self) -> u64 {
+        self.round_timeout_multiplier
+    }
+
+    pub fn max_block_size(&self) -> u64 {
+        self.max_block_size
+    }
+
+    pub fn proposal_delay(&self) -> u64 {
+        self.proposal_delay
+    }
+
+    /// Number of consecutive view changes tried before giving up.
+    pub fn vc_retry_times(&self) -> u64 {
+        self.vc_retry_times
     }
 
+    /// Number of sync requests sent before a lagging validator gives up.
+    pub fn recovery_retry_times(&self) -> u64 {
+        self.recovery_retry_times
+    }
+
+    pub fn set_round_timeout(&mut self, base: u64, multiplier: u64) {
+        self.round_timeout_base = base;
+        self.round_timeout_multiplier = multiplier;
+    }
+
+    pub fn set_max_block_size(&mut self, max_block_size: u64) {
+        self.max_block_size = max_block_size;
+    }
+
+    pub fn set_proposal_delay(&mut self, proposal_delay: u64) {
+        self.proposal_delay = proposal_delay;
+    }
+}
+
+/// Reload a configuration file when it changes, polled from the node loop.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    /// Load `path` a first time.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<(Self, GBFTConfig)> {
+        let mut watcher = ConfigWatcher { path: path.into(), modified: None };
+        watcher.modified = watcher.modified_time();
+        let config = GBFTConfig::load(&watcher.path)?;
+        Ok((watcher, config))
+    }
+
+    /// The new configuration if the file changed since the last poll. A file
+    /// that fails to load is reported once, then ignored until it changes again.
+    pub fn poll(&mut self) -> Result<Option<GBFTConfig>> {
+        let modified = self.modified_time();
+        if modified == self.modified {
+            return Ok(None);
+        }
+        self.modified = modified;
+        info!("reload gbft config from {}", self.path.display());
+        GBFTConfig::load(&self.path).map(Some)
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
 }
 
-#[test]
-fn test_get_version(){
-    let config = GBFTConfig{
-        version: String::from("1.0.1"),
-        vc_retry_times: 10,
-        recovery_retry_times: 12,
-    };
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_get_version() {
+        let config = GBFTConfig::new(String::from("1.0.1"), 10, 12);
+        assert_eq!(config.get_version(), "1.0.1");
+        assert_eq!(config.recovery_retry_times(), 12);
+    }
+
+    #[test]
+    fn test_parse() {
+        let toml = r#"
+            version = "2.0"
+            round_timeout_base = 1000
+            round_timeout_multiplier = 3
+            proposal_delay = 200
+        "#;
+        let config = GBFTConfig::from_toml(toml).unwrap();
+        assert_eq!(config.get_version(), "2.0");
+        assert_eq!((config.round_timeout_base(), config.round_timeout_multiplier()), (1000, 3));
+        assert_eq!(config.proposal_delay(), 200);
+        assert_eq!(config.max_block_size(), GBFTConfig::default().max_block_size());
 
-    assert_eq!(config.get_version(), "1.0.1");
+        let json = r#"{ "version": "2.0", "round_timeout_base": 1000, "round_timeout_multiplier": 3, "proposal_delay": 200 }"#;
+        assert_eq!(GBFTConfig::from_json(json).unwrap(), config);
+        assert_eq!(GBFTConfig::from_toml("").unwrap(), GBFTConfig::default());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(GBFTConfig::from_toml("round_timeout_base = 10").is_err());
+        assert!(GBFTConfig::from_toml("round_timeout_multiplier = 0").is_err());
+        assert!(GBFTConfig::from_toml("max_block_size = 0").is_err());
+        assert!(GBFTConfig::from_toml("proposal_delay = 3000").is_err());
+        assert!(GBFTConfig::from_toml("vc_retry_times = 0").is_err());
+        assert!(GBFTConfig::from_toml("round_timeout = 1000").is_err());
+        assert!(GBFTConfig::from_json(r#"{ "round_timeout_base": "fast" }"#).is_err());
+    }
 
+    #[test]
+    fn test_check_reload() {
+        let config = GBFTConfig::default();
+        let mut other = config.clone();
+        other.set_round_timeout(5000, 3);
+        other.set_proposal_delay(500);
+        config.check_reload(&other).unwrap();
+        other.set_max_block_size(1024);
+        assert!(config.check_reload(&other).is_err());
+        let other = GBFTConfig::new("2.0".to_owned(), 10, 10);
+        assert!(config.check_reload(&other).is_err());
+    }
+
+    #[test]
+    fn test_watcher() {
+        let dir = TempDir::new("gbft-config").unwrap();
+        let path = dir.path().join("gbft.toml");
+        fs::write(&path, "round_timeout_base = 1000\n").unwrap();
+        let (mut watcher, config) = ConfigWatcher::new(&path).unwrap();
+        assert_eq!(config.round_timeout_base(), 1000);
+        assert_eq!(watcher.poll().unwrap(), None);
+
+        // force a different modification time, file systems may be coarse
+        watcher.modified = None;
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "round_timeout_multiplier = 4").unwrap();
+        let config = watcher.poll().unwrap().unwrap();
+        assert_eq!(config.round_timeout_multiplier(), 4);
+        assert_eq!(watcher.poll().unwrap(), None);
+    }
 }
@@ -0,0 +1,17 @@
+use crate::error::Result;
+use crate::message::{Address, Hash, Signature};
+
+/// Hashing and signing primitives GBFT is generic over.
+///
+/// Implementations must be deterministic, the state machine relies on every
+/// validator computing the same hash for the same bytes.
+pub trait Crypto {
+    /// Hash arbitrary bytes, used for block hashes and signing payloads.
+    fn hash(&self, msg: &[u8]) -> Hash;
+
+    /// Sign a hash with the local validator key, failing without one.
+    fn sign(&self, hash: &Hash) -> Result<Signature>;
+
+    /// Check that `signature` over `hash` was produced by `signer`.
+    fn verify(&self, signature: &Signature, hash: &Hash, signer: &Address) -> bool;
+}
@@ -0,0 +1,87 @@
+//! GBFT behind the shared `consensus_engine::Engine` interface.
+//!
+//! Blocks are not sealed by their author: the proposer hands
+//! `ConsensusHeader::unsealed` to `State::propose` and the header is sealed
+//! with the aggregated certificate of the commit, see `seal_header`. Importing
+//! a header then only checks that certificate.
+
+use codec::Encode;
+use consensus_engine::{
+    ConsensusHeader, Engine, EngineSpec, Error as EngineError, Finality, Header, Proposal, Result as EngineResult,
+};
+use primitives::ConsensusEngineId;
+use serde::{Deserialize, Serialize};
+
+use crate::certificate::{verify_header, AggregatedCertificate};
+use crate::crypto::Crypto;
+use crate::error::Result;
+use crate::message::Commit;
+use crate::validator_set::{Epochs, Validator, ValidatorSet};
+use crate::GBFT_ENGINE_ID;
+
+/// Name of the engine in the chain spec.
+pub const NAME: &str = "gbft";
+
+/// Chain spec parameters of GBFT.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GbftParams {
+    /// validators of the genesis epoch
+    pub validators: Vec<Validator>,
+}
+
+#[derive(Debug)]
+pub struct GbftEngine<C> {
+    crypto: C,
+    epochs: Epochs,
+}
+
+impl<C: Crypto> GbftEngine<C> {
+    pub fn new(crypto: C, validators: ValidatorSet) -> Self {
+        GbftEngine { crypto, epochs: Epochs::new(validators) }
+    }
+
+    pub fn from_spec(crypto: C, spec: &EngineSpec) -> EngineResult<Self> {
+        let params: GbftParams = spec.params(NAME)?;
+        let validators =
+            ValidatorSet::new(0, 0, params.validators).map_err(|e| EngineError::InvalidParams(e.to_string()))?;
+        Ok(GbftEngine::new(crypto, validators))
+    }
+
+    /// Follow the validator sets scheduled by the runtime, like `State::schedule_validators`.
+    pub fn schedule_validators(&mut self, validators: ValidatorSet) -> Result<()> {
+        self.epochs.schedule(validators)
+    }
+
+    /// Seal of the block committed by `commit`.
+    pub fn seal(&self, commit: &Commit) -> Result<Vec<u8>> {
+        let certificate = AggregatedCertificate::aggregate(&commit.certificate, self.epochs.at(commit.height))?;
+        Ok(certificate.encode())
+    }
+}
+
+impl<C: Crypto> Engine for GbftEngine<C> {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn id(&self) -> ConsensusEngineId {
+        GBFT_ENGINE_ID
+    }
+
+    fn finality(&self) -> Finality {
+        Finality::Instant
+    }
+
+    fn propose(&mut self, _header: &Header) -> EngineResult<Proposal> {
+        Ok(Proposal::Pending)
+    }
+
+    fn verify_header(&self, header: &Header) -> EngineResult<()> {
+        if header.seal(&GBFT_ENGINE_ID).is_none() {
+            return Err(EngineError::MissingSeal(header.number));
+        }
+        verify_header(&self.crypto, self.epochs.at(header.number), header)
+            .map(|_| ())
+            .map_err(|e| EngineError::InvalidSeal(e.to_string()))
+    }
+}
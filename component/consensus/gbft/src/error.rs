@@ -0,0 +1,115 @@
+//! GBFT consensus possible errors.
+
+use std::{fmt, io};
+
+/// Result type alias for GBFT.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error type for GBFT.
+#[derive(derive_more::Display)]
+pub enum Error {
+    /// The message belongs to another height.
+    #[display(fmt = "Message for height {} while at height {}", got, current)]
+    WrongHeight {
+        /// Height carried by the message.
+        got: u64,
+        /// Height of the local state.
+        current: u64,
+    },
+    /// The message belongs to another round.
+    #[display(fmt = "Message for round {} while at round {}", got, current)]
+    WrongRound {
+        /// Round carried by the message.
+        got: u64,
+        /// Round of the local state.
+        current: u64,
+    },
+    /// The sender is not part of the validator set.
+    #[display(fmt = "Unknown validator {:?}", _0)]
+    UnknownValidator(Vec<u8>),
+    /// The proposal was not sent by the proposer of its round.
+    #[display(fmt = "Proposal from {:?} who is not the proposer", _0)]
+    InvalidProposer(Vec<u8>),
+    /// The signature does not match the signer.
+    #[display(fmt = "Invalid signature from {:?}", _0)]
+    InvalidSignature(Vec<u8>),
+    /// The block hash does not match the proposed content.
+    #[display(fmt = "Block hash does not match the proposed content")]
+    InvalidBlockHash,
+    /// A certificate does not reach the quorum or mixes votes.
+    #[display(fmt = "Invalid certificate: {}", _0)]
+    InvalidCertificate(&'static str),
+    /// The evidence does not prove an equivocation.
+    #[display(fmt = "Invalid evidence: {}", _0)]
+    InvalidEvidence(&'static str),
+    /// A validator set cannot be used or scheduled.
+    #[display(fmt = "Invalid validator set: {}", _0)]
+    InvalidValidatorSet(&'static str),
+    /// The block content exceeds `max_block_size`.
+    #[display(fmt = "Block of {} bytes exceeds the maximum of {}", size, max)]
+    BlockTooLarge {
+        /// Size of the content.
+        size: u64,
+        /// Configured maximum.
+        max: u64,
+    },
+    /// The configuration cannot be parsed, is out of range or cannot be reloaded.
+    #[display(fmt = "Invalid config: {}", _0)]
+    InvalidConfig(String),
+    /// `State::propose` was called while not expecting a proposal.
+    #[display(fmt = "Not the proposer of height {} round {}", height, round)]
+    NotProposer {
+        /// Current height.
+        height: u64,
+        /// Current round.
+        round: u64,
+    },
+    /// No new view was reached within `vc_retry_times` view changes.
+    #[display(fmt = "View change at height {} gave up after {} attempts, last round {}", height, attempts, round)]
+    ViewChangeFailed {
+        /// Current height.
+        height: u64,
+        /// Round targeted by the last view change.
+        round: u64,
+        /// Number of view changes started.
+        attempts: u64,
+    },
+    /// Catching up with the network failed `recovery_retry_times` times in a row.
+    #[display(fmt = "Catching up from height {} to {} gave up after {} attempts", height, target, attempts)]
+    RecoveryFailed {
+        /// Current height.
+        height: u64,
+        /// Highest height seen from the network.
+        target: u64,
+        /// Number of sync requests sent.
+        attempts: u64,
+    },
+    /// The local validator key cannot sign.
+    #[display(fmt = "Cannot sign: {}", _0)]
+    Signing(String),
+    /// Reading or writing the write ahead log failed.
+    #[display(fmt = "Write ahead log error: {}", _0)]
+    Wal(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Wal(err)
+    }
+}
+
+// Make `Debug` use the `Display` implementation.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Wal(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
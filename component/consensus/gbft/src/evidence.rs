@@ -0,0 +1,88 @@
+//! Proof that a validator signed two conflicting messages.
+//!
+//! An `Evidence` carries both signed messages, so anyone knowing the
+//! validator set can check it with `Evidence::verify`, without trusting the
+//! reporter. It is SCALE encoded when submitted to the runtime.
+
+use codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Crypto;
+use crate::error::{Error, Result};
+use crate::message::{proposal_payload, vote_payload, Address, SignedProposal, SignedVote};
+use crate::validator_set::ValidatorSet;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum Evidence {
+    /// The proposer signed two blocks for the same height and round.
+    DoubleProposal { first: SignedProposal, second: SignedProposal },
+    /// The voter voted for two blocks in the same height, round and phase.
+    DoubleVote { first: SignedVote, second: SignedVote },
+}
+
+impl Evidence {
+    /// Validator that equivocated.
+    pub fn offender(&self) -> &Address {
+        match self {
+            Evidence::DoubleProposal { first, .. } => &first.proposal.proposer,
+            Evidence::DoubleVote { first, .. } => &first.vote.voter,
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        match self {
+            Evidence::DoubleProposal { first, .. } => first.proposal.height,
+            Evidence::DoubleVote { first, .. } => first.vote.height,
+        }
+    }
+
+    pub fn round(&self) -> u64 {
+        match self {
+            Evidence::DoubleProposal { first, .. } => first.proposal.round,
+            Evidence::DoubleVote { first, .. } => first.vote.round,
+        }
+    }
+
+    /// Check that both messages are signed by the same member of
+    /// `validators`, the set of the evidence height, for the same slot and
+    /// different blocks.
+    pub fn verify<C: Crypto>(&self, crypto: &C, validators: &ValidatorSet) -> Result<()> {
+        let offender = self.offender();
+        if !validators.contains(offender) {
+            return Err(Error::UnknownValidator(offender.clone()));
+        }
+        match self {
+            Evidence::DoubleProposal { first, second } => {
+                let (a, b) = (&first.proposal, &second.proposal);
+                if (a.height, a.round, &a.proposer) != (b.height, b.round, &b.proposer) {
+                    return Err(Error::InvalidEvidence("proposals of different slots"));
+                }
+                if a.block_hash == b.block_hash {
+                    return Err(Error::InvalidEvidence("proposals of the same block"));
+                }
+                for signed in &[first, second] {
+                    let hash = crypto.hash(&proposal_payload(&signed.proposal));
+                    if !crypto.verify(&signed.signature, &hash, offender) {
+                        return Err(Error::InvalidSignature(offender.clone()));
+                    }
+                }
+            }
+            Evidence::DoubleVote { first, second } => {
+                let (a, b) = (&first.vote, &second.vote);
+                if (a.height, a.round, a.vote_type, &a.voter) != (b.height, b.round, b.vote_type, &b.voter) {
+                    return Err(Error::InvalidEvidence("votes of different slots"));
+                }
+                if a.block_hash == b.block_hash {
+                    return Err(Error::InvalidEvidence("votes for the same block"));
+                }
+                for signed in &[first, second] {
+                    let hash = crypto.hash(&vote_payload(&signed.vote));
+                    if !crypto.verify(&signed.signature, &hash, offender) {
+                        return Err(Error::InvalidSignature(offender.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
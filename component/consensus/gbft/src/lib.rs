@@ -1,12 +1,52 @@
 mod state;
 mod config;
+mod crypto;
+mod error;
+mod message;
+mod evidence;
+mod certificate;
+mod metrics;
+mod engine;
+mod validator_set;
 
-use state::{State};
-use config::{GBFTConfig};
+#[cfg(test)]
+mod state_test;
+#[cfg(test)]
+mod simulation;
 
-pub struct GPBFInitlizer {
-    pub state: State,
+pub use state::{State, TimeoutStruct};
+pub use state::wal::{Replay, WalEntry, WAL};
+pub use config::{ConfigWatcher, GBFTConfig};
+pub use crypto::Crypto;
+pub use error::{Error, Result};
+pub use evidence::Evidence;
+pub use engine::{GbftEngine, GbftParams, NAME};
+pub use certificate::{seal_header, verify_finality, verify_header, AggregatedCertificate, GBFT_ENGINE_ID};
+#[cfg(feature = "prometheus")]
+pub use metrics::Metrics;
+pub use metrics::{Cause, RoundTrace, Transition};
+pub use validator_set::{Epochs, Validator, ValidatorSet};
+pub use message::*;
+
+pub struct GPBFInitlizer<C> {
+    pub state: State<C>,
     pub current: u64,
-    pub config: GBFTConfig,
 }
 
+impl<C: Crypto> GPBFInitlizer<C> {
+    /// Start at height `current` with the validator set in charge of it,
+    /// later epochs are added through `State::schedule_validators`.
+    pub fn new(
+        crypto: C,
+        address: Address,
+        validators: ValidatorSet,
+        config: GBFTConfig,
+        current: u64,
+    ) -> Self {
+        let timeout = TimeoutStruct { height: current, round: 0, duration: config.round_timeout_base() };
+        GPBFInitlizer {
+            state: State::new(crypto, address, validators, config, timeout),
+            current,
+        }
+    }
+}
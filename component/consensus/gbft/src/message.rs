@@ -0,0 +1,184 @@
+use codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::evidence::Evidence;
+
+/// Block hash produced by `Crypto::hash`.
+pub type Hash = Vec<u8>;
+/// Identity of a validator.
+pub type Address = Vec<u8>;
+/// Signature produced by `Crypto::sign`.
+pub type Signature = Vec<u8>;
+
+/// Phase of a round the state machine is currently in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Step {
+    /// waiting for the proposer's pre-prepare
+    PrePrepare,
+    /// prepare vote sent, waiting for a prepare quorum
+    Prepare,
+    /// commit vote sent, waiting for a commit quorum
+    Commit,
+    /// the round timed out, waiting for the new view of a later round
+    ViewChange,
+}
+
+/// The two voting phases following a pre-prepare.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Encode, Decode)]
+pub enum VoteType {
+    Prepare,
+    Commit,
+}
+
+/// Pre-prepare message sent by the proposer of a round.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct Proposal {
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: Hash,
+    /// the opaque block, `block_hash` must be its hash
+    pub content: Vec<u8>,
+    pub proposer: Address,
+    /// prepare quorum justifying a re-proposal of a locked block
+    pub lock: Option<PreparedCertificate>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct SignedProposal {
+    pub proposal: Proposal,
+    pub signature: Signature,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encode, Decode)]
+pub struct Vote {
+    pub height: u64,
+    pub round: u64,
+    pub vote_type: VoteType,
+    pub block_hash: Hash,
+    pub voter: Address,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encode, Decode)]
+pub struct SignedVote {
+    pub vote: Vote,
+    pub signature: Signature,
+}
+
+/// A quorum of prepare votes for one block in one round.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct PreparedCertificate {
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: Hash,
+    pub votes: Vec<SignedVote>,
+}
+
+/// A quorum of commit votes finalizing a block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitCertificate {
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: Hash,
+    pub votes: Vec<SignedVote>,
+}
+
+/// Request to move to `round`, carrying the highest prepared block of the sender.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct ViewChange {
+    pub height: u64,
+    pub round: u64,
+    pub prepared: Option<PreparedCertificate>,
+    /// content of the prepared block, present iff `prepared` is
+    pub content: Option<Vec<u8>>,
+    pub voter: Address,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct SignedViewChange {
+    pub view_change: ViewChange,
+    pub signature: Signature,
+}
+
+/// Sent by the proposer of `round` once a quorum asked for it. The proposal
+/// must carry the highest prepared block of the view changes, if any.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewView {
+    pub height: u64,
+    pub round: u64,
+    pub view_changes: Vec<SignedViewChange>,
+    pub proposal: SignedProposal,
+}
+
+/// Asks peers for the commits from `height` on, sent by a validator that saw
+/// messages of a later height.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRequest {
+    pub height: u64,
+    pub requester: Address,
+}
+
+/// Answer to a `SyncRequest`. Both parts are checked on their own, the
+/// responder does not need to be trusted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncResponse {
+    /// consecutive commits, oldest first
+    pub commits: Vec<Commit>,
+    /// new view that opened the current round of the responder, if any
+    pub new_view: Option<NewView>,
+}
+
+/// Messages exchanged between validators.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsensusMessage {
+    Proposal(SignedProposal),
+    Vote(SignedVote),
+    ViewChange(SignedViewChange),
+    NewView(NewView),
+    SyncRequest(SyncRequest),
+    SyncResponse(SyncResponse),
+    Evidence(Evidence),
+}
+
+/// A block that reached BFT finality.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commit {
+    pub height: u64,
+    pub content: Vec<u8>,
+    pub certificate: CommitCertificate,
+}
+
+/// Effects requested by the state machine, to be carried out by the caller.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Output {
+    /// send the message to every other validator
+    Broadcast(ConsensusMessage),
+    /// send the message to one validator only
+    Send { to: Address, message: ConsensusMessage },
+    /// arm a timer, feed it back through `State::handle_timeout` once it fires
+    ScheduleTimeout(crate::state::TimeoutStruct),
+    /// we are the proposer of this round, answer with `State::propose`
+    /// after `delay` milliseconds
+    RequestProposal { height: u64, round: u64, delay: u64 },
+    /// arm the catch-up retry timer, feed it back through `State::handle_sync_timeout`
+    ScheduleSync(crate::state::TimeoutStruct),
+    /// the block is final
+    Commit(Commit),
+    /// a validator equivocated, submit the evidence to the runtime
+    Evidence(Evidence),
+}
+
+/// Bytes covered by a proposal signature, SCALE encoded like the evidence
+/// so that the runtime checks the signatures of the evidence it is given.
+pub fn proposal_payload(proposal: &Proposal) -> Vec<u8> {
+    proposal.encode()
+}
+
+/// Bytes covered by a vote signature, SCALE encoded as proposals are.
+pub fn vote_payload(vote: &Vote) -> Vec<u8> {
+    vote.encode()
+}
+
+/// Bytes covered by a view change signature, SCALE encoded as proposals are.
+pub fn view_change_payload(view_change: &ViewChange) -> Vec<u8> {
+    view_change.encode()
+}
@@ -0,0 +1,302 @@
+//! Observability of the state machine.
+//!
+//! Every change of round or step is kept in a bounded `RoundTrace`, which can
+//! be dumped as JSON lines when debugging a stuck height. With the
+//! `prometheus` feature the same events feed `Metrics`, registered through
+//! `substrate_prometheus_endpoint::register`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::message::{Step, VoteType};
+
+/// Number of transitions kept by default.
+const TRACE_CAPACITY: usize = 1024;
+
+/// What made the state machine move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cause {
+    /// `State::start`
+    Start,
+    /// a valid proposal was accepted
+    Proposal,
+    /// a prepare quorum was reached
+    Prepared,
+    /// the round timed out or a weak quorum asked for a view change
+    ViewChange,
+    /// a new view opened a later round
+    NewView,
+    /// the previous height committed
+    Commit,
+    /// the previous height was caught up from another validator
+    Sync,
+}
+
+/// One change of round or step.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub height: u64,
+    pub from_round: u64,
+    pub from_step: Step,
+    pub round: u64,
+    pub step: Step,
+    pub cause: Cause,
+    /// time spent in `from_step`, in milliseconds
+    pub elapsed: u64,
+}
+
+/// The last transitions, oldest first.
+#[derive(Clone, Debug)]
+pub struct RoundTrace {
+    transitions: VecDeque<Transition>,
+    capacity: usize,
+}
+
+impl Default for RoundTrace {
+    fn default() -> Self {
+        RoundTrace::with_capacity(TRACE_CAPACITY)
+    }
+}
+
+impl RoundTrace {
+    pub fn with_capacity(capacity: usize) -> Self {
+        RoundTrace { transitions: VecDeque::new(), capacity }
+    }
+
+    pub fn push(&mut self, transition: Transition) {
+        if self.transitions.len() == self.capacity {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(transition);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Transition> {
+        self.transitions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    /// One JSON object per line.
+    pub fn dump(&self) -> String {
+        self.transitions
+            .iter()
+            .map(|t| serde_json::to_string(t).expect("a transition always serializes; qed") + "\n")
+            .collect()
+    }
+}
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::Metrics;
+
+#[cfg(feature = "prometheus")]
+mod prometheus {
+    use std::time::Duration;
+
+    use substrate_prometheus_endpoint::{
+        exponential_buckets, register, Counter, CounterVec, Gauge, Histogram, HistogramOpts, HistogramVec, Opts,
+        PrometheusError, Registry, U64,
+    };
+
+    use crate::message::{Step, VoteType};
+
+    /// GBFT metrics, cheap to clone.
+    #[derive(Clone)]
+    pub struct Metrics {
+        height: Gauge<U64>,
+        round: Gauge<U64>,
+        phase_duration: HistogramVec,
+        view_changes: Counter<U64>,
+        votes: CounterVec<U64>,
+        rounds: CounterVec<U64>,
+        wal_sync: Histogram,
+    }
+
+    impl Metrics {
+        pub fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+            Ok(Metrics {
+                height: register(Gauge::new("gbft_height", "Current height")?, registry)?,
+                round: register(Gauge::new("gbft_round", "Current round")?, registry)?,
+                phase_duration: register(
+                    HistogramVec::new(
+                        HistogramOpts::new("gbft_phase_duration_seconds", "Time spent in each step of a round")
+                            .buckets(exponential_buckets(0.01, 2.0, 14)?),
+                        &["phase"],
+                    )?,
+                    registry,
+                )?,
+                view_changes: register(
+                    Counter::new("gbft_view_changes_total", "View changes started")?,
+                    registry,
+                )?,
+                votes: register(
+                    CounterVec::new(
+                        Opts::new("gbft_votes_received_total", "Votes received from other validators"),
+                        &["phase"],
+                    )?,
+                    registry,
+                )?,
+                rounds: register(
+                    CounterVec::new(
+                        Opts::new("gbft_rounds_total", "Rounds ended, committed ones are proposer hits"),
+                        &["outcome"],
+                    )?,
+                    registry,
+                )?,
+                wal_sync: register(
+                    Histogram::with_opts(
+                        HistogramOpts::new("gbft_wal_sync_seconds", "Write ahead log append and fsync latency")
+                            .buckets(exponential_buckets(0.0001, 2.0, 14)?),
+                    )?,
+                    registry,
+                )?,
+            })
+        }
+
+        pub(crate) fn position(&self, height: u64, round: u64) {
+            self.height.set(height);
+            self.round.set(round);
+        }
+
+        pub(crate) fn phase(&self, step: Step, elapsed: Duration) {
+            self.phase_duration.with_label_values(&[phase(step)]).observe(elapsed.as_secs_f64());
+        }
+
+        pub(crate) fn vote(&self, vote_type: Option<VoteType>) {
+            let label = match vote_type {
+                Some(VoteType::Prepare) => "prepare",
+                Some(VoteType::Commit) => "commit",
+                None => "view_change",
+            };
+            self.votes.with_label_values(&[label]).inc();
+        }
+
+        pub(crate) fn view_change(&self) {
+            self.view_changes.inc();
+            self.rounds.with_label_values(&["timeout"]).inc();
+        }
+
+        pub(crate) fn commit(&self) {
+            self.rounds.with_label_values(&["committed"]).inc();
+        }
+
+        pub(crate) fn wal_sync(&self, elapsed: Duration) {
+            self.wal_sync.observe(elapsed.as_secs_f64());
+        }
+    }
+
+    fn phase(step: Step) -> &'static str {
+        match step {
+            Step::PrePrepare => "pre_prepare",
+            Step::Prepare => "prepare",
+            Step::Commit => "commit",
+            Step::ViewChange => "view_change",
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::config::GBFTConfig;
+        use crate::state::{State, TimeoutStruct};
+        use crate::state_test::TestCrypto;
+        use crate::validator_set::ValidatorSet;
+
+        #[test]
+        fn test_metrics_follow_the_state() {
+            let registry = Registry::new();
+            let metrics = Metrics::register(&registry).unwrap();
+            assert!(Metrics::register(&registry).is_err());
+
+            let validators = ValidatorSet::equal(vec![vec![0]]).unwrap();
+            let config = GBFTConfig::new("1.0".to_owned(), 3, 3);
+            let timeout = TimeoutStruct { height: 1, round: 0, duration: config.round_timeout_base() };
+            let mut state = State::new(TestCrypto(vec![0]), vec![0], validators, config, timeout);
+            state.set_metrics(metrics.clone());
+            let _ = state.start();
+            let _ = state.propose(b"one".to_vec()).unwrap();
+            assert_eq!((metrics.height.get(), metrics.round.get()), (2, 0));
+            assert_eq!(metrics.rounds.with_label_values(&["committed"]).get(), 1);
+            assert_eq!(metrics.view_changes.get(), 0);
+
+            let timeout = TimeoutStruct { height: 2, round: 0, duration: state.round_duration(0) };
+            let _ = state.handle_timeout(timeout).unwrap();
+            assert_eq!((metrics.height.get(), metrics.round.get()), (2, 1));
+            assert_eq!(metrics.view_changes.get(), 1);
+            assert_eq!(metrics.rounds.with_label_values(&["timeout"]).get(), 1);
+            assert!(metrics.phase_duration.with_label_values(&["commit"]).get_sample_count() > 0);
+
+            let names: Vec<String> = registry.gather().iter().map(|family| family.get_name().to_owned()).collect();
+            assert!(names.contains(&"gbft_view_changes_total".to_owned()));
+            assert!(names.contains(&"gbft_rounds_total".to_owned()));
+        }
+    }
+}
+
+/// Trace and metrics kept by a `State`.
+#[derive(Default)]
+pub(crate) struct Telemetry {
+    pub trace: RoundTrace,
+    entered: Option<Instant>,
+    #[cfg(feature = "prometheus")]
+    pub metrics: Option<Metrics>,
+}
+
+impl std::fmt::Debug for Telemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Telemetry").field("trace", &self.trace.len()).finish()
+    }
+}
+
+#[cfg_attr(not(feature = "prometheus"), allow(unused_variables))]
+impl Telemetry {
+    pub fn transition(&mut self, height: u64, from: (u64, Step), to: (u64, Step), cause: Cause) {
+        let now = Instant::now();
+        let elapsed = self.entered.map_or(Duration::default(), |entered| now - entered);
+        self.entered = Some(now);
+        self.trace.push(Transition {
+            height,
+            from_round: from.0,
+            from_step: from.1,
+            round: to.0,
+            step: to.1,
+            cause,
+            elapsed: elapsed.as_millis() as u64,
+        });
+
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.position(height, to.0);
+            if cause != Cause::Start {
+                metrics.phase(from.1, elapsed);
+            }
+            match cause {
+                Cause::ViewChange => metrics.view_change(),
+                Cause::Commit => metrics.commit(),
+                _ => {}
+            }
+        }
+    }
+
+    /// A vote from another validator, `None` for a view change.
+    pub fn vote(&self, vote_type: Option<VoteType>) {
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.vote(vote_type);
+        }
+    }
+
+    pub fn wal_sync(&self, elapsed: Duration) {
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.metrics {
+            metrics.wal_sync(elapsed);
+        }
+    }
+}
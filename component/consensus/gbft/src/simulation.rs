@@ -0,0 +1,468 @@
+//! Deterministic simulation of a GBFT network.
+//!
+//! `N` state machines run over a virtual clock and a virtual network driven
+//! by a seeded generator, so a failing seed replays exactly. Before the global
+//! stabilization time (GST) the network delays messages arbitrarily, drops
+//! them and partitions the validators; after it every message between honest
+//! validators arrives within the maximum latency.
+//!
+//! Every commit is checked for safety as it happens, `Report::check_liveness`
+//! checks that every honest validator reached the target height.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+use crate::config::GBFTConfig;
+use crate::crypto::Crypto;
+use crate::evidence::Evidence;
+use crate::message::*;
+use crate::state::{State, TimeoutStruct};
+use crate::state_test::TestCrypto;
+use crate::validator_set::ValidatorSet;
+
+/// Small seeded generator (splitmix64), the simulation must not depend on
+/// anything but its seed.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[low, high]`.
+    pub fn range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next_u64() % (high - low + 1)
+    }
+
+    /// True with probability `percent / 100`.
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.next_u64() % 100 < percent
+    }
+}
+
+/// Misbehaviour of a faulty validator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Byzantine {
+    /// crashed from the start, never sends anything
+    Silent,
+    /// sends conflicting proposals and votes to half of the validators, some
+    /// of them get both versions
+    Equivocate,
+}
+
+/// Validators split in groups that cannot reach each other during `[from, until)`.
+#[derive(Clone, Debug)]
+pub struct Partition {
+    pub from: u64,
+    pub until: u64,
+    pub groups: Vec<Vec<usize>>,
+}
+
+impl Partition {
+    fn separates(&self, time: u64, a: usize, b: usize) -> bool {
+        time >= self.from
+            && time < self.until
+            && !self.groups.iter().any(|group| group.contains(&a) && group.contains(&b))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub validators: usize,
+    pub byzantine: BTreeMap<usize, Byzantine>,
+    /// round timeout of round 0, in virtual milliseconds
+    pub base_timeout: u64,
+    pub min_latency: u64,
+    pub max_latency: u64,
+    /// maximum latency before GST
+    pub async_latency: u64,
+    /// percentage of messages dropped before GST
+    pub drop_rate: u64,
+    pub partitions: Vec<Partition>,
+    pub gst: u64,
+    pub target_height: u64,
+    /// the run stops at this time even if the target is not reached
+    pub horizon: u64,
+}
+
+impl SimConfig {
+    /// A synchronous network of `validators` honest validators.
+    pub fn synchronous(validators: usize, target_height: u64) -> Self {
+        SimConfig {
+            validators,
+            byzantine: BTreeMap::new(),
+            base_timeout: 1000,
+            min_latency: 10,
+            max_latency: 100,
+            async_latency: 100,
+            drop_rate: 0,
+            partitions: vec![],
+            gst: 0,
+            target_height,
+            horizon: 1_000_000,
+        }
+    }
+
+    /// A network of 4 to 7 validators with up to `f` faulty ones, drops,
+    /// delays and maybe a partition until a GST below 30 seconds.
+    pub fn random(rng: &mut Rng, target_height: u64) -> Self {
+        let validators = rng.range(4, 7) as usize;
+        let faulty = rng.range(0, (validators as u64 - 1) / 3) as usize;
+        let mut byzantine = BTreeMap::new();
+        while byzantine.len() < faulty {
+            let behaviour = if rng.chance(50) { Byzantine::Silent } else { Byzantine::Equivocate };
+            byzantine.insert(rng.range(0, validators as u64 - 1) as usize, behaviour);
+        }
+
+        let gst = rng.range(0, 30_000);
+        let mut partitions = vec![];
+        if rng.chance(50) {
+            let mut groups = vec![vec![], vec![]];
+            for i in 0..validators {
+                groups[rng.range(0, 1) as usize].push(i);
+            }
+            let from = rng.range(0, gst);
+            partitions.push(Partition { from, until: rng.range(from, gst), groups });
+        }
+
+        SimConfig {
+            validators,
+            byzantine,
+            base_timeout: 1000,
+            min_latency: 5,
+            max_latency: rng.range(20, 300),
+            async_latency: rng.range(300, 5000),
+            drop_rate: rng.range(0, 40),
+            partitions,
+            gst,
+            target_height,
+            horizon: gst + 2_000_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Event {
+    Deliver { to: usize, message: Box<ConsensusMessage> },
+    Timeout { node: usize, timeout: TimeoutStruct },
+    SyncTimeout { node: usize, timeout: TimeoutStruct },
+    Propose { node: usize, height: u64, round: u64 },
+}
+
+#[derive(Debug)]
+struct Scheduled {
+    time: u64,
+    seq: u64,
+    event: Event,
+}
+
+// Earliest first, ties broken by scheduling order.
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+/// Outcome of a run.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub seed: u64,
+    /// height reached by every validator
+    pub heights: Vec<u64>,
+    pub honest: Vec<usize>,
+    /// validators caught equivocating by an honest validator
+    pub offenders: BTreeSet<usize>,
+    pub target_height: u64,
+    pub time: u64,
+}
+
+impl Report {
+    pub fn check_liveness(&self) {
+        for &i in &self.honest {
+            assert!(
+                self.heights[i] > self.target_height,
+                "seed {}: validator {} stuck at height {} after {}ms",
+                self.seed,
+                i,
+                self.heights[i],
+                self.time
+            );
+        }
+    }
+}
+
+pub struct Simulation {
+    seed: u64,
+    rng: Rng,
+    config: SimConfig,
+    nodes: Vec<State<TestCrypto>>,
+    time: u64,
+    seq: u64,
+    queue: BinaryHeap<Scheduled>,
+    finalized: BTreeMap<u64, Hash>, // block committed at each height by any honest validator
+    committed: Vec<u64>, // last height committed by each validator
+    offenders: BTreeSet<usize>,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, config: SimConfig) -> Self {
+        let addresses: Vec<Address> = (0..config.validators as u8).map(|i| vec![i]).collect();
+        let validators = ValidatorSet::equal(addresses.clone()).expect("at least one validator; qed");
+        // the validators never give up on their own, liveness is only
+        // checked after GST
+        let mut gbft = GBFTConfig::new("sim".to_owned(), u64::MAX, u64::MAX);
+        gbft.set_round_timeout(config.base_timeout, 2);
+        let start = TimeoutStruct { height: 1, round: 0, duration: config.base_timeout };
+        let nodes = addresses
+            .iter()
+            .map(|address| {
+                State::new(TestCrypto(address.clone()), address.clone(), validators.clone(), gbft.clone(), start.clone())
+            })
+            .collect();
+        Simulation {
+            seed,
+            rng: Rng::new(seed),
+            committed: vec![0; config.validators],
+            offenders: BTreeSet::new(),
+            config,
+            nodes,
+            time: 0,
+            seq: 0,
+            queue: BinaryHeap::new(),
+            finalized: BTreeMap::new(),
+        }
+    }
+
+    /// Run until every honest validator passed the target height or the horizon.
+    pub fn run(mut self) -> Report {
+        for node in 0..self.nodes.len() {
+            let outputs = self.nodes[node].start();
+            self.dispatch(node, outputs);
+        }
+
+        while let Some(Scheduled { time, event, .. }) = self.queue.pop() {
+            if time > self.config.horizon || self.done() {
+                break;
+            }
+            self.time = time;
+            let (node, result) = match event {
+                Event::Deliver { to, message } => (to, self.nodes[to].handle_message(*message)),
+                Event::Timeout { node, timeout } => (node, self.nodes[node].handle_timeout(timeout)),
+                Event::SyncTimeout { node, timeout } => (node, self.nodes[node].handle_sync_timeout(timeout)),
+                Event::Propose { node, height, round } => {
+                    let content = format!("block-{}-{}-{}", height, round, node).into_bytes();
+                    (node, self.nodes[node].propose(content))
+                }
+            };
+            // invalid or late messages are expected on a faulty network
+            if let Ok(outputs) = result {
+                self.dispatch(node, outputs);
+            }
+        }
+
+        Report {
+            seed: self.seed,
+            heights: self.nodes.iter().map(State::height).collect(),
+            honest: self.honest().collect(),
+            offenders: self.offenders,
+            target_height: self.config.target_height,
+            time: self.time,
+        }
+    }
+
+    fn honest(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.nodes.len()).filter(move |i| !self.config.byzantine.contains_key(i))
+    }
+
+    fn done(&self) -> bool {
+        self.honest().all(|i| self.nodes[i].height() > self.config.target_height)
+    }
+
+    fn schedule(&mut self, delay: u64, event: Event) {
+        self.seq += 1;
+        self.queue.push(Scheduled { time: self.time + delay, seq: self.seq, event });
+    }
+
+    fn dispatch(&mut self, node: usize, outputs: Vec<Output>) {
+        for output in outputs {
+            match output {
+                Output::Broadcast(message) => {
+                    for to in 0..self.nodes.len() {
+                        if to != node {
+                            self.send(node, to, message.clone());
+                        }
+                    }
+                }
+                Output::Send { to, message } => self.send(node, to[0] as usize, message),
+                Output::ScheduleTimeout(timeout) => {
+                    let delay = timeout.duration;
+                    self.schedule(delay, Event::Timeout { node, timeout });
+                }
+                Output::ScheduleSync(timeout) => {
+                    let delay = timeout.duration;
+                    self.schedule(delay, Event::SyncTimeout { node, timeout });
+                }
+                Output::RequestProposal { height, round, delay } => {
+                    self.schedule(delay, Event::Propose { node, height, round })
+                }
+                Output::Commit(commit) => self.on_commit(node, commit),
+                Output::Evidence(evidence) => self.on_evidence(node, evidence),
+            }
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: ConsensusMessage) {
+        match self.config.byzantine.get(&from) {
+            Some(Byzantine::Silent) => {}
+            Some(Byzantine::Equivocate) if to % 2 == 1 => {
+                // some validators get both versions, and can catch the equivocation
+                if to % 4 == 1 {
+                    self.transmit(from, to, message.clone());
+                }
+                self.transmit(from, to, equivocate(from, message));
+            }
+            _ => self.transmit(from, to, message),
+        }
+    }
+
+    fn transmit(&mut self, from: usize, to: usize, message: ConsensusMessage) {
+        let asynchronous = self.time < self.config.gst;
+        if asynchronous
+            && (self.rng.chance(self.config.drop_rate)
+                || self.config.partitions.iter().any(|p| p.separates(self.time, from, to)))
+        {
+            return;
+        }
+        let max = if asynchronous { self.config.async_latency } else { self.config.max_latency };
+        let delay = self.rng.range(self.config.min_latency, max.max(self.config.min_latency));
+        self.schedule(delay, Event::Deliver { to, message: Box::new(message) });
+    }
+
+    fn on_evidence(&mut self, node: usize, evidence: Evidence) {
+        if self.config.byzantine.contains_key(&node) {
+            return;
+        }
+        let offender = evidence.offender()[0] as usize;
+        assert!(
+            self.config.byzantine.contains_key(&offender),
+            "seed {}: validator {} reported honest validator {}",
+            self.seed,
+            node,
+            offender
+        );
+        assert!(evidence.verify(&TestCrypto(vec![]), self.nodes[node].validators()).is_ok());
+        self.offenders.insert(offender);
+    }
+
+    fn on_commit(&mut self, node: usize, commit: Commit) {
+        assert_eq!(
+            commit.height,
+            self.committed[node] + 1,
+            "seed {}: validator {} skipped a height",
+            self.seed,
+            node
+        );
+        self.committed[node] = commit.height;
+        if self.config.byzantine.contains_key(&node) {
+            return;
+        }
+        let block_hash = commit.certificate.block_hash;
+        let finalized = self.finalized.entry(commit.height).or_insert_with(|| block_hash.clone());
+        assert_eq!(
+            finalized, &block_hash,
+            "seed {}: conflicting commits at height {}",
+            self.seed, commit.height
+        );
+    }
+}
+
+/// The conflicting version of a message sent by an equivocating validator.
+fn equivocate(from: usize, message: ConsensusMessage) -> ConsensusMessage {
+    let crypto = TestCrypto(vec![from as u8]);
+    let forge = |signed: SignedProposal| {
+        let mut proposal = signed.proposal;
+        proposal.content.extend_from_slice(b"-evil");
+        proposal.block_hash = crypto.hash(&proposal.content);
+        let signature = crypto.sign(&crypto.hash(&proposal_payload(&proposal))).unwrap();
+        SignedProposal { proposal, signature }
+    };
+    match message {
+        ConsensusMessage::Proposal(signed) => ConsensusMessage::Proposal(forge(signed)),
+        ConsensusMessage::NewView(mut new_view) => {
+            new_view.proposal = forge(new_view.proposal);
+            ConsensusMessage::NewView(new_view)
+        }
+        ConsensusMessage::Vote(signed) => {
+            let mut vote = signed.vote;
+            vote.block_hash = crypto.hash(&vote.block_hash);
+            let signature = crypto.sign(&crypto.hash(&vote_payload(&vote))).unwrap();
+            ConsensusMessage::Vote(SignedVote { vote, signature })
+        }
+        message => message,
+    }
+}
+
+#[test]
+fn test_synchronous_network_commits() {
+    let report = Simulation::new(0, SimConfig::synchronous(4, 10)).run();
+    report.check_liveness();
+    assert!(report.heights.iter().all(|h| *h == 11));
+}
+
+#[test]
+fn test_silent_validator() {
+    let mut config = SimConfig::synchronous(4, 10);
+    config.byzantine.insert(1, Byzantine::Silent);
+    Simulation::new(1, config).run().check_liveness();
+}
+
+#[test]
+fn test_equivocating_validator() {
+    let mut config = SimConfig::synchronous(7, 10);
+    config.byzantine.insert(0, Byzantine::Equivocate);
+    config.byzantine.insert(3, Byzantine::Equivocate);
+    let report = Simulation::new(2, config).run();
+    report.check_liveness();
+    assert!(report.offenders.contains(&0));
+}
+
+#[test]
+fn test_partition_heals_after_gst() {
+    let mut config = SimConfig::synchronous(4, 5);
+    config.gst = 20_000;
+    config.partitions.push(Partition { from: 0, until: 20_000, groups: vec![vec![0, 1], vec![2, 3]] });
+    let report = Simulation::new(3, config).run();
+    report.check_liveness();
+    assert!(report.time > 20_000);
+}
+
+#[test]
+fn test_random_networks_are_safe_and_live() {
+    for seed in 0..2000 {
+        let config = SimConfig::random(&mut Rng::new(seed), 3);
+        Simulation::new(seed, config).run().check_liveness();
+    }
+}
@@ -1,27 +1,1322 @@
-mod wal;
+pub mod wal;
 
-use log::{info,trace,warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::time::Instant;
+
+use log::{debug, info, trace, warn};
 use serde::{Deserialize, Serialize};
 
-use wal::{WAL};
+use crate::config::GBFTConfig;
+use crate::crypto::Crypto;
+use crate::error::{Error, Result};
+use crate::certificate::AggregatedCertificate;
+use crate::evidence::Evidence;
+#[cfg(feature = "prometheus")]
+use crate::metrics::Metrics;
+use crate::metrics::{Cause, RoundTrace, Telemetry};
+use crate::validator_set::{Epochs, ValidatorSet};
+use wal::{WalEntry, WAL};
+use crate::message::{
+    proposal_payload, vote_payload, view_change_payload, Address, Commit, CommitCertificate,
+    ConsensusMessage, Hash, NewView, Output, PreparedCertificate, Proposal, SignedProposal,
+    SignedViewChange, SignedVote, Step, SyncRequest, SyncResponse, ViewChange, Vote, VoteType,
+};
+
+/// Round timeouts stop growing after this many rounds.
+const MAX_BACKOFF_ROUNDS: u64 = 16;
+/// Number of recent commits kept to answer sync requests.
+const SYNC_HISTORY: usize = 256;
+/// Votes and view changes are accepted up to this many rounds ahead.
+const MAX_FUTURE_ROUNDS: u64 = 16;
+/// Inputs of the next height kept per sender until we get there.
+const MAX_FUTURE_MESSAGES: usize = 16;
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TimeoutStruct {
     pub height: u64,
     pub round:  u64,
     pub duration: u64,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct State {
-    timeout: TimeoutStruct,
-    wal: WAL, // write ahead log file
+impl TimeoutStruct {
+    /// Whether both timers are for the same round, whatever their durations:
+    /// these depend on the config in use when the timer was armed.
+    fn same_round(&self, other: &TimeoutStruct) -> bool {
+        (self.height, self.round) == (other.height, other.round)
+    }
+}
+
+/// Votes of the current height, indexed by round and phase, one vote per voter.
+#[derive(Clone, Debug, Default)]
+struct VoteSet {
+    votes: BTreeMap<(u64, VoteType), BTreeMap<Address, SignedVote>>,
+}
+
+impl VoteSet {
+    fn contains(&self, vote: &Vote) -> bool {
+        self.get(vote).is_some()
+    }
+
+    /// Vote of the same voter in the same round and phase.
+    fn get(&self, vote: &Vote) -> Option<&SignedVote> {
+        self.votes.get(&(vote.round, vote.vote_type))?.get(&vote.voter)
+    }
+
+    /// Returns false if the voter already voted in this round and phase.
+    fn add(&mut self, vote: SignedVote) -> bool {
+        let by_voter = self.votes.entry((vote.vote.round, vote.vote.vote_type)).or_default();
+        if by_voter.contains_key(&vote.vote.voter) {
+            return false;
+        }
+        by_voter.insert(vote.vote.voter.clone(), vote);
+        true
+    }
+
+    /// The block hash reaching a quorum of `validators` in `round` and its votes, sorted by voter.
+    fn quorum(&self, round: u64, vote_type: VoteType, validators: &ValidatorSet) -> Option<(Hash, Vec<SignedVote>)> {
+        let by_voter = self.votes.get(&(round, vote_type))?;
+        let mut by_hash: BTreeMap<&Hash, Vec<SignedVote>> = BTreeMap::new();
+        for vote in by_voter.values().filter(|v| validators.contains(&v.vote.voter)) {
+            by_hash.entry(&vote.vote.block_hash).or_default().push(vote.clone());
+        }
+        by_hash
+            .into_iter()
+            .find(|(_, votes)| validators.power_of(votes.iter().map(|v| &v.vote.voter)) >= validators.quorum_power())
+            .map(|(hash, votes)| (hash.clone(), votes))
+    }
+
+    fn rounds(&self, vote_type: VoteType) -> Vec<u64> {
+        self.votes.keys().filter(|(_, t)| *t == vote_type).map(|(r, _)| *r).collect()
+    }
+}
+
+/// View changes of the current height, indexed by target round, one per voter.
+#[derive(Clone, Debug, Default)]
+struct ViewChangeSet {
+    view_changes: BTreeMap<u64, BTreeMap<Address, SignedViewChange>>,
+}
+
+impl ViewChangeSet {
+    fn contains(&self, view_change: &ViewChange) -> bool {
+        matches!(self.view_changes.get(&view_change.round), Some(by_voter) if by_voter.contains_key(&view_change.voter))
+    }
+
+    /// Returns false if the voter already asked for this round.
+    fn add(&mut self, signed: SignedViewChange) -> bool {
+        let by_voter = self.view_changes.entry(signed.view_change.round).or_default();
+        if by_voter.contains_key(&signed.view_change.voter) {
+            return false;
+        }
+        by_voter.insert(signed.view_change.voter.clone(), signed);
+        true
+    }
+
+    /// Voting power asking for `round`.
+    fn power(&self, round: u64, validators: &ValidatorSet) -> u64 {
+        self.view_changes.get(&round).map(|by_voter| validators.power_of(by_voter.keys())).unwrap_or(0)
+    }
+
+    /// View changes for `round`, sorted by voter.
+    fn get(&self, round: u64) -> Vec<SignedViewChange> {
+        self.view_changes.get(&round).map(|by_voter| by_voter.values().cloned().collect()).unwrap_or_default()
+    }
 }
 
-impl State {
+/// The prepared block with the highest round among `view_changes`.
+fn highest_prepared(view_changes: &[SignedViewChange]) -> Option<(PreparedCertificate, Vec<u8>)> {
+    let mut highest: Option<(PreparedCertificate, Vec<u8>)> = None;
+    for signed in view_changes {
+        let view_change = &signed.view_change;
+        if let (Some(cert), Some(content)) = (&view_change.prepared, &view_change.content) {
+            if !matches!(highest, Some((ref h, _)) if h.round >= cert.round) {
+                highest = Some((cert.clone(), content.clone()));
+            }
+        }
+    }
+    highest
+}
+
+/// Three-phase (pre-prepare, prepare, commit) GBFT state machine.
+///
+/// The state is pure: it never touches the network or a clock. Messages, timer
+/// ticks and proposals go in, `Output`s describing what to send, which timer
+/// to arm and which block got finalized come out.
+///
+/// When a round times out the validators run a view change: each one sends
+/// its highest prepared certificate to the proposer of the next round, which
+/// answers with a new view re-proposing the highest prepared block. Round
+/// timeouts double on every round of a height.
+///
+/// A validator seeing messages of a later height asks its peers for the
+/// commits it missed and for the new view of their round, see
+/// `State::handle_sync_timeout`.
+///
+/// Votes are weighted by the voting power of the validator set of their
+/// height, see `State::schedule_validators`.
+///
+/// With a `WAL` attached every input is logged before it is applied, see
+/// `State::recover`.
+#[derive(Debug)]
+pub struct State<C> {
+    crypto: C,
+    address: Address,
+    epochs: Epochs,
+    config: GBFTConfig,
+    height: u64,
+    round: u64,
+    step: Step,
+    timeout: TimeoutStruct, // timer armed for the current round
+    vc_round: u64, // round targeted by the running view change
+    vc_attempts: u64, // view changes started since the last new view
+    proposal: Option<SignedProposal>,
+    blocks: HashMap<Hash, Vec<u8>>, // contents proposed at this height
+    votes: VoteSet,
+    view_changes: ViewChangeSet,
+    new_view: Option<Vec<SignedViewChange>>, // quorum making us the proposer of `round`
+    lock: Option<PreparedCertificate>,
+    last_new_view: Option<NewView>, // new view that opened `round`
+    history: VecDeque<Commit>, // last commits, oldest first
+    sync_target: u64, // highest height seen while catching up
+    sync_attempts: u64,
+    sync_timer: Option<TimeoutStruct>,
+    future: BTreeMap<Address, Vec<WalEntry>>, // authenticated inputs of the next height, per sender
+    reported: BTreeSet<(Address, u64, u64, Option<VoteType>)>, // equivocations already reported
+    wal: Option<WAL>,
+    wal_height: Option<u64>, // height of the last `NewHeight` logged
+    telemetry: Telemetry,
+}
+
+impl<C: Crypto> State<C> {
+    /// Create a state for `address` starting at the height and round of
+    /// `timeout`, round durations come from `config`. `validators` is the set in charge
+    /// of that height.
+    pub fn new(
+        crypto: C,
+        address: Address,
+        validators: ValidatorSet,
+        config: GBFTConfig,
+        timeout: TimeoutStruct,
+    ) -> Self {
+        State {
+            crypto,
+            address,
+            epochs: Epochs::new(validators),
+            config,
+            height: timeout.height,
+            round: timeout.round,
+            step: Step::PrePrepare,
+            vc_round: timeout.round,
+            timeout,
+            vc_attempts: 0,
+            proposal: None,
+            blocks: HashMap::new(),
+            votes: VoteSet::default(),
+            view_changes: ViewChangeSet::default(),
+            new_view: None,
+            lock: None,
+            last_new_view: None,
+            history: VecDeque::new(),
+            sync_target: 0,
+            sync_attempts: 0,
+            sync_timer: None,
+            future: BTreeMap::new(),
+            reported: BTreeSet::new(),
+            wal: None,
+            wal_height: None,
+            telemetry: Telemetry::default(),
+        }
+    }
+
+    /// Rebuild the state by replaying the current height of `wal`, which then
+    /// logs every further input.
+    ///
+    /// `timeout` gives the height to start from when the log is empty or
+    /// behind it. The outputs are the ones still relevant for the recovered
+    /// round, re-broadcasting them sends the very same votes again.
+    ///
+    /// Validator sets scheduled for later heights are not logged, schedule
+    /// them again after recovering.
+    pub fn recover(
+        crypto: C,
+        address: Address,
+        validators: ValidatorSet,
+        config: GBFTConfig,
+        timeout: TimeoutStruct,
+        wal: WAL,
+    ) -> Result<(Self, Vec<Output>)> {
+        let mut entries = wal.current_height_entries()?;
+        let logged_height = match entries.first() {
+            Some(WalEntry::NewHeight(height)) if *height >= timeout.height => Some(*height),
+            _ => None,
+        };
+        if logged_height.is_none() {
+            entries.clear();
+        }
+
+        let start = TimeoutStruct {
+            height: logged_height.unwrap_or(timeout.height),
+            round: if logged_height.is_some() { 0 } else { timeout.round },
+            duration: timeout.duration,
+        };
+        let mut state = State::new(crypto, address, validators, config, start);
+        info!("recover state at height {} from {} wal entries", state.height, entries.len());
+
+        let mut outputs = state.start();
+        for entry in entries {
+            match state.apply(entry) {
+                Ok(more) => outputs.extend(more),
+                Err(e) => debug!("skip wal entry: {}", e),
+            }
+        }
+
+        let (round, timeout) = (state.round, state.timeout.clone());
+        let expecting = state.is_proposer() && state.step == Step::PrePrepare && state.proposal.is_none();
+        outputs.retain(|output| match output {
+            Output::ScheduleTimeout(t) => t.same_round(&timeout),
+            Output::RequestProposal { round: r, .. } => *r == round && expecting,
+            _ => true,
+        });
+
+        state.wal = Some(wal);
+        state.wal_height = logged_height;
+        Ok((state, outputs))
+    }
+
     pub fn print_info(&self) {
-        trace!("current state info {:?}", self);
+        trace!("current state info height {} round {} step {:?}", self.height, self.round, self.step);
     }
 
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Validator set of the current height.
+    pub fn validators(&self) -> &ValidatorSet {
+        self.epochs.at(self.height)
+    }
+
+    /// Validator set in charge of `height`.
+    pub fn validators_at(&self, height: u64) -> &ValidatorSet {
+        self.epochs.at(height)
+    }
+
+    /// Compact certificate of a commit, to seal in the header of its block.
+    pub fn aggregate(&self, commit: &Commit) -> Result<AggregatedCertificate> {
+        AggregatedCertificate::aggregate(&commit.certificate, self.epochs.at(commit.height))
+    }
+
+    /// Add the validator set of the next epoch, as decided by the runtime.
+    ///
+    /// The set must start after the current height, so that every validator
+    /// switches at the same height.
+    pub fn schedule_validators(&mut self, validators: ValidatorSet) -> Result<()> {
+        if validators.start_height() <= self.height {
+            return Err(Error::InvalidValidatorSet("starts before the next height"));
+        }
+        info!("epoch {} starts at height {}", validators.epoch(), validators.start_height());
+        self.epochs.schedule(validators)
+    }
+
+    /// Last round and step transitions, see `RoundTrace::dump`.
+    pub fn trace(&self) -> &RoundTrace {
+        &self.telemetry.trace
+    }
+
+    /// Report to `metrics` from now on.
+    #[cfg(feature = "prometheus")]
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.telemetry.metrics = Some(metrics);
+    }
+
+    pub fn config(&self) -> &GBFTConfig {
+        &self.config
+    }
+
+    /// Switch to `config` without restarting, see `GBFTConfig::check_reload`.
+    /// Timers already armed keep their duration.
+    pub fn reload_config(&mut self, config: GBFTConfig) -> Result<()> {
+        self.config.check_reload(&config)?;
+        info!("reload gbft config {:?}", config);
+        self.config = config;
+        Ok(())
+    }
+
+    /// Prepare certificate the local validator is locked on, if any.
+    pub fn lock(&self) -> Option<&PreparedCertificate> {
+        self.lock.as_ref()
+    }
+
+    /// Voting power needed for a quorum at the current height.
+    pub fn quorum_size(&self) -> u64 {
+        self.validators().quorum_power()
+    }
+
+    /// Voting power that contains at least one honest validator.
+    pub fn weak_quorum_size(&self) -> u64 {
+        self.validators().weak_quorum_power()
+    }
+
+    /// Leader of `round` at `height`, rotating over the validator set of `height`.
+    pub fn proposer(&self, height: u64, round: u64) -> &Address {
+        self.epochs.at(height).proposer(height, round)
+    }
+
+    /// Whether we vote at the current height.
+    pub fn is_validator(&self) -> bool {
+        self.validators().contains(&self.address)
+    }
+
+    pub fn is_proposer(&self) -> bool {
+        self.proposer(self.height, self.round) == &self.address
+    }
+
+    /// Timeout of `round`, the base duration multiplied for every round before it.
+    pub fn round_duration(&self, round: u64) -> u64 {
+        let multiplier = self.config.round_timeout_multiplier();
+        let exponent = round.min(MAX_BACKOFF_ROUNDS) as u32;
+        self.config.round_timeout_base().saturating_mul(multiplier.saturating_pow(exponent))
+    }
+
+    /// Enter the round configured at construction.
+    pub fn start(&mut self) -> Vec<Output> {
+        self.enter_round(self.round, Cause::Start)
+    }
+
+    /// Propose `content` for the current round, answering `Output::RequestProposal`.
+    pub fn propose(&mut self, content: Vec<u8>) -> Result<Vec<Output>> {
+        self.input(WalEntry::Propose(content))
+    }
+
+    pub fn handle_message(&mut self, message: ConsensusMessage) -> Result<Vec<Output>> {
+        match message {
+            ConsensusMessage::Proposal(proposal) => self.handle_proposal(proposal),
+            ConsensusMessage::Vote(vote) => self.handle_vote(vote),
+            ConsensusMessage::ViewChange(view_change) => self.handle_view_change(view_change),
+            ConsensusMessage::NewView(new_view) => self.handle_new_view(new_view),
+            ConsensusMessage::SyncRequest(request) => Ok(self.handle_sync_request(request)),
+            ConsensusMessage::SyncResponse(response) => self.handle_sync_response(response),
+            ConsensusMessage::Evidence(evidence) => self.handle_evidence(evidence),
+        }
+    }
+
+    pub fn handle_proposal(&mut self, proposal: SignedProposal) -> Result<Vec<Output>> {
+        self.input(WalEntry::Proposal(proposal))
+    }
+
+    pub fn handle_vote(&mut self, vote: SignedVote) -> Result<Vec<Output>> {
+        self.input(WalEntry::Vote(vote))
+    }
+
+    pub fn handle_view_change(&mut self, view_change: SignedViewChange) -> Result<Vec<Output>> {
+        self.input(WalEntry::ViewChange(view_change))
+    }
+
+    pub fn handle_new_view(&mut self, new_view: NewView) -> Result<Vec<Output>> {
+        self.input(WalEntry::NewView(new_view))
+    }
+
+    /// Answer a lagging validator with the commits and the new view we know of.
+    pub fn handle_sync_request(&mut self, request: SyncRequest) -> Vec<Output> {
+        let known = self.validators().contains(&request.requester) || self.epochs.latest().contains(&request.requester);
+        if request.height > self.height || !known {
+            return vec![];
+        }
+        let commits: Vec<Commit> = self.history.iter().filter(|c| c.height >= request.height).cloned().collect();
+        let new_view = self.last_new_view.clone();
+        if commits.is_empty() && new_view.is_none() {
+            return vec![];
+        }
+        debug!("send {} commits from height {} to {:?}", commits.len(), request.height, request.requester);
+        vec![Output::Send {
+            to: request.requester,
+            message: ConsensusMessage::SyncResponse(SyncResponse { commits, new_view }),
+        }]
+    }
+
+    /// Check an equivocation reported by another validator and pass it on
+    /// to the runtime, once.
+    pub fn handle_evidence(&mut self, evidence: Evidence) -> Result<Vec<Output>> {
+        evidence.verify(&self.crypto, self.epochs.at(evidence.height()))?;
+        if evidence.height() + (SYNC_HISTORY as u64) < self.height {
+            return Ok(vec![]);
+        }
+        Ok(self.report(evidence, false))
+    }
+
+    pub fn handle_sync_response(&mut self, response: SyncResponse) -> Result<Vec<Output>> {
+        self.input(WalEntry::Sync(response))
+    }
+
+    /// Feed back a timer armed through `Output::ScheduleSync`.
+    ///
+    /// Asks the peers again while the validator is still behind, and fails
+    /// with `Error::RecoveryFailed` after `recovery_retry_times` requests.
+    /// A later message from the network starts over.
+    pub fn handle_sync_timeout(&mut self, timeout: TimeoutStruct) -> Result<Vec<Output>> {
+        if self.sync_timer.as_ref() != Some(&timeout) {
+            return Ok(vec![]);
+        }
+        self.sync_timer = None;
+        if self.height >= self.sync_target {
+            self.sync_target = 0;
+            return Ok(vec![]);
+        }
+        if self.sync_attempts >= self.config.recovery_retry_times() {
+            warn!("give up catching up from height {} to {}", self.height, self.sync_target);
+            let err = Error::RecoveryFailed {
+                height: self.height,
+                target: self.sync_target,
+                attempts: self.sync_attempts,
+            };
+            self.sync_target = 0;
+            self.sync_attempts = 0;
+            return Err(err);
+        }
+        Ok(self.request_sync())
+    }
+
+    /// Feed back a timer armed through `Output::ScheduleTimeout`.
+    ///
+    /// Fails with `Error::ViewChangeFailed` once `vc_retry_times` view changes
+    /// in a row did not reach a new view, the state then waits for a new view
+    /// or a commit from the network.
+    pub fn handle_timeout(&mut self, timeout: TimeoutStruct) -> Result<Vec<Output>> {
+        self.input(WalEntry::Timeout(timeout))
+    }
+
+    /// Check, log and apply an input. Inputs of the next height are kept
+    /// and applied once it is entered.
+    fn input(&mut self, entry: WalEntry) -> Result<Vec<Output>> {
+        match entry_height(&entry) {
+            Some(height) if height > self.height => {
+                self.authenticate(height, &entry)?;
+                if height == self.height + 1 {
+                    self.buffer(entry);
+                }
+                return Ok(self.start_sync(height));
+            }
+            _ => {}
+        }
+        let height = self.height;
+        let admitted = self.admit(&entry)?;
+        let mut outputs = self.detect_equivocation(&entry);
+        if admitted {
+            self.log(&entry)?;
+            outputs.extend(self.on_entry(entry)?);
+        }
+        if self.height > height {
+            outputs.extend(self.replay_future());
+        }
+        Ok(outputs)
+    }
+
+    /// Keep an authenticated input of the next height, up to
+    /// `MAX_FUTURE_MESSAGES` per sender.
+    fn buffer(&mut self, entry: WalEntry) {
+        let sender = match entry_sender(&entry) {
+            Some(sender) => sender.clone(),
+            None => return,
+        };
+        let kept = self.future.entry(sender).or_default();
+        if kept.len() >= MAX_FUTURE_MESSAGES {
+            debug!("drop input of height {}, too many kept from its sender", self.height + 1);
+            return;
+        }
+        kept.push(entry);
+    }
+
+    /// Apply the inputs kept for the height just entered, dropping the others.
+    fn replay_future(&mut self) -> Vec<Output> {
+        let future = std::mem::take(&mut self.future);
+        let mut outputs = vec![];
+        for entry in future.into_values().flatten() {
+            if entry_height(&entry) != Some(self.height) {
+                continue;
+            }
+            match self.input(entry) {
+                Ok(more) => outputs.extend(more),
+                Err(e) => debug!("skip kept input: {}", e),
+            }
+        }
+        outputs
+    }
+
+    /// Apply a logged input, without logging it again.
+    fn apply(&mut self, entry: WalEntry) -> Result<Vec<Output>> {
+        if !self.admit(&entry)? {
+            return Ok(vec![]);
+        }
+        self.on_entry(entry)
+    }
+
+    /// Validate an input against the current state. `Ok(false)` means the
+    /// input is valid but has no effect, it is neither logged nor applied.
+    fn admit(&self, entry: &WalEntry) -> Result<bool> {
+        match entry {
+            WalEntry::NewHeight(_) => Ok(false),
+            WalEntry::Proposal(signed) => {
+                self.check_height_round(signed.proposal.height, signed.proposal.round)?;
+                self.verify_proposal(signed)?;
+                Ok(self.step == Step::PrePrepare && self.proposal.is_none())
+            }
+            WalEntry::Vote(signed) => {
+                self.check_height(signed.vote.height)?;
+                self.check_round_ahead(signed.vote.round)?;
+                self.verify_vote(signed)?;
+                Ok(!self.votes.contains(&signed.vote))
+            }
+            WalEntry::Propose(content) => {
+                if !self.is_proposer() || self.step != Step::PrePrepare || self.proposal.is_some() {
+                    return Err(Error::NotProposer { height: self.height, round: self.round });
+                }
+                self.check_block_size(content)?;
+                Ok(true)
+            }
+            WalEntry::Timeout(timeout) => Ok(timeout.same_round(&self.timeout)),
+            WalEntry::ViewChange(signed) => {
+                self.check_height(signed.view_change.height)?;
+                self.check_round_ahead(signed.view_change.round)?;
+                self.verify_view_change(signed)?;
+                Ok(!self.view_changes.contains(&signed.view_change))
+            }
+            WalEntry::NewView(new_view) => {
+                self.check_height(new_view.height)?;
+                if new_view.round <= self.round {
+                    return Ok(false);
+                }
+                self.verify_new_view(new_view)?;
+                Ok(true)
+            }
+            WalEntry::Sync(response) => {
+                let mut height = self.height;
+                for commit in response.commits.iter().filter(|c| c.height >= self.height) {
+                    if commit.height != height {
+                        return Err(Error::WrongHeight { got: commit.height, current: height });
+                    }
+                    self.verify_commit(commit)?;
+                    height += 1;
+                }
+                let new_view = matches!(response.new_view, Some(ref nv) if nv.height == height && nv.round > self.round);
+                Ok(height > self.height || new_view)
+            }
+        }
+    }
+
+    fn on_entry(&mut self, entry: WalEntry) -> Result<Vec<Output>> {
+        match entry {
+            WalEntry::NewHeight(_) => Ok(vec![]),
+            WalEntry::Proposal(proposal) => Ok(self.on_proposal(proposal)),
+            WalEntry::Vote(vote) => {
+                if vote.vote.voter != self.address {
+                    self.telemetry.vote(Some(vote.vote.vote_type));
+                }
+                Ok(self.add_vote(vote))
+            }
+            WalEntry::Propose(content) => {
+                let lock = self.lock.clone();
+                Ok(self.broadcast_proposal(content, lock))
+            }
+            WalEntry::Timeout(_) => self.on_timeout(),
+            WalEntry::ViewChange(view_change) => {
+                if view_change.view_change.voter != self.address {
+                    self.telemetry.vote(None);
+                }
+                Ok(self.add_view_change(view_change))
+            }
+            WalEntry::NewView(new_view) => Ok(self.on_new_view(new_view)),
+            WalEntry::Sync(response) => Ok(self.on_sync(response)),
+        }
+    }
+
+    /// Write `entry` to the log, opening the log of a new height first.
+    fn log(&mut self, entry: &WalEntry) -> Result<()> {
+        if let Some(ref mut wal) = self.wal {
+            if self.wal_height != Some(self.height) {
+                wal.new_height(self.height)?;
+                self.wal_height = Some(self.height);
+            }
+            let started = Instant::now();
+            wal.append(entry)?;
+            self.telemetry.wal_sync(started.elapsed());
+        }
+        Ok(())
+    }
+
+    fn on_proposal(&mut self, signed: SignedProposal) -> Vec<Output> {
+        if self.step != Step::PrePrepare || self.proposal.is_some() {
+            debug!("ignore proposal at height {} round {}, already past pre-prepare", self.height, self.round);
+            return vec![];
+        }
+
+        // A locked validator only prepares its locked block, unless the proposal
+        // carries a prepare quorum for another block from a later round.
+        let proposal = &signed.proposal;
+        if let Some(ref lock) = self.lock {
+            let unlocked = matches!(proposal.lock, Some(ref cert) if cert.round > lock.round);
+            if lock.block_hash != proposal.block_hash && !unlocked {
+                debug!("locked on round {}, refuse proposal of round {}", lock.round, proposal.round);
+                return vec![];
+            }
+        }
+
+        trace!("accept proposal at height {} round {}", self.height, self.round);
+        self.blocks.insert(proposal.block_hash.clone(), proposal.content.clone());
+        let block_hash = proposal.block_hash.clone();
+        self.proposal = Some(signed);
+        self.transition(self.round, Step::Prepare, Cause::Proposal);
+
+        let mut outputs = self.broadcast_vote(VoteType::Prepare, block_hash);
+        outputs.extend(self.try_commit());
+        outputs
+    }
+
+    /// The round, or the running view change, timed out: ask for the next round.
+    fn on_timeout(&mut self) -> Result<Vec<Output>> {
+        let (target, attempts) = if self.step == Step::ViewChange {
+            (self.vc_round + 1, self.vc_attempts + 1)
+        } else {
+            (self.round + 1, 1)
+        };
+        info!("round {} at height {} timed out", target - 1, self.height);
+
+        if attempts > self.config.vc_retry_times() {
+            warn!("give up view change at height {} after {} attempts", self.height, attempts - 1);
+            return Err(Error::ViewChangeFailed {
+                height: self.height,
+                round: target - 1,
+                attempts: attempts - 1,
+            });
+        }
+        Ok(self.start_view_change(target, attempts))
+    }
+
+    fn start_view_change(&mut self, round: u64, attempts: u64) -> Vec<Output> {
+        debug!("view change to round {} at height {}, attempt {}", round, self.height, attempts);
+        self.transition(round, Step::ViewChange, Cause::ViewChange);
+        self.vc_round = round;
+        self.vc_attempts = attempts;
+        let mut outputs = self.arm_timeout(round);
+        if !self.is_validator() {
+            return outputs;
+        }
+
+        let content = self.lock.as_ref().and_then(|lock| self.blocks.get(&lock.block_hash).cloned());
+        let view_change = ViewChange {
+            height: self.height,
+            round,
+            prepared: content.as_ref().and(self.lock.clone()),
+            content,
+            voter: self.address.clone(),
+        };
+        let hash = self.crypto.hash(&view_change_payload(&view_change));
+        let signature = match self.crypto.sign(&hash) {
+            Ok(signature) => signature,
+            Err(e) => {
+                warn!("cannot sign view change to round {}: {}", round, e);
+                return outputs;
+            }
+        };
+        let signed = SignedViewChange { signature, view_change };
+
+        outputs.push(Output::Broadcast(ConsensusMessage::ViewChange(signed.clone())));
+        outputs.extend(self.add_view_change(signed));
+        outputs
+    }
+
+    fn add_view_change(&mut self, signed: SignedViewChange) -> Vec<Output> {
+        let round = signed.view_change.round;
+        if !self.view_changes.add(signed) {
+            return vec![];
+        }
+
+        // join a view change enough validators asked for, one of them is honest
+        let mut outputs = vec![];
+        let current = if self.step == Step::ViewChange { self.vc_round } else { self.round };
+        if round > current && self.view_changes.power(round, self.validators()) >= self.weak_quorum_size() {
+            let attempts = if self.step == Step::ViewChange { self.vc_attempts } else { 1 };
+            outputs.extend(self.start_view_change(round, attempts));
+        }
+        outputs.extend(self.try_new_view(round));
+        outputs
+    }
+
+    /// As proposer of `round`, enter it once a quorum asked for it.
+    fn try_new_view(&mut self, round: u64) -> Vec<Output> {
+        if round <= self.round
+            || self.proposer(self.height, round) != &self.address
+            || self.view_changes.power(round, self.validators()) < self.quorum_size()
+        {
+            return vec![];
+        }
+
+        let view_changes = self.view_changes.get(round);
+        let prepared = highest_prepared(&view_changes);
+        info!("lead round {} at height {}", round, self.height);
+        let mut outputs = self.enter_view(round, prepared.clone());
+        self.new_view = Some(view_changes);
+        match prepared {
+            Some((cert, content)) => outputs.extend(self.broadcast_proposal(content, Some(cert))),
+            None => outputs.push(self.request_proposal(round)),
+        }
+        outputs
+    }
+
+    fn on_new_view(&mut self, new_view: NewView) -> Vec<Output> {
+        info!("new view for round {} at height {}", new_view.round, self.height);
+        let prepared = highest_prepared(&new_view.view_changes);
+        let mut outputs = self.enter_view(new_view.round, prepared);
+        self.last_new_view = Some(new_view.clone());
+        outputs.extend(self.on_proposal(new_view.proposal));
+        outputs
+    }
+
+    /// Ask the peers for the heights up to `height`, unless already doing so.
+    fn start_sync(&mut self, height: u64) -> Vec<Output> {
+        if height <= self.sync_target {
+            return vec![];
+        }
+        info!("saw height {} at height {}, catch up", height, self.height);
+        let running = self.sync_timer.is_some();
+        self.sync_target = height;
+        if running {
+            return vec![];
+        }
+        self.sync_attempts = 0;
+        self.request_sync()
+    }
+
+    fn request_sync(&mut self) -> Vec<Output> {
+        self.sync_attempts += 1;
+        let timer = TimeoutStruct {
+            height: self.height,
+            round: self.sync_attempts,
+            duration: self.config.round_timeout_base(),
+        };
+        self.sync_timer = Some(timer.clone());
+        let request = SyncRequest { height: self.height, requester: self.address.clone() };
+        vec![
+            Output::Broadcast(ConsensusMessage::SyncRequest(request)),
+            Output::ScheduleSync(timer),
+        ]
+    }
+
+    /// Finalize the verified commits, then join the round of the responder.
+    fn on_sync(&mut self, response: SyncResponse) -> Vec<Output> {
+        let mut outputs = vec![];
+        for commit in response.commits {
+            if commit.height != self.height {
+                continue;
+            }
+            info!("catch up commit at height {} round {}", commit.height, commit.certificate.round);
+            outputs.retain(|o| matches!(o, Output::Commit(_)));
+            outputs.push(Output::Commit(commit.clone()));
+            self.remember(commit);
+            outputs.extend(self.enter_height(self.height + 1, Cause::Sync));
+        }
+        if self.height >= self.sync_target {
+            self.sync_target = 0;
+            self.sync_timer = None;
+        }
+
+        if let Some(new_view) = response.new_view {
+            let entry = WalEntry::NewView(new_view);
+            match self.admit(&entry) {
+                Ok(true) => outputs.extend(self.on_entry(entry).unwrap_or_default()),
+                Ok(false) => {}
+                Err(e) => debug!("ignore synced new view: {}", e),
+            }
+        }
+        outputs
+    }
+
+    /// Evidence against the sender of a verified input signing something
+    /// else for the same slot.
+    fn detect_equivocation(&mut self, entry: &WalEntry) -> Vec<Output> {
+        let evidence = match entry {
+            WalEntry::Proposal(signed) => self.conflicting_proposal(signed),
+            WalEntry::NewView(new_view) => self.conflicting_proposal(&new_view.proposal),
+            WalEntry::Vote(signed) => self
+                .votes
+                .get(&signed.vote)
+                .filter(|first| first.vote.block_hash != signed.vote.block_hash)
+                .map(|first| Evidence::DoubleVote { first: first.clone(), second: signed.clone() }),
+            _ => None,
+        };
+        match evidence {
+            Some(evidence) => self.report(evidence, true),
+            None => vec![],
+        }
+    }
+
+    fn conflicting_proposal(&self, signed: &SignedProposal) -> Option<Evidence> {
+        let first = self.proposal.as_ref()?;
+        let (a, b) = (&first.proposal, &signed.proposal);
+        if (a.height, a.round) != (b.height, b.round) || a.block_hash == b.block_hash {
+            return None;
+        }
+        self.verify_proposal(signed).ok()?;
+        Some(Evidence::DoubleProposal { first: first.clone(), second: signed.clone() })
+    }
+
+    /// Hand `evidence` to the runtime, and to the other validators if we found it.
+    fn report(&mut self, evidence: Evidence, gossip: bool) -> Vec<Output> {
+        let vote_type = match evidence {
+            Evidence::DoubleProposal { .. } => None,
+            Evidence::DoubleVote { ref first, .. } => Some(first.vote.vote_type),
+        };
+        let slot = (evidence.offender().clone(), evidence.height(), evidence.round(), vote_type);
+        if !self.reported.insert(slot) {
+            return vec![];
+        }
+        warn!(
+            "validator {:?} equivocated at height {} round {}",
+            evidence.offender(),
+            evidence.height(),
+            evidence.round()
+        );
+        let mut outputs = vec![];
+        if gossip {
+            outputs.push(Output::Broadcast(ConsensusMessage::Evidence(evidence.clone())));
+        }
+        outputs.push(Output::Evidence(evidence));
+        outputs
+    }
+
+    fn remember(&mut self, commit: Commit) {
+        if self.history.len() == SYNC_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(commit);
+    }
+
+    /// Move to `round` after a view change, adopting its highest prepared
+    /// block as lock: a block committed earlier is necessarily that one.
+    fn enter_view(&mut self, round: u64, prepared: Option<(PreparedCertificate, Vec<u8>)>) -> Vec<Output> {
+        self.transition(round, Step::PrePrepare, Cause::NewView);
+        self.proposal = None;
+        self.new_view = None;
+        self.vc_attempts = 0;
+        self.lock = prepared.map(|(cert, content)| {
+            self.blocks.insert(cert.block_hash.clone(), content);
+            cert
+        });
+        if self.timeout.round == round {
+            vec![]
+        } else {
+            self.arm_timeout(round)
+        }
+    }
+
+    fn check_height(&self, height: u64) -> Result<()> {
+        if height != self.height {
+            return Err(Error::WrongHeight { got: height, current: self.height });
+        }
+        Ok(())
+    }
+
+    fn check_height_round(&self, height: u64, round: u64) -> Result<()> {
+        self.check_height(height)?;
+        if round != self.round {
+            return Err(Error::WrongRound { got: round, current: self.round });
+        }
+        Ok(())
+    }
+
+    /// Bound the rounds kept for a height, a validator cannot make us store
+    /// votes for any number of rounds.
+    fn check_round_ahead(&self, round: u64) -> Result<()> {
+        let current = self.round.max(self.vc_round);
+        if round > current.saturating_add(MAX_FUTURE_ROUNDS) {
+            return Err(Error::WrongRound { got: round, current });
+        }
+        Ok(())
+    }
+
+    fn check_validator(&self, height: u64, address: &Address) -> Result<()> {
+        if !self.epochs.at(height).contains(address) {
+            return Err(Error::UnknownValidator(address.clone()));
+        }
+        Ok(())
+    }
+
+    /// Check the sender of a message of the future `height`: a validator we
+    /// know of, signing it. The rest is checked once at that height.
+    fn authenticate(&self, height: u64, entry: &WalEntry) -> Result<()> {
+        let (signer, signature, payload) = match entry {
+            WalEntry::Proposal(signed) => (&signed.proposal.proposer, &signed.signature, proposal_payload(&signed.proposal)),
+            WalEntry::Vote(signed) => (&signed.vote.voter, &signed.signature, vote_payload(&signed.vote)),
+            WalEntry::ViewChange(signed) => {
+                (&signed.view_change.voter, &signed.signature, view_change_payload(&signed.view_change))
+            }
+            WalEntry::NewView(new_view) => {
+                let signed = &new_view.proposal;
+                (&signed.proposal.proposer, &signed.signature, proposal_payload(&signed.proposal))
+            }
+            _ => return Ok(()),
+        };
+        self.check_validator(height, signer)?;
+        if !self.crypto.verify(signature, &self.crypto.hash(&payload), signer) {
+            return Err(Error::InvalidSignature(signer.clone()));
+        }
+        Ok(())
+    }
+
+    fn check_block_size(&self, content: &[u8]) -> Result<()> {
+        let (size, max) = (content.len() as u64, self.config.max_block_size());
+        if size > max {
+            return Err(Error::BlockTooLarge { size, max });
+        }
+        Ok(())
+    }
+
+    fn verify_proposal(&self, signed: &SignedProposal) -> Result<()> {
+        let proposal = &signed.proposal;
+        if &proposal.proposer != self.proposer(proposal.height, proposal.round) {
+            return Err(Error::InvalidProposer(proposal.proposer.clone()));
+        }
+        let hash = self.crypto.hash(&proposal_payload(proposal));
+        if !self.crypto.verify(&signed.signature, &hash, &proposal.proposer) {
+            return Err(Error::InvalidSignature(proposal.proposer.clone()));
+        }
+        if self.crypto.hash(&proposal.content) != proposal.block_hash {
+            return Err(Error::InvalidBlockHash);
+        }
+        self.check_block_size(&proposal.content)?;
+        if let Some(ref cert) = proposal.lock {
+            self.verify_prepared(cert)?;
+            if cert.block_hash != proposal.block_hash || cert.round >= proposal.round {
+                return Err(Error::InvalidCertificate("lock does not justify the proposal"));
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_vote(&self, signed: &SignedVote) -> Result<()> {
+        let voter = &signed.vote.voter;
+        self.check_validator(signed.vote.height, voter)?;
+        let hash = self.crypto.hash(&vote_payload(&signed.vote));
+        if !self.crypto.verify(&signed.signature, &hash, voter) {
+            return Err(Error::InvalidSignature(voter.clone()));
+        }
+        Ok(())
+    }
+
+    /// Check a prepare certificate of the current height.
+    fn verify_prepared(&self, cert: &PreparedCertificate) -> Result<()> {
+        if cert.height != self.height {
+            return Err(Error::InvalidCertificate("wrong height"));
+        }
+        self.verify_quorum(VoteType::Prepare, cert.height, cert.round, &cert.block_hash, &cert.votes)
+    }
+
+    /// Check a commit: a commit quorum for the hash of its content.
+    fn verify_commit(&self, commit: &Commit) -> Result<()> {
+        let cert = &commit.certificate;
+        if cert.height != commit.height {
+            return Err(Error::InvalidCertificate("wrong height"));
+        }
+        if self.crypto.hash(&commit.content) != cert.block_hash {
+            return Err(Error::InvalidBlockHash);
+        }
+        self.verify_quorum(VoteType::Commit, cert.height, cert.round, &cert.block_hash, &cert.votes)
+    }
+
+    /// Check certificate votes: one valid vote per validator, all for the
+    /// same block, reaching the quorum.
+    fn verify_quorum(
+        &self,
+        vote_type: VoteType,
+        height: u64,
+        round: u64,
+        block_hash: &Hash,
+        votes: &[SignedVote],
+    ) -> Result<()> {
+        let mut voters = Vec::with_capacity(votes.len());
+        for signed in votes {
+            let vote = &signed.vote;
+            if vote.vote_type != vote_type
+                || vote.height != height
+                || vote.round != round
+                || &vote.block_hash != block_hash
+            {
+                return Err(Error::InvalidCertificate("vote does not match the certificate"));
+            }
+            if voters.contains(&&vote.voter) {
+                return Err(Error::InvalidCertificate("duplicated voter"));
+            }
+            self.verify_vote(signed)?;
+            voters.push(&vote.voter);
+        }
+        let validators = self.epochs.at(height);
+        if validators.power_of(voters) < validators.quorum_power() {
+            return Err(Error::InvalidCertificate("not enough votes"));
+        }
+        Ok(())
+    }
+
+    fn verify_view_change(&self, signed: &SignedViewChange) -> Result<()> {
+        let view_change = &signed.view_change;
+        self.check_validator(view_change.height, &view_change.voter)?;
+        let hash = self.crypto.hash(&view_change_payload(view_change));
+        if !self.crypto.verify(&signed.signature, &hash, &view_change.voter) {
+            return Err(Error::InvalidSignature(view_change.voter.clone()));
+        }
+        match (&view_change.prepared, &view_change.content) {
+            (Some(cert), Some(content)) => {
+                self.verify_prepared(cert)?;
+                if cert.round >= view_change.round || self.crypto.hash(content) != cert.block_hash {
+                    return Err(Error::InvalidCertificate("prepared block does not match the view change"));
+                }
+                Ok(())
+            }
+            (None, None) => Ok(()),
+            _ => Err(Error::InvalidCertificate("prepared block content missing")),
+        }
+    }
+
+    /// Check a new view: a quorum of view changes for its round, and a
+    /// proposal for their highest prepared block if any.
+    fn verify_new_view(&self, new_view: &NewView) -> Result<()> {
+        let proposal = &new_view.proposal.proposal;
+        if proposal.height != new_view.height || proposal.round != new_view.round {
+            return Err(Error::InvalidCertificate("proposal does not match the new view"));
+        }
+        let mut voters = Vec::with_capacity(new_view.view_changes.len());
+        for signed in &new_view.view_changes {
+            let view_change = &signed.view_change;
+            if view_change.height != new_view.height || view_change.round != new_view.round {
+                return Err(Error::InvalidCertificate("view change does not match the new view"));
+            }
+            if voters.contains(&&view_change.voter) {
+                return Err(Error::InvalidCertificate("duplicated voter"));
+            }
+            self.verify_view_change(signed)?;
+            voters.push(&view_change.voter);
+        }
+        let validators = self.epochs.at(new_view.height);
+        if validators.power_of(voters) < validators.quorum_power() {
+            return Err(Error::InvalidCertificate("not enough view changes"));
+        }
+        if let Some((cert, _)) = highest_prepared(&new_view.view_changes) {
+            if cert.block_hash != proposal.block_hash {
+                return Err(Error::InvalidCertificate("new view does not re-propose the prepared block"));
+            }
+        }
+        self.verify_proposal(&new_view.proposal)
+    }
+
+    fn arm_timeout(&mut self, round: u64) -> Vec<Output> {
+        self.timeout = TimeoutStruct {
+            height: self.height,
+            round,
+            duration: self.round_duration(round),
+        };
+        vec![Output::ScheduleTimeout(self.timeout.clone())]
+    }
+
+    fn request_proposal(&self, round: u64) -> Output {
+        Output::RequestProposal { height: self.height, round, delay: self.config.proposal_delay() }
+    }
+
+    /// Record the move to `round` and `step`, `round` is only entered for
+    /// steps other than view change.
+    fn transition(&mut self, round: u64, step: Step, cause: Cause) {
+        self.telemetry.transition(self.height, (self.round, self.step), (round, step), cause);
+        if step != Step::ViewChange {
+            self.round = round;
+        }
+        self.step = step;
+    }
+
+    fn enter_round(&mut self, round: u64, cause: Cause) -> Vec<Output> {
+        trace!("enter round {} at height {}", round, self.height);
+        self.transition(round, Step::PrePrepare, cause);
+        self.vc_round = round;
+        self.proposal = None;
+        self.new_view = None;
+
+        let mut outputs = self.arm_timeout(round);
+        if self.is_proposer() {
+            outputs.push(self.request_proposal(round));
+        }
+        outputs
+    }
+
+    fn enter_height(&mut self, height: u64, cause: Cause) -> Vec<Output> {
+        self.height = height;
+        self.blocks.clear();
+        self.votes = VoteSet::default();
+        self.view_changes = ViewChangeSet::default();
+        self.lock = None;
+        self.last_new_view = None;
+        self.vc_attempts = 0;
+        let oldest = height.saturating_sub(SYNC_HISTORY as u64);
+        self.reported.retain(|(_, h, _, _)| *h >= oldest);
+        self.epochs.prune(oldest);
+        self.enter_round(0, cause)
+    }
+
+    fn broadcast_proposal(&mut self, content: Vec<u8>, lock: Option<PreparedCertificate>) -> Vec<Output> {
+        let proposal = Proposal {
+            height: self.height,
+            round: self.round,
+            block_hash: self.crypto.hash(&content),
+            content,
+            proposer: self.address.clone(),
+            lock,
+        };
+        let hash = self.crypto.hash(&proposal_payload(&proposal));
+        let signature = match self.crypto.sign(&hash) {
+            Ok(signature) => signature,
+            Err(e) => {
+                warn!("cannot sign proposal at height {} round {}: {}", self.height, self.round, e);
+                return vec![];
+            }
+        };
+        let signed = SignedProposal { signature, proposal };
+
+        let message = match self.new_view.clone() {
+            Some(view_changes) => {
+                let new_view = NewView {
+                    height: self.height,
+                    round: self.round,
+                    view_changes,
+                    proposal: signed.clone(),
+                };
+                self.last_new_view = Some(new_view.clone());
+                ConsensusMessage::NewView(new_view)
+            }
+            None => ConsensusMessage::Proposal(signed.clone()),
+        };
+        let mut outputs = vec![Output::Broadcast(message)];
+        outputs.extend(self.on_proposal(signed));
+        outputs
+    }
+
+    fn broadcast_vote(&mut self, vote_type: VoteType, block_hash: Hash) -> Vec<Output> {
+        if !self.is_validator() {
+            return vec![];
+        }
+        let vote = Vote {
+            height: self.height,
+            round: self.round,
+            vote_type,
+            block_hash,
+            voter: self.address.clone(),
+        };
+        let hash = self.crypto.hash(&vote_payload(&vote));
+        let signature = match self.crypto.sign(&hash) {
+            Ok(signature) => signature,
+            Err(e) => {
+                warn!("cannot sign {:?} vote at height {} round {}: {}", vote_type, self.height, self.round, e);
+                return vec![];
+            }
+        };
+        let signed = SignedVote { signature, vote };
+
+        let mut outputs = vec![Output::Broadcast(ConsensusMessage::Vote(signed.clone()))];
+        outputs.extend(self.add_vote(signed));
+        outputs
+    }
+
+    fn add_vote(&mut self, vote: SignedVote) -> Vec<Output> {
+        let (round, vote_type) = (vote.vote.round, vote.vote.vote_type);
+        if !self.votes.add(vote) {
+            return vec![];
+        }
+
+        match vote_type {
+            VoteType::Prepare if round == self.round => self.try_prepare(),
+            VoteType::Prepare => vec![],
+            VoteType::Commit => self.try_commit(),
+        }
+    }
+
+    /// On a prepare quorum for our proposal, lock on it and vote commit.
+    fn try_prepare(&mut self) -> Vec<Output> {
+        if self.step != Step::Prepare {
+            return vec![];
+        }
+        let proposed = match self.proposal {
+            Some(ref p) => p.proposal.block_hash.clone(),
+            None => return vec![],
+        };
+        let (block_hash, votes) = match self.votes.quorum(self.round, VoteType::Prepare, self.epochs.at(self.height)) {
+            Some(quorum) => quorum,
+            None => return vec![],
+        };
+        if block_hash != proposed {
+            return vec![];
+        }
+
+        trace!("prepared block at height {} round {}", self.height, self.round);
+        self.lock = Some(PreparedCertificate {
+            height: self.height,
+            round: self.round,
+            block_hash: block_hash.clone(),
+            votes,
+        });
+        self.transition(self.round, Step::Commit, Cause::Prepared);
+        self.broadcast_vote(VoteType::Commit, block_hash)
+    }
+
+    /// On a commit quorum in any round for a known block, finalize it and move on.
+    fn try_commit(&mut self) -> Vec<Output> {
+        let validators = self.epochs.at(self.height);
+        let committed = self.votes.rounds(VoteType::Commit).into_iter().find_map(|round| {
+            self.votes
+                .quorum(round, VoteType::Commit, validators)
+                .filter(|(hash, _)| self.blocks.contains_key(hash))
+                .map(|(hash, votes)| (round, hash, votes))
+        });
+        let (round, block_hash, votes) = match committed {
+            Some(committed) => committed,
+            None => return vec![],
+        };
+
+        info!("commit block at height {} round {}", self.height, round);
+        let commit = Commit {
+            height: self.height,
+            content: self.blocks[&block_hash].clone(),
+            certificate: CommitCertificate { height: self.height, round, block_hash, votes },
+        };
+        self.remember(commit.clone());
+        let mut outputs = vec![Output::Commit(commit)];
+        outputs.extend(self.enter_height(self.height + 1, Cause::Commit));
+        outputs
+    }
+}
+
+/// Height of an input received from the network.
+fn entry_height(entry: &WalEntry) -> Option<u64> {
+    match entry {
+        WalEntry::Proposal(signed) => Some(signed.proposal.height),
+        WalEntry::Vote(signed) => Some(signed.vote.height),
+        WalEntry::ViewChange(signed) => Some(signed.view_change.height),
+        WalEntry::NewView(new_view) => Some(new_view.height),
+        _ => None,
+    }
 }
 
+/// Signer of an input received from the network.
+fn entry_sender(entry: &WalEntry) -> Option<&Address> {
+    match entry {
+        WalEntry::Proposal(signed) => Some(&signed.proposal.proposer),
+        WalEntry::Vote(signed) => Some(&signed.vote.voter),
+        WalEntry::ViewChange(signed) => Some(&signed.view_change.voter),
+        WalEntry::NewView(new_view) => Some(&new_view.proposal.proposal.proposer),
+        _ => None,
+    }
+}
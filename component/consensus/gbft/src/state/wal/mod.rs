@@ -1,6 +1,559 @@
+//! Append-only write ahead log of the GBFT state machine inputs.
+//!
+//! Every input is appended and synced before the state acts on it, so a
+//! restarted validator replays exactly what it saw and signs nothing new.
+//!
+//! The log is a directory of numbered segments. A record is
+//! `len: u32 LE | crc32: u32 LE | json payload`. A new segment is started
+//! when the current one grows past the segment size and on every new height,
+//! segments older than the previous height are removed.
+//!
+//! Payloads are never empty: a zero length is the zero filled tail a crash
+//! may leave after the last record. Only the last segment may end with a
+//! torn record, every segment is checked when the log is opened.
+
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+use crate::message::{NewView, SignedProposal, SignedViewChange, SignedVote, SyncResponse};
+use crate::state::TimeoutStruct;
+
+/// Segments are rotated once they grow past this size.
+pub const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Records are at most this long, longer lengths in a header are corruption.
+pub const MAX_RECORD_SIZE: usize = 32 * 1024 * 1024;
+
+const SEGMENT_EXT: &str = "wal";
+const HEADER_LEN: usize = 8;
+
+/// One logged input of the state machine.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalEntry {
+    /// The state entered a new height, replay starts from the last one.
+    NewHeight(u64),
+    /// A proposal received from the network.
+    Proposal(SignedProposal),
+    /// A vote received from the network.
+    Vote(SignedVote),
+    /// Content given to `State::propose`.
+    Propose(Vec<u8>),
+    /// A round timer fired.
+    Timeout(TimeoutStruct),
+    /// A view change received from the network.
+    ViewChange(SignedViewChange),
+    /// A new view received from the network.
+    NewView(NewView),
+    /// Commits received while catching up with the network.
+    Sync(SyncResponse),
+}
+
+#[derive(Debug)]
 pub struct WAL {
-    fd: String,
-}
\ No newline at end of file
+    dir: PathBuf,
+    file: File,
+    seq: u64, // segment currently appended to
+    size: u64,
+    max_segment_size: u64,
+    height_seq: u64, // segment the current height starts in
+}
+
+impl WAL {
+    /// Open the log in `dir`, creating it if needed.
+    ///
+    /// A torn record at the end of the last segment, as left by a crash in the
+    /// middle of a write, is cut off when `repair` is set and reported as
+    /// `InvalidData` otherwise. A record failing its checksum before the end
+    /// of the segment, or a torn record in an earlier segment, is corruption,
+    /// reported as `InvalidData` either way.
+    pub fn open<P: AsRef<Path>>(dir: P, repair: bool) -> io::Result<WAL> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let segments = segments(&dir)?;
+        let seq = segments.last().map(|(seq, _)| *seq).unwrap_or(0);
+        let path = segment_path(&dir, seq);
+
+        // segments before the last one were complete when it was started
+        for (_, path) in segments.iter().rev().skip(1) {
+            let valid = check_segment(path)?;
+            if valid < fs::metadata(path)?.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("torn record at offset {} of {:?}, not the last segment", valid, path),
+                ));
+            }
+        }
+        let valid = match segments.last() {
+            Some((_, path)) => check_segment(path)?,
+            None => 0,
+        };
+        let file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+        if segments.is_empty() {
+            sync_dir(&dir)?;
+        }
+        let size = file.metadata()?.len();
+        if valid < size {
+            if !repair {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("torn record at offset {} of {:?}", valid, path),
+                ));
+            }
+            warn!("truncate torn wal record at offset {} of {:?}", valid, path);
+            file.set_len(valid)?;
+            file.sync_all()?;
+        }
+
+        let height_seq = last_height_segment(&segments)?.unwrap_or(seq);
+        Ok(WAL {
+            dir,
+            file,
+            seq,
+            size: valid,
+            max_segment_size: DEFAULT_SEGMENT_SIZE,
+            height_seq,
+        })
+    }
+
+    pub fn with_segment_size(mut self, max_segment_size: u64) -> Self {
+        self.max_segment_size = max_segment_size;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Append an entry and sync it to disk.
+    pub fn append(&mut self, entry: &WalEntry) -> io::Result<()> {
+        if self.size >= self.max_segment_size {
+            self.rotate()?;
+        }
+
+        let payload = serde_json::to_vec(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if payload.len() > MAX_RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("wal record of {} bytes, at most {} allowed", payload.len(), MAX_RECORD_SIZE),
+            ));
+        }
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Log the start of `height` in a fresh segment and drop the segments
+    /// written before the previous height.
+    pub fn new_height(&mut self, height: u64) -> io::Result<()> {
+        if self.size > 0 {
+            self.rotate()?;
+        }
+        let mut removed = false;
+        for (seq, path) in segments(&self.dir)? {
+            if seq < self.height_seq {
+                debug!("remove wal segment {:?}", path);
+                fs::remove_file(path)?;
+                removed = true;
+            }
+        }
+        if removed {
+            sync_dir(&self.dir)?;
+        }
+        self.height_seq = self.seq;
+        self.append(&WalEntry::NewHeight(height))
+    }
+
+    /// Entries from the last `NewHeight` on, what a restarted state replays.
+    pub fn current_height_entries(&self) -> io::Result<Vec<WalEntry>> {
+        let mut entries = vec![];
+        for entry in WAL::replay(&self.dir)? {
+            let entry = entry?;
+            if let WalEntry::NewHeight(_) = entry {
+                entries.clear();
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Iterate over every entry of the log in `dir`, oldest first.
+    pub fn replay<P: AsRef<Path>>(dir: P) -> io::Result<Replay> {
+        let mut segments: Vec<PathBuf> = segments(dir.as_ref())?.into_iter().map(|(_, p)| p).collect();
+        segments.reverse();
+        Ok(Replay { segments, reader: None })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.seq += 1;
+        let path = segment_path(&self.dir, self.seq);
+        debug!("rotate wal to {:?}", path);
+        self.file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        // the entry of the new segment in the directory must survive a crash too
+        sync_dir(&self.dir)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Sync the entries of `dir`, the segments created or removed in it.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Iterator over the entries of a log, see `WAL::replay`.
+pub struct Replay {
+    segments: Vec<PathBuf>, // remaining segments, next one last
+    reader: Option<BufReader<File>>,
+}
+
+impl Iterator for Replay {
+    type Item = io::Result<WalEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.reader.is_none() {
+                let path = self.segments.pop()?;
+                match File::open(&path) {
+                    Ok(file) => self.reader = Some(BufReader::new(file)),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            let reader = self.reader.as_mut().expect("reader was just set; qed");
+            match read_record(reader) {
+                Ok(Some(payload)) => {
+                    return Some(serde_json::from_slice(&payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+                }
+                Ok(None) => self.reader = None,
+                Err(e) => {
+                    self.segments.clear();
+                    self.reader = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Read one record, `None` on a clean end of segment.
+fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    match read_unchecked(reader)? {
+        Some((payload, _)) if payload.is_empty() => {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "empty wal record"))
+        }
+        Some((payload, crc)) if crc32fast::hash(&payload) != crc => {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "wal record checksum mismatch"))
+        }
+        record => Ok(record.map(|(payload, _)| payload)),
+    }
+}
+
+/// Read one record and its checksum without checking it.
+fn read_unchecked<R: Read>(reader: &mut R) -> io::Result<Option<(Vec<u8>, u32)>> {
+    let mut header = [0u8; HEADER_LEN];
+    let read = read_full(reader, &mut header)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read < HEADER_LEN {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "torn wal record header"));
+    }
+
+    let mut len = [0u8; 4];
+    let mut crc = [0u8; 4];
+    len.copy_from_slice(&header[..4]);
+    crc.copy_from_slice(&header[4..]);
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_RECORD_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("wal record length {} exceeds {}", len, MAX_RECORD_SIZE),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    if read_full(reader, &mut payload)? < payload.len() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "torn wal record payload"));
+    }
+    Ok(Some((payload, u32::from_le_bytes(crc))))
+}
+
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Length of the valid prefix of a segment, the rest being a torn record: a
+/// short one, a last one failing its checksum, or zeros. A record failing its
+/// checksum before the end is `InvalidData`.
+fn check_segment(path: &Path) -> io::Result<u64> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut valid = 0;
+    loop {
+        match read_unchecked(&mut reader) {
+            Ok(Some((payload, crc))) if payload.is_empty() => {
+                // the crc of an empty payload is zero, a zero filled tail would pass
+                let mut rest = crc.to_le_bytes().to_vec();
+                reader.read_to_end(&mut rest)?;
+                if rest.iter().any(|b| *b != 0) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("empty wal record at offset {} of {:?}", valid, path),
+                    ));
+                }
+                return Ok(valid);
+            }
+            Ok(Some((payload, crc))) => {
+                let end = valid + (HEADER_LEN + payload.len()) as u64;
+                if crc32fast::hash(&payload) != crc {
+                    if end == file_len {
+                        return Ok(valid);
+                    }
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("wal record checksum mismatch at offset {} of {:?}", valid, path),
+                    ));
+                }
+                valid = end;
+            }
+            Ok(None) => return Ok(valid),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(valid),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Latest segment starting with a `NewHeight` entry.
+fn last_height_segment(segments: &[(u64, PathBuf)]) -> io::Result<Option<u64>> {
+    for (seq, path) in segments.iter().rev() {
+        let mut reader = BufReader::new(File::open(path)?);
+        if let Ok(Some(payload)) = read_record(&mut reader) {
+            if let Ok(WalEntry::NewHeight(_)) = serde_json::from_slice(&payload) {
+                return Ok(Some(*seq));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, SEGMENT_EXT))
+}
+
+/// Segments of the log in `dir`, sorted by sequence number.
+fn segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new(SEGMENT_EXT)) {
+            continue;
+        }
+        if let Some(seq) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+            segments.push((seq, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn timeout(round: u64) -> WalEntry {
+        WalEntry::Timeout(TimeoutStruct { height: 1, round, duration: 1000 })
+    }
+
+    fn replay(dir: &Path) -> Vec<WalEntry> {
+        WAL::replay(dir).unwrap().map(|e| e.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_append_and_replay() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap();
+        wal.append(&timeout(0)).unwrap();
+        wal.append(&WalEntry::Propose(b"block".to_vec())).unwrap();
+        drop(wal);
+
+        assert_eq!(replay(tempdir.path()), vec![timeout(0), WalEntry::Propose(b"block".to_vec())]);
+
+        let mut wal = WAL::open(tempdir.path(), false).unwrap();
+        wal.append(&timeout(1)).unwrap();
+        assert_eq!(replay(tempdir.path()).len(), 3);
+    }
+
+    #[test]
+    fn test_segment_rotation() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap().with_segment_size(1);
+        for round in 0..5 {
+            wal.append(&timeout(round)).unwrap();
+        }
+
+        assert_eq!(segments(tempdir.path()).unwrap().len(), 5);
+        assert_eq!(replay(tempdir.path()), (0..5).map(timeout).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_new_height_prunes_old_segments() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap();
+        wal.new_height(1).unwrap();
+        wal.append(&timeout(0)).unwrap();
+        wal.new_height(2).unwrap();
+        wal.append(&timeout(0)).unwrap();
+        wal.new_height(3).unwrap();
+        wal.append(&timeout(1)).unwrap();
+
+        // height 1 is gone, height 2 is kept as the previous height
+        assert_eq!(replay(tempdir.path())[0], WalEntry::NewHeight(2));
+        assert_eq!(wal.current_height_entries().unwrap(), vec![WalEntry::NewHeight(3), timeout(1)]);
+
+        let wal = WAL::open(tempdir.path(), false).unwrap();
+        assert_eq!(wal.height_seq, wal.seq);
+    }
+
+    #[test]
+    fn test_torn_tail_repair() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap();
+        wal.append(&timeout(0)).unwrap();
+        wal.append(&timeout(1)).unwrap();
+        let path = segment_path(tempdir.path(), 0);
+        drop(wal);
+
+        // simulate a crash in the middle of the second record
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+
+        assert!(WAL::replay(tempdir.path()).unwrap().any(|e| e.is_err()));
+        assert_eq!(WAL::open(tempdir.path(), false).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut wal = WAL::open(tempdir.path(), true).unwrap();
+        wal.append(&timeout(2)).unwrap();
+        assert_eq!(replay(tempdir.path()), vec![timeout(0), timeout(2)]);
+    }
+
+    #[test]
+    fn test_zero_filled_tail() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap();
+        wal.append(&timeout(0)).unwrap();
+        let path = segment_path(tempdir.path(), 0);
+        drop(wal);
+
+        // a crash after the file grew but before the record was written
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len + 64).unwrap();
+
+        assert!(WAL::replay(tempdir.path()).unwrap().any(|e| e.is_err()));
+        assert_eq!(WAL::open(tempdir.path(), false).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut wal = WAL::open(tempdir.path(), true).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        wal.append(&timeout(1)).unwrap();
+        assert_eq!(replay(tempdir.path()), vec![timeout(0), timeout(1)]);
+
+        // followed by something else, it is corruption
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.extend_from_slice(&[0; 16]);
+        bytes.push(1);
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(WAL::open(tempdir.path(), true).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_torn_earlier_segment() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap().with_segment_size(1);
+        wal.append(&timeout(0)).unwrap();
+        wal.append(&timeout(1)).unwrap();
+        drop(wal);
+
+        let path = segment_path(tempdir.path(), 0);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+        // only the last segment is repaired
+        assert_eq!(WAL::open(tempdir.path(), true).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap();
+        wal.append(&timeout(0)).unwrap();
+        let path = segment_path(tempdir.path(), 0);
+        drop(wal);
+
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        let err = WAL::replay(tempdir.path()).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // the last record of the segment, torn
+        assert!(WAL::open(tempdir.path(), true).is_ok());
+        assert!(replay(tempdir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_corruption_not_repaired() {
+        let tempdir = TempDir::new("").unwrap();
+        let mut wal = WAL::open(tempdir.path(), false).unwrap();
+        wal.append(&timeout(0)).unwrap();
+        wal.append(&timeout(1)).unwrap();
+        let path = segment_path(tempdir.path(), 0);
+        drop(wal);
+
+        // corrupt the payload of the first record, a valid one follows
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_LEN] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        assert_eq!(WAL::open(tempdir.path(), true).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_oversized_record() {
+        let tempdir = TempDir::new("").unwrap();
+        let path = segment_path(tempdir.path(), 0);
+        let mut header = vec![];
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        fs::write(&path, &header).unwrap();
+
+        let err = WAL::replay(tempdir.path()).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(WAL::open(tempdir.path(), true).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,7 +1,855 @@
-#[cfg(test)]
-mod state_test;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::Hasher;
+
+use consensus_engine::{new_header, ConsensusHeader, Engine, EngineSpec, Fields, Proposal as EngineProposal};
+
+use crate::certificate::{seal_header, verify_finality, verify_header, AggregatedCertificate};
+use crate::config::GBFTConfig;
+use crate::engine::{GbftEngine, GbftParams};
+use crate::metrics::Cause;
+use crate::crypto::Crypto;
+use crate::error::Error;
+use crate::evidence::Evidence;
+use crate::message::*;
+use crate::state::wal::WAL;
+use crate::state::{State, TimeoutStruct};
+use crate::validator_set::{Validator, ValidatorSet};
+use codec::{Decode, Encode};
+use tempdir::TempDir;
+
+/// Insecure crypto for tests: a signature is the signer followed by the hash.
+#[derive(Clone, Debug)]
+pub struct TestCrypto(pub Address);
+
+impl Crypto for TestCrypto {
+    fn hash(&self, msg: &[u8]) -> Hash {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(msg);
+        hasher.finish().to_be_bytes().to_vec()
+    }
+
+    fn sign(&self, hash: &Hash) -> crate::error::Result<Signature> {
+        Ok([&self.0[..], &hash[..]].concat())
+    }
+
+    fn verify(&self, signature: &Signature, hash: &Hash, signer: &Address) -> bool {
+        signature[..] == [&signer[..], &hash[..]].concat()[..]
+    }
+}
+
+fn validators(n: u8) -> ValidatorSet {
+    ValidatorSet::equal((0..n).map(|i| vec![i]).collect()).unwrap()
+}
+
+fn timeout(height: u64) -> TimeoutStruct {
+    TimeoutStruct { height, round: 0, duration: 1000 }
+}
+
+fn config() -> GBFTConfig {
+    let mut config = GBFTConfig::new("1.0".to_owned(), 3, 3);
+    config.set_round_timeout(1000, 2);
+    config
+}
+
+fn new_states(n: u8) -> Vec<State<TestCrypto>> {
+    (0..n)
+        .map(|i| State::new(TestCrypto(vec![i]), vec![i], validators(n), config(), timeout(1)))
+        .collect()
+}
+
+/// Deliver every output to every node until the network is quiet, proposing
+/// blocks up to `max_height`.
+fn run(states: &mut [State<TestCrypto>], initial: Vec<(usize, Output)>, max_height: u64) -> Vec<(usize, Commit)> {
+    run_with(states, initial, max_height, |_, _, _| true)
+}
+
+/// Same as `run`, only delivering the messages `deliver(from, to, message)` accepts.
+fn run_with<F>(
+    states: &mut [State<TestCrypto>],
+    initial: Vec<(usize, Output)>,
+    max_height: u64,
+    mut deliver: F,
+) -> Vec<(usize, Commit)>
+where
+    F: FnMut(usize, usize, &ConsensusMessage) -> bool,
+{
+    let mut queue: VecDeque<(usize, Output)> = initial.into();
+    let mut commits = vec![];
+    while let Some((from, output)) = queue.pop_front() {
+        match output {
+            Output::Broadcast(message) => {
+                for (to, state) in states.iter_mut().enumerate() {
+                    if to != from && deliver(from, to, &message) {
+                        let outputs = state.handle_message(message.clone()).unwrap_or_default();
+                        queue.extend(outputs.into_iter().map(|o| (to, o)));
+                    }
+                }
+            }
+            Output::Send { to, message } => {
+                let to = to[0] as usize;
+                if deliver(from, to, &message) {
+                    let outputs = states[to].handle_message(message).unwrap_or_default();
+                    queue.extend(outputs.into_iter().map(|o| (to, o)));
+                }
+            }
+            Output::RequestProposal { height, .. } if height <= max_height => {
+                let outputs = states[from].propose(format!("block-{}", height).into_bytes()).unwrap();
+                queue.extend(outputs.into_iter().map(|o| (from, o)));
+            }
+            Output::Commit(commit) => commits.push((from, commit)),
+            _ => {}
+        }
+    }
+    commits
+}
+
+fn start(states: &mut [State<TestCrypto>]) -> Vec<(usize, Output)> {
+    states
+        .iter_mut()
+        .enumerate()
+        .flat_map(|(i, s)| s.start().into_iter().map(move |o| (i, o)))
+        .collect()
+}
+
+/// Fire the round 0 timer of every node.
+fn time_out(states: &mut [State<TestCrypto>]) -> Vec<(usize, Output)> {
+    states
+        .iter_mut()
+        .enumerate()
+        .flat_map(|(i, s)| s.handle_timeout(timeout(1)).unwrap().into_iter().map(move |o| (i, o)))
+        .collect()
+}
+
+fn sign_vote(round: u64, vote_type: VoteType, block_hash: Hash, voter: u8) -> SignedVote {
+    let crypto = TestCrypto(vec![voter]);
+    let vote = Vote { height: 1, round, vote_type, block_hash, voter: vec![voter] };
+    SignedVote { signature: crypto.sign(&crypto.hash(&vote_payload(&vote))).unwrap(), vote }
+}
+
+fn sign_proposal(round: u64, content: &[u8], proposer: u8) -> SignedProposal {
+    let crypto = TestCrypto(vec![proposer]);
+    let proposal = Proposal {
+        height: 1,
+        round,
+        block_hash: crypto.hash(content),
+        content: content.to_vec(),
+        proposer: vec![proposer],
+        lock: None,
+    };
+    SignedProposal { signature: crypto.sign(&crypto.hash(&proposal_payload(&proposal))).unwrap(), proposal }
+}
+
+fn sign_view_change(height: u64, round: u64, voter: u8) -> SignedViewChange {
+    let crypto = TestCrypto(vec![voter]);
+    let view_change = ViewChange { height, round, prepared: None, content: None, voter: vec![voter] };
+    SignedViewChange { signature: crypto.sign(&crypto.hash(&view_change_payload(&view_change))).unwrap(), view_change }
+}
 
 #[test]
 fn test_print_info() {
-    assert_eq!(2 + 2, 4);
-}
\ No newline at end of file
+    let states = new_states(4);
+    states[0].print_info();
+    assert_eq!(states[0].height(), 1);
+    assert_eq!(states[0].step(), Step::PrePrepare);
+}
+
+#[test]
+fn test_quorum_size() {
+    assert_eq!(new_states(1)[0].quorum_size(), 1);
+    assert_eq!(new_states(4)[0].quorum_size(), 3);
+    assert_eq!(new_states(5)[0].quorum_size(), 4);
+    assert_eq!(new_states(7)[0].quorum_size(), 5);
+}
+
+#[test]
+fn test_all_validators_commit() {
+    let mut states = new_states(4);
+    let initial = start(&mut states);
+    let commits = run(&mut states, initial, 1);
+
+    assert_eq!(commits.len(), 4);
+    for (_, commit) in &commits {
+        assert_eq!(commit.height, 1);
+        assert_eq!(commit.content, b"block-1".to_vec());
+        assert!(commit.certificate.votes.len() >= 3);
+    }
+    for state in &states {
+        assert_eq!(state.height(), 2);
+        assert_eq!(state.round(), 0);
+    }
+}
+
+#[test]
+fn test_single_validator_commits_alone() {
+    let mut states = new_states(1);
+    let outputs = states[0].start();
+    assert!(outputs.contains(&Output::RequestProposal { height: 1, round: 0, delay: 0 }));
+
+    let outputs = states[0].propose(b"solo".to_vec()).unwrap();
+    assert!(outputs.iter().any(|o| match o {
+        Output::Commit(c) => c.height == 1 && c.content == b"solo".to_vec(),
+        _ => false,
+    }));
+    assert_eq!(states[0].height(), 2);
+}
+
+#[test]
+fn test_reload_config() {
+    let mut states = new_states(4);
+    let mut reloaded = config();
+    reloaded.set_round_timeout(500, 3);
+    reloaded.set_proposal_delay(100);
+    states[1].reload_config(reloaded.clone()).unwrap();
+    assert_eq!(states[1].round_duration(2), 4500);
+    let outputs = states[1].start();
+    assert!(outputs.contains(&Output::RequestProposal { height: 1, round: 0, delay: 100 }));
+
+    reloaded.set_max_block_size(1024);
+    assert!(matches!(states[1].reload_config(reloaded), Err(Error::InvalidConfig(_))));
+    assert_eq!(states[1].config().max_block_size(), config().max_block_size());
+}
+
+#[test]
+fn test_block_too_large() {
+    let mut small = config();
+    small.set_max_block_size(4);
+    let mut states: Vec<_> = (0..4u8)
+        .map(|i| State::new(TestCrypto(vec![i]), vec![i], validators(4), small.clone(), timeout(1)))
+        .collect();
+    let _ = states[1].start();
+    assert!(matches!(states[1].propose(b"large".to_vec()), Err(Error::BlockTooLarge { size: 5, max: 4 })));
+
+    let _ = states[0].start();
+    let proposal = ConsensusMessage::Proposal(sign_proposal(0, b"large", 1));
+    assert!(matches!(states[0].handle_message(proposal), Err(Error::BlockTooLarge { .. })));
+    assert!(states[1].propose(b"tiny".to_vec()).is_ok());
+}
+
+#[test]
+fn test_round_trace() {
+    let mut states = new_states(4);
+    let initial = start(&mut states);
+    let _ = run(&mut states, initial, 1);
+    let _ = states[0].handle_timeout(timeout(2)).unwrap();
+
+    let steps: Vec<_> = states[0].trace().iter().map(|t| (t.height, t.round, t.step, t.cause)).collect();
+    assert_eq!(steps, vec![
+        (1, 0, Step::PrePrepare, Cause::Start),
+        (1, 0, Step::Prepare, Cause::Proposal),
+        (1, 0, Step::Commit, Cause::Prepared),
+        (2, 0, Step::PrePrepare, Cause::Commit),
+        (2, 1, Step::ViewChange, Cause::ViewChange),
+    ]);
+    let dump = states[0].trace().dump();
+    assert_eq!(dump.lines().count(), 5);
+    assert!(dump.lines().last().unwrap().contains(r#""from_step":"PrePrepare","round":1,"step":"ViewChange""#));
+}
+
+#[test]
+fn test_timeout_starts_view_change() {
+    let mut states = new_states(4);
+    let _ = states[2].start();
+    assert_eq!(states[2].proposer(1, 0), &vec![1]);
+
+    let outputs = states[2].handle_timeout(timeout(1)).unwrap();
+    assert_eq!((states[2].round(), states[2].step()), (0, Step::ViewChange));
+    assert!(outputs.contains(&Output::ScheduleTimeout(TimeoutStruct { height: 1, round: 1, duration: 2000 })));
+    assert!(outputs.contains(&Output::Broadcast(ConsensusMessage::ViewChange(sign_view_change(1, 1, 2)))));
+    assert_eq!(states[2].round_duration(3), 8000);
+
+    // signed over its SCALE encoding, as the runtime decodes it
+    let signed = sign_view_change(1, 1, 2);
+    assert_eq!(view_change_payload(&signed.view_change), signed.view_change.encode());
+    assert_eq!(SignedViewChange::decode(&mut &signed.encode()[..]).unwrap(), signed);
+}
+
+#[test]
+fn test_join_view_change_on_weak_quorum() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    assert!(states[0].handle_view_change(sign_view_change(1, 1, 1)).unwrap().is_empty());
+    assert_eq!(states[0].step(), Step::PrePrepare);
+
+    // two validators cannot all be faulty, follow them
+    let outputs = states[0].handle_view_change(sign_view_change(1, 1, 3)).unwrap();
+    assert_eq!(states[0].step(), Step::ViewChange);
+    assert!(outputs.contains(&Output::Broadcast(ConsensusMessage::ViewChange(sign_view_change(1, 1, 0)))));
+}
+
+#[test]
+fn test_faulty_leader_is_replaced() {
+    let mut states = new_states(4);
+    let initial = start(&mut states);
+    // the proposer of round 0 is validator 1, which never gets a message out
+    assert!(run_with(&mut states, initial, 1, |from, _, _| from != 1).is_empty());
+
+    let outputs = time_out(&mut states);
+    let commits = run_with(&mut states, outputs, 1, |from, _, _| from != 1);
+    assert_eq!(commits.len(), 4);
+    assert!(commits.iter().all(|(_, c)| c.content == b"block-1".to_vec() && c.certificate.round == 1));
+    assert!(states.iter().all(|s| s.height() == 2));
+}
+
+#[test]
+fn test_view_change_gives_up() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    let mut timer = timeout(1);
+    for round in 1..=3 {
+        let outputs = states[0].handle_timeout(timer).unwrap();
+        timer = outputs
+            .into_iter()
+            .find_map(|o| match o {
+                Output::ScheduleTimeout(t) => Some(t),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!((timer.round, timer.duration), (round, 1000 << round));
+    }
+
+    let res = states[0].handle_timeout(timer.clone());
+    assert!(matches!(res, Err(Error::ViewChangeFailed { height: 1, round: 3, attempts: 3 })));
+    assert_eq!((states[0].round(), states[0].step()), (0, Step::ViewChange));
+    assert!(states[0].handle_timeout(timer).is_err());
+}
+
+#[test]
+fn test_stale_timeout_is_ignored() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    assert!(states[0].handle_timeout(TimeoutStruct { height: 1, round: 3, duration: 1000 }).unwrap().is_empty());
+    assert!(states[0].handle_timeout(timeout(0)).unwrap().is_empty());
+    assert_eq!(states[0].round(), 0);
+}
+
+#[test]
+fn test_proposal_from_wrong_proposer_is_rejected() {
+    let mut states = new_states(4);
+    let _ = states[0].start(); // the proposer is 1
+    let _ = states[3].start();
+    let outputs = states[0].propose(b"evil".to_vec());
+    assert!(matches!(outputs, Err(Error::NotProposer { .. })));
+
+    let crypto = TestCrypto(vec![0]);
+    let proposal = Proposal {
+        height: 1,
+        round: 0,
+        block_hash: crypto.hash(b"evil"),
+        content: b"evil".to_vec(),
+        proposer: vec![0],
+        lock: None,
+    };
+    let signature = crypto.sign(&crypto.hash(&proposal_payload(&proposal))).unwrap();
+    let res = states[3].handle_proposal(SignedProposal { proposal, signature });
+    assert!(matches!(res, Err(Error::InvalidProposer(_))));
+}
+
+#[test]
+fn test_forged_vote_is_rejected() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    let vote = Vote { height: 1, round: 0, vote_type: VoteType::Prepare, block_hash: vec![1], voter: vec![2] };
+    let forged = SignedVote { signature: TestCrypto(vec![3]).sign(&vec![0]).unwrap(), vote: vote.clone() };
+    assert!(matches!(states[0].handle_vote(forged), Err(Error::InvalidSignature(_))));
+
+    let stranger = Vote { voter: vec![9], ..vote };
+    let crypto = TestCrypto(vec![9]);
+    let signature = crypto.sign(&crypto.hash(&vote_payload(&stranger))).unwrap();
+    let res = states[0].handle_vote(SignedVote { vote: stranger, signature });
+    assert!(matches!(res, Err(Error::UnknownValidator(_))));
+}
+
+#[test]
+fn test_far_future_round_is_rejected() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    let vote = sign_vote(17, VoteType::Prepare, vec![1], 1);
+    assert!(matches!(states[0].handle_vote(vote), Err(Error::WrongRound { got: 17, current: 0 })));
+    let view_change = sign_view_change(1, u64::MAX, 1);
+    assert!(matches!(states[0].handle_view_change(view_change), Err(Error::WrongRound { .. })));
+
+    assert!(states[0].handle_vote(sign_vote(16, VoteType::Prepare, vec![1], 1)).is_ok());
+    assert!(states[0].handle_view_change(sign_view_change(1, 16, 1)).is_ok());
+}
+
+#[test]
+fn test_locked_validator_refuses_other_block() {
+    let mut states = new_states(4);
+    let initial = start(&mut states);
+
+    // let the prepare votes through but drop every commit vote
+    let mut queue: VecDeque<(usize, Output)> = initial.into();
+    while let Some((from, output)) = queue.pop_front() {
+        match output {
+            Output::Broadcast(ConsensusMessage::Vote(ref v)) if v.vote.vote_type == VoteType::Commit => {}
+            Output::Broadcast(message) => {
+                for (to, state) in states.iter_mut().enumerate() {
+                    if to != from {
+                        let outputs = state.handle_message(message.clone()).unwrap_or_default();
+                        queue.extend(outputs.into_iter().map(|o| (to, o)));
+                    }
+                }
+            }
+            Output::RequestProposal { .. } => {
+                let outputs = states[from].propose(b"first".to_vec()).unwrap();
+                queue.extend(outputs.into_iter().map(|o| (from, o)));
+            }
+            _ => {}
+        }
+    }
+    assert!(states.iter().all(|s| s.lock().is_some() && s.height() == 1));
+
+    // round 1: the new proposer re-proposes the locked block with its certificate
+    let outputs = time_out(&mut states);
+    let mut new_views = vec![];
+    let commits = run_with(&mut states, outputs, 1, |_, _, message| match message {
+        ConsensusMessage::NewView(new_view) => {
+            new_views.push(new_view.clone());
+            false
+        }
+        _ => true,
+    });
+    assert!(commits.is_empty());
+    let new_view = new_views.pop().unwrap();
+    assert_eq!(new_view.proposal.proposal.content, b"first".to_vec());
+    assert!(new_view.proposal.proposal.lock.is_some());
+
+    // a new view proposing another block is rejected
+    let crypto = TestCrypto(vec![2]);
+    let mut forged = new_view.clone();
+    let proposal = Proposal {
+        block_hash: crypto.hash(b"second"),
+        content: b"second".to_vec(),
+        lock: None,
+        ..forged.proposal.proposal
+    };
+    forged.proposal = SignedProposal { signature: crypto.sign(&crypto.hash(&proposal_payload(&proposal))).unwrap(), proposal };
+    assert!(matches!(states[0].handle_new_view(forged), Err(Error::InvalidCertificate(_))));
+    assert_eq!(states[0].step(), Step::ViewChange);
+
+    let initial = vec![(2, Output::Broadcast(ConsensusMessage::NewView(new_view)))];
+    let commits = run(&mut states, initial, 1);
+    assert_eq!(commits.len(), 4);
+    assert!(commits.iter().all(|(_, c)| c.content == b"first".to_vec()));
+}
+
+#[test]
+fn test_lagging_validator_catches_up() {
+    let mut states = new_states(4);
+    let initial = start(&mut states);
+    let commits = run_with(&mut states, initial, 2, |_, to, _| to != 3);
+    assert_eq!(commits.len(), 6);
+    assert_eq!(states[3].height(), 1);
+
+    // validator 3 proposes height 3 and never does, the view change of height 3
+    // tells it that it is behind
+    let outputs = states[0].handle_timeout(timeout(3)).unwrap();
+    let initial = outputs.into_iter().map(|o| (0, o)).collect();
+    let commits: Vec<(u64, Vec<u8>)> = run(&mut states, initial, 2)
+        .into_iter()
+        .map(|(i, c)| {
+            assert_eq!(i, 3);
+            (c.height, c.content)
+        })
+        .collect();
+    assert_eq!(commits, vec![(1, b"block-1".to_vec()), (2, b"block-2".to_vec())]);
+    assert_eq!(states[3].height(), 3);
+}
+
+#[test]
+fn test_next_height_is_applied_after_commit() {
+    let mut states = new_states(4);
+    let initial = start(&mut states);
+    // validator 3 misses the commit votes of height 1 and cannot catch up
+    let mut withheld = vec![];
+    let commits = run_with(&mut states, initial, 2, |_, to, message| match message {
+        ConsensusMessage::Vote(signed) if to == 3 && signed.vote.height == 1 && signed.vote.vote_type == VoteType::Commit => {
+            withheld.push(signed.clone());
+            false
+        }
+        ConsensusMessage::SyncRequest(_) | ConsensusMessage::SyncResponse(_) => false,
+        _ => true,
+    });
+    assert_eq!(commits.len(), 6);
+    assert_eq!(states[3].height(), 1);
+
+    let mut committed = vec![];
+    for vote in withheld {
+        let outputs = states[3].handle_vote(vote).unwrap_or_default();
+        committed.extend(outputs.into_iter().filter_map(|o| match o {
+            Output::Commit(commit) => Some((commit.height, commit.content)),
+            _ => None,
+        }));
+    }
+    assert_eq!(committed, vec![(1, b"block-1".to_vec()), (2, b"block-2".to_vec())]);
+    assert_eq!(states[3].height(), 3);
+}
+
+#[test]
+fn test_forged_sync_response_is_rejected() {
+    let mut states = new_states(4);
+    let initial = start(&mut states);
+    let mut commits = run_with(&mut states, initial, 1, |_, to, _| to != 3);
+    let (_, mut commit) = commits.pop().unwrap();
+    commit.content = b"evil".to_vec();
+    let response = SyncResponse { commits: vec![commit.clone()], new_view: None };
+    assert!(matches!(states[3].handle_sync_response(response), Err(Error::InvalidBlockHash)));
+
+    commit.content = b"block-1".to_vec();
+    commit.certificate.votes.truncate(2);
+    let response = SyncResponse { commits: vec![commit], new_view: None };
+    assert!(matches!(states[3].handle_sync_response(response), Err(Error::InvalidCertificate(_))));
+    assert_eq!(states[3].height(), 1);
+}
+
+#[test]
+fn test_catch_up_gives_up() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    let crypto = TestCrypto(vec![1]);
+    let vote = Vote { height: 5, round: 0, vote_type: VoteType::Prepare, block_hash: vec![1], voter: vec![1] };
+    let signature = crypto.sign(&crypto.hash(&vote_payload(&vote))).unwrap();
+    let outputs = states[0].handle_vote(SignedVote { vote, signature }).unwrap();
+    assert!(outputs.contains(&Output::Broadcast(ConsensusMessage::SyncRequest(SyncRequest { height: 1, requester: vec![0] }))));
+
+    let mut timer = TimeoutStruct { height: 1, round: 1, duration: 1000 };
+    assert!(outputs.contains(&Output::ScheduleSync(timer.clone())));
+    assert!(states[0].handle_sync_timeout(timeout(1)).unwrap().is_empty());
+    for attempt in 2..=3 {
+        let outputs = states[0].handle_sync_timeout(timer).unwrap();
+        timer = TimeoutStruct { height: 1, round: attempt, duration: 1000 };
+        assert!(outputs.contains(&Output::ScheduleSync(timer.clone())));
+    }
+    let res = states[0].handle_sync_timeout(timer);
+    assert!(matches!(res, Err(Error::RecoveryFailed { height: 1, target: 5, attempts: 3 })));
+}
+
+#[test]
+fn test_forged_future_message_is_ignored() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    // unsigned, then from an unknown peer
+    let vote = Vote { height: u64::MAX, round: 0, vote_type: VoteType::Prepare, block_hash: vec![1], voter: vec![1] };
+    let forged = SignedVote { vote: vote.clone(), signature: vec![] };
+    assert!(matches!(states[0].handle_vote(forged), Err(Error::InvalidSignature(_))));
+    let stranger = TestCrypto(vec![9]);
+    let vote = Vote { voter: vec![9], ..vote };
+    let signature = stranger.sign(&stranger.hash(&vote_payload(&vote))).unwrap();
+    assert!(matches!(states[0].handle_vote(SignedVote { vote, signature }), Err(Error::UnknownValidator(_))));
+
+    // no catch up started: no timer to give up on
+    assert!(states[0].handle_sync_timeout(TimeoutStruct { height: 1, round: 1, duration: 1000 }).unwrap().is_empty());
+    let mut view_change = sign_view_change(7, 0, 2);
+    view_change.signature = TestCrypto(vec![3]).sign(&view_change.signature).unwrap();
+    assert!(states[0].handle_view_change(view_change).is_err());
+    assert_eq!(states[0].height(), 1);
+}
+
+#[test]
+fn test_double_vote_is_reported() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    let first = sign_vote(0, VoteType::Prepare, vec![1], 2);
+    let second = sign_vote(0, VoteType::Prepare, vec![2], 2);
+    assert!(states[0].handle_vote(first.clone()).unwrap().is_empty());
+
+    let outputs = states[0].handle_vote(second.clone()).unwrap();
+    let evidence = Evidence::DoubleVote { first, second: second.clone() };
+    assert_eq!(
+        outputs,
+        vec![Output::Broadcast(ConsensusMessage::Evidence(evidence.clone())), Output::Evidence(evidence.clone())]
+    );
+    assert!(states[0].handle_vote(second).unwrap().is_empty());
+
+    // anyone can check the decoded evidence, and reports it once
+    let decoded = Evidence::decode(&mut &evidence.encode()[..]).unwrap();
+    assert_eq!(decoded, evidence);
+    assert!(decoded.verify(&TestCrypto(vec![]), &validators(4)).is_ok());
+    assert_eq!(states[1].handle_evidence(decoded.clone()).unwrap(), vec![Output::Evidence(decoded.clone())]);
+    assert!(states[1].handle_evidence(decoded).unwrap().is_empty());
+}
+
+#[test]
+fn test_double_proposal_is_reported() {
+    let mut states = new_states(4);
+    let _ = states[0].start();
+    let first = sign_proposal(0, b"first", 1);
+    let second = sign_proposal(0, b"second", 1);
+    let _ = states[0].handle_proposal(first.clone()).unwrap();
+
+    let outputs = states[0].handle_proposal(second.clone()).unwrap();
+    assert!(outputs.contains(&Output::Evidence(Evidence::DoubleProposal { first, second })));
+    assert_eq!(states[0].step(), Step::Prepare);
+}
+
+#[test]
+fn test_invalid_evidence_is_rejected() {
+    let mut states = new_states(4);
+    let first = sign_vote(0, VoteType::Commit, vec![1], 2);
+
+    let same_block = Evidence::DoubleVote { first: first.clone(), second: first.clone() };
+    assert!(matches!(states[1].handle_evidence(same_block), Err(Error::InvalidEvidence(_))));
+
+    let other_round = Evidence::DoubleVote { first: first.clone(), second: sign_vote(1, VoteType::Commit, vec![2], 2) };
+    assert!(matches!(states[1].handle_evidence(other_round), Err(Error::InvalidEvidence(_))));
+
+    let mut forged = sign_vote(0, VoteType::Commit, vec![2], 2);
+    forged.signature = TestCrypto(vec![3]).sign(&forged.signature).unwrap();
+    let forged = Evidence::DoubleVote { first, second: forged };
+    assert!(matches!(states[1].handle_evidence(forged), Err(Error::InvalidSignature(_))));
+}
+
+#[test]
+fn test_weighted_quorum() {
+    let powers = [1, 1, 4];
+    let set = ValidatorSet::new(
+        0,
+        0,
+        powers.iter().enumerate().map(|(i, power)| Validator { address: vec![i as u8], power: *power }).collect(),
+    )
+    .unwrap();
+    let new_states = || -> Vec<State<TestCrypto>> {
+        (0..3).map(|i| State::new(TestCrypto(vec![i]), vec![i], set.clone(), config(), timeout(1))).collect()
+    };
+    assert_eq!(new_states()[0].quorum_size(), 5);
+
+    // validators 1 and 2 hold 5 of 6
+    let mut states = new_states();
+    let initial = start(&mut states);
+    let commits = run_with(&mut states, initial, 1, |from, to, _| from != 0 && to != 0);
+    assert_eq!(commits.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 2]);
+
+    // validators 0 and 1 only hold 2
+    let mut states = new_states();
+    let initial = start(&mut states);
+    assert!(run_with(&mut states, initial, 1, |from, to, _| from != 2 && to != 2).is_empty());
+}
+
+#[test]
+fn test_validator_set_changes_at_epoch() {
+    let mut states = new_states(4);
+    let next = ValidatorSet::new(1, 3, (0..3).map(|i| Validator { address: vec![i], power: 1 }).collect()).unwrap();
+    for state in states.iter_mut() {
+        state.schedule_validators(next.clone()).unwrap();
+    }
+    let late = ValidatorSet::new(2, 1, vec![Validator { address: vec![0], power: 1 }]).unwrap();
+    assert!(matches!(states[0].schedule_validators(late), Err(Error::InvalidValidatorSet(_))));
+
+    let initial = start(&mut states);
+    let commits = run(&mut states, initial, 4);
+    assert_eq!(commits.len(), 16);
+    for (_, commit) in commits.iter().filter(|(_, c)| c.height >= 3) {
+        assert!(commit.certificate.votes.iter().all(|v| v.vote.voter != vec![3]));
+    }
+    assert_eq!(states[3].validators().epoch(), 1);
+    assert_eq!(states[3].quorum_size(), 3);
+    assert_eq!(states[3].validators_at(2).len(), 4);
+
+    // the removed validator is not counted anymore
+    let stranger = sign_vote(0, VoteType::Prepare, vec![1], 3);
+    let vote = Vote { height: 5, ..stranger.vote };
+    let signature = TestCrypto(vec![3]).sign(&TestCrypto(vec![3]).hash(&vote_payload(&vote))).unwrap();
+    assert!(matches!(states[0].handle_vote(SignedVote { vote, signature }), Err(Error::UnknownValidator(_))));
+}
+
+#[test]
+fn test_aggregated_certificate() {
+    let mut states = new_states(7);
+    let initial = start(&mut states);
+    let (_, commit) = run(&mut states, initial, 1).pop().unwrap();
+    let aggregated = states[0].aggregate(&commit).unwrap();
+    assert_eq!(aggregated.signatures.len(), commit.certificate.votes.len());
+    assert_eq!(aggregated.expand(&validators(7)).unwrap(), commit.certificate);
+    assert!(aggregated.encode().len() < serde_json::to_vec(&commit.certificate).unwrap().len() / 4);
+
+    // a light client only needs the block, the seal and the validator set
+    let crypto = TestCrypto(vec![]);
+    let seal = aggregated.encode();
+    assert_eq!(verify_finality(&crypto, &validators(7), 1, &commit.content, &seal).unwrap(), aggregated);
+    assert!(matches!(verify_finality(&crypto, &validators(7), 1, b"other", &seal), Err(Error::InvalidBlockHash)));
+    assert!(verify_finality(&crypto, &validators(7), 2, &commit.content, &seal).is_err());
+    assert!(verify_finality(&crypto, &validators(7), 1, &commit.content, &seal[1..]).is_err());
+
+    // dropping a signer breaks the quorum, swapping signers breaks the signatures
+    let mut short = aggregated.clone();
+    short.signatures.truncate(4);
+    assert!(matches!(short.verify(&crypto, &validators(7)), Err(Error::InvalidCertificate(_))));
+    let first = short.signers[0].trailing_zeros();
+    let mut swapped = aggregated.clone();
+    swapped.signers[0] ^= 1 << first;
+    swapped.signers[0] |= 1 << (0..7).find(|i| aggregated.signers[0] & (1 << i) == 0).unwrap();
+    assert!(matches!(swapped.verify(&crypto, &validators(7)), Err(Error::InvalidSignature(_))));
+    assert!(AggregatedCertificate::aggregate(&commit.certificate, &validators(3)).is_err());
+}
+
+#[test]
+fn test_certificate_in_header_digest() {
+    use primitives::Headerly;
+    type ChainHeader = primitives::Header<u64, primitives::BlakeTwo256>;
+    let mut states = new_states(4);
+    let _ = start(&mut states);
+    let mut header = ChainHeader::new(1, [1; 32], [2; 32], [3; 32], primitives::Digest::default());
+    let outputs = states[1].propose(header.encode()).unwrap();
+    let initial = outputs.into_iter().map(|o| (1, o)).collect();
+    let (_, commit) = run(&mut states, initial, 1).pop().unwrap();
+    let aggregated = states[0].aggregate(&commit).unwrap();
+
+    let crypto = TestCrypto(vec![]);
+    assert!(matches!(verify_header(&crypto, &validators(4), &header), Err(Error::InvalidCertificate(_))));
+    seal_header(&mut header, &aggregated);
+    assert_eq!(header.digest.seal(&crate::GBFT_ENGINE_ID), Some(&aggregated.encode()[..]));
+    assert_eq!(verify_header(&crypto, &validators(4), &header).unwrap(), aggregated);
+
+    // the seal covers the rest of the header
+    let mut other = header.clone();
+    other.state_root = [4; 32];
+    assert!(matches!(verify_header(&crypto, &validators(4), &other), Err(Error::InvalidBlockHash)));
+    other.number = 2;
+    assert!(verify_header(&crypto, &validators(4), &other).is_err());
+}
+
+#[test]
+fn test_engine_verifies_sealed_header() {
+    let mut states = new_states(4);
+    let _ = start(&mut states);
+    let mut header = new_header([0; 32], 1, Fields { timestamp: 10, author: vec![1], ..Fields::default() });
+    let outputs = states[1].propose(header.unsealed()).unwrap();
+    let initial = outputs.into_iter().map(|o| (1, o)).collect();
+    let (_, commit) = run(&mut states, initial, 1).pop().unwrap();
+
+    let spec = EngineSpec::new("gbft", &GbftParams { validators: validators(4).validators().to_vec() }).unwrap();
+    let mut engine = GbftEngine::from_spec(TestCrypto(vec![]), &spec).unwrap();
+    assert_eq!(engine.propose(&header).unwrap(), EngineProposal::Pending);
+    assert!(engine.verify_header(&header).is_err());
+    header.set_seal(crate::GBFT_ENGINE_ID, engine.seal(&commit).unwrap());
+    engine.verify_header(&header).unwrap();
+
+    let mut forged = header;
+    forged.set_fields(Fields { timestamp: 11, ..forged.fields().unwrap() });
+    assert!(engine.verify_header(&forged).is_err());
+}
+
+#[test]
+fn test_recover_does_not_double_sign() {
+    let tempdir = TempDir::new("").unwrap();
+    let mut states = new_states(4);
+    let (mut node, _) = State::recover(
+        TestCrypto(vec![2]),
+        vec![2],
+        validators(4),
+        config(),
+        timeout(1),
+        WAL::open(tempdir.path(), false).unwrap(),
+    )
+    .unwrap();
+
+    // the proposer of round 0 is validator 1
+    let outputs = states[1].start();
+    let proposal = outputs
+        .into_iter()
+        .find_map(|o| match o {
+            Output::RequestProposal { .. } => Some(states[1].propose(b"first".to_vec()).unwrap()),
+            _ => None,
+        })
+        .unwrap()
+        .into_iter()
+        .find_map(|o| match o {
+            Output::Broadcast(ConsensusMessage::Proposal(p)) => Some(p),
+            _ => None,
+        })
+        .unwrap();
+    let prepared: Vec<Output> = node.handle_proposal(proposal).unwrap();
+    assert_eq!(node.step(), Step::Prepare);
+    drop(node);
+
+    // crash and restart, the same prepare vote comes out again
+    let (mut node, outputs) = State::recover(
+        TestCrypto(vec![2]),
+        vec![2],
+        validators(4),
+        config(),
+        timeout(1),
+        WAL::open(tempdir.path(), true).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(node.step(), Step::Prepare);
+    assert!(outputs.contains(&prepared[0]));
+    assert!(outputs.contains(&Output::ScheduleTimeout(timeout(1))));
+
+    // a conflicting proposal for the same round gets no vote, only reported
+    let outputs = node.handle_proposal(sign_proposal(0, b"second", 1)).unwrap();
+    assert!(outputs.iter().all(|o| matches!(o, Output::Evidence(_) | Output::Broadcast(ConsensusMessage::Evidence(_)))));
+}
+
+#[test]
+fn test_recover_with_new_round_timeout() {
+    let tempdir = TempDir::new("").unwrap();
+    let (mut node, _) = State::recover(
+        TestCrypto(vec![0]),
+        vec![0],
+        validators(4),
+        config(),
+        timeout(1),
+        WAL::open(tempdir.path(), false).unwrap(),
+    )
+    .unwrap();
+    let _ = node.handle_timeout(timeout(1)).unwrap();
+    assert_eq!(node.step(), Step::ViewChange);
+    drop(node);
+
+    // the timer logged with the old duration still fires on replay
+    let mut config = config();
+    config.set_round_timeout(500, 2);
+    let (node, outputs) = State::recover(
+        TestCrypto(vec![0]),
+        vec![0],
+        validators(4),
+        config,
+        timeout(1),
+        WAL::open(tempdir.path(), false).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(node.step(), Step::ViewChange);
+    assert!(outputs.contains(&Output::ScheduleTimeout(TimeoutStruct { height: 1, round: 1, duration: 1000 })));
+    assert!(outputs.contains(&Output::Broadcast(ConsensusMessage::ViewChange(sign_view_change(1, 1, 0)))));
+}
+
+#[test]
+fn test_recover_across_heights() {
+    let tempdir = TempDir::new("").unwrap();
+    let (mut node, outputs) = State::recover(
+        TestCrypto(vec![0]),
+        vec![0],
+        validators(1),
+        config(),
+        timeout(1),
+        WAL::open(tempdir.path(), false).unwrap(),
+    )
+    .unwrap();
+    assert!(outputs.contains(&Output::RequestProposal { height: 1, round: 0, delay: 0 }));
+    let _ = node.propose(b"one".to_vec()).unwrap();
+    let _ = node.propose(b"two".to_vec()).unwrap();
+    let _ = node.handle_timeout(TimeoutStruct { height: 3, round: 0, duration: 1000 }).unwrap();
+    assert_eq!((node.height(), node.round()), (3, 1));
+    drop(node);
+
+    let (node, mut outputs) = State::recover(
+        TestCrypto(vec![0]),
+        vec![0],
+        validators(1),
+        config(),
+        timeout(1),
+        WAL::open(tempdir.path(), false).unwrap(),
+    )
+    .unwrap();
+    assert_eq!((node.height(), node.round()), (3, 1));
+    outputs.retain(|o| !matches!(o, Output::Broadcast(_)));
+    assert_eq!(
+        outputs,
+        vec![
+            Output::ScheduleTimeout(TimeoutStruct { height: 3, round: 1, duration: 2000 }),
+            Output::RequestProposal { height: 3, round: 1, delay: 0 },
+        ]
+    );
+}
@@ -0,0 +1,225 @@
+//! Weighted validator sets, one per epoch.
+//!
+//! An epoch starts at a fixed height and lasts until the next scheduled set
+//! starts. Quorums are measured in voting power: a quorum holds more than two
+//! thirds of the power of the set of its height.
+
+use std::collections::BTreeMap;
+
+use codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::message::Address;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct Validator {
+    pub address: Address,
+    pub power: u64,
+}
+
+/// Validators of one epoch, from `start_height` on.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct ValidatorSet {
+    epoch: u64,
+    start_height: u64,
+    validators: Vec<Validator>,
+    total_power: u64,
+}
+
+impl ValidatorSet {
+    /// Fails on an empty set, a duplicated address or a validator without power.
+    pub fn new(epoch: u64, start_height: u64, validators: Vec<Validator>) -> Result<Self> {
+        if validators.is_empty() {
+            return Err(Error::InvalidValidatorSet("no validator"));
+        }
+        let mut total_power: u64 = 0;
+        for (i, validator) in validators.iter().enumerate() {
+            if validator.power == 0 {
+                return Err(Error::InvalidValidatorSet("validator without voting power"));
+            }
+            if validators[..i].iter().any(|v| v.address == validator.address) {
+                return Err(Error::InvalidValidatorSet("duplicated validator"));
+            }
+            total_power = total_power
+                .checked_add(validator.power)
+                .ok_or(Error::InvalidValidatorSet("voting power overflow"))?;
+        }
+        Ok(ValidatorSet { epoch, start_height, validators, total_power })
+    }
+
+    /// The validators of epoch 0, with one vote each.
+    pub fn equal(addresses: Vec<Address>) -> Result<Self> {
+        let validators = addresses.into_iter().map(|address| Validator { address, power: 1 }).collect();
+        ValidatorSet::new(0, 0, validators)
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn start_height(&self) -> u64 {
+        self.start_height
+    }
+
+    pub fn validators(&self) -> &[Validator] {
+        &self.validators
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn contains(&self, address: &Address) -> bool {
+        self.power(address).is_some()
+    }
+
+    pub fn power(&self, address: &Address) -> Option<u64> {
+        self.validators.iter().find(|v| &v.address == address).map(|v| v.power)
+    }
+
+    pub fn total_power(&self) -> u64 {
+        self.total_power
+    }
+
+    /// Power of a quorum, more than two thirds of the total.
+    pub fn quorum_power(&self) -> u64 {
+        self.total_power * 2 / 3 + 1
+    }
+
+    /// Power that contains at least one honest validator.
+    pub fn weak_quorum_power(&self) -> u64 {
+        self.total_power - self.quorum_power() + 1
+    }
+
+    /// Sum of the power of `voters`, unknown ones count for nothing.
+    pub fn power_of<'a, I: IntoIterator<Item = &'a Address>>(&self, voters: I) -> u64 {
+        voters.into_iter().filter_map(|voter| self.power(voter)).sum()
+    }
+
+    /// Leader of `round` at `height`: validators take turns in proportion
+    /// to their power.
+    pub fn proposer(&self, height: u64, round: u64) -> &Address {
+        let mut slot = height.wrapping_add(round) % self.total_power;
+        for validator in &self.validators {
+            if slot < validator.power {
+                return &validator.address;
+            }
+            slot -= validator.power;
+        }
+        unreachable!("slot is below the total power; qed")
+    }
+}
+
+/// The validator set of every epoch still needed, by start height.
+#[derive(Clone, Debug)]
+pub struct Epochs {
+    sets: BTreeMap<u64, ValidatorSet>,
+}
+
+impl Epochs {
+    pub fn new(genesis: ValidatorSet) -> Self {
+        let mut sets = BTreeMap::new();
+        sets.insert(genesis.start_height, genesis);
+        Epochs { sets }
+    }
+
+    /// Set in charge of `height`, the first one covers everything before it.
+    pub fn at(&self, height: u64) -> &ValidatorSet {
+        self.sets
+            .range(..=height)
+            .next_back()
+            .or_else(|| self.sets.iter().next())
+            .map(|(_, set)| set)
+            .expect("there is always a validator set; qed")
+    }
+
+    pub fn latest(&self) -> &ValidatorSet {
+        self.sets.values().next_back().expect("there is always a validator set; qed")
+    }
+
+    /// Add the set of a later epoch, which must start after the latest one.
+    /// Epochs without a change are skipped. Scheduling the same set again is
+    /// a no-op.
+    pub fn schedule(&mut self, set: ValidatorSet) -> Result<()> {
+        let latest = self.latest();
+        if latest == &set {
+            return Ok(());
+        }
+        if set.epoch <= latest.epoch || set.start_height <= latest.start_height {
+            return Err(Error::InvalidValidatorSet("does not follow the latest epoch"));
+        }
+        self.sets.insert(set.start_height, set);
+        Ok(())
+    }
+
+    /// Forget the sets ended before `height`.
+    pub fn prune(&mut self, height: u64) {
+        let keep = self.at(height).start_height;
+        self.sets = self.sets.split_off(&keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(epoch: u64, start_height: u64, powers: &[u64]) -> ValidatorSet {
+        let validators = powers
+            .iter()
+            .enumerate()
+            .map(|(i, power)| Validator { address: vec![i as u8], power: *power })
+            .collect();
+        ValidatorSet::new(epoch, start_height, validators).unwrap()
+    }
+
+    #[test]
+    fn test_weighted_quorum() {
+        let set = set(0, 0, &[1, 1, 1, 3]);
+        assert_eq!(set.total_power(), 6);
+        assert_eq!(set.quorum_power(), 5);
+        assert_eq!(set.weak_quorum_power(), 2);
+        assert_eq!(set.power_of(&[vec![3], vec![0], vec![9]]), 4);
+        assert_eq!(set.power_of(&[vec![3], vec![0], vec![1]]), 5);
+    }
+
+    #[test]
+    fn test_weighted_proposer() {
+        let set = set(0, 0, &[1, 2]);
+        let proposers: Vec<&Address> = (0..6).map(|h| set.proposer(h, 0)).collect();
+        assert_eq!(proposers, vec![&vec![0], &vec![1], &vec![1], &vec![0], &vec![1], &vec![1]]);
+        assert_eq!(set.proposer(1, 2), set.proposer(3, 0));
+    }
+
+    #[test]
+    fn test_invalid_sets() {
+        assert!(ValidatorSet::new(0, 0, vec![]).is_err());
+        let zero = vec![Validator { address: vec![0], power: 0 }];
+        assert!(ValidatorSet::new(0, 0, zero).is_err());
+        let duplicated = vec![Validator { address: vec![0], power: 1 }, Validator { address: vec![0], power: 2 }];
+        assert!(ValidatorSet::new(0, 0, duplicated).is_err());
+    }
+
+    #[test]
+    fn test_epochs() {
+        let mut epochs = Epochs::new(set(0, 1, &[1, 1, 1, 1]));
+        assert!(epochs.schedule(set(0, 11, &[1])).is_err());
+        assert!(epochs.schedule(set(1, 1, &[1])).is_err());
+        epochs.schedule(set(1, 11, &[1, 1])).unwrap();
+        epochs.schedule(set(1, 11, &[1, 1])).unwrap();
+        epochs.schedule(set(3, 21, &[1, 1, 1])).unwrap();
+
+        assert_eq!(epochs.at(0).epoch(), 0);
+        assert_eq!(epochs.at(10).epoch(), 0);
+        assert_eq!(epochs.at(11).epoch(), 1);
+        assert_eq!(epochs.at(100).epoch(), 3);
+
+        epochs.prune(15);
+        assert_eq!(epochs.at(10).epoch(), 1);
+        assert_eq!(epochs.at(21).len(), 3);
+    }
+}
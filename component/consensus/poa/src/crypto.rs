@@ -0,0 +1,16 @@
+use consensus_engine::Result;
+
+/// Address of an authority.
+pub type Address = Vec<u8>;
+
+/// Hashing and signing primitives the PoA engine is generic over.
+pub trait Crypto {
+    /// Hash the unsealed header, the authority signs this hash.
+    fn hash(&self, msg: &[u8]) -> Vec<u8>;
+
+    /// Sign a hash with the local authority key, failing without one.
+    fn sign(&self, hash: &[u8]) -> Result<Vec<u8>>;
+
+    /// Check that `signature` over `hash` was produced by `signer`.
+    fn verify(&self, signature: &[u8], hash: &[u8], signer: &Address) -> bool;
+}
@@ -0,0 +1,498 @@
+//! Clique style proof of authority.
+//!
+//! Signers take turns: the in-turn signer of block `n`, `signers[n % len]`
+//! in address order, seals it with difficulty 2; any other signer that did
+//! not seal one of the last `len / 2` blocks may seal it with difficulty 1,
+//! so the chain goes on when a signer is offline. The seal is the signature
+//! of `ConsensusHeader::unsealed` by the author, at least `period` seconds
+//! after the parent. Signers change by vote, see `snapshot`.
+//!
+//! Snapshots are kept in memory for the recent blocks and, with a
+//! `SnapshotStore`, persisted every `interval` blocks. After a restart or
+//! on a deep reorg only the headers since the last persisted snapshot need
+//! to be imported again. Once a block is final, the persisted snapshots
+//! before the last checkpoint up to it are removed.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use codec::Encode;
+use consensus_engine::{
+    check_parent, ConsensusEngineId, ConsensusHeader, Engine, EngineSpec, Error, Finality, Header, Proposal, Result,
+};
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{Address, Crypto};
+use crate::snapshot::{Ballot, Extra, Snapshot};
+use crate::store::SnapshotStore;
+
+/// Name of the engine in the chain spec.
+pub const NAME: &str = "poa";
+
+/// Id of the PoA seal in the header digest.
+pub const POA_ENGINE_ID: ConsensusEngineId = *b"poa_";
+
+/// Difficulty of a block sealed by its in-turn signer.
+pub const DIFF_IN_TURN: u128 = 2;
+/// Difficulty of a block sealed by another signer.
+pub const DIFF_NO_TURN: u128 = 1;
+
+/// Number of snapshots kept in memory.
+const SNAPSHOT_CACHE: usize = 1024;
+
+/// Blocks between persisted snapshots.
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1024;
+
+fn default_epoch() -> u64 {
+    30_000
+}
+
+/// Chain spec parameters of PoA.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PoaParams {
+    /// signers of the genesis
+    pub signers: Vec<Address>,
+    /// minimum time between blocks, in seconds
+    pub period: u64,
+    /// blocks between checkpoints, votes are reset at each of them
+    #[serde(default = "default_epoch")]
+    pub epoch: u64,
+}
+
+#[derive(Debug)]
+pub struct PoaEngine<C> {
+    crypto: C,
+    /// local signer, `None` on nodes that only import
+    address: Option<Address>,
+    params: PoaParams,
+    /// snapshots by block hash
+    snapshots: BTreeMap<Vec<u8>, Snapshot>,
+    /// votes the local signer casts, one per block it seals
+    ballots: BTreeMap<Address, bool>,
+    store: Option<SnapshotStore>,
+    interval: u64, // blocks between persisted snapshots
+}
+
+impl<C: Crypto> PoaEngine<C> {
+    pub fn new(crypto: C, address: Option<Address>, params: PoaParams) -> Result<Self> {
+        if params.signers.is_empty() {
+            return Err(Error::InvalidParams("no signer".to_owned()));
+        }
+        if params.epoch == 0 {
+            return Err(Error::InvalidParams("epoch is zero".to_owned()));
+        }
+        Ok(PoaEngine {
+            crypto,
+            address,
+            params,
+            snapshots: BTreeMap::new(),
+            ballots: BTreeMap::new(),
+            store: None,
+            interval: DEFAULT_SNAPSHOT_INTERVAL,
+        })
+    }
+
+    /// Persist the snapshots of the blocks whose number is a multiple of
+    /// `interval`, and of the checkpoints, in `store`.
+    pub fn with_store(mut self, store: SnapshotStore, interval: u64) -> Self {
+        self.store = Some(store);
+        self.interval = interval.max(1);
+        self
+    }
+
+    pub fn from_spec(crypto: C, address: Option<Address>, spec: &EngineSpec) -> Result<Self> {
+        PoaEngine::new(crypto, address, spec.params(NAME)?)
+    }
+
+    pub fn params(&self) -> &PoaParams {
+        &self.params
+    }
+
+    /// Hash identifying `header` as a parent, its `parent_hash` in the child.
+    pub fn block_hash(&self, header: &Header) -> Vec<u8> {
+        header.hash().to_vec()
+    }
+
+    /// Signers after `header`, which must be the genesis or imported.
+    pub fn snapshot(&self, header: &Header) -> Result<Snapshot> {
+        let hash = self.block_hash(header);
+        match self.lookup(&hash) {
+            Some(snapshot) => Ok(snapshot),
+            None if header.number == 0 => Ok(self.genesis(hash)),
+            None => Err(Error::UnknownParent(header.number + 1)),
+        }
+    }
+
+    /// Persisted snapshots, sorted by block number, empty without a store.
+    pub fn snapshot_history(&self) -> io::Result<Vec<Snapshot>> {
+        match self.store {
+            Some(ref store) => store.history(),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Signers before `header`, whose parent must be the genesis or imported.
+    fn parent_snapshot(&self, header: &Header) -> Result<Snapshot> {
+        match self.lookup(&header.parent_hash) {
+            Some(snapshot) => Ok(snapshot),
+            None if header.number == 1 => Ok(self.genesis(header.parent_hash.to_vec())),
+            None => Err(Error::UnknownParent(header.number)),
+        }
+    }
+
+    fn genesis(&self, hash: Vec<u8>) -> Snapshot {
+        Snapshot::new(0, hash, self.params.signers.iter().cloned().collect())
+    }
+
+    /// Snapshot at block `hash`, from memory or else from the store.
+    fn lookup(&self, hash: &[u8]) -> Option<Snapshot> {
+        if let Some(snapshot) = self.snapshots.get(hash) {
+            return Some(snapshot.clone());
+        }
+        let store = self.store.as_ref()?;
+        store.get(hash).unwrap_or_else(|e| {
+            warn!("cannot load snapshot {:?} from {:?}: {}", hash, store.path(), e);
+            None
+        })
+    }
+
+    /// Vote to add or remove `candidate` in the blocks we seal, until it is done.
+    pub fn propose_ballot(&mut self, candidate: Address, authorize: bool) {
+        self.ballots.insert(candidate, authorize);
+    }
+
+    pub fn discard_ballot(&mut self, candidate: &Address) {
+        self.ballots.remove(candidate);
+    }
+
+    fn remember(&mut self, snapshot: Snapshot) {
+        if self.snapshots.len() >= SNAPSHOT_CACHE {
+            let oldest = self.snapshots.iter().min_by_key(|(_, s)| s.number).map(|(hash, _)| hash.clone());
+            if let Some(hash) = oldest {
+                self.snapshots.remove(&hash);
+            }
+        }
+        self.snapshots.insert(snapshot.hash.clone(), snapshot);
+    }
+}
+
+impl<C: Crypto> Engine for PoaEngine<C> {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn id(&self) -> ConsensusEngineId {
+        POA_ENGINE_ID
+    }
+
+    fn finality(&self) -> Finality {
+        Finality::Probabilistic { confirmations: self.params.signers.len() as u64 / 2 + 1 }
+    }
+
+    fn prepare(&mut self, header: &mut Header, parent: &Header) -> Result<()> {
+        let snapshot = self.snapshot(parent)?;
+        let address = self.address.clone().unwrap_or_default();
+        let mut fields = header.fields()?;
+        header.parent_hash = parent.hash();
+        fields.author = address.clone();
+        fields.difficulty = if snapshot.in_turn(header.number, &address) { DIFF_IN_TURN } else { DIFF_NO_TURN };
+        fields.timestamp = fields.timestamp.max(parent.fields()?.timestamp + self.params.period);
+
+        let mut extra = Extra::default();
+        if header.number % self.params.epoch == 0 {
+            extra.checkpoint = snapshot.signers.iter().cloned().collect();
+        } else {
+            // drop the votes already decided, then cast the first one left
+            self.ballots.retain(|candidate, authorize| {
+                snapshot.valid_ballot(&Ballot { candidate: candidate.clone(), authorize: *authorize })
+            });
+            extra.ballot = self
+                .ballots
+                .iter()
+                .map(|(candidate, authorize)| Ballot { candidate: candidate.clone(), authorize: *authorize })
+                .find(|ballot| !snapshot.votes.iter().any(|v| v.signer == address && &v.ballot == ballot));
+        }
+        fields.extra = extra.encode();
+        header.set_fields(fields);
+        Ok(())
+    }
+
+    fn propose(&mut self, header: &Header) -> Result<Proposal> {
+        let fields = header.fields()?;
+        let address = match self.address {
+            Some(ref address) if address == &fields.author => address,
+            _ => return Ok(Proposal::NotAuthor),
+        };
+        let snapshot = self.parent_snapshot(header)?;
+        if !snapshot.is_signer(address) || snapshot.recently_signed(header.number, address) {
+            debug!("not allowed to seal block {}", header.number);
+            return Ok(Proposal::NotAuthor);
+        }
+        trace!("seal block {}, in turn: {}", header.number, fields.difficulty == DIFF_IN_TURN);
+        let hash = self.crypto.hash(&header.unsealed());
+        Ok(Proposal::Sealed(self.crypto.sign(&hash)?))
+    }
+
+    fn verify_header(&self, header: &Header) -> Result<()> {
+        let seal = header.seal(&POA_ENGINE_ID).ok_or(Error::MissingSeal(header.number))?;
+        let fields = header.fields()?;
+        if fields.difficulty != DIFF_IN_TURN && fields.difficulty != DIFF_NO_TURN {
+            return Err(Error::InvalidDifficulty { expected: DIFF_NO_TURN, got: fields.difficulty });
+        }
+        let extra = Extra::of(header)?;
+        let checkpoint = header.number % self.params.epoch == 0;
+        if checkpoint && extra.ballot.is_some() {
+            return Err(Error::InvalidExtra("vote in a checkpoint".to_owned()));
+        }
+        if !checkpoint && !extra.checkpoint.is_empty() {
+            return Err(Error::InvalidExtra("signer list out of a checkpoint".to_owned()));
+        }
+        let hash = self.crypto.hash(&header.unsealed());
+        if !self.crypto.verify(seal, &hash, &fields.author) {
+            return Err(Error::InvalidSeal("signature does not match the author".to_owned()));
+        }
+        Ok(())
+    }
+
+    fn verify_import(&self, header: &Header, parent: &Header) -> Result<()> {
+        check_parent(header, parent)?;
+        let (fields, parent_timestamp) = (header.fields()?, parent.fields()?.timestamp);
+        if fields.timestamp < parent_timestamp + self.params.period {
+            return Err(Error::InvalidTimestamp { timestamp: fields.timestamp, parent: parent_timestamp });
+        }
+        let snapshot = self.snapshot(parent)?;
+        let expected = if snapshot.in_turn(header.number, &fields.author) { DIFF_IN_TURN } else { DIFF_NO_TURN };
+        if fields.difficulty != expected {
+            return Err(Error::InvalidDifficulty { expected, got: fields.difficulty });
+        }
+        if header.number % self.params.epoch == 0 {
+            let signers = Extra::of(header)?.checkpoint;
+            if signers.iter().collect::<Vec<_>>() != snapshot.signers() {
+                return Err(Error::InvalidExtra("checkpoint signers do not match".to_owned()));
+            }
+        }
+        snapshot.apply(header, self.block_hash(header), self.params.epoch).map(|_| ())
+    }
+
+    fn imported(&mut self, header: &Header) -> Result<()> {
+        let parent = self.parent_snapshot(header)?;
+        let snapshot = parent.apply(header, self.block_hash(header), self.params.epoch)?;
+        if snapshot.signers != parent.signers {
+            debug!("signers at block {}: {:?}", header.number, snapshot.signers);
+        }
+        if let Some(ref store) = self.store {
+            let number = snapshot.number;
+            if number % self.interval == 0 || number % self.params.epoch == 0 {
+                // a lost snapshot only means more headers to import again after a restart
+                if let Err(e) = store.insert(&snapshot) {
+                    warn!("cannot persist snapshot of block {} in {:?}: {}", number, store.path(), e);
+                }
+            }
+        }
+        self.remember(snapshot);
+        Ok(())
+    }
+
+    fn finalized(&mut self, header: &Header) {
+        // the last checkpoint up to `header` is kept, and the snapshots after
+        // it for the blocks that may still be reorganized
+        let checkpoint = header.number - header.number % self.params.epoch;
+        if let Some(ref store) = self.store {
+            if let Err(e) = store.prune(checkpoint) {
+                warn!("cannot prune snapshots before block {} in {:?}: {}", checkpoint, store.path(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus_engine::{new_header, Fields};
+    use tempdir::TempDir;
+
+    /// Signatures are the signer address followed by the hash.
+    struct TestCrypto(Address);
+
+    impl Crypto for TestCrypto {
+        fn hash(&self, msg: &[u8]) -> Vec<u8> {
+            msg.iter().fold(0u64, |h, b| h.wrapping_mul(31).wrapping_add(*b as u64)).to_be_bytes().to_vec()
+        }
+
+        fn sign(&self, hash: &[u8]) -> Result<Vec<u8>> {
+            Ok([&self.0[..], hash].concat())
+        }
+
+        fn verify(&self, signature: &[u8], hash: &[u8], signer: &Address) -> bool {
+            signature == &[&signer[..], hash].concat()[..]
+        }
+    }
+
+    fn params(signers: &[u8]) -> PoaParams {
+        PoaParams { signers: signers.iter().map(|s| vec![*s]).collect(), period: 5, epoch: 6 }
+    }
+
+    /// One engine per signer, `n` of them, plus a genesis header.
+    fn network(n: u8) -> (Vec<PoaEngine<TestCrypto>>, Header) {
+        let engines = (0..n)
+            .map(|i| PoaEngine::new(TestCrypto(vec![i]), Some(vec![i]), params(&(0..n).collect::<Vec<_>>())).unwrap())
+            .collect();
+        (engines, new_header([0; 32], 0, Fields { timestamp: 100, ..Fields::default() }))
+    }
+
+    /// `header` with its fields changed by `change`, the seal kept.
+    fn altered(header: &Header, change: impl FnOnce(&mut Fields)) -> Header {
+        let mut fields = header.fields().unwrap();
+        change(&mut fields);
+        let mut altered = header.clone();
+        altered.set_fields(fields);
+        altered
+    }
+
+    /// `signer` seals a child of `parent`, imported by every engine.
+    fn seal(engines: &mut [PoaEngine<TestCrypto>], signer: usize, parent: &Header) -> Result<Header> {
+        let mut header = new_header([0; 32], parent.number + 1, Fields::default());
+        engines[signer].prepare(&mut header, parent)?;
+        match engines[signer].propose(&header)? {
+            Proposal::Sealed(seal) => header.set_seal(POA_ENGINE_ID, seal),
+            other => return Err(Error::InvalidSeal(format!("{:?}", other))),
+        }
+        for engine in engines.iter_mut() {
+            engine.verify_header(&header)?;
+            engine.verify_import(&header, parent)?;
+            engine.imported(&header)?;
+        }
+        Ok(header)
+    }
+
+    #[test]
+    fn test_in_turn_difficulty() {
+        let (mut engines, genesis) = network(3);
+        let first = seal(&mut engines, 1, &genesis).unwrap();
+        let fields = first.fields().unwrap();
+        assert_eq!((fields.difficulty, fields.timestamp), (DIFF_IN_TURN, 105));
+        let second = seal(&mut engines, 0, &first).unwrap();
+        assert_eq!(second.fields().unwrap().difficulty, DIFF_NO_TURN);
+
+        // 0 sealed block 2, it must wait for another signer
+        let mut third = new_header([0; 32], 3, Fields::default());
+        engines[0].prepare(&mut third, &second).unwrap();
+        assert_eq!(engines[0].propose(&third).unwrap(), Proposal::NotAuthor);
+        seal(&mut engines, 2, &second).unwrap();
+    }
+
+    #[test]
+    fn test_forged_headers_are_rejected() {
+        let (mut engines, genesis) = network(3);
+        let mut header = new_header([0; 32], 1, Fields::default());
+        engines[1].prepare(&mut header, &genesis).unwrap();
+        match engines[1].propose(&header).unwrap() {
+            Proposal::Sealed(seal) => header.set_seal(POA_ENGINE_ID, seal),
+            other => panic!("unexpected {:?}", other),
+        }
+        let verifier = &engines[2];
+        verifier.verify_header(&header).unwrap();
+        verifier.verify_import(&header, &genesis).unwrap();
+
+        let wrong_turn = altered(&header, |fields| fields.difficulty = DIFF_NO_TURN);
+        assert!(matches!(verifier.verify_import(&wrong_turn, &genesis), Err(Error::InvalidDifficulty { .. })));
+        let early = altered(&header, |fields| fields.timestamp = 104);
+        assert!(verifier.verify_import(&early, &genesis).is_err());
+        let stranger = altered(&header, |fields| fields.author = vec![7]);
+        assert!(verifier.verify_header(&stranger).is_err());
+        let orphan = Header { parent_hash: [1; 32], ..header.clone() };
+        assert!(matches!(verifier.verify_import(&orphan, &genesis), Err(Error::UnknownParent(1))));
+        let mut unsealed = header.clone();
+        unsealed.digest.pop();
+        assert!(matches!(verifier.verify_header(&unsealed), Err(Error::MissingSeal(1))));
+        let mut unknown = altered(&header, |fields| fields.timestamp = 200);
+        unknown.number = 2;
+        assert!(matches!(verifier.verify_import(&unknown, &unknown.clone()), Err(Error::InvalidNumber { .. })));
+    }
+
+    #[test]
+    fn test_vote_in_signer() {
+        let (mut engines, genesis) = network(3);
+        engines[0].propose_ballot(vec![9], true);
+        engines[1].propose_ballot(vec![9], true);
+
+        let first = seal(&mut engines, 1, &genesis).unwrap();
+        assert_eq!(Extra::of(&first).unwrap().ballot, Some(Ballot { candidate: vec![9], authorize: true }));
+        let second = seal(&mut engines, 0, &first).unwrap();
+        let snapshot = engines[2].snapshot(&second).unwrap();
+        assert!(snapshot.is_signer(&vec![9]));
+
+        // the vote passed, the ballot is dropped
+        let third = seal(&mut engines, 2, &second).unwrap();
+        let mut fourth = new_header([0; 32], 4, Fields::default());
+        engines[1].prepare(&mut fourth, &third).unwrap();
+        assert_eq!(Extra::of(&fourth).unwrap().ballot, None);
+    }
+
+    #[test]
+    fn test_checkpoint_lists_signers() {
+        let (mut engines, genesis) = network(2);
+        engines[0].propose_ballot(vec![9], true);
+        let mut parent = genesis;
+        for number in 1..7 {
+            parent = seal(&mut engines, (number % 2) as usize, &parent).unwrap();
+        }
+        let extra = Extra::of(&parent).unwrap();
+        assert_eq!(parent.number, 6);
+        assert_eq!(extra.checkpoint, vec![vec![0], vec![1]]);
+        assert!(extra.ballot.is_none());
+        assert!(engines[1].snapshot(&parent).unwrap().votes.is_empty());
+    }
+
+    #[test]
+    fn test_persisted_snapshots() {
+        let dir = TempDir::new("poa_snapshots").unwrap();
+        let signers = params(&[0, 1, 2]);
+        let stored = |address: u8| {
+            let store = SnapshotStore::open(dir.path()).unwrap();
+            PoaEngine::new(TestCrypto(vec![address]), Some(vec![address]), signers.clone()).unwrap().with_store(store, 4)
+        };
+        let (mut engines, genesis) = network(3);
+        engines[2] = stored(2);
+        let mut chain = vec![genesis];
+        for number in 1..10 {
+            let header = seal(&mut engines, number % 3, chain.last().unwrap()).unwrap();
+            chain.push(header);
+        }
+        // every 4 blocks and the checkpoint
+        let numbers: Vec<u64> = engines[2].snapshot_history().unwrap().iter().map(|s| s.number).collect();
+        assert_eq!(numbers, vec![4, 6, 8]);
+
+        // a restarted node only imports the headers after block 8
+        let mut restarted = stored(2);
+        assert!(restarted.snapshot(&chain[7]).is_err());
+        assert_eq!(restarted.snapshot(&chain[8]).unwrap(), engines[2].snapshot(&chain[8]).unwrap());
+        restarted.verify_import(&chain[9], &chain[8]).unwrap();
+        restarted.imported(&chain[9]).unwrap();
+        assert_eq!(restarted.snapshot(&chain[9]).unwrap(), engines[0].snapshot(&chain[9]).unwrap());
+        let mut fresh = PoaEngine::new(TestCrypto(vec![0]), None, signers.clone()).unwrap();
+        assert!(matches!(fresh.imported(&chain[9]), Err(Error::UnknownParent(9))));
+
+        // finality prunes up to the last checkpoint, which a restart still finds
+        restarted.finalized(&chain[5]);
+        assert_eq!(restarted.snapshot_history().unwrap().len(), 3);
+        restarted.finalized(&chain[9]);
+        let numbers: Vec<u64> = restarted.snapshot_history().unwrap().iter().map(|s| s.number).collect();
+        assert_eq!(numbers, vec![6, 8]);
+        let restarted = stored(2);
+        assert_eq!(restarted.snapshot(&chain[6]).unwrap(), engines[0].snapshot(&chain[6]).unwrap());
+    }
+
+    #[test]
+    fn test_from_spec() {
+        let spec: EngineSpec =
+            serde_json::from_str(r#"{ "engine": "poa", "params": { "signers": [[0], [1]], "period": 3 } }"#).unwrap();
+        let engine = PoaEngine::from_spec(TestCrypto(vec![0]), None, &spec).unwrap();
+        assert_eq!(engine.params().epoch, 30_000);
+        let genesis = new_header([0; 32], 0, Fields::default());
+        assert!(engine.snapshot(&genesis).unwrap().in_turn(3, &vec![1]));
+        let empty = EngineSpec::new(NAME, &PoaParams { signers: vec![], period: 3, epoch: 10 }).unwrap();
+        assert!(PoaEngine::from_spec(TestCrypto(vec![0]), None, &empty).is_err());
+    }
+}
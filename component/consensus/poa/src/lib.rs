@@ -1,7 +1,9 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+mod crypto;
+mod engine;
+mod snapshot;
+mod store;
+
+pub use crypto::{Address, Crypto};
+pub use engine::{PoaEngine, PoaParams, DEFAULT_SNAPSHOT_INTERVAL, DIFF_IN_TURN, DIFF_NO_TURN, NAME, POA_ENGINE_ID};
+pub use snapshot::{Ballot, Extra, Snapshot, Tally, Vote};
+pub use store::SnapshotStore;
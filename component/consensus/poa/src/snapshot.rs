@@ -0,0 +1,283 @@
+//! Authorized signers at a given block, and the votes to change them.
+//!
+//! Signers vote through the `Extra` of the headers they seal: a candidate
+//! is added or removed once more than half of the signers voted for it. Votes
+//! are forgotten at every checkpoint, the first block of an epoch, whose
+//! extra lists the signers instead.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use codec::{Decode, Encode};
+use consensus_engine::{ConsensusHeader, Error, Header, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Address;
+
+/// Vote of the signer of a header.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct Ballot {
+    pub candidate: Address,
+    /// add the candidate if true, remove it otherwise
+    pub authorize: bool,
+}
+
+/// The PoA content of `Fields::extra`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct Extra {
+    pub ballot: Option<Ballot>,
+    /// signers of the epoch, on checkpoints only
+    pub checkpoint: Vec<Address>,
+}
+
+impl Extra {
+    /// Decode the extra of `header`, empty meaning no vote nor checkpoint.
+    pub fn of(header: &Header) -> Result<Self> {
+        let extra = header.fields()?.extra;
+        if extra.is_empty() {
+            return Ok(Extra::default());
+        }
+        Extra::decode(&mut &extra[..]).map_err(|e| Error::InvalidExtra(e.to_string()))
+    }
+}
+
+/// A ballot cast in the current epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct Vote {
+    pub signer: Address,
+    pub number: u64,
+    pub ballot: Ballot,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct Tally {
+    pub authorize: bool,
+    pub votes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct Snapshot {
+    /// block the snapshot was taken at
+    pub number: u64,
+    pub hash: Vec<u8>,
+    pub signers: BTreeSet<Address>,
+    /// signer of each recent block, a signer seals at most one block out of `limit`
+    pub recents: BTreeMap<u64, Address>,
+    pub votes: Vec<Vote>,
+    pub tally: BTreeMap<Address, Tally>,
+}
+
+impl Snapshot {
+    /// Snapshot at a checkpoint, with no vote running.
+    pub fn new(number: u64, hash: Vec<u8>, signers: BTreeSet<Address>) -> Self {
+        Snapshot {
+            number,
+            hash,
+            signers,
+            recents: BTreeMap::new(),
+            votes: vec![],
+            tally: BTreeMap::new(),
+        }
+    }
+
+    /// Signers in turn order.
+    pub fn signers(&self) -> Vec<&Address> {
+        self.signers.iter().collect()
+    }
+
+    pub fn is_signer(&self, address: &Address) -> bool {
+        self.signers.contains(address)
+    }
+
+    /// Whether `signer` is expected to seal block `number`.
+    pub fn in_turn(&self, number: u64, signer: &Address) -> bool {
+        let index = (number % self.signers.len() as u64) as usize;
+        self.signers.iter().nth(index) == Some(signer)
+    }
+
+    /// Number of consecutive blocks among which a signer seals at most one.
+    pub fn limit(&self) -> u64 {
+        self.signers.len() as u64 / 2 + 1
+    }
+
+    /// Whether `signer` sealed one of the blocks preventing it from sealing `number`.
+    pub fn recently_signed(&self, number: u64, signer: &Address) -> bool {
+        let since = (number + 1).saturating_sub(self.limit());
+        self.recents.range(since..).any(|(_, recent)| recent == signer)
+    }
+
+    /// Whether `ballot` would change the signers.
+    pub fn valid_ballot(&self, ballot: &Ballot) -> bool {
+        self.signers.contains(&ballot.candidate) != ballot.authorize
+    }
+
+    /// Snapshot after the child `header`, of hash `hash`, whose author is
+    /// assumed to have signed it. `epoch` is the checkpoint interval.
+    pub fn apply(&self, header: &Header, hash: Vec<u8>, epoch: u64) -> Result<Snapshot> {
+        if header.number != self.number + 1 {
+            return Err(Error::InvalidNumber { number: header.number, parent: self.number });
+        }
+        let signer = &header.fields()?.author;
+        if !self.is_signer(signer) {
+            return Err(Error::UnauthorizedAuthor(signer.clone()));
+        }
+        if self.recently_signed(header.number, signer) {
+            return Err(Error::UnauthorizedAuthor(signer.clone()));
+        }
+
+        let mut snapshot = self.clone();
+        snapshot.number = header.number;
+        snapshot.hash = hash;
+        let limit = snapshot.limit();
+        if header.number >= limit {
+            snapshot.recents.remove(&(header.number - limit));
+        }
+        snapshot.recents.insert(header.number, signer.clone());
+
+        if header.number % epoch == 0 {
+            snapshot.votes.clear();
+            snapshot.tally.clear();
+        }
+        if let Some(ballot) = Extra::of(header)?.ballot {
+            snapshot.cast(signer, header.number, ballot);
+        }
+        Ok(snapshot)
+    }
+
+    /// Replace the previous vote of `signer` for the candidate, then apply
+    /// the outcome once more than half of the signers agree.
+    fn cast(&mut self, signer: &Address, number: u64, ballot: Ballot) {
+        if let Some(index) =
+            self.votes.iter().position(|v| &v.signer == signer && v.ballot.candidate == ballot.candidate)
+        {
+            let previous = self.votes.remove(index);
+            self.uncount(&previous.ballot);
+        }
+        if !self.valid_ballot(&ballot) {
+            return;
+        }
+
+        let tally = self
+            .tally
+            .entry(ballot.candidate.clone())
+            .or_insert(Tally { authorize: ballot.authorize, votes: 0 });
+        if tally.authorize != ballot.authorize {
+            return;
+        }
+        tally.votes += 1;
+        let passed = tally.votes > self.signers.len() as u64 / 2;
+        self.votes.push(Vote { signer: signer.clone(), number, ballot: ballot.clone() });
+        if !passed {
+            return;
+        }
+
+        let candidate = ballot.candidate;
+        if ballot.authorize {
+            self.signers.insert(candidate.clone());
+        } else {
+            self.signers.remove(&candidate);
+            // the shorter limit frees the oldest recent signer
+            let limit = self.limit();
+            if number >= limit {
+                self.recents.remove(&(number - limit));
+            }
+            let cast: Vec<Vote> = self.votes.iter().filter(|v| v.signer == candidate).cloned().collect();
+            for vote in cast {
+                self.uncount(&vote.ballot);
+            }
+            self.votes.retain(|v| v.signer != candidate);
+        }
+        self.votes.retain(|v| v.ballot.candidate != candidate);
+        self.tally.remove(&candidate);
+    }
+
+    fn uncount(&mut self, ballot: &Ballot) {
+        if let Some(tally) = self.tally.get_mut(&ballot.candidate) {
+            if tally.authorize == ballot.authorize {
+                tally.votes -= 1;
+                if tally.votes == 0 {
+                    self.tally.remove(&ballot.candidate);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codec::Encode;
+    use consensus_engine::{new_header, Fields};
+
+    use super::*;
+
+    fn genesis(signers: &[u8]) -> Snapshot {
+        Snapshot::new(0, vec![0], signers.iter().map(|s| vec![*s]).collect())
+    }
+
+    fn header(number: u64, signer: u8, ballot: Option<(u8, bool)>) -> Header {
+        let extra = Extra {
+            ballot: ballot.map(|(candidate, authorize)| Ballot { candidate: vec![candidate], authorize }),
+            checkpoint: vec![],
+        };
+        new_header([0; 32], number, Fields { author: vec![signer], extra: extra.encode(), ..Fields::default() })
+    }
+
+    fn apply(snapshot: &Snapshot, number: u64, signer: u8, ballot: Option<(u8, bool)>) -> Snapshot {
+        snapshot.apply(&header(number, signer, ballot), vec![number as u8], 1000).unwrap()
+    }
+
+    #[test]
+    fn test_turns() {
+        let snapshot = genesis(&[2, 1, 3]);
+        assert_eq!(snapshot.signers(), vec![&vec![1], &vec![2], &vec![3]]);
+        assert!(snapshot.in_turn(4, &vec![2]));
+        assert_eq!(snapshot.limit(), 2);
+
+        let snapshot = apply(&snapshot, 1, 1, None);
+        assert!(snapshot.recently_signed(2, &vec![1]));
+        assert!(!snapshot.recently_signed(3, &vec![1]));
+        assert!(snapshot.apply(&header(2, 1, None), vec![2], 1000).is_err());
+        assert!(snapshot.apply(&header(2, 4, None), vec![2], 1000).is_err());
+        let snapshot = apply(&snapshot, 2, 2, None);
+        let snapshot = apply(&snapshot, 3, 1, None);
+        assert_eq!(snapshot.recents.len(), 2);
+    }
+
+    #[test]
+    fn test_authorize() {
+        let snapshot = genesis(&[1, 2]);
+        let snapshot = apply(&snapshot, 1, 1, Some((3, true)));
+        assert!(!snapshot.is_signer(&vec![3]));
+        assert_eq!(snapshot.tally[&vec![3]].votes, 1);
+        let snapshot = apply(&snapshot, 2, 2, Some((3, true)));
+        assert!(snapshot.is_signer(&vec![3]));
+        assert!(snapshot.votes.is_empty() && snapshot.tally.is_empty());
+    }
+
+    #[test]
+    fn test_deauthorize_discards_votes() {
+        let snapshot = genesis(&[1, 2, 3]);
+        // 3 votes to add 4, then 1 and 2 remove 3
+        let snapshot = apply(&snapshot, 1, 3, Some((4, true)));
+        let snapshot = apply(&snapshot, 2, 1, Some((3, false)));
+        let snapshot = apply(&snapshot, 3, 2, Some((3, false)));
+        assert_eq!(snapshot.signers(), vec![&vec![1], &vec![2]]);
+        assert!(snapshot.votes.is_empty() && snapshot.tally.is_empty());
+    }
+
+    #[test]
+    fn test_revote_and_invalid_ballots() {
+        let snapshot = genesis(&[1, 2, 3]);
+        let snapshot = apply(&snapshot, 1, 1, Some((4, true)));
+        let snapshot = apply(&snapshot, 2, 2, None);
+        // voting again replaces the previous vote
+        let snapshot = apply(&snapshot, 3, 1, Some((4, true)));
+        assert_eq!(snapshot.tally[&vec![4]].votes, 1);
+        // removing a non signer or adding a signer is ignored
+        let snapshot = apply(&snapshot, 4, 2, Some((5, false)));
+        let snapshot = apply(&snapshot, 5, 3, Some((1, true)));
+        assert_eq!(snapshot.votes.len(), 1);
+        // checkpoints reset the votes
+        let snapshot = snapshot.apply(&header(6, 1, None), vec![6], 3).unwrap();
+        assert!(snapshot.votes.is_empty() && snapshot.tally.is_empty());
+    }
+}
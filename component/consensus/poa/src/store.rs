@@ -0,0 +1,148 @@
+//! Snapshots persisted on disk, so the signers at a block are found without
+//! replaying the headers from the genesis.
+//!
+//! The store is a directory with one file per snapshot, named after the hex
+//! block hash and holding the SCALE encoded `Snapshot`. Files are written to
+//! a temporary name, renamed and the directory synced, a crash leaves either
+//! the old or the new one. Unreadable files are left out of the history, so
+//! one of them does not stop the pruning.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use codec::{Decode, Encode};
+use log::warn;
+
+use crate::snapshot::Snapshot;
+
+const SNAPSHOT_EXT: &str = "snap";
+const TMP_EXT: &str = "tmp";
+
+#[derive(Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    /// Open the store in `dir`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<SnapshotStore> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(SnapshotStore { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Persist `snapshot`, replacing the one stored for the same block.
+    pub fn insert(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let path = self.snapshot_path(&snapshot.hash);
+        let tmp = path.with_extension(TMP_EXT);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&snapshot.encode())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        sync_dir(&self.dir)
+    }
+
+    /// The snapshot taken at block `hash`.
+    pub fn get(&self, hash: &[u8]) -> io::Result<Option<Snapshot>> {
+        match fs::read(self.snapshot_path(hash)) {
+            Ok(bytes) => decode(&bytes).map(Some),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Every readable stored snapshot, sorted by block number.
+    pub fn history(&self) -> io::Result<Vec<Snapshot>> {
+        let mut history = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new(SNAPSHOT_EXT)) {
+                continue;
+            }
+            match fs::read(&path).and_then(|bytes| decode(&bytes)) {
+                Ok(snapshot) => history.push(snapshot),
+                Err(e) => warn!("skip unreadable snapshot {:?}: {}", path, e),
+            }
+        }
+        history.sort_by(|a, b| (a.number, &a.hash).cmp(&(b.number, &b.hash)));
+        Ok(history)
+    }
+
+    /// Remove the snapshots of the blocks below `number`.
+    pub fn prune(&self, number: u64) -> io::Result<()> {
+        for snapshot in self.history()?.iter().take_while(|s| s.number < number) {
+            fs::remove_file(self.snapshot_path(&snapshot.hash))?;
+        }
+        Ok(())
+    }
+
+    fn snapshot_path(&self, hash: &[u8]) -> PathBuf {
+        self.dir.join(format!("{}.{}", hex::encode(hash), SNAPSHOT_EXT))
+    }
+}
+
+/// Sync the entries of `dir`, the snapshots renamed in it.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn decode(bytes: &[u8]) -> io::Result<Snapshot> {
+    Snapshot::decode(&mut &bytes[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn snapshot(number: u64) -> Snapshot {
+        Snapshot::new(number, vec![number as u8, 0xab], (0..3u8).map(|s| vec![s]).collect())
+    }
+
+    #[test]
+    fn test_insert_get_history() {
+        let dir = TempDir::new("poa_store").unwrap();
+        let store = SnapshotStore::open(dir.path()).unwrap();
+        assert_eq!(store.get(&[1, 0xab]).unwrap(), None);
+        for number in &[20, 10, 30] {
+            store.insert(&snapshot(*number)).unwrap();
+        }
+        assert_eq!(store.get(&[10, 0xab]).unwrap(), Some(snapshot(10)));
+
+        // reopened, as after a restart
+        let store = SnapshotStore::open(dir.path()).unwrap();
+        let numbers: Vec<u64> = store.history().unwrap().iter().map(|s| s.number).collect();
+        assert_eq!(numbers, vec![10, 20, 30]);
+        store.prune(30).unwrap();
+        assert_eq!(store.history().unwrap(), vec![snapshot(30)]);
+    }
+
+    #[test]
+    fn test_corrupted_snapshot() {
+        let dir = TempDir::new("poa_store").unwrap();
+        let store = SnapshotStore::open(dir.path()).unwrap();
+        store.insert(&snapshot(1)).unwrap();
+        fs::write(store.snapshot_path(&[1, 0xab]), [1, 2, 3]).unwrap();
+        let err = store.get(&[1, 0xab]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // left out of the history, the others are still pruned
+        store.insert(&snapshot(2)).unwrap();
+        store.insert(&snapshot(3)).unwrap();
+        assert_eq!(store.history().unwrap(), vec![snapshot(2), snapshot(3)]);
+        store.prune(3).unwrap();
+        assert_eq!(store.history().unwrap(), vec![snapshot(3)]);
+    }
+}
@@ -0,0 +1,60 @@
+//! Hashing speed of the CPU miner, per thread.
+//!
+//! ```text
+//! cargo run --release --example benchmark -- [threads] [block number] [seconds] [--full] [--progpow] [--quick]
+//! ```
+//!
+//! Hashes from the light cache, or with `--full` from the full dataset of the
+//! epoch, generated into the temporary directory on the first run. Ethash
+//! unless `--progpow`. With `--quick`, measures the boundary check of seals
+//! instead, on one thread.
+
+use std::env;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use pow::{benchmark, quick_get_difficulty, Dataset, Verifier};
+
+fn main() {
+	let full = env::args().any(|arg| arg == "--full");
+	let progpow = env::args().any(|arg| arg == "--progpow");
+	let args: Vec<u64> = env::args().skip(1).filter(|arg| !arg.starts_with("--")).map(|arg| {
+		arg.parse().unwrap_or_else(|_| panic!("invalid argument {}", arg))
+	}).collect();
+	let threads = args.first().map_or_else(
+		num_cpus::get,
+		|&threads| threads as usize,
+	);
+	let number = args.get(1).cloned().unwrap_or(0);
+	let duration = Duration::from_secs(args.get(2).cloned().unwrap_or(10));
+
+	if env::args().any(|arg| arg == "--quick") {
+		let start = Instant::now();
+		let mut checks = 0u64;
+		let mut value = [0u8; 32];
+		while start.elapsed() < duration {
+			for nonce in checks..checks + 10_000 {
+				value = quick_get_difficulty(&value, nonce, &[0u8; 32], progpow);
+			}
+			checks += 10_000;
+		}
+		println!("quick check: {:.1} checks/s", checks as f64 / start.elapsed().as_secs_f64());
+		return;
+	}
+
+	let cache_dir = env::temp_dir().join("pow-benchmark");
+	let verifier = Verifier::new(&cache_dir, if progpow { 0 } else { u64::MAX });
+	let light = verifier.light(number);
+	let dataset = if full {
+		let abort = AtomicBool::new(false);
+		Some(Dataset::open_or_generate(&light, &cache_dir, threads, &abort).expect("generate the dataset"))
+	} else {
+		None
+	};
+
+	let rates = benchmark(&light, dataset.as_ref(), number, threads, duration);
+	for (thread, rate) in rates.iter().enumerate() {
+		println!("thread {}: {:.1} H/s", thread, rate);
+	}
+	println!("total: {:.1} H/s", rates.iter().sum::<f64>());
+}
@@ -1,11 +1,13 @@
-use compute::Light;
+use crate::compute::Light;
+use crate::progpow::{generate_cdag, CDag};
 use either::Either;
-use keccak::{H256, keccak_512};
+use log::warn;
+use crate::keccak::{H256, keccak_512};
 use memmap::MmapMut;
-use parking_lot::Mutex;
-use seed_compute::SeedHashCompute;
+use once_cell::sync::OnceCell;
+use crate::seeds::SeedHashCache;
 
-use shared::{ETHASH_CACHE_ROUNDS, NODE_BYTES, Node, epoch, get_cache_size, to_hex};
+use crate::shared::{ETHASH_CACHE_ROUNDS, NODE_BYTES, Node, OptimizeFor, epoch, get_cache_size, to_hex};
 
 use std::borrow::Cow;
 use std::fs;
@@ -14,8 +16,6 @@ use std::path::{Path, PathBuf};
 use std::slice;
 use std::sync::Arc;
 
-use common_types::engines::OptimizeFor;
-
 type Cache = Either<Vec<Node>, MmapMut>;
 
 fn byte_size(cache: &Cache) -> usize {
@@ -40,7 +40,7 @@ fn new_buffer(path: &Path, num_nodes: usize, ident: &H256, optimize_for: Optimiz
 
 #[derive(Clone)]
 pub struct NodeCacheBuilder {
-	seedhash: Arc<Mutex<SeedHashCompute>>,
+	seeds: Arc<SeedHashCache>,
 	optimize_for: OptimizeFor,
 	progpow_transition: u64,
 }
@@ -51,6 +51,8 @@ pub struct NodeCache {
 	cache_path: PathBuf,
 	epoch: u64,
 	cache: Cache,
+	/// ProgPoW cache of the first dataset items, generated on first use
+	c_dag: OnceCell<Box<CDag>>,
 }
 
 impl NodeCacheBuilder {
@@ -64,18 +66,28 @@ impl NodeCacheBuilder {
 
 	pub fn new<T: Into<Option<OptimizeFor>>>(optimize_for: T, progpow_transition: u64) -> Self {
 		NodeCacheBuilder {
-			seedhash: Arc::new(Mutex::new(SeedHashCompute::default())),
+			seeds: Arc::new(SeedHashCache::in_memory()),
 			optimize_for: optimize_for.into().unwrap_or_default(),
 			progpow_transition
 		}
 	}
 
+	/// Take the seed hashes from `seeds`, shared with other builders.
+	pub fn with_seeds(mut self, seeds: Arc<SeedHashCache>) -> Self {
+		self.seeds = seeds;
+		self
+	}
+
+	pub fn seeds(&self) -> &Arc<SeedHashCache> {
+		&self.seeds
+	}
+
 	fn block_number_to_ident(&self, block_number: u64) -> H256 {
-		self.seedhash.lock().hash_block_number(block_number)
+		self.seeds.seed_hash_of_block(block_number)
 	}
 
 	fn epoch_to_ident(&self, epoch: u64) -> H256 {
-		self.seedhash.lock().hash_epoch(epoch)
+		self.seeds.seed_hash(epoch)
 	}
 
 	pub fn from_file<P: Into<Cow<'static, Path>>>(
@@ -98,6 +110,7 @@ impl NodeCacheBuilder {
 				cache_dir: cache_dir,
 				cache_path: path,
 				cache: cache,
+				c_dag: OnceCell::new(),
 			})
 		} else {
 			Err(io::Error::new(
@@ -131,6 +144,7 @@ impl NodeCacheBuilder {
 			cache_dir: cache_dir.into(),
 			cache_path: path,
 			cache: nodes,
+			c_dag: OnceCell::new(),
 		}
 	}
 }
@@ -140,6 +154,16 @@ impl NodeCache {
 		&self.cache_path
 	}
 
+	/// Seed hash of the epoch.
+	pub fn seed_hash(&self) -> H256 {
+		self.builder.epoch_to_ident(self.epoch)
+	}
+
+	/// The ProgPoW CDag of the epoch, generated once.
+	pub fn c_dag(&self) -> &CDag {
+		self.c_dag.get_or_init(|| Box::new(generate_cdag(self.as_ref())))
+	}
+
 	pub fn flush(&mut self) -> io::Result<()> {
 		if let Some(last) = self.epoch.checked_sub(2).map(|ep| {
 			cache_path(self.cache_dir.as_ref(), &self.builder.epoch_to_ident(ep))
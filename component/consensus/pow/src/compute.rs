@@ -1,9 +1,10 @@
 
-use keccak::{keccak_512, keccak_256, H256};
-use cache::{NodeCache, NodeCacheBuilder};
-use progpow::{CDag, generate_cdag, progpow, keccak_f800_short, keccak_f800_long};
-use seed_compute::SeedHashCompute;
-use shared::*;
+use crate::keccak::{keccak_512, keccak_256, H256};
+use crate::cache::{NodeCache, NodeCacheBuilder};
+use crate::hashing::{fnv_hash, fnv_mix, FNV_PRIME};
+use crate::progpow::{progpow, progpow_with};
+use crate::seed_compute::SeedHashCompute;
+use crate::shared::*;
 use std::io;
 
 use std::mem;
@@ -11,7 +12,6 @@ use std::path::Path;
 
 const MIX_WORDS: usize = ETHASH_MIX_BYTES / 4;
 const MIX_NODES: usize = MIX_WORDS / NODE_WORDS;
-pub const FNV_PRIME: u32 = 0x01000193;
 
 /// Computation result
 pub struct ProofOfWork {
@@ -23,7 +23,7 @@ pub struct ProofOfWork {
 
 enum Algorithm {
 	Hashimoto,
-	Progpow(Box<CDag>),
+	Progpow,
 }
 
 pub struct Light {
@@ -43,7 +43,7 @@ impl Light {
 		let cache = builder.new_cache(cache_dir.to_path_buf(), block_number);
 
 		let algorithm = if block_number >= progpow_transition {
-			Algorithm::Progpow(Box::new(generate_cdag(cache.as_ref())))
+			Algorithm::Progpow
 		} else {
 			Algorithm::Hashimoto
 		};
@@ -56,13 +56,13 @@ impl Light {
 	/// `nonce` - The nonce to pack into the mix
 	pub fn compute(&self, header_hash: &H256, nonce: u64, block_number: u64) -> ProofOfWork {
 		match self.algorithm {
-			Algorithm::Progpow(ref c_dag) => {
+			Algorithm::Progpow => {
 				let (value, mix_hash) = progpow(
 					*header_hash,
 					nonce,
 					block_number,
 					self.cache.as_ref(),
-					c_dag,
+					self.cache.c_dag(),
 				);
 
 				ProofOfWork { value, mix_hash }
@@ -72,6 +72,54 @@ impl Light {
 
 	}
 
+	/// Hash with the algorithm of the cache, reading the dataset items from
+	/// `dag_item` instead of computing them from the cache.
+	pub(crate) fn compute_with<F: Fn(u32) -> Node>(
+		&self,
+		header_hash: &H256,
+		nonce: u64,
+		block_number: u64,
+		dag_item: F,
+	) -> ProofOfWork {
+		match self.algorithm {
+			Algorithm::Progpow => {
+				let (value, mix_hash) = progpow_with(*header_hash, nonce, block_number, self.cache.c_dag(), dag_item);
+
+				ProofOfWork { value, mix_hash }
+			},
+			Algorithm::Hashimoto => {
+				hash_compute(get_data_size(self.block_number), header_hash, nonce, dag_item)
+			},
+		}
+	}
+
+	/// Block number the cache was built for.
+	pub fn block_number(&self) -> u64 {
+		self.block_number
+	}
+
+	pub(crate) fn cache(&self) -> &[Node] {
+		self.cache.as_ref()
+	}
+
+	/// Epoch of the light cache.
+	pub fn epoch(&self) -> u64 {
+		epoch(self.block_number)
+	}
+
+	/// Seed hash of the epoch of the light cache.
+	pub fn seed_hash(&self) -> H256 {
+		self.cache.seed_hash()
+	}
+
+	/// Whether the cache computes ProgPoW rather than Ethash hashes.
+	pub fn is_progpow(&self) -> bool {
+		match self.algorithm {
+			Algorithm::Progpow => true,
+			Algorithm::Hashimoto => false,
+		}
+	}
+
 	pub fn from_file_with_builder(
 		builder: &NodeCacheBuilder,
 		cache_dir: &Path,
@@ -81,7 +129,7 @@ impl Light {
 		let cache = builder.from_file(cache_dir.to_path_buf(), block_number)?;
 
 		let algorithm = if block_number >= progpow_transition {
-			Algorithm::Progpow(Box::new(generate_cdag(cache.as_ref())))
+			Algorithm::Progpow
 		} else {
 			Algorithm::Hashimoto
 		};
@@ -99,49 +147,17 @@ pub fn slow_hash_block_number(block_number: u64) -> H256 {
 	SeedHashCompute::resume_compute_seedhash([0u8; 32], 0, block_number / ETHASH_EPOCH_LENGTH)
 }
 
-fn fnv_hash(x: u32, y: u32) -> u32 {
-	return x.wrapping_mul(FNV_PRIME) ^ y;
-}
-
-/// Difficulty quick check for POW preverification
-///
-/// `header_hash`      The hash of the header
-/// `nonce`            The block's nonce
-/// `mix_hash`         The mix digest hash
-/// Boundary recovered from mix hash
-pub fn quick_get_difficulty(header_hash: &H256, nonce: u64, mix_hash: &H256, progpow: bool) -> H256 {
-	unsafe {
-		if progpow {
-			let seed = keccak_f800_short(*header_hash, nonce, [0u32; 8]);
-			keccak_f800_long(*header_hash, seed, mem::transmute(*mix_hash))
-		} else {
-			let mut buf = [0u8; 64 + 32];
-
-			let hash_len = header_hash.len();
-			buf[..hash_len].copy_from_slice(header_hash);
-			buf[hash_len..hash_len + mem::size_of::<u64>()].copy_from_slice(&nonce.to_ne_bytes());
-
-			keccak_512::unchecked(buf.as_mut_ptr(), 64, buf.as_ptr(), 40);
-			buf[64..].copy_from_slice(mix_hash);
-
-			let mut hash = [0u8; 32];
-			keccak_256::unchecked(hash.as_mut_ptr(), hash.len(), buf.as_ptr(), buf.len());
-
-			hash
-		}
-	}
-}
-
 /// Calculate the light client data
 /// `light` - The light client handler
 /// `header_hash` - The header hash to pack into the mix
 /// `nonce` - The nonce to pack into the mix
 pub fn light_compute(light: &Light, header_hash: &H256, nonce: u64) -> ProofOfWork {
 	let full_size = get_data_size(light.block_number);
-	hash_compute(light, full_size, header_hash, nonce)
+	let cache: &[Node] = light.cache.as_ref();
+	hash_compute(full_size, header_hash, nonce, |index| calculate_dag_item(index, cache))
 }
 
-fn hash_compute(light: &Light, full_size: usize, header_hash: &H256, nonce: u64) -> ProofOfWork {
+fn hash_compute<F: Fn(u32) -> Node>(full_size: usize, header_hash: &H256, nonce: u64, dag_item: F) -> ProofOfWork {
 	macro_rules! make_const_array {
 		($n:expr, $value:expr) => {{
 			// We use explicit lifetimes to ensure that val's borrow is invalidated until the
@@ -202,8 +218,6 @@ fn hash_compute(light: &Light, full_size: usize, header_hash: &H256, nonce: u64)
 
 	let page_size = 4 * MIX_WORDS;
 	let num_full_pages = (full_size / page_size) as u32;
-	// deref once for better performance
-	let cache: &[Node] = light.cache.as_ref();
 	let first_val = buf.half_mix.as_words()[0];
 
 	debug_assert_eq!(MIX_NODES, 2);
@@ -221,15 +235,10 @@ fn hash_compute(light: &Light, full_size: usize, header_hash: &H256, nonce: u64)
 
 		// MIX_NODES
 		for n in 0..2 {
-			let tmp_node = calculate_dag_item(
-				index * MIX_NODES as u32 + n as u32,
-				cache,
-			);
+			let tmp_node = dag_item(index * MIX_NODES as u32 + n as u32);
 
 			// NODE_WORDS
-			for (a, b) in mix[n].as_words_mut().iter_mut().zip(tmp_node.as_words()) {
-				*a = fnv_hash(*a, *b);
-			}
+			fnv_mix(mix[n].as_words_mut(), tmp_node.as_words());
 		}
 	}
 
@@ -295,9 +304,7 @@ pub fn calculate_dag_item(node_index: u32, cache: &[Node]) -> Node {
 			num_parent_nodes as u32;
 		let parent = &cache[parent_index as usize];
 
-		for (a, b) in ret.as_words_mut().iter_mut().zip(parent.as_words()) {
-			*a = fnv_hash(*a, *b);
-		}
+		fnv_mix(ret.as_words_mut(), parent.as_words());
 	}
 
 	keccak_512::inplace(ret.as_bytes_mut());
@@ -336,33 +343,6 @@ mod test {
 		assert_eq!(18245220736usize, get_data_size(2047 * ETHASH_EPOCH_LENGTH));
 	}
 
-	#[test]
-	fn test_difficulty_test() {
-		let hash = [
-			0xf5, 0x7e, 0x6f, 0x3a, 0xcf, 0xc0, 0xdd, 0x4b, 0x5b, 0xf2, 0xbe, 0xe4, 0x0a, 0xb3,
-			0x35, 0x8a, 0xa6, 0x87, 0x73, 0xa8, 0xd0, 0x9f, 0x5e, 0x59, 0x5e, 0xab, 0x55, 0x94,
-			0x05, 0x52, 0x7d, 0x72,
-		];
-		let mix_hash = [
-			0x1f, 0xff, 0x04, 0xce, 0xc9, 0x41, 0x73, 0xfd, 0x59, 0x1e, 0x3d, 0x89, 0x60, 0xce,
-			0x6b, 0xdf, 0x8b, 0x19, 0x71, 0x04, 0x8c, 0x71, 0xff, 0x93, 0x7b, 0xb2, 0xd3, 0x2a,
-			0x64, 0x31, 0xab, 0x6d,
-		];
-		let nonce = 0xd7b3ac70a301a249;
-		let boundary_good = [
-			0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x3e, 0x9b, 0x6c, 0x69, 0xbc, 0x2c, 0xe2, 0xa2,
-			0x4a, 0x8e, 0x95, 0x69, 0xef, 0xc7, 0xd7, 0x1b, 0x33, 0x35, 0xdf, 0x36, 0x8c, 0x9a,
-			0xe9, 0x7e, 0x53, 0x84,
-		];
-		assert_eq!(quick_get_difficulty(&hash, nonce, &mix_hash, false)[..], boundary_good[..]);
-		let boundary_bad = [
-			0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x3a, 0x9b, 0x6c, 0x69, 0xbc, 0x2c, 0xe2, 0xa2,
-			0x4a, 0x8e, 0x95, 0x69, 0xef, 0xc7, 0xd7, 0x1b, 0x33, 0x35, 0xdf, 0x36, 0x8c, 0x9a,
-			0xe9, 0x7e, 0x53, 0x84,
-		];
-		assert!(quick_get_difficulty(&hash, nonce, &mix_hash, false)[..] != boundary_bad[..]);
-	}
-
 	#[test]
 	fn test_light_compute() {
 		let hash = [
@@ -0,0 +1,370 @@
+//! Full datasets for mining.
+//!
+//! Hashing from the light cache computes each of the 128 dataset items a hash
+//! reads from 256 cache nodes, which verification can afford but mining
+//! cannot. A `Dataset` holds every item of an epoch, over 1 GB, generated into
+//! a file of the cache directory and memory-mapped. `Datasets` generates them
+//! in the background: the epoch being mined, and the next one as soon as the
+//! chain is `PREGENERATE_BLOCKS` blocks away from the boundary, so the miner
+//! moves on to the new dataset without a pause. Datasets of older epochs are
+//! removed from the cache directory.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use log::{debug, info, warn};
+use memmap::{Mmap, MmapMut};
+use parking_lot::Mutex;
+
+use crate::compute::{calculate_dag_item, Light, ProofOfWork};
+use crate::keccak::H256;
+use crate::shared::{epoch, get_data_size, to_hex, Node, ETHASH_EPOCH_LENGTH, NODE_BYTES};
+use crate::verifier::Verifier;
+
+/// Blocks before the epoch boundary the next dataset starts generating at.
+pub const PREGENERATE_BLOCKS: u64 = 3000;
+
+/// Dataset files are named after the revision of the algorithm and the seed
+/// hash of their epoch, like the ones of other Ethash miners.
+const DATASET_PREFIX: &str = "full-R23-";
+
+/// Nodes generated between two checks of the abort flag.
+const GENERATE_BATCH: usize = 1 << 14;
+
+pub struct Dataset {
+	epoch: u64,
+	path: PathBuf,
+	nodes: Mmap,
+}
+
+impl Dataset {
+	/// Map the dataset of the epoch of `light` from `dir`, or generate it there
+	/// with `threads` threads. The generation ends with `ErrorKind::Interrupted`
+	/// once `abort` is set.
+	pub fn open_or_generate(light: &Light, dir: &Path, threads: usize, abort: &AtomicBool) -> io::Result<Dataset> {
+		match Dataset::open(light, dir) {
+			Ok(dataset) => Ok(dataset),
+			Err(e) => {
+				debug!("generate dataset of epoch {}: {}", light.epoch(), e);
+				Dataset::generate(light, dir, threads, abort)
+			},
+		}
+	}
+
+	/// Map the dataset of the epoch of `light` from `dir`.
+	pub fn open(light: &Light, dir: &Path) -> io::Result<Dataset> {
+		let num_nodes = get_data_size(light.block_number()) / NODE_BYTES;
+		open_nodes(light.epoch(), &dataset_path(dir, &light.seed_hash()), num_nodes)
+	}
+
+	/// Generate the dataset of the epoch of `light` into `dir`.
+	pub fn generate(light: &Light, dir: &Path, threads: usize, abort: &AtomicBool) -> io::Result<Dataset> {
+		let num_nodes = get_data_size(light.block_number()) / NODE_BYTES;
+		generate_nodes(light, dir, num_nodes, threads, abort)
+	}
+
+	pub fn epoch(&self) -> u64 {
+		self.epoch
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Hash with the algorithm of `light`, reading the dataset items from the
+	/// dataset rather than computing them from the cache.
+	pub fn compute(&self, light: &Light, header_hash: &H256, nonce: u64, block_number: u64) -> ProofOfWork {
+		debug_assert_eq!(light.epoch(), self.epoch);
+		let nodes = self.nodes();
+		light.compute_with(header_hash, nonce, block_number, |index| nodes[index as usize].clone())
+	}
+
+	fn nodes(&self) -> &[Node] {
+		// the mapping is page aligned and a whole number of nodes, checked on open
+		unsafe { slice::from_raw_parts(self.nodes.as_ptr() as *const Node, self.nodes.len() / NODE_BYTES) }
+	}
+}
+
+fn dataset_path(dir: &Path, seed: &H256) -> PathBuf {
+	dir.join(format!("{}{}", DATASET_PREFIX, to_hex(&seed[..8])))
+}
+
+fn open_nodes(epoch: u64, path: &Path, num_nodes: usize) -> io::Result<Dataset> {
+	let file = fs::File::open(path)?;
+	if file.metadata()?.len() != (num_nodes * NODE_BYTES) as u64 {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Dataset is of incorrect size"));
+	}
+	let nodes = unsafe { Mmap::map(&file)? };
+	Ok(Dataset { epoch, path: path.to_owned(), nodes })
+}
+
+/// Generate the first `num_nodes` nodes of the dataset into a temporary file,
+/// moved in place once complete so a dataset file is never partial.
+fn generate_nodes(light: &Light, dir: &Path, num_nodes: usize, threads: usize, abort: &AtomicBool) -> io::Result<Dataset> {
+	let path = dataset_path(dir, &light.seed_hash());
+	let tmp_path = path.with_extension("tmp");
+	fs::create_dir_all(dir)?;
+
+	let result = (|| {
+		let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp_path)?;
+		file.set_len((num_nodes * NODE_BYTES) as u64)?;
+		let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+		let nodes = unsafe { slice::from_raw_parts_mut(mmap.as_mut_ptr() as *mut Node, num_nodes) };
+
+		let cache: &[Node] = light.cache();
+		let chunk = ((num_nodes + threads.max(1) - 1) / threads.max(1)).max(1);
+		let complete = crossbeam_utils::thread::scope(|scope| {
+			let workers: Vec<_> = nodes
+				.chunks_mut(chunk)
+				.enumerate()
+				.map(|(i, nodes)| {
+					scope.spawn(move |_| {
+						let start = i * chunk;
+						for (b, batch) in nodes.chunks_mut(GENERATE_BATCH).enumerate() {
+							if abort.load(Ordering::Relaxed) {
+								return false;
+							}
+							let first = start + b * GENERATE_BATCH;
+							for (j, node) in batch.iter_mut().enumerate() {
+								*node = calculate_dag_item((first + j) as u32, cache);
+							}
+						}
+						true
+					})
+				})
+				.collect();
+			workers.into_iter().all(|worker| worker.join().expect("dataset generation does not panic; qed"))
+		})
+		.expect("dataset generation threads are joined; qed");
+		if !complete {
+			return Err(io::Error::new(io::ErrorKind::Interrupted, "Dataset generation aborted"));
+		}
+
+		mmap.flush()?;
+		fs::rename(&tmp_path, &path)
+	})();
+	if let Err(e) = result {
+		let _ = fs::remove_file(&tmp_path);
+		return Err(e);
+	}
+	open_nodes(light.epoch(), &path, num_nodes)
+}
+
+/// Remove the dataset files of `dir` but the ones of the seed hashes in `keep`.
+fn prune(dir: &Path, keep: &[H256]) -> io::Result<()> {
+	let keep: Vec<PathBuf> = keep.iter().map(|seed| dataset_path(dir, seed)).collect();
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		let is_dataset = path
+			.file_name()
+			.and_then(|name| name.to_str())
+			.map_or(false, |name| name.starts_with(DATASET_PREFIX));
+		if is_dataset && !keep.contains(&path) {
+			debug!("remove stale dataset {:?}", path);
+			fs::remove_file(&path)?;
+		}
+	}
+	Ok(())
+}
+
+struct State {
+	/// epoch of the latest block asked for
+	head: Option<u64>,
+	ready: Vec<Arc<Dataset>>,
+	pending: VecDeque<u64>,
+	/// epochs whose generation failed, not retried
+	failed: BTreeSet<u64>,
+	running: bool,
+	worker: Option<JoinHandle<()>>,
+}
+
+struct Inner {
+	verifier: Arc<Verifier>,
+	threads: usize,
+	abort: AtomicBool,
+	state: Mutex<State>,
+}
+
+/// Datasets generated in the background into the cache directory of the verifier.
+pub struct Datasets {
+	inner: Arc<Inner>,
+}
+
+impl Datasets {
+	/// Datasets generated with `threads` threads, from the light caches of `verifier`.
+	pub fn new(verifier: Arc<Verifier>, threads: usize) -> Self {
+		Datasets {
+			inner: Arc::new(Inner {
+				verifier,
+				threads: threads.max(1),
+				abort: AtomicBool::new(false),
+				state: Mutex::new(State {
+					head: None,
+					ready: Vec::new(),
+					pending: VecDeque::new(),
+					failed: BTreeSet::new(),
+					running: false,
+					worker: None,
+				}),
+			}),
+		}
+	}
+
+	pub fn verifier(&self) -> &Arc<Verifier> {
+		&self.inner.verifier
+	}
+
+	/// The dataset to mine block `number` with, if generated. Otherwise its
+	/// generation is started, and the one of the next epoch near the boundary.
+	pub fn get(&self, number: u64) -> Option<Arc<Dataset>> {
+		let current = epoch(number);
+		let mut state = self.inner.state.lock();
+		if state.head.map_or(true, |head| current > head) {
+			state.head = Some(current);
+			self.advance(&mut state, current);
+		}
+
+		let dataset = state.ready.iter().find(|d| d.epoch() == current).cloned();
+		if dataset.is_none() {
+			self.schedule(&mut state, current);
+		}
+		if number % ETHASH_EPOCH_LENGTH >= ETHASH_EPOCH_LENGTH - PREGENERATE_BLOCKS {
+			self.schedule(&mut state, current + 1);
+		}
+		dataset
+	}
+
+	/// Drop the datasets older than `current`, in memory and on disk.
+	fn advance(&self, state: &mut State, current: u64) {
+		state.ready.retain(|d| d.epoch() >= current);
+		state.pending.retain(|&epoch| epoch >= current);
+		let seeds = self.inner.verifier.seeds();
+		let keep = [seeds.seed_hash(current), seeds.seed_hash(current + 1)];
+		if let Err(e) = prune(self.inner.verifier.cache_dir(), &keep) {
+			warn!("cannot remove stale datasets: {}", e);
+		}
+	}
+
+	fn schedule(&self, state: &mut State, epoch: u64) {
+		if state.ready.iter().any(|d| d.epoch() == epoch) ||
+			state.pending.contains(&epoch) ||
+			state.failed.contains(&epoch)
+		{
+			return;
+		}
+		state.pending.push_back(epoch);
+		if !state.running {
+			state.running = true;
+			let inner = self.inner.clone();
+			let worker = thread::Builder::new()
+				.name("pow-dataset".into())
+				.spawn(move || generate(&inner))
+				.expect("spawn dataset thread; qed");
+			state.worker = Some(worker);
+		}
+	}
+}
+
+impl Drop for Datasets {
+	fn drop(&mut self) {
+		self.inner.abort.store(true, Ordering::SeqCst);
+		let worker = self.inner.state.lock().worker.take();
+		if let Some(worker) = worker {
+			let _ = worker.join();
+		}
+	}
+}
+
+/// Generate the pending datasets one after the other.
+fn generate(inner: &Inner) {
+	loop {
+		let epoch = {
+			let mut state = inner.state.lock();
+			match state.pending.pop_front() {
+				Some(epoch) if !inner.abort.load(Ordering::SeqCst) => epoch,
+				_ => {
+					state.running = false;
+					return;
+				},
+			}
+		};
+
+		let light = inner.verifier.light(epoch * ETHASH_EPOCH_LENGTH);
+		info!("generating dataset of epoch {}", epoch);
+		match Dataset::open_or_generate(&light, inner.verifier.cache_dir(), inner.threads, &inner.abort) {
+			Ok(dataset) => {
+				info!("dataset of epoch {} ready at {:?}", epoch, dataset.path());
+				let mut state = inner.state.lock();
+				if state.head.map_or(true, |head| epoch >= head) {
+					state.ready.push(Arc::new(dataset));
+				}
+			},
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+			Err(e) => {
+				warn!("cannot generate dataset of epoch {}: {}", epoch, e);
+				inner.state.lock().failed.insert(epoch);
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cache::NodeCacheBuilder;
+	use crate::seed_compute::SeedHashCompute;
+	use tempdir::TempDir;
+
+	#[test]
+	fn test_generate() {
+		let tempdir = TempDir::new("").unwrap();
+		let light = NodeCacheBuilder::new(None, u64::MAX).light(tempdir.path(), 0);
+		let abort = AtomicBool::new(false);
+		let dataset = generate_nodes(&light, tempdir.path(), 1000, 3, &abort).unwrap();
+		assert_eq!(dataset.epoch(), 0);
+		assert_eq!(dataset.path(), dataset_path(tempdir.path(), &light.seed_hash()).as_path());
+		for (i, node) in dataset.nodes().iter().enumerate() {
+			assert_eq!(node.as_bytes()[..], calculate_dag_item(i as u32, light.cache()).as_bytes()[..]);
+		}
+
+		// a complete file is mapped again, one of another size is not
+		assert_eq!(open_nodes(0, dataset.path(), 1000).unwrap().nodes().len(), 1000);
+		assert_eq!(open_nodes(0, dataset.path(), 1001).err().unwrap().kind(), io::ErrorKind::InvalidData);
+		assert!(Dataset::open(&light, tempdir.path()).is_err());
+	}
+
+	#[test]
+	fn test_abort() {
+		let tempdir = TempDir::new("").unwrap();
+		let light = NodeCacheBuilder::new(None, u64::MAX).light(tempdir.path(), 0);
+		let abort = AtomicBool::new(true);
+		let err = generate_nodes(&light, tempdir.path(), 1000, 2, &abort).err().unwrap();
+		assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+		assert!(!dataset_path(tempdir.path(), &light.seed_hash()).exists());
+		assert!(!dataset_path(tempdir.path(), &light.seed_hash()).with_extension("tmp").exists());
+	}
+
+	#[test]
+	fn test_prune() {
+		let tempdir = TempDir::new("").unwrap();
+		let seeds: Vec<H256> = (0..4).map(|epoch| SeedHashCompute::default().hash_epoch(epoch)).collect();
+		for seed in &seeds {
+			fs::write(dataset_path(tempdir.path(), seed), b"").unwrap();
+		}
+		let other = tempdir.path().join("light");
+		fs::write(&other, b"").unwrap();
+
+		prune(tempdir.path(), &seeds[2..]).unwrap();
+		assert!(!dataset_path(tempdir.path(), &seeds[0]).exists());
+		assert!(!dataset_path(tempdir.path(), &seeds[1]).exists());
+		assert!(dataset_path(tempdir.path(), &seeds[2]).exists());
+		assert!(dataset_path(tempdir.path(), &seeds[3]).exists());
+		assert!(other.exists());
+	}
+}
@@ -0,0 +1,122 @@
+//! Difficulty adjustment.
+//!
+//! Homestead style retargeting with the uncle rule of EIP-100: every block
+//! moves the difficulty by `parent / bound_divisor` steps, one up for a block
+//! sealed within `duration_limit` seconds of its parent, two when the parent
+//! has uncles, and one down for every further `duration_limit` seconds, at
+//! most `max_steps_down` of them. The block time settles between one and two
+//! `duration_limit` whatever the hashrate.
+
+use serde::{Deserialize, Serialize};
+
+/// Chain spec parameters of the difficulty adjustment.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DifficultyParams {
+	/// the difficulty moves by `parent / bound_divisor` per step
+	pub bound_divisor: u128,
+	/// seconds of block time per step
+	pub duration_limit: u64,
+	/// steps down a single block can take
+	pub max_steps_down: u64,
+}
+
+impl Default for DifficultyParams {
+	fn default() -> Self {
+		DifficultyParams { bound_divisor: 2048, duration_limit: 9, max_steps_down: 99 }
+	}
+}
+
+impl DifficultyParams {
+	pub fn validate(&self) -> Result<(), String> {
+		if self.bound_divisor == 0 {
+			return Err("difficulty bound divisor is zero".to_owned());
+		}
+		if self.duration_limit == 0 {
+			return Err("difficulty duration limit is zero".to_owned());
+		}
+		Ok(())
+	}
+}
+
+/// Difficulty of a block sealed at `timestamp` on a parent of `parent_difficulty`
+/// sealed at `parent_timestamp` with `parent_uncles` uncles, at least `minimum`.
+pub fn calculate_difficulty(
+	params: &DifficultyParams,
+	minimum: u128,
+	parent_timestamp: u64,
+	parent_difficulty: u128,
+	parent_uncles: usize,
+	timestamp: u64,
+) -> u128 {
+	let step = parent_difficulty / params.bound_divisor;
+	let up = if parent_uncles == 0 { 1 } else { 2 };
+	let down = (timestamp.saturating_sub(parent_timestamp) / params.duration_limit).min(params.max_steps_down + up);
+	let difficulty = if up >= down {
+		parent_difficulty.saturating_add(step * (up - down) as u128)
+	} else {
+		parent_difficulty.saturating_sub(step * (down - up) as u128)
+	};
+	difficulty.max(minimum)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_vectors() {
+		let params = DifficultyParams::default();
+		// (parent timestamp, parent difficulty, parent uncles, timestamp, difficulty)
+		let vectors = [
+			(1000, 1_000_000, 0, 1005, 1_000_488),
+			(1000, 1_000_000, 0, 1009, 1_000_000),
+			(1000, 1_000_000, 0, 1017, 1_000_000),
+			(1000, 1_000_000, 0, 1020, 999_512),
+			(1000, 1_000_000, 1, 1005, 1_000_976),
+			(1000, 1_000_000, 2, 1020, 1_000_000),
+			// at most 99 steps down
+			(1000, 1_000_000, 0, 2000, 951_688),
+			(1000, 1_000_000, 1, 2000, 951_688),
+			// never below the minimum
+			(1000, 131_072, 0, 1020, 131_072),
+			(1000, 2_000_000_000_000, 0, 1001, 2_000_976_562_500),
+			(1000, u128::MAX, 0, 1001, u128::MAX),
+		];
+		for &(parent_timestamp, parent_difficulty, uncles, timestamp, expected) in vectors.iter() {
+			let difficulty = calculate_difficulty(&params, 131_072, parent_timestamp, parent_difficulty, uncles, timestamp);
+			assert_eq!(difficulty, expected, "{} {} {} {}", parent_timestamp, parent_difficulty, uncles, timestamp);
+		}
+	}
+
+	#[test]
+	fn test_block_time_follows_hashrate() {
+		let params = DifficultyParams::default();
+		let mut difficulty = 10_000_000u128;
+		let mut timestamp = 0;
+		// average block time of `blocks` blocks mined at `hashrate`
+		let mut mine = |hashrate: u128, blocks: u64| {
+			let start = timestamp;
+			for _ in 0..blocks {
+				let parent_timestamp = timestamp;
+				timestamp += (difficulty / hashrate) as u64;
+				difficulty = calculate_difficulty(&params, 131_072, parent_timestamp, difficulty, 0, timestamp);
+			}
+			(timestamp - start) / blocks
+		};
+		for &hashrate in [100_000, 800_000, 50_000].iter() {
+			// settle, then measure
+			mine(hashrate, 10_000);
+			let block_time = mine(hashrate, 2000);
+			assert!((9..18).contains(&block_time), "{} s at {} H/s", block_time, hashrate);
+		}
+	}
+
+	#[test]
+	fn test_params() {
+		let params: DifficultyParams = serde_json::from_str(r#"{ "duration_limit": 13 }"#).unwrap();
+		assert_eq!(params.bound_divisor, 2048);
+		params.validate().unwrap();
+		assert!(DifficultyParams { bound_divisor: 0, ..params }.validate().is_err());
+	}
+}
@@ -0,0 +1,402 @@
+//! Ethash proof of work behind the shared `consensus_engine::Engine` interface.
+//!
+//! The seal carries the nonce and the mix hash found by the miner for the
+//! keccak-256 hash of `ConsensusHeader::unsealed`. Headers are verified by the
+//! `Verifier`, the cheap boundary check first and then the light cache.
+//! The `Extra` of a header lists its uncles, see `uncles`: their seals are
+//! verified with the header, their ancestry on import against the headers
+//! the engine imported lately. With a `HeaderStore` those headers are
+//! persisted and read back after a restart, until their blocks are too old
+//! to be the ancestor of a block after the last final one.
+//!
+//! Rewards are computed once a block is final, so side chains dropped by a
+//! reorg earn nothing. The engine only hands them to a `RewardHandler`.
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use codec::{Decode, Encode};
+use consensus_engine::{
+	check_parent, ConsensusEngineId, ConsensusHeader, Engine, EngineSpec, Error, Finality, Header, Proposal, Result,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::difficulty::{calculate_difficulty, DifficultyParams};
+use crate::keccak::{keccak_256, H256};
+use crate::miner::Work;
+use crate::reward::{rewards, RewardHandler};
+use crate::store::HeaderStore;
+use crate::uncles::{check_uncles, Extra, RecentHeaders, MAX_UNCLE_AGE};
+use crate::verifier::Verifier;
+
+/// Name of the engine in the chain spec.
+pub const NAME: &str = "pow";
+
+/// Id of the PoW seal in the header digest.
+pub const POW_ENGINE_ID: ConsensusEngineId = *b"pow_";
+
+/// Chain spec parameters of PoW.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PowParams {
+	/// difficulty of the genesis and lower bound of every block
+	pub minimum_difficulty: u128,
+	/// blocks on top of a block before it is considered final
+	pub confirmations: u64,
+	/// first block hashed with ProgPoW instead of Ethash
+	#[serde(default = "default_progpow_transition")]
+	pub progpow_transition: u64,
+	/// retargeting of the difficulty
+	#[serde(default)]
+	pub difficulty: DifficultyParams,
+	/// reward of the block author, uncle authors earn part of it
+	#[serde(default)]
+	pub block_reward: u128,
+}
+
+fn default_progpow_transition() -> u64 {
+	u64::MAX
+}
+
+impl PowParams {
+	/// Check the parameters without building an engine, which opens its cache directory.
+	pub fn validate(&self) -> Result<()> {
+		if self.minimum_difficulty == 0 {
+			return Err(Error::InvalidParams("minimum difficulty is zero".to_owned()));
+		}
+		self.difficulty.validate().map_err(Error::InvalidParams)
+	}
+}
+
+/// Seal of a mined header.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Seal {
+	pub nonce: u64,
+	pub mix_hash: H256,
+}
+
+pub struct PowEngine {
+	params: PowParams,
+	verifier: Arc<Verifier>,
+	/// ancestors and uncle candidates of the next blocks
+	recent: RecentHeaders,
+	store: Option<HeaderStore>,
+	rewards: Option<Box<dyn RewardHandler>>,
+}
+
+impl PowEngine {
+	/// Engine keeping its light caches in `cache_dir`.
+	pub fn new(params: PowParams, cache_dir: &Path) -> Result<Self> {
+		params.validate()?;
+		let verifier = Arc::new(Verifier::new(cache_dir, params.progpow_transition));
+		Ok(PowEngine { params, verifier, recent: RecentHeaders::default(), store: None, rewards: None })
+	}
+
+	/// Persist the imported headers in `store`, starting from the ones it holds.
+	pub fn with_store(mut self, store: HeaderStore) -> io::Result<Self> {
+		for header in store.headers()? {
+			self.recent.insert(header);
+		}
+		self.store = Some(store);
+		Ok(self)
+	}
+
+	/// Hand the rewards of every final block to `handler`.
+	pub fn with_rewards(mut self, handler: Box<dyn RewardHandler>) -> Self {
+		self.rewards = Some(handler);
+		self
+	}
+
+	pub fn from_spec(spec: &EngineSpec, cache_dir: &Path) -> Result<Self> {
+		PowEngine::new(spec.params(NAME)?, cache_dir)
+	}
+
+	/// The verifier, whose light caches a `Miner` shares.
+	pub fn verifier(&self) -> &Arc<Verifier> {
+		&self.verifier
+	}
+
+	/// Work package of a prepared header.
+	pub fn work(&self, header: &Header) -> Result<Work> {
+		let difficulty = header.fields()?.difficulty;
+		Ok(Work { header_hash: header_hash(header), number: header.number, difficulty })
+	}
+
+	/// Difficulty of a child of `parent` sealed at `timestamp`.
+	pub fn difficulty(&self, parent: &Header, timestamp: u64) -> u128 {
+		// the fields and extra of an imported parent are valid
+		let fields = parent.fields().unwrap_or_default();
+		let parent_uncles = Extra::of(parent).map_or(0, |extra| extra.uncles.len());
+		calculate_difficulty(
+			&self.params.difficulty,
+			self.params.minimum_difficulty,
+			fields.timestamp,
+			fields.difficulty,
+			parent_uncles,
+			timestamp,
+		)
+	}
+
+	/// Check the seal of `header`, a block or an uncle.
+	fn verify_seal(&self, header: &Header) -> Result<()> {
+		let mut seal = header.seal(&POW_ENGINE_ID).ok_or(Error::MissingSeal(header.number))?;
+		let difficulty = header.fields()?.difficulty;
+		if difficulty < self.params.minimum_difficulty {
+			return Err(Error::InvalidDifficulty { expected: self.params.minimum_difficulty, got: difficulty });
+		}
+		let seal = Seal::decode(&mut seal).map_err(|_| Error::InvalidSeal("malformed pow seal".to_owned()))?;
+		self.verifier.verify(&header_hash(header), header.number, difficulty, &seal)?;
+		Ok(())
+	}
+}
+
+/// Hash the miner searches a nonce for.
+pub fn header_hash(header: &Header) -> H256 {
+	let mut hash = [0u8; 32];
+	keccak_256::write(&header.unsealed(), &mut hash);
+	hash
+}
+
+impl Engine for PowEngine {
+	fn name(&self) -> &'static str {
+		NAME
+	}
+
+	fn id(&self) -> ConsensusEngineId {
+		POW_ENGINE_ID
+	}
+
+	fn finality(&self) -> Finality {
+		Finality::Probabilistic { confirmations: self.params.confirmations }
+	}
+
+	fn prepare(&mut self, header: &mut Header, parent: &Header) -> Result<()> {
+		let mut fields = header.fields()?;
+		fields.timestamp = fields.timestamp.max(parent.fields()?.timestamp + 1);
+		fields.difficulty = self.difficulty(parent, fields.timestamp);
+		let uncles = self.recent.candidates(parent);
+		fields.extra = if uncles.is_empty() { vec![] } else { Extra { uncles }.encode() };
+		header.set_fields(fields);
+		Ok(())
+	}
+
+	/// Blocks are sealed by a miner working on `header_hash`.
+	fn propose(&mut self, _header: &Header) -> Result<Proposal> {
+		Ok(Proposal::Pending)
+	}
+
+	fn verify_header(&self, header: &Header) -> Result<()> {
+		self.verify_seal(header)?;
+		for uncle in Extra::of(header)?.uncles {
+			self.verify_seal(&uncle)?;
+		}
+		Ok(())
+	}
+
+	fn verify_import(&self, header: &Header, parent: &Header) -> Result<()> {
+		check_parent(header, parent)?;
+		let fields = header.fields()?;
+		let expected = self.difficulty(parent, fields.timestamp);
+		if fields.difficulty != expected {
+			return Err(Error::InvalidDifficulty { expected, got: fields.difficulty });
+		}
+		let uncles = Extra::of(header)?.uncles;
+		if uncles.is_empty() {
+			return Ok(());
+		}
+		let ancestors = self.recent.ancestors(parent);
+		for (uncle, uncle_parent) in uncles.iter().zip(check_uncles(header.number, &uncles, &ancestors)?) {
+			let fields = uncle.fields()?;
+			let expected = self.difficulty(uncle_parent, fields.timestamp);
+			if fields.difficulty != expected {
+				return Err(Error::InvalidDifficulty { expected, got: fields.difficulty });
+			}
+		}
+		Ok(())
+	}
+
+	fn imported(&mut self, header: &Header) -> Result<()> {
+		if let Some(ref store) = self.store {
+			if let Err(e) = store.insert(header) {
+				warn!("cannot persist header of block {} in {:?}: {}", header.number, store.path(), e);
+			}
+		}
+		self.recent.insert(header.clone());
+		Ok(())
+	}
+
+	fn finalized(&mut self, header: &Header) {
+		if let Some(ref mut handler) = self.rewards {
+			// the extra of an imported header is valid
+			match rewards(header, self.params.block_reward) {
+				Ok(rewards) => handler.reward(header, &rewards),
+				Err(e) => warn!("cannot reward block {}: {}", header.number, e),
+			}
+		}
+		// the children of `header` look that far back for ancestors and uncles
+		let oldest = header.number.saturating_sub(MAX_UNCLE_AGE);
+		if let Some(ref store) = self.store {
+			if let Err(e) = store.prune(oldest) {
+				warn!("cannot prune headers before block {} in {:?}: {}", oldest, store.path(), e);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::reward::Reward;
+	use crate::uncles::block_hash;
+	use consensus_engine::{new_header, Fields};
+	use parking_lot::Mutex;
+	use tempdir::TempDir;
+
+	fn params(minimum_difficulty: u128) -> PowParams {
+		PowParams {
+			minimum_difficulty,
+			confirmations: 6,
+			progpow_transition: u64::MAX,
+			difficulty: DifficultyParams::default(),
+			block_reward: 3200,
+		}
+	}
+
+	/// Seal `header`, with a difficulty any value meets.
+	fn seal(engine: &PowEngine, header: &mut Header) {
+		let pow = engine.verifier().light(header.number).compute(&header_hash(header), 0, header.number);
+		header.set_seal(POW_ENGINE_ID, Seal { nonce: 0, mix_hash: pow.mix_hash }.encode());
+	}
+
+	/// Change the fields of `header`, its seal kept.
+	fn alter(header: &mut Header, change: impl FnOnce(&mut Fields)) {
+		let mut fields = header.fields().unwrap();
+		change(&mut fields);
+		header.set_fields(fields);
+	}
+
+	#[test]
+	fn test_verify_header() {
+		let tempdir = TempDir::new("").unwrap();
+		let engine = PowEngine::new(params(1), tempdir.path()).unwrap();
+		let mut header = new_header([0; 32], 1, Fields { timestamp: 10, difficulty: u128::MAX, ..Fields::default() });
+		assert!(matches!(engine.verify_header(&header), Err(Error::MissingSeal(1))));
+		header.set_seal(POW_ENGINE_ID, Seal { nonce: 0, mix_hash: [0u8; 32] }.encode());
+		// rejected by the quick check
+		assert!(matches!(engine.verify_header(&header), Err(Error::InvalidSeal(_))));
+		// any value is below the boundary of difficulty 1, but the mix hash is wrong
+		alter(&mut header, |fields| fields.difficulty = 1);
+		assert!(matches!(engine.verify_header(&header), Err(Error::InvalidSeal(_))));
+		header.set_seal(POW_ENGINE_ID, vec![1]);
+		assert!(engine.verify_header(&header).is_err());
+		header.set_seal(*b"gbft", Seal { nonce: 0, mix_hash: [0u8; 32] }.encode());
+		assert!(matches!(engine.verify_header(&header), Err(Error::MissingSeal(1))));
+	}
+
+	#[test]
+	fn test_verify_import() {
+		let tempdir = TempDir::new("").unwrap();
+		let mut engine = PowEngine::new(params(131_072), tempdir.path()).unwrap();
+		let parent = new_header([0; 32], 1, Fields { timestamp: 10, difficulty: 1_000_000, ..Fields::default() });
+		let mut header = new_header(parent.hash(), 2, Fields { timestamp: 5, ..Fields::default() });
+		engine.prepare(&mut header, &parent).unwrap();
+		let fields = header.fields().unwrap();
+		assert_eq!((fields.timestamp, fields.difficulty), (11, 1_000_488));
+		assert_eq!(engine.work(&header).unwrap().difficulty, 1_000_488);
+		engine.verify_import(&header, &parent).unwrap();
+		// sealed later, the difficulty goes down
+		alter(&mut header, |fields| fields.timestamp = 30);
+		assert!(matches!(
+			engine.verify_import(&header, &parent),
+			Err(Error::InvalidDifficulty { expected: 999_512, got: 1_000_488 })
+		));
+	}
+
+	struct Rewards(Arc<Mutex<Vec<Reward>>>);
+
+	impl RewardHandler for Rewards {
+		fn reward(&mut self, _header: &Header, rewards: &[Reward]) {
+			self.0.lock().extend_from_slice(rewards);
+		}
+	}
+
+	/// Build a block on `parent` and import it.
+	fn import(engine: &mut PowEngine, parent: &Header, author: u8) -> Header {
+		let mut header = new_header(block_hash(parent), parent.number + 1, Fields { author: vec![author], ..Fields::default() });
+		engine.prepare(&mut header, parent).unwrap();
+		seal(engine, &mut header);
+		engine.verify_header(&header).unwrap();
+		engine.verify_import(&header, parent).unwrap();
+		engine.imported(&header).unwrap();
+		header
+	}
+
+	#[test]
+	fn test_uncles() {
+		let tempdir = TempDir::new("").unwrap();
+		let rewards = Arc::new(Mutex::new(vec![]));
+		let mut engine = PowEngine::new(params(1), tempdir.path()).unwrap().with_rewards(Box::new(Rewards(rewards.clone())));
+		let genesis = new_header([0; 32], 0, Fields { difficulty: 1, ..Fields::default() });
+		let first = import(&mut engine, &genesis, 1);
+		let second = import(&mut engine, &first, 1);
+		let uncle = import(&mut engine, &first, 2);
+
+		// the next block includes the sibling of its parent
+		let third = import(&mut engine, &second, 1);
+		assert_eq!(Extra::of(&third).unwrap().uncles, vec![uncle.clone()]);
+		// rewarded once final only
+		assert!(rewards.lock().is_empty());
+		engine.finalized(&third);
+		assert_eq!(rewards.lock().iter().map(|r| (r.author[0], r.amount)).collect::<Vec<_>>(), vec![(1, 3300), (2, 2800)]);
+		assert_eq!(Extra::of(&import(&mut engine, &third, 1)).unwrap().uncles, vec![]);
+
+		// an uncle with a broken seal fails with the header
+		let mut forged = uncle.clone();
+		forged.set_seal(POW_ENGINE_ID, Seal { nonce: 1, mix_hash: [0u8; 32] }.encode());
+		let mut including = third.clone();
+		alter(&mut including, |fields| fields.extra = Extra { uncles: vec![forged] }.encode());
+		seal(&engine, &mut including);
+		assert!(matches!(engine.verify_header(&including), Err(Error::InvalidSeal(_))));
+
+		// an uncle already included is refused on import
+		let timestamp = third.fields().unwrap().timestamp + 1;
+		let extra = Extra { uncles: vec![uncle] }.encode();
+		let again = new_header(block_hash(&third), 4, Fields { timestamp, difficulty: 1, extra, ..Fields::default() });
+		assert!(matches!(engine.verify_import(&again, &third), Err(Error::InvalidExtra(_))));
+	}
+	#[test]
+	fn test_restart() {
+		let tempdir = TempDir::new("").unwrap();
+		let stored = || {
+			let store = HeaderStore::open(tempdir.path().join("headers")).unwrap();
+			PowEngine::new(params(1), tempdir.path()).unwrap().with_store(store).unwrap()
+		};
+		let mut engine = stored();
+		let genesis = new_header([0; 32], 0, Fields { difficulty: 1, ..Fields::default() });
+		let first = import(&mut engine, &genesis, 1);
+		let second = import(&mut engine, &first, 1);
+		let uncle = import(&mut engine, &first, 2);
+		let third = import(&mut engine, &second, 1);
+		assert_eq!(Extra::of(&third).unwrap().uncles, vec![uncle.clone()]);
+
+		// a restarted node knows the ancestors and the uncle candidates
+		let mut restarted = stored();
+		restarted.verify_import(&third, &second).unwrap();
+		let mut header = new_header(block_hash(&second), 3, Fields { author: vec![1], ..Fields::default() });
+		restarted.prepare(&mut header, &second).unwrap();
+		assert_eq!(Extra::of(&header).unwrap().uncles, vec![uncle]);
+		let fresh = PowEngine::new(params(1), tempdir.path()).unwrap();
+		assert!(matches!(fresh.verify_import(&third, &second), Err(Error::InvalidExtra(_))));
+
+		// finality prunes the headers no child of a final block looks at
+		let mut parent = third;
+		for _ in 0..MAX_UNCLE_AGE {
+			parent = import(&mut restarted, &parent, 1);
+		}
+		restarted.finalized(&parent);
+		let store = HeaderStore::open(tempdir.path().join("headers")).unwrap();
+		let numbers: Vec<u64> = store.headers().unwrap().iter().map(|h| h.number).collect();
+		assert_eq!(numbers, vec![3, 4, 5, 6, 7, 8, 9]);
+	}
+}
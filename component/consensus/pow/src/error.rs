@@ -0,0 +1,57 @@
+//! Proof of work verification errors.
+
+use std::fmt;
+
+use crate::keccak::H256;
+use crate::seeds::MAX_EPOCH;
+use crate::shared::to_hex;
+
+/// Result type alias for PoW.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error type for PoW.
+#[derive(derive_more::Display)]
+pub enum Error {
+	/// The mix hash of the seal is not the one computed from the light cache.
+	#[display(fmt = "Mix hash {} where {} was computed", "to_hex(got)", "to_hex(expected)")]
+	MismatchedMixHash {
+		/// Mix hash computed from the light cache.
+		expected: H256,
+		/// Mix hash of the seal.
+		got: H256,
+	},
+	/// The proof of work value is above the boundary of the difficulty.
+	#[display(fmt = "Proof of work {} above the boundary of difficulty {}", "to_hex(value)", difficulty)]
+	InsufficientDifficulty {
+		/// Difficulty of the header.
+		difficulty: u128,
+		/// Value recovered from the seal.
+		value: H256,
+	},
+	/// The light cache belongs to another epoch than the header.
+	#[display(fmt = "Light cache of epoch {} for a header of epoch {}", got, expected)]
+	WrongEpoch {
+		/// Epoch of the header.
+		expected: u64,
+		/// Epoch of the light cache.
+		got: u64,
+	},
+	/// The epoch is past the last one the sizes and seed hashes are computed for.
+	#[display(fmt = "Epoch {} past the last supported epoch {}", _0, MAX_EPOCH)]
+	EpochTooLarge(u64),
+}
+
+// Make `Debug` use the `Display` implementation.
+impl fmt::Debug for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for consensus_engine::Error {
+	fn from(err: Error) -> Self {
+		consensus_engine::Error::InvalidSeal(err.to_string())
+	}
+}
@@ -0,0 +1,235 @@
+//! Hashing primitives shared by the miner, the verifier and the stratum server.
+//!
+//! FNV mixes whole nodes at once: `fnv_mix` works on fixed size arrays, which
+//! the compiler turns into SIMD multiplies and xors without any unsafe code.
+//! `quick_get_difficulty` recovers the value of a seal from its claimed mix
+//! hash, which a difficulty accepts when it is at most the boundary of the
+//! difficulty, `(2^256 - 1) / difficulty`.
+
+use crate::keccak::{keccak_256, keccak_512, H256};
+use crate::progpow::{keccak_f800_long, keccak_f800_short};
+use crate::shared::NodeWords;
+
+pub const FNV_PRIME: u32 = 0x01000193;
+
+#[inline]
+pub fn fnv_hash(x: u32, y: u32) -> u32 {
+	x.wrapping_mul(FNV_PRIME) ^ y
+}
+
+/// `fnv_hash` of every word of `mix` with the word of `data` at the same index.
+#[inline]
+pub fn fnv_mix(mix: &mut NodeWords, data: &NodeWords) {
+	for (a, b) in mix.iter_mut().zip(data.iter()) {
+		*a = fnv_hash(*a, *b);
+	}
+}
+
+/// Value of a seal recovered from its claimed mix hash, to compare with the
+/// boundary before recomputing the mix hash.
+///
+/// `header_hash`      The hash of the header
+/// `nonce`            The block's nonce
+/// `mix_hash`         The mix digest hash
+pub fn quick_get_difficulty(header_hash: &H256, nonce: u64, mix_hash: &H256, progpow: bool) -> H256 {
+	if progpow {
+		let mut mix = [0u32; 8];
+		for (word, bytes) in mix.iter_mut().zip(mix_hash.chunks_exact(4)) {
+			*word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+		}
+		let seed = keccak_f800_short(*header_hash, nonce, [0u32; 8]);
+		keccak_f800_long(*header_hash, seed, mix)
+	} else {
+		let mut buf = [0u8; 64 + 32];
+		buf[..32].copy_from_slice(header_hash);
+		buf[32..40].copy_from_slice(&nonce.to_le_bytes());
+		let mut seed = [0u8; 64];
+		keccak_512::write(&buf[..40], &mut seed);
+		buf[..64].copy_from_slice(&seed);
+		buf[64..].copy_from_slice(mix_hash);
+
+		let mut hash = [0u8; 32];
+		keccak_256::write(&buf, &mut hash);
+		hash
+	}
+}
+
+/// Whether `value` is below the boundary of `difficulty`, `2^256 / difficulty`.
+pub fn meets_difficulty(value: &H256, difficulty: u128) -> bool {
+	if difficulty == 0 {
+		return false;
+	}
+	// value * difficulty < 2^256, on little endian 64 bit limbs
+	let mut limbs = [0u64; 4];
+	for (i, limb) in limbs.iter_mut().enumerate() {
+		let mut bytes = [0u8; 8];
+		bytes.copy_from_slice(&value[24 - i * 8..32 - i * 8]);
+		*limb = u64::from_be_bytes(bytes);
+	}
+	let factors = [difficulty as u64, (difficulty >> 64) as u64];
+	let mut product = [0u64; 6];
+	for (i, factor) in factors.iter().enumerate() {
+		let mut carry = 0u128;
+		for (j, limb) in limbs.iter().enumerate() {
+			let t = product[i + j] as u128 + *factor as u128 * *limb as u128 + carry;
+			product[i + j] = t as u64;
+			carry = t >> 64;
+		}
+		product[i + 4] = carry as u64;
+	}
+	product[4] == 0 && product[5] == 0
+}
+
+/// Largest value meeting `difficulty`, `(2^256 - 1) / difficulty`, as miners
+/// are handed it.
+pub fn difficulty_to_boundary(difficulty: u128) -> H256 {
+	if difficulty <= 1 {
+		return [0xff; 32];
+	}
+	let mut boundary = [0u8; 32];
+	// long division of 2^256 - 1, bit by bit
+	let mut remainder = 0u128;
+	for bit in 0..256 {
+		let overflow = remainder >> 127 == 1;
+		remainder = (remainder << 1) | 1;
+		if overflow || remainder >= difficulty {
+			remainder = remainder.wrapping_sub(difficulty);
+			boundary[bit / 8] |= 0x80 >> (bit % 8);
+		}
+	}
+	boundary
+}
+
+/// Difficulty of `boundary`, `(2^256 - 1) / boundary`, the highest one a value
+/// at the boundary meets. Boundaries below `2^128` saturate to `u128::MAX`.
+pub fn boundary_to_difficulty(boundary: &H256) -> u128 {
+	let mut bytes = [0u8; 16];
+	bytes.copy_from_slice(&boundary[..16]);
+	let high = u128::from_be_bytes(bytes);
+	bytes.copy_from_slice(&boundary[16..]);
+	let low = u128::from_be_bytes(bytes);
+	if high == 0 {
+		return u128::MAX;
+	}
+	// the high half of 2^256 - 1 is below the boundary, divide the low half bit by bit
+	let (mut rem_high, mut rem_low) = (0u128, u128::MAX);
+	let mut difficulty = 0u128;
+	for _ in 0..128 {
+		let overflow = rem_high >> 127 == 1;
+		rem_high = (rem_high << 1) | (rem_low >> 127);
+		rem_low = (rem_low << 1) | 1;
+		difficulty <<= 1;
+		if overflow || (rem_high, rem_low) >= (high, low) {
+			let (sub, borrow) = rem_low.overflowing_sub(low);
+			rem_high = rem_high.wrapping_sub(high).wrapping_sub(borrow as u128);
+			rem_low = sub;
+			difficulty |= 1;
+		}
+	}
+	difficulty
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_quick_get_difficulty() {
+		let hash = [
+			0xf5, 0x7e, 0x6f, 0x3a, 0xcf, 0xc0, 0xdd, 0x4b, 0x5b, 0xf2, 0xbe, 0xe4, 0x0a, 0xb3,
+			0x35, 0x8a, 0xa6, 0x87, 0x73, 0xa8, 0xd0, 0x9f, 0x5e, 0x59, 0x5e, 0xab, 0x55, 0x94,
+			0x05, 0x52, 0x7d, 0x72,
+		];
+		let mix_hash = [
+			0x1f, 0xff, 0x04, 0xce, 0xc9, 0x41, 0x73, 0xfd, 0x59, 0x1e, 0x3d, 0x89, 0x60, 0xce,
+			0x6b, 0xdf, 0x8b, 0x19, 0x71, 0x04, 0x8c, 0x71, 0xff, 0x93, 0x7b, 0xb2, 0xd3, 0x2a,
+			0x64, 0x31, 0xab, 0x6d,
+		];
+		let nonce = 0xd7b3ac70a301a249;
+		let boundary_good = [
+			0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x3e, 0x9b, 0x6c, 0x69, 0xbc, 0x2c, 0xe2, 0xa2,
+			0x4a, 0x8e, 0x95, 0x69, 0xef, 0xc7, 0xd7, 0x1b, 0x33, 0x35, 0xdf, 0x36, 0x8c, 0x9a,
+			0xe9, 0x7e, 0x53, 0x84,
+		];
+		assert_eq!(quick_get_difficulty(&hash, nonce, &mix_hash, false)[..], boundary_good[..]);
+		let boundary_bad = [
+			0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x3a, 0x9b, 0x6c, 0x69, 0xbc, 0x2c, 0xe2, 0xa2,
+			0x4a, 0x8e, 0x95, 0x69, 0xef, 0xc7, 0xd7, 0x1b, 0x33, 0x35, 0xdf, 0x36, 0x8c, 0x9a,
+			0xe9, 0x7e, 0x53, 0x84,
+		];
+		assert!(quick_get_difficulty(&hash, nonce, &mix_hash, false)[..] != boundary_bad[..]);
+	}
+
+	#[test]
+	fn test_fnv_mix() {
+		let mut mix = [0u32; 16];
+		let mut data = [0u32; 16];
+		for i in 0..16 {
+			mix[i] = (i as u32).wrapping_mul(0x9e3779b9);
+			data[i] = !(i as u32);
+		}
+		let expected: Vec<u32> = mix.iter().zip(data.iter()).map(|(a, b)| fnv_hash(*a, *b)).collect();
+		fnv_mix(&mut mix, &data);
+		assert_eq!(mix[..], expected[..]);
+		assert_eq!(fnv_hash(1, 2), FNV_PRIME ^ 2);
+	}
+
+	#[test]
+	fn test_meets_difficulty() {
+		let mut value = [0u8; 32];
+		value[0] = 0x01;
+		// 2^248 * 255 < 2^256 <= 2^248 * 256
+		assert!(meets_difficulty(&value, 255));
+		assert!(!meets_difficulty(&value, 256));
+		assert!(meets_difficulty(&[0xff; 32], 1));
+		assert!(!meets_difficulty(&[0xff; 32], 2));
+		assert!(meets_difficulty(&[0u8; 32], u128::MAX));
+		assert!(!meets_difficulty(&[0u8; 32], 0));
+
+		let mut small = [0u8; 32];
+		small[15] = 0x01;
+		// 2^128 * (2^128 - 1) < 2^256
+		assert!(meets_difficulty(&small, u128::MAX));
+		small[15] = 0x02;
+		assert!(!meets_difficulty(&small, u128::MAX));
+	}
+
+	#[test]
+	fn test_difficulty_to_boundary() {
+		assert_eq!(difficulty_to_boundary(1), [0xff; 32]);
+		let mut half = [0xff; 32];
+		half[0] = 0x7f;
+		assert_eq!(difficulty_to_boundary(2), half);
+
+		for &difficulty in [3, 256, 1_000_000, 0x085657254bd9, u128::MAX].iter() {
+			let boundary = difficulty_to_boundary(difficulty);
+			assert!(meets_difficulty(&boundary, difficulty));
+			// the next value is above the boundary
+			let mut next = boundary;
+			for byte in next.iter_mut().rev() {
+				let (sum, carry) = byte.overflowing_add(1);
+				*byte = sum;
+				if !carry {
+					break;
+				}
+			}
+			assert!(!meets_difficulty(&next, difficulty), "{}", difficulty);
+		}
+	}
+
+	#[test]
+	fn test_boundary_to_difficulty() {
+		for &difficulty in [1, 2, 3, 256, 1_000_000, 0x085657254bd9, u128::MAX].iter() {
+			assert_eq!(boundary_to_difficulty(&difficulty_to_boundary(difficulty)), difficulty);
+		}
+		let mut boundary = [0u8; 32];
+		boundary[0] = 0x01;
+		// (2^256 - 1) / 2^248
+		assert_eq!(boundary_to_difficulty(&boundary), 255);
+		assert_eq!(boundary_to_difficulty(&[0u8; 32]), u128::MAX);
+		boundary = [0u8; 32];
+		boundary[15] = 0x01;
+		// (2^256 - 1) / 2^128
+		assert_eq!(boundary_to_difficulty(&boundary), u128::MAX);
+	}
+}
@@ -0,0 +1,262 @@
+//! Multi-threaded CPU miner.
+//!
+//! Worker threads share the light cache of the work epoch, from the
+//! `Verifier`, and hash with Ethash or ProgPoW as the cache was built for.
+//! Started with `Datasets`, they read the full dataset of the epoch once it
+//! is generated, which the light cache stands in for until then.
+//! Thread `i` of `n` tries the nonces `i`, `i + n`, `i + 2n`... and checks
+//! the job version before every hash, so a new work package, a solution or
+//! `stop` interrupts all of them at the next nonce.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+use parking_lot::{Condvar, Mutex};
+
+use crate::compute::Light;
+use crate::dataset::{Dataset, Datasets};
+use crate::engine::Seal;
+use crate::hashing::meets_difficulty;
+use crate::keccak::H256;
+use crate::verifier::Verifier;
+
+/// What the miner searches a seal for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Work {
+	/// `header_hash` of the header to seal
+	pub header_hash: H256,
+	pub number: u64,
+	pub difficulty: u128,
+}
+
+/// A seal found for `work`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Solution {
+	pub work: Work,
+	pub seal: Seal,
+}
+
+struct Job {
+	/// bumped on every change of `work`, workers drop a job whose version moved on
+	version: u64,
+	work: Option<Work>,
+	shutdown: bool,
+}
+
+struct Shared {
+	verifier: Arc<Verifier>,
+	datasets: Option<Arc<Datasets>>,
+	job: Mutex<Job>,
+	changed: Condvar,
+	version: AtomicU64,
+	hashes: AtomicU64,
+}
+
+impl Shared {
+	/// Replace the current work, under the job lock.
+	fn replace(&self, job: &mut Job, work: Option<Work>) {
+		job.version += 1;
+		job.work = work;
+		self.version.store(job.version, Ordering::SeqCst);
+		self.changed.notify_all();
+	}
+}
+
+pub struct Miner {
+	shared: Arc<Shared>,
+	workers: Vec<JoinHandle<()>>,
+}
+
+impl Miner {
+	/// Start `threads` idle workers, solutions are sent to the returned receiver.
+	pub fn start(verifier: Arc<Verifier>, threads: usize) -> (Miner, Receiver<Solution>) {
+		Miner::spawn(verifier, None, threads)
+	}
+
+	/// Start `threads` idle workers hashing with the full datasets of `datasets`.
+	pub fn start_with_datasets(datasets: Arc<Datasets>, threads: usize) -> (Miner, Receiver<Solution>) {
+		Miner::spawn(datasets.verifier().clone(), Some(datasets), threads)
+	}
+
+	fn spawn(verifier: Arc<Verifier>, datasets: Option<Arc<Datasets>>, threads: usize) -> (Miner, Receiver<Solution>) {
+		let shared = Arc::new(Shared {
+			verifier,
+			datasets,
+			job: Mutex::new(Job { version: 0, work: None, shutdown: false }),
+			changed: Condvar::new(),
+			version: AtomicU64::new(0),
+			hashes: AtomicU64::new(0),
+		});
+		let (sender, receiver) = channel();
+		let threads = threads.max(1);
+		let workers = (0..threads)
+			.map(|index| {
+				let shared = shared.clone();
+				let sender = sender.clone();
+				thread::Builder::new()
+					.name(format!("pow-miner-{}", index))
+					.spawn(move || work(&shared, index as u64, threads as u64, &sender))
+					.expect("spawn miner thread; qed")
+			})
+			.collect();
+		(Miner { shared, workers }, receiver)
+	}
+
+	pub fn threads(&self) -> usize {
+		self.workers.len()
+	}
+
+	/// Mine `work` instead of the current work.
+	pub fn set_work(&self, work: Work) {
+		debug!("mine block {} at difficulty {}", work.number, work.difficulty);
+		let mut job = self.shared.job.lock();
+		self.shared.replace(&mut job, Some(work));
+	}
+
+	/// Stop mining until the next `set_work`.
+	pub fn stop(&self) {
+		let mut job = self.shared.job.lock();
+		self.shared.replace(&mut job, None);
+	}
+
+	/// Hashes computed since the start.
+	pub fn hashes(&self) -> u64 {
+		self.shared.hashes.load(Ordering::Relaxed)
+	}
+}
+
+impl Drop for Miner {
+	fn drop(&mut self) {
+		{
+			let mut job = self.shared.job.lock();
+			job.shutdown = true;
+			self.shared.replace(&mut job, None);
+		}
+		for worker in self.workers.drain(..) {
+			let _ = worker.join();
+		}
+	}
+}
+
+/// Loop of the worker `index` out of `threads`.
+fn work(shared: &Shared, index: u64, threads: u64, solutions: &Sender<Solution>) {
+	let mut version = 0;
+	loop {
+		let work = {
+			let mut job = shared.job.lock();
+			while !job.shutdown && (job.version == version || job.work.is_none()) {
+				version = job.version;
+				shared.changed.wait(&mut job);
+			}
+			if job.shutdown {
+				return;
+			}
+			version = job.version;
+			job.work.clone().expect("checked by the loop above; qed")
+		};
+
+		let light = shared.verifier.light(work.number);
+		let dataset = shared.datasets.as_ref().and_then(|datasets| datasets.get(work.number));
+		let mut nonce = index;
+		while shared.version.load(Ordering::SeqCst) == version {
+			let pow = match dataset {
+				Some(ref dataset) => dataset.compute(&light, &work.header_hash, nonce, work.number),
+				None => light.compute(&work.header_hash, nonce, work.number),
+			};
+			shared.hashes.fetch_add(1, Ordering::Relaxed);
+			if meets_difficulty(&pow.value, work.difficulty) {
+				let mut job = shared.job.lock();
+				if job.version == version {
+					trace!("found nonce {} for block {}", nonce, work.number);
+					let seal = Seal { nonce, mix_hash: pow.mix_hash };
+					let _ = solutions.send(Solution { work: work.clone(), seal });
+					shared.replace(&mut job, None);
+				}
+				break;
+			}
+			nonce = nonce.wrapping_add(threads);
+		}
+	}
+}
+
+/// Hashes per second of each of `threads` threads hashing block `number` for
+/// `duration`, with `dataset` if any or else `light`.
+pub fn benchmark(light: &Light, dataset: Option<&Dataset>, number: u64, threads: usize, duration: Duration) -> Vec<f64> {
+	let threads = threads.max(1);
+	crossbeam_utils::thread::scope(|scope| {
+		let workers: Vec<_> = (0..threads)
+			.map(|index| {
+				scope.spawn(move |_| {
+					let header_hash = [index as u8; 32];
+					let start = Instant::now();
+					let mut hashes = 0u64;
+					while hashes % 16 != 0 || start.elapsed() < duration {
+						let nonce = hashes;
+						match dataset {
+							Some(dataset) => dataset.compute(light, &header_hash, nonce, number),
+							None => light.compute(&header_hash, nonce, number),
+						};
+						hashes += 1;
+					}
+					hashes as f64 / start.elapsed().as_secs_f64()
+				})
+			})
+			.collect();
+		workers.into_iter().map(|worker| worker.join().expect("benchmark thread does not panic; qed")).collect()
+	})
+	.expect("benchmark threads are joined; qed")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+	use tempdir::TempDir;
+
+	fn work(difficulty: u128) -> Work {
+		Work { header_hash: [7u8; 32], number: 1, difficulty }
+	}
+
+	#[test]
+	fn test_mine() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Arc::new(Verifier::new(tempdir.path(), u64::MAX));
+		let (miner, solutions) = Miner::start(verifier.clone(), 2);
+		assert_eq!(miner.threads(), 2);
+
+		// a new package interrupts the one no thread could solve
+		miner.set_work(work(u128::MAX));
+		miner.set_work(work(8));
+		let solution = solutions.recv_timeout(Duration::from_secs(600)).unwrap();
+		assert_eq!(solution.work, work(8));
+		verifier.verify(&solution.work.header_hash, 1, 8, &solution.seal).unwrap();
+		assert!(miner.hashes() > 0);
+
+		// idle once solved
+		assert!(solutions.recv_timeout(Duration::from_millis(100)).is_err());
+	}
+
+	#[test]
+	fn test_benchmark() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Verifier::new(tempdir.path(), u64::MAX);
+		let rates = benchmark(&verifier.light(1), None, 1, 2, Duration::from_millis(100));
+		assert_eq!(rates.len(), 2);
+		assert!(rates.iter().all(|&rate| rate > 0.0));
+	}
+
+	#[test]
+	fn test_stop() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Arc::new(Verifier::new(tempdir.path(), u64::MAX));
+		let (miner, solutions) = Miner::start(verifier, 3);
+		miner.set_work(work(u128::MAX));
+		miner.stop();
+		drop(miner);
+		assert!(solutions.recv().is_err());
+	}
+}
@@ -1,7 +1,8 @@
 
-use compute::{FNV_PRIME, calculate_dag_item};
-use keccak::H256;
-use shared::{ETHASH_ACCESSES, ETHASH_MIX_BYTES, Node, get_data_size};
+use crate::compute::calculate_dag_item;
+use crate::hashing::FNV_PRIME;
+use crate::keccak::H256;
+use crate::shared::{ETHASH_ACCESSES, ETHASH_MIX_BYTES, Node, get_data_size};
 
 const PROGPOW_CACHE_BYTES: usize = 16 * 1024;
 const PROGPOW_CACHE_WORDS: usize = PROGPOW_CACHE_BYTES / 4;
@@ -103,12 +104,11 @@ pub fn keccak_f800_long(header_hash: H256, nonce: u64, result: [u32; 8]) -> H256
 	let mut st = [0u32; 25];
 	keccak_f800(header_hash, nonce, result, &mut st);
 
-	// NOTE: transmute from `[u32; 8]` to `[u8; 32]`
-	unsafe {
-		std::mem::transmute(
-			[st[0], st[1], st[2], st[3], st[4], st[5], st[6], st[7]]
-		)
+	let mut hash = [0u8; 32];
+	for (bytes, word) in hash.chunks_exact_mut(4).zip(st.iter()) {
+		bytes.copy_from_slice(&word.to_le_bytes());
 	}
+	hash
 }
 
 #[inline]
@@ -222,11 +222,11 @@ fn progpow_init(seed: u64) -> (Kiss99, [u32; PROGPOW_REGS], [u32; PROGPOW_REGS])
 
 pub type CDag = [u32; PROGPOW_CACHE_WORDS];
 
-fn progpow_loop(
+fn progpow_loop<F: Fn(u32) -> Node>(
 	seed: u64,
 	loop_: usize,
 	mix: &mut [[u32; PROGPOW_REGS]; PROGPOW_LANES],
-	cache: &[Node],
+	dag_item: &F,
 	c_dag: &CDag,
 	data_size: usize,
 ) {
@@ -236,13 +236,13 @@ fn progpow_loop(
 		(64 * data_size / (PROGPOW_LANES * PROGPOW_DAG_LOADS));
 
 	// 256 bytes of dag data
-	let mut dag_item = [0u32; 64];
+	let mut dag_words = [0u32; 64];
 
 	// Fetch DAG nodes (64 bytes each)
 	for l in 0..PROGPOW_DAG_LOADS {
 		let index = g_offset * PROGPOW_LANES * PROGPOW_DAG_LOADS + l * 16;
-		let node = calculate_dag_item(index as u32 / 16, cache);
-		dag_item[l * 16..(l + 1) * 16].clone_from_slice(node.as_words());
+		let node = dag_item(index as u32 / 16);
+		dag_words[l * 16..(l + 1) * 16].clone_from_slice(node.as_words());
 	}
 
 	let (rnd, mix_seq_dst, mix_seq_cache) = progpow_init(seed);
@@ -298,7 +298,7 @@ fn progpow_loop(
 		let mut data_g = [0u32; PROGPOW_DAG_LOADS];
 		let index = ((l ^ loop_) % PROGPOW_LANES) * PROGPOW_DAG_LOADS;
 		for i in 0..PROGPOW_DAG_LOADS {
-			data_g[i] = dag_item[index + i];
+			data_g[i] = dag_words[index + i];
 		}
 
 		// Consume the global load data at the very end of the loop to allow
@@ -318,6 +318,18 @@ pub fn progpow(
 	block_number: u64,
 	cache: &[Node],
 	c_dag: &CDag,
+) -> (H256, H256) {
+	progpow_with(header_hash, nonce, block_number, c_dag, |index| calculate_dag_item(index, cache))
+}
+
+/// ProgPoW hash reading the dataset items from `dag_item`, computed from the
+/// light cache or read from the full dataset.
+pub fn progpow_with<F: Fn(u32) -> Node>(
+	header_hash: H256,
+	nonce: u64,
+	block_number: u64,
+	c_dag: &CDag,
+	dag_item: F,
 ) -> (H256, H256) {
 	let mut mix = [[0u32; PROGPOW_REGS]; PROGPOW_LANES];
 	let mut lane_results = [0u32; PROGPOW_LANES];
@@ -343,7 +355,7 @@ pub fn progpow(
 			period,
 			i,
 			&mut mix,
-			cache,
+			&dag_item,
 			c_dag,
 			data_size,
 		);
@@ -388,16 +400,17 @@ pub fn generate_cdag(cache: &[Node]) -> CDag {
 mod test {
 	use tempdir::TempDir;
 
-	use common_types::engines::OptimizeFor;
-	use cache::NodeCacheBuilder;
-	use keccak::H256;
+	use crate::cache::NodeCacheBuilder;
+	use crate::hashing::quick_get_difficulty;
+	use crate::keccak::H256;
+	use crate::shared::{epoch, OptimizeFor};
 	use rustc_hex::FromHex;
 	use serde_json::{self, Value};
-	use std::collections::VecDeque;
+	use std::collections::{BTreeMap, VecDeque};
 	use super::*;
 
 	fn h256(hex: &str) -> H256 {
-		let bytes = FromHex::from_hex(hex).unwrap();
+		let bytes: Vec<u8> = hex.from_hex().unwrap();
 		let mut res = [0; 32];
 		res.copy_from_slice(&bytes);
 		res
@@ -422,6 +435,10 @@ mod test {
 			c_dag.iter().take(20).cloned().collect::<Vec<_>>(),
 			expected,
 		);
+
+		// generated once by the cache
+		assert_eq!(cache.c_dag()[..], c_dag[..]);
+		assert!(::std::ptr::eq(cache.c_dag(), cache.c_dag()));
 	}
 
 	#[test]
@@ -515,16 +532,16 @@ mod test {
 			&c_dag,
 		);
 
-		let expected_digest = FromHex::from_hex("63155f732f2bf556967f906155b510c917e48e99685ead76ea83f4eca03ab12b").unwrap();
-		let expected_result = FromHex::from_hex("faeb1be51075b03a4ff44b335067951ead07a3b078539ace76fd56fc410557a3").unwrap();
+		let expected_digest = h256("63155f732f2bf556967f906155b510c917e48e99685ead76ea83f4eca03ab12b");
+		let expected_result = h256("faeb1be51075b03a4ff44b335067951ead07a3b078539ace76fd56fc410557a3");
 
 		assert_eq!(
-			digest.to_vec(),
+			digest,
 			expected_digest,
 		);
 
 		assert_eq!(
-			result.to_vec(),
+			result,
 			expected_result,
 		);
 	}
@@ -560,22 +577,26 @@ mod test {
 			}
 		}).collect();
 
+		// vectors of an epoch share its cache and CDag
+		let builder = NodeCacheBuilder::new(OptimizeFor::Memory, u64::max_value());
+		let tempdir = TempDir::new("").unwrap();
+		let mut caches = BTreeMap::new();
 		for test in tests {
-			let builder = NodeCacheBuilder::new(OptimizeFor::Memory, u64::max_value());
-			let tempdir = TempDir::new("").unwrap();
-			let cache = builder.new_cache(tempdir.path().to_owned(), test.block_number);
-			let c_dag = generate_cdag(cache.as_ref());
+			let cache = caches
+				.entry(epoch(test.block_number))
+				.or_insert_with(|| builder.new_cache(tempdir.path().to_owned(), test.block_number));
 
 			let (digest, result) = progpow(
 				test.header_hash,
 				test.nonce,
 				test.block_number,
 				cache.as_ref(),
-				&c_dag,
+				cache.c_dag(),
 			);
 
 			assert_eq!(digest, test.final_hash);
 			assert_eq!(result, test.mix_hash);
+			assert_eq!(quick_get_difficulty(&test.header_hash, test.nonce, &test.mix_hash, true), test.final_hash);
 		}
 	}
 }
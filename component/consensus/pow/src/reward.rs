@@ -0,0 +1,75 @@
+//! Block rewards.
+//!
+//! The author of a block earns the block reward and a 32nd of it for each
+//! uncle the block includes. The author of an uncle `depth` blocks older than
+//! the block earns `(8 - depth) / 8` of it. The engine computes them once a
+//! block is final, so blocks of side chains earn nothing, and hands them to a
+//! `RewardHandler`. Crediting them is left to the handler: the runtime has no
+//! reward module yet.
+
+use consensus_engine::{ConsensusHeader, Header, Result};
+
+use crate::uncles::Extra;
+
+/// Why an author earns a reward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RewardKind {
+	/// sealed the block
+	Author,
+	/// sealed an uncle of the block, `depth` blocks older than it
+	Uncle { depth: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reward {
+	pub author: Vec<u8>,
+	pub amount: u128,
+	pub kind: RewardKind,
+}
+
+/// Receives the rewards of the final blocks, in the order they become final.
+pub trait RewardHandler: Send {
+	fn reward(&mut self, header: &Header, rewards: &[Reward]);
+}
+
+/// Rewards of `header` for a reward of `block_reward` per block, the author's first.
+pub fn rewards(header: &Header, block_reward: u128) -> Result<Vec<Reward>> {
+	let uncles = Extra::of(header)?.uncles;
+	let bonus = block_reward / 32 * uncles.len() as u128;
+	let mut rewards = vec![Reward {
+		author: header.fields()?.author,
+		amount: block_reward.saturating_add(bonus),
+		kind: RewardKind::Author,
+	}];
+	for uncle in uncles {
+		let depth = header.number.saturating_sub(uncle.number);
+		rewards.push(Reward {
+			author: uncle.fields()?.author,
+			amount: block_reward / 8 * u128::from(8u64.saturating_sub(depth)),
+			kind: RewardKind::Uncle { depth },
+		});
+	}
+	Ok(rewards)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Encode;
+	use consensus_engine::{new_header, Fields};
+
+	#[test]
+	fn test_rewards() {
+		let author = |author: u8| Fields { author: vec![author], ..Fields::default() };
+		let uncles = vec![new_header([0; 32], 9, author(2)), new_header([0; 32], 4, author(3))];
+		let header = new_header([0; 32], 10, Fields { extra: Extra { uncles }.encode(), ..author(1) });
+		assert_eq!(rewards(&header, 3200).unwrap(), vec![
+			Reward { author: vec![1], amount: 3400, kind: RewardKind::Author },
+			Reward { author: vec![2], amount: 2800, kind: RewardKind::Uncle { depth: 1 } },
+			Reward { author: vec![3], amount: 800, kind: RewardKind::Uncle { depth: 6 } },
+		]);
+
+		let header = new_header([0; 32], 10, author(1));
+		assert_eq!(rewards(&header, 3200).unwrap()[0].amount, 3200);
+	}
+}
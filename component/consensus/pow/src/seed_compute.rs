@@ -1,5 +1,5 @@
-use shared;
-use keccak::{keccak_256, H256};
+use crate::shared;
+use crate::keccak::{keccak_256, H256};
 
 use std::cell::Cell;
 
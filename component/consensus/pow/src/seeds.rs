@@ -0,0 +1,254 @@
+//! Epochs: seed hashes and the sizes of the light cache and dataset.
+//!
+//! The seed hash of epoch `n` is the keccak-256 hash of the one of `n - 1`,
+//! from zero, so computing it takes `n` hashes. `SeedHashCache` keeps every
+//! seed hash it computed, in a file of the cache directory, and computes the
+//! next ones from the last one it knows. The file is checked hash by hash
+//! when opened, only its valid prefix is kept.
+//!
+//! Epochs past `MAX_EPOCH` are refused: their sizes would overflow and their
+//! seed hashes take too long to compute.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::error::{Error, Result};
+use crate::keccak::{keccak_256, H256};
+use crate::seed_compute::SeedHashCompute;
+use crate::shared::{epoch, get_cache_size, get_data_size, ETHASH_EPOCH_LENGTH};
+
+/// File of the seed hashes in the cache directory.
+const SEEDS_FILE: &str = "seedhashes";
+
+/// Epochs whose seed hash is kept, the later ones are computed from the last of them.
+const CACHED_EPOCHS: usize = 4096;
+
+/// Last supported epoch, its dataset is some 257 GiB.
+pub const MAX_EPOCH: u64 = 1 << 15;
+
+/// Refuse epochs past `MAX_EPOCH`.
+pub fn check_epoch(epoch: u64) -> Result<u64> {
+	if epoch > MAX_EPOCH {
+		return Err(Error::EpochTooLarge(epoch));
+	}
+	Ok(epoch)
+}
+
+/// Byte size of the light cache of `epoch`.
+pub fn cache_size(epoch: u64) -> Result<usize> {
+	Ok(get_cache_size(check_epoch(epoch)? * ETHASH_EPOCH_LENGTH))
+}
+
+/// Byte size of the dataset of `epoch`.
+pub fn dataset_size(epoch: u64) -> Result<usize> {
+	Ok(get_data_size(check_epoch(epoch)? * ETHASH_EPOCH_LENGTH))
+}
+
+pub struct SeedHashCache {
+	path: Option<PathBuf>,
+	/// seed hashes from epoch 0 on
+	seeds: Mutex<Vec<H256>>,
+}
+
+impl SeedHashCache {
+	pub fn in_memory() -> Self {
+		SeedHashCache { path: None, seeds: Mutex::new(vec![[0u8; 32]]) }
+	}
+
+	/// Cache persisted in `dir`, with the seed hashes stored there.
+	pub fn open(dir: &Path) -> io::Result<Self> {
+		let path = dir.join(SEEDS_FILE);
+		let mut seeds = vec![[0u8; 32]];
+		match fs::read(&path) {
+			Ok(bytes) => {
+				// a write cut short leaves a partial last seed hash, dropped
+				seeds = bytes.chunks_exact(32).take(CACHED_EPOCHS).map(|chunk| {
+					let mut seed = [0u8; 32];
+					seed.copy_from_slice(chunk);
+					seed
+				}).collect();
+				let valid = valid_prefix(&seeds);
+				if valid < seeds.len() {
+					warn!("ignore invalid seed hashes in {:?} from epoch {}", path, valid);
+					seeds.truncate(valid);
+				}
+				if seeds.is_empty() {
+					seeds.push([0u8; 32]);
+				}
+				if bytes.len() != seeds.len() * 32 {
+					fs::write(&path, seeds.concat())?;
+				}
+			},
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+				fs::create_dir_all(dir)?;
+				fs::write(&path, seeds.concat())?;
+			},
+			Err(e) => return Err(e),
+		}
+
+		Ok(SeedHashCache { path: Some(path), seeds: Mutex::new(seeds) })
+	}
+
+	/// Seed hash of `epoch`, hashing up to `MAX_EPOCH` times.
+	pub fn seed_hash(&self, epoch: u64) -> H256 {
+		assert!(epoch <= MAX_EPOCH, "epoch {} past the last supported epoch", epoch);
+		let mut seeds = self.seeds.lock();
+		if let Some(seed) = seeds.get(epoch as usize) {
+			return *seed;
+		}
+
+		let known = seeds.len();
+		let mut seed = *seeds.last().expect("epoch 0 is always known; qed");
+		while seeds.len() < CACHED_EPOCHS && seeds.len() as u64 <= epoch {
+			keccak_256::inplace(&mut seed);
+			seeds.push(seed);
+		}
+		// past the cached epochs, only hash on
+		let last = seeds.len() as u64 - 1;
+		if epoch > last {
+			seed = SeedHashCompute::resume_compute_seedhash(seed, last, epoch);
+		}
+
+		if seeds.len() > known {
+			if let Err(e) = self.append(&seeds[known..]) {
+				warn!("cannot save seed hashes: {}", e);
+			}
+		}
+		seed
+	}
+
+	/// Seed hash of the epoch of block `number`.
+	pub fn seed_hash_of_block(&self, number: u64) -> H256 {
+		self.seed_hash(epoch(number))
+	}
+
+	/// Epochs whose seed hash is known without hashing.
+	pub fn known(&self) -> u64 {
+		self.seeds.lock().len() as u64
+	}
+
+	fn append(&self, seeds: &[H256]) -> io::Result<()> {
+		if let Some(ref path) = self.path {
+			let mut file = OpenOptions::new().append(true).open(path)?;
+			file.write_all(&seeds.concat())?;
+		}
+		Ok(())
+	}
+}
+
+/// Number of seed hashes of `seeds` each hashing to the next, from zero.
+fn valid_prefix(seeds: &[H256]) -> usize {
+	if seeds.first() != Some(&[0u8; 32]) {
+		return 0;
+	}
+	1 + seeds.windows(2).take_while(|pair| {
+		let mut next = pair[0];
+		keccak_256::inplace(&mut next);
+		next == pair[1]
+	}).count()
+}
+
+/// What an epoch is made of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpochInfo {
+	pub epoch: u64,
+	pub first_block: u64,
+	pub last_block: u64,
+	pub seed_hash: H256,
+	pub cache_size: usize,
+	pub dataset_size: usize,
+}
+
+impl EpochInfo {
+	/// Epoch of block `number`, with its seed hash from `seeds`.
+	pub fn of_block(number: u64, seeds: &SeedHashCache) -> Result<Self> {
+		let epoch = check_epoch(epoch(number))?;
+		let first_block = epoch * ETHASH_EPOCH_LENGTH;
+		Ok(EpochInfo {
+			epoch,
+			first_block,
+			last_block: first_block + ETHASH_EPOCH_LENGTH - 1,
+			seed_hash: seeds.seed_hash(epoch),
+			cache_size: cache_size(epoch)?,
+			dataset_size: dataset_size(epoch)?,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempdir::TempDir;
+
+	#[test]
+	fn test_seed_hash() {
+		let seeds = SeedHashCache::in_memory();
+		let compute = SeedHashCompute::default();
+		for &epoch in [0, 1, 16, 2, 300].iter() {
+			assert_eq!(seeds.seed_hash(epoch), compute.hash_epoch(epoch));
+		}
+		assert_eq!(seeds.known(), 301);
+		assert_eq!(seeds.seed_hash_of_block(486382), compute.hash_block_number(486382));
+
+		// past the cached epochs
+		let epoch = CACHED_EPOCHS as u64 + 10;
+		assert_eq!(seeds.seed_hash(epoch), compute.hash_epoch(epoch));
+		assert_eq!(seeds.known(), CACHED_EPOCHS as u64);
+		assert_eq!(seeds.seed_hash(epoch - 5), compute.hash_epoch(epoch - 5));
+	}
+
+	#[test]
+	fn test_persisted() {
+		let tempdir = TempDir::new("").unwrap();
+		let seed = SeedHashCache::open(tempdir.path()).unwrap().seed_hash(20);
+		let path = tempdir.path().join(SEEDS_FILE);
+		assert_eq!(fs::metadata(&path).unwrap().len(), 21 * 32);
+
+		// reopened, the seed hashes are read back
+		let seeds = SeedHashCache::open(tempdir.path()).unwrap();
+		assert_eq!(seeds.known(), 21);
+		assert_eq!(seeds.seed_hash(20), seed);
+
+		// a partial seed hash is dropped
+		let mut bytes = fs::read(&path).unwrap();
+		bytes.truncate(10 * 32 + 7);
+		fs::write(&path, &bytes).unwrap();
+		let seeds = SeedHashCache::open(tempdir.path()).unwrap();
+		assert_eq!(seeds.known(), 10);
+		assert_eq!(seeds.seed_hash(20), seed);
+		assert_eq!(fs::metadata(&path).unwrap().len(), 21 * 32);
+
+		// so is everything from a seed hash not hashing from the previous one
+		let mut bytes = fs::read(&path).unwrap();
+		bytes[12 * 32] ^= 1;
+		fs::write(&path, &bytes).unwrap();
+		let seeds = SeedHashCache::open(tempdir.path()).unwrap();
+		assert_eq!(seeds.known(), 12);
+		assert_eq!(fs::metadata(&path).unwrap().len(), 12 * 32);
+		assert_eq!(seeds.seed_hash(20), seed);
+	}
+
+	#[test]
+	fn test_epoch_info() {
+		let seeds = SeedHashCache::in_memory();
+		let info = EpochInfo::of_block(ETHASH_EPOCH_LENGTH + 5, &seeds).unwrap();
+		assert_eq!(info.epoch, 1);
+		assert_eq!(info.first_block, 30000);
+		assert_eq!(info.last_block, 59999);
+		assert_eq!(info.cache_size, 16907456);
+		assert_eq!(info.dataset_size, 1082130304);
+		assert_eq!(info.seed_hash, SeedHashCompute::default().hash_epoch(1));
+
+		let last = (MAX_EPOCH + 1) * ETHASH_EPOCH_LENGTH - 1;
+		assert_eq!(EpochInfo::of_block(last, &seeds).unwrap().dataset_size, 275951645824);
+		match EpochInfo::of_block(u64::MAX, &seeds) {
+			Err(Error::EpochTooLarge(epoch)) => assert_eq!(epoch, u64::MAX / ETHASH_EPOCH_LENGTH),
+			other => panic!("unexpected {:?}", other),
+		}
+		assert!(cache_size(MAX_EPOCH + 1).is_err());
+	}
+}
@@ -0,0 +1,124 @@
+//! Recent headers persisted on disk, so the ancestors and uncle candidates
+//! of the next blocks are known again after a restart.
+//!
+//! The store is a directory with one file per header, named after the hex
+//! block hash and holding the SCALE encoded `Header`. Files are written to a
+//! temporary name, renamed and the directory synced, a crash leaves either no
+//! file or a whole one. Unreadable files are skipped.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use codec::{Decode, Encode};
+use consensus_engine::Header;
+use log::warn;
+
+use crate::shared::to_hex;
+use crate::uncles::block_hash;
+
+const HEADER_EXT: &str = "header";
+const TMP_EXT: &str = "tmp";
+
+#[derive(Debug)]
+pub struct HeaderStore {
+	dir: PathBuf,
+}
+
+impl HeaderStore {
+	/// Open the store in `dir`, creating it if needed.
+	pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<HeaderStore> {
+		let dir = dir.as_ref().to_path_buf();
+		fs::create_dir_all(&dir)?;
+		Ok(HeaderStore { dir })
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.dir
+	}
+
+	/// Persist `header`.
+	pub fn insert(&self, header: &Header) -> io::Result<()> {
+		let path = self.header_path(&block_hash(header));
+		let tmp = path.with_extension(TMP_EXT);
+		let mut file = File::create(&tmp)?;
+		file.write_all(&header.encode())?;
+		file.sync_all()?;
+		fs::rename(&tmp, &path)?;
+		sync_dir(&self.dir)
+	}
+
+	/// Every readable stored header, sorted by block number.
+	pub fn headers(&self) -> io::Result<Vec<Header>> {
+		let mut headers = vec![];
+		for entry in fs::read_dir(&self.dir)? {
+			let path = entry?.path();
+			if path.extension() != Some(OsStr::new(HEADER_EXT)) {
+				continue;
+			}
+			let header = fs::read(&path).and_then(|bytes| {
+				Header::decode(&mut &bytes[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+			});
+			match header {
+				Ok(header) => headers.push(header),
+				Err(e) => warn!("skip unreadable header {:?}: {}", path, e),
+			}
+		}
+		headers.sort_by_key(|header| header.number);
+		Ok(headers)
+	}
+
+	/// Remove the headers of the blocks below `number`.
+	pub fn prune(&self, number: u64) -> io::Result<()> {
+		for header in self.headers()?.iter().take_while(|h| h.number < number) {
+			fs::remove_file(self.header_path(&block_hash(header)))?;
+		}
+		Ok(())
+	}
+
+	fn header_path(&self, hash: &[u8]) -> PathBuf {
+		self.dir.join(format!("{}.{}", to_hex(hash), HEADER_EXT))
+	}
+}
+
+/// Sync the entries of `dir`, the headers renamed in it.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+	File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use consensus_engine::{new_header, Fields};
+	use tempdir::TempDir;
+
+	#[test]
+	fn test_insert_headers_prune() {
+		let dir = TempDir::new("pow_headers").unwrap();
+		let store = HeaderStore::open(dir.path()).unwrap();
+		let header = |number: u64| new_header([0; 32], number, Fields { author: vec![number as u8], ..Fields::default() });
+		for number in &[2, 1, 3] {
+			store.insert(&header(*number)).unwrap();
+		}
+		// again, replaced
+		store.insert(&header(2)).unwrap();
+
+		// reopened, as after a restart
+		let store = HeaderStore::open(dir.path()).unwrap();
+		assert_eq!(store.headers().unwrap(), vec![header(1), header(2), header(3)]);
+		store.prune(3).unwrap();
+		assert_eq!(store.headers().unwrap(), vec![header(3)]);
+
+		// an unreadable header is skipped
+		fs::write(store.header_path(&block_hash(&header(3))), [1, 2, 3]).unwrap();
+		store.insert(&header(4)).unwrap();
+		assert_eq!(store.headers().unwrap(), vec![header(4)]);
+	}
+}
@@ -0,0 +1,621 @@
+//! Stratum server for external miners.
+//!
+//! Miners connect over TCP and exchange JSON-RPC messages, one per line:
+//! `mining.subscribe`, `mining.authorize` with the worker name and password,
+//! and `mining.submit` with `[worker, job id, nonce, header hash, mix hash]`.
+//! Every `set_work` pushes a `mining.notify` with
+//! `[job id, header hash, seed hash, boundary, clean]` to the subscribed and
+//! authorized sessions, the boundary being the one of the share difficulty.
+//!
+//! Shares are checked with `quick_get_difficulty` only, so are counted on
+//! the mix hash the miner claims. A share meeting the difficulty of the work
+//! is verified with the light cache before it is sent to the node as a
+//! `Solution`.
+//!
+//! Sessions are limited to `max_sessions`, and dropped when they send a line
+//! longer than `MAX_LINE` or nothing for `READ_TIMEOUT`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, info, trace, warn};
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+
+use crate::engine::Seal;
+use crate::hashing::difficulty_to_boundary;
+use crate::keccak::H256;
+use crate::miner::{Solution, Work};
+use crate::shared::to_hex;
+use crate::verifier::Verifier;
+
+/// Sessions not reading their notifications are dropped after this.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Sessions sending nothing, not even a share, are dropped after this.
+const READ_TIMEOUT: Duration = Duration::from_secs(600);
+/// Longest request line, in bytes.
+pub const MAX_LINE: usize = 16 * 1024;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INVALID_SOLUTION: i64 = 20;
+const STALE_JOB: i64 = 21;
+const DUPLICATE_SHARE: i64 = 22;
+const LOW_DIFFICULTY: i64 = 23;
+const UNAUTHORIZED: i64 = 24;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StratumParams {
+	/// difficulty of the shares, capped at the one of the work
+	pub share_difficulty: u128,
+	/// password workers authorize with, any is accepted without
+	pub secret: Option<String>,
+	/// connections served at once, the others are closed
+	pub max_sessions: usize,
+}
+
+struct Job {
+	id: String,
+	work: Work,
+	seed_hash: H256,
+	share_difficulty: u128,
+	/// nonces submitted for the job
+	nonces: HashSet<u64>,
+}
+
+impl Job {
+	fn notify(&self) -> Value {
+		json!({
+			"id": null,
+			"method": "mining.notify",
+			"params": [
+				self.id,
+				hex(&self.work.header_hash),
+				hex(&self.seed_hash),
+				hex(&difficulty_to_boundary(self.share_difficulty)),
+				true,
+			],
+		})
+	}
+}
+
+struct Session {
+	/// writers of the session lock it, one line at a time
+	stream: Arc<Mutex<TcpStream>>,
+	subscribed: bool,
+	worker: Option<String>,
+}
+
+struct Shared {
+	verifier: Arc<Verifier>,
+	params: StratumParams,
+	jobs: AtomicU64,
+	job: Mutex<Option<Job>>,
+	sessions: Mutex<BTreeMap<u64, Session>>,
+	solutions: Sender<Solution>,
+	accepted: AtomicU64,
+	rejected: AtomicU64,
+	shutdown: AtomicBool,
+}
+
+/// Thread serving a session, flagged done once it returns.
+type SessionThread = (Arc<AtomicBool>, JoinHandle<()>);
+
+pub struct Stratum {
+	shared: Arc<Shared>,
+	addr: SocketAddr,
+	threads: Arc<Mutex<Vec<SessionThread>>>,
+	acceptor: Option<JoinHandle<()>>,
+}
+
+impl Stratum {
+	/// Listen on `addr`, solutions are sent to the returned receiver.
+	pub fn start(
+		addr: &SocketAddr,
+		verifier: Arc<Verifier>,
+		params: StratumParams,
+	) -> io::Result<(Stratum, Receiver<Solution>)> {
+		let listener = TcpListener::bind(addr)?;
+		let addr = listener.local_addr()?;
+		let (solutions, receiver) = channel();
+		let shared = Arc::new(Shared {
+			verifier,
+			params,
+			jobs: AtomicU64::new(0),
+			job: Mutex::new(None),
+			sessions: Mutex::new(BTreeMap::new()),
+			solutions,
+			accepted: AtomicU64::new(0),
+			rejected: AtomicU64::new(0),
+			shutdown: AtomicBool::new(false),
+		});
+		let threads = Arc::new(Mutex::new(Vec::new()));
+		let acceptor = {
+			let shared = shared.clone();
+			let threads = threads.clone();
+			thread::Builder::new()
+				.name("pow-stratum".into())
+				.spawn(move || accept(&shared, listener, &threads))?
+		};
+		info!("stratum listening on {}", addr);
+		Ok((Stratum { shared, addr, threads, acceptor: Some(acceptor) }, receiver))
+	}
+
+	pub fn local_addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	/// Hand out `work` instead of the current work, to every session.
+	pub fn set_work(&self, work: Work) {
+		let notify = {
+			let mut job = self.shared.job.lock();
+			let id = self.shared.jobs.fetch_add(1, Ordering::SeqCst) + 1;
+			let new = Job {
+				id: format!("{:x}", id),
+				share_difficulty: self.shared.params.share_difficulty.clamp(1, work.difficulty.max(1)),
+				seed_hash: self.shared.verifier.seeds().seed_hash_of_block(work.number),
+				work,
+				nonces: HashSet::new(),
+			};
+			let notify = new.notify();
+			*job = Some(new);
+			notify
+		};
+		let ready: Vec<_> = {
+			let sessions = self.shared.sessions.lock();
+			sessions
+				.iter()
+				.filter(|(_, session)| session.subscribed && session.worker.is_some())
+				.map(|(id, session)| (*id, session.stream.clone()))
+				.collect()
+		};
+		for (id, stream) in ready {
+			self.shared.write(id, &stream, &notify);
+		}
+	}
+
+	/// Connected sessions.
+	pub fn sessions(&self) -> usize {
+		self.shared.sessions.lock().len()
+	}
+
+	/// Shares accepted and rejected since the start.
+	pub fn shares(&self) -> (u64, u64) {
+		(self.shared.accepted.load(Ordering::Relaxed), self.shared.rejected.load(Ordering::Relaxed))
+	}
+}
+
+impl Drop for Stratum {
+	fn drop(&mut self) {
+		self.shared.shutdown.store(true, Ordering::SeqCst);
+		// wake the acceptor up, then the sessions
+		let _ = TcpStream::connect(self.addr);
+		if let Some(acceptor) = self.acceptor.take() {
+			let _ = acceptor.join();
+		}
+		let streams: Vec<_> = self.shared.sessions.lock().values().map(|session| session.stream.clone()).collect();
+		for stream in streams {
+			let _ = stream.lock().shutdown(Shutdown::Both);
+		}
+		for (_, thread) in self.threads.lock().drain(..) {
+			let _ = thread.join();
+		}
+	}
+}
+
+fn accept(shared: &Arc<Shared>, listener: TcpListener, threads: &Mutex<Vec<SessionThread>>) {
+	let mut next = 0u64;
+	for stream in listener.incoming() {
+		if shared.shutdown.load(Ordering::SeqCst) {
+			return;
+		}
+		let stream = match stream.and_then(|s| {
+			s.set_write_timeout(Some(WRITE_TIMEOUT))?;
+			s.set_read_timeout(Some(READ_TIMEOUT))?;
+			Ok(s)
+		}) {
+			Ok(stream) => stream,
+			Err(e) => {
+				warn!("stratum accept: {}", e);
+				continue;
+			},
+		};
+		let writer = match stream.try_clone() {
+			Ok(writer) => writer,
+			Err(e) => {
+				warn!("stratum accept: {}", e);
+				continue;
+			},
+		};
+		next += 1;
+		let id = next;
+		{
+			// a session leaves the map before its connection is closed
+			let mut sessions = shared.sessions.lock();
+			if sessions.len() >= shared.params.max_sessions {
+				debug!("stratum refuses {:?}, {} sessions", stream.peer_addr(), sessions.len());
+				continue;
+			}
+			debug!("stratum session {} from {:?}", id, stream.peer_addr());
+			sessions.insert(id, Session { stream: Arc::new(Mutex::new(writer)), subscribed: false, worker: None });
+		}
+
+		let done = Arc::new(AtomicBool::new(false));
+		let session = {
+			let (shared, done) = (shared.clone(), done.clone());
+			thread::Builder::new().name(format!("pow-stratum-{}", id)).spawn(move || {
+				serve(&shared, id, stream);
+				done.store(true, Ordering::SeqCst);
+			})
+		};
+		let mut threads = threads.lock();
+		threads.retain(|(done, _)| !done.load(Ordering::SeqCst));
+		match session {
+			Ok(session) => threads.push((done, session)),
+			Err(e) => {
+				warn!("stratum session {}: {}", id, e);
+				shared.sessions.lock().remove(&id);
+			},
+		}
+	}
+}
+
+/// Answer the requests of the session `id` until it disconnects.
+fn serve(shared: &Shared, id: u64, stream: TcpStream) {
+	let mut reader = BufReader::new(stream);
+	let mut line = Vec::new();
+	loop {
+		line.clear();
+		match (&mut reader).take(MAX_LINE as u64).read_until(b'\n', &mut line) {
+			Ok(0) => break,
+			Ok(read) if read == MAX_LINE && !line.ends_with(b"\n") => {
+				debug!("stratum session {}: line longer than {} bytes", id, MAX_LINE);
+				break;
+			},
+			Ok(_) => (),
+			Err(e) => {
+				debug!("stratum session {}: {}", id, e);
+				break;
+			},
+		}
+		let request = std::str::from_utf8(&line).ok().and_then(|line| serde_json::from_str::<Value>(line).ok());
+		let (response, notify) = match request {
+			Some(request) => shared.handle(id, &request),
+			None => (error(&Value::Null, PARSE_ERROR, "Parse error"), false),
+		};
+		if !shared.send(id, &response) {
+			break;
+		}
+		if notify {
+			let notify = shared.job.lock().as_ref().map(Job::notify);
+			if let Some(notify) = notify {
+				shared.send(id, &notify);
+			}
+		}
+	}
+	shared.sessions.lock().remove(&id);
+}
+
+impl Shared {
+	/// The response to `request`, and whether the current job is to be sent after it.
+	fn handle(&self, id: u64, request: &Value) -> (Value, bool) {
+		let rid = &request["id"];
+		let params = request["params"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+		trace!("stratum session {}: {}", id, request);
+		match request["method"].as_str() {
+			Some("mining.subscribe") => {
+				let ready = self.update(id, |session| {
+					session.subscribed = true;
+					session.worker.is_some()
+				});
+				(response(rid, json!(true)), ready)
+			},
+			Some("mining.authorize") => {
+				let worker = match params.first().and_then(Value::as_str) {
+					Some(worker) => worker.to_owned(),
+					None => return (error(rid, INVALID_PARAMS, "Invalid params"), false),
+				};
+				let password = params.get(1).and_then(Value::as_str);
+				if self.params.secret.as_ref().map_or(false, |secret| Some(secret.as_str()) != password) {
+					info!("stratum worker {} refused", worker);
+					return (response(rid, json!(false)), false);
+				}
+				let ready = self.update(id, |session| {
+					session.worker = Some(worker);
+					session.subscribed
+				});
+				(response(rid, json!(true)), ready)
+			},
+			Some("mining.submit") => {
+				if !self.update(id, |session| session.worker.is_some()) {
+					return (error(rid, UNAUTHORIZED, "Unauthorized worker"), false);
+				}
+				let job = params.get(1).and_then(Value::as_str);
+				let nonce = params.get(2).and_then(parse_nonce);
+				let header_hash = params.get(3).and_then(parse_hash);
+				let mix_hash = params.get(4).and_then(parse_hash);
+				let result = match (job, nonce, header_hash, mix_hash) {
+					(Some(job), Some(nonce), Some(header_hash), Some(mix_hash)) => {
+						self.submit(job, &header_hash, Seal { nonce, mix_hash })
+					},
+					_ => Err((INVALID_PARAMS, "Invalid params")),
+				};
+				match result {
+					Ok(()) => {
+						self.accepted.fetch_add(1, Ordering::Relaxed);
+						(response(rid, json!(true)), false)
+					},
+					Err((code, message)) => {
+						debug!("stratum session {}: share rejected, {}", id, message);
+						self.rejected.fetch_add(1, Ordering::Relaxed);
+						(error(rid, code, message), false)
+					},
+				}
+			},
+			_ => (error(rid, METHOD_NOT_FOUND, "Method not found"), false),
+		}
+	}
+
+	/// Check a share of the job `job`, send it on as a solution if it meets the work.
+	fn submit(&self, job: &str, header_hash: &H256, seal: Seal) -> Result<(), (i64, &'static str)> {
+		let work = {
+			let mut current = self.job.lock();
+			let current = match *current {
+				Some(ref mut current) if current.id == job && current.work.header_hash == *header_hash => current,
+				_ => return Err((STALE_JOB, "Job not found")),
+			};
+			let work = &current.work;
+			if self.verifier.quick_check(header_hash, work.number, current.share_difficulty, &seal).is_err() {
+				return Err((LOW_DIFFICULTY, "Low difficulty share"));
+			}
+			if current.nonces.contains(&seal.nonce) {
+				return Err((DUPLICATE_SHARE, "Duplicate share"));
+			}
+			if self.verifier.quick_check(header_hash, work.number, work.difficulty, &seal).is_err() {
+				current.nonces.insert(seal.nonce);
+				return Ok(());
+			}
+			work.clone()
+		};
+		// the light cache verification takes a while, other sessions submit meanwhile
+		if let Err(e) = self.verifier.verify(header_hash, work.number, work.difficulty, &seal) {
+			warn!("stratum solution for block {} rejected: {}", work.number, e);
+			return Err((INVALID_SOLUTION, "Invalid solution"));
+		}
+		if let Some(ref mut current) = *self.job.lock() {
+			if current.id == job && !current.nonces.insert(seal.nonce) {
+				return Err((DUPLICATE_SHARE, "Duplicate share"));
+			}
+		}
+		info!("stratum solution for block {}", work.number);
+		let _ = self.solutions.send(Solution { work, seal });
+		Ok(())
+	}
+
+	/// Apply `f` to the session `id`, false once it is gone.
+	fn update<F: FnOnce(&mut Session) -> bool>(&self, id: u64, f: F) -> bool {
+		self.sessions.lock().get_mut(&id).map_or(false, f)
+	}
+
+	/// Write `value` to the session `id`, false once it is gone.
+	fn send(&self, id: u64, value: &Value) -> bool {
+		let stream = match self.sessions.lock().get(&id) {
+			Some(session) => session.stream.clone(),
+			None => return false,
+		};
+		self.write(id, &stream, value)
+	}
+
+	/// Write `value` to `stream` of the session `id`, which is dropped if it
+	/// fails. The sessions are not locked meanwhile.
+	fn write(&self, id: u64, stream: &Mutex<TcpStream>, value: &Value) -> bool {
+		let result = {
+			let mut stream = stream.lock();
+			let result = write_line(&mut stream, value);
+			if result.is_err() {
+				let _ = stream.shutdown(Shutdown::Both);
+			}
+			result
+		};
+		if let Err(e) = result {
+			debug!("drop stratum session {}: {}", id, e);
+			self.sessions.lock().remove(&id);
+			return false;
+		}
+		true
+	}
+}
+
+fn write_line(stream: &mut TcpStream, value: &Value) -> io::Result<()> {
+	let mut line = serde_json::to_vec(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	line.push(b'\n');
+	stream.write_all(&line)
+}
+
+fn response(id: &Value, result: Value) -> Value {
+	json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error(id: &Value, code: i64, message: &str) -> Value {
+	json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn hex(bytes: &[u8]) -> String {
+	format!("0x{}", to_hex(bytes))
+}
+
+fn strip_hex(value: &Value) -> Option<&str> {
+	let s = value.as_str()?;
+	Some(s.strip_prefix("0x").unwrap_or(s))
+}
+
+fn parse_hash(value: &Value) -> Option<H256> {
+	let s = strip_hex(value)?;
+	if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return None;
+	}
+	let mut hash = [0u8; 32];
+	for (i, byte) in hash.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+	}
+	Some(hash)
+}
+
+fn parse_nonce(value: &Value) -> Option<u64> {
+	let s = strip_hex(value)?;
+	if s.is_empty() || s.len() > 16 {
+		return None;
+	}
+	u64::from_str_radix(s, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::hashing::{meets_difficulty, quick_get_difficulty};
+	use tempdir::TempDir;
+
+	struct Client {
+		reader: BufReader<TcpStream>,
+		writer: TcpStream,
+		id: u64,
+	}
+
+	impl Client {
+		fn connect(addr: SocketAddr) -> Client {
+			let writer = TcpStream::connect(addr).unwrap();
+			writer.set_read_timeout(Some(Duration::from_secs(60))).unwrap();
+			Client { reader: BufReader::new(writer.try_clone().unwrap()), writer, id: 0 }
+		}
+
+		fn read(&mut self) -> Value {
+			let mut line = String::new();
+			self.reader.read_line(&mut line).unwrap();
+			serde_json::from_str(&line).unwrap()
+		}
+
+		fn call(&mut self, method: &str, params: Value) -> Value {
+			self.id += 1;
+			write_line(&mut self.writer, &json!({ "id": self.id, "method": method, "params": params })).unwrap();
+			let response = self.read();
+			assert_eq!(response["id"], self.id);
+			response
+		}
+
+		fn submit(&mut self, job: &Value, nonce: u64, header_hash: &H256, mix_hash: &H256) -> Value {
+			let params = json!(["worker", job, format!("0x{:016x}", nonce), hex(header_hash), hex(mix_hash)]);
+			self.call("mining.submit", params)
+		}
+	}
+
+	fn work(header_hash: H256, difficulty: u128) -> Work {
+		Work { header_hash, number: 1, difficulty }
+	}
+
+	#[test]
+	fn test_session() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Arc::new(Verifier::new(tempdir.path(), u64::MAX));
+		let params = StratumParams { share_difficulty: 2, secret: Some("secret".into()), max_sessions: 8 };
+		let (stratum, solutions) = Stratum::start(&"127.0.0.1:0".parse().unwrap(), verifier.clone(), params).unwrap();
+		let mut client = Client::connect(stratum.local_addr());
+
+		assert_eq!(client.call("mining.subscribe", json!([]))["result"], true);
+		assert_eq!(client.call("mining.submit", json!([]))["error"]["code"], UNAUTHORIZED);
+		assert_eq!(client.call("mining.authorize", json!(["worker", "wrong"]))["result"], false);
+		assert_eq!(client.call("mining.authorize", json!(["worker", "secret"]))["result"], true);
+		assert_eq!(client.call("mining.unknown", json!([]))["error"]["code"], METHOD_NOT_FOUND);
+
+		// garbage is answered, not fatal
+		client.writer.write_all(b"\xff\xfe\n").unwrap();
+		assert_eq!(client.read()["error"]["code"], PARSE_ERROR);
+
+		// shares are checked on the claimed mix hash only
+		stratum.set_work(work([1u8; 32], 1 << 40));
+		let notify = client.read();
+		assert_eq!(notify["method"], "mining.notify");
+		let job = notify["params"][0].clone();
+		assert_eq!(notify["params"][1], hex(&[1u8; 32]));
+		assert_eq!(notify["params"][3], hex(&difficulty_to_boundary(2)));
+		let mix_hash = [0x55; 32];
+		let meets = |nonce: &u64| meets_difficulty(&quick_get_difficulty(&[1u8; 32], *nonce, &mix_hash, false), 2);
+		let share = (0..).find(meets).unwrap();
+		let low = (0..).find(|nonce| !meets(nonce)).unwrap();
+		assert_eq!(client.submit(&job, share, &[1u8; 32], &mix_hash)["result"], true);
+		assert_eq!(client.submit(&job, share, &[1u8; 32], &mix_hash)["error"]["code"], DUPLICATE_SHARE);
+		assert_eq!(client.submit(&job, low, &[1u8; 32], &mix_hash)["error"]["code"], LOW_DIFFICULTY);
+		assert!(solutions.try_recv().is_err());
+
+		// a new head makes the job stale
+		stratum.set_work(work([2u8; 32], 4));
+		let notify = client.read();
+		assert_eq!(client.submit(&job, share, &[1u8; 32], &mix_hash)["error"]["code"], STALE_JOB);
+
+		// a share meeting the work is a solution
+		let job = notify["params"][0].clone();
+		let light = verifier.light(1);
+		let (nonce, pow) = (0..)
+			.map(|nonce| (nonce, light.compute(&[2u8; 32], nonce, 1)))
+			.find(|(_, pow)| meets_difficulty(&pow.value, 4))
+			.unwrap();
+		let forged = (0..=255u8)
+			.map(|byte| [byte; 32])
+			.find(|mix_hash| meets_difficulty(&quick_get_difficulty(&[2u8; 32], nonce, mix_hash, false), 4))
+			.unwrap();
+		assert_eq!(client.submit(&job, nonce, &[2u8; 32], &forged)["error"]["code"], INVALID_SOLUTION);
+		assert_eq!(client.submit(&job, nonce, &[2u8; 32], &pow.mix_hash)["result"], true);
+		let solution = solutions.recv_timeout(Duration::from_secs(10)).unwrap();
+		assert_eq!(solution.work, work([2u8; 32], 4));
+		assert_eq!(solution.seal, Seal { nonce, mix_hash: pow.mix_hash });
+		assert_eq!(stratum.shares(), (2, 4));
+	}
+
+	#[test]
+	fn test_notify_on_authorize() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Arc::new(Verifier::new(tempdir.path(), u64::MAX));
+		let params = StratumParams { share_difficulty: 1 << 20, secret: None, max_sessions: 8 };
+		let (stratum, _solutions) = Stratum::start(&"127.0.0.1:0".parse().unwrap(), verifier, params).unwrap();
+		stratum.set_work(work([3u8; 32], 1 << 10));
+
+		let mut client = Client::connect(stratum.local_addr());
+		assert_eq!(client.call("mining.authorize", json!(["worker"]))["result"], true);
+		client.call("mining.subscribe", json!([]));
+		// the share difficulty is capped at the one of the work
+		assert_eq!(client.read()["params"][3], hex(&difficulty_to_boundary(1 << 10)));
+		assert_eq!(stratum.sessions(), 1);
+
+		drop(client);
+		drop(stratum);
+	}
+
+	#[test]
+	fn test_session_limits() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Arc::new(Verifier::new(tempdir.path(), u64::MAX));
+		let params = StratumParams { share_difficulty: 1, secret: None, max_sessions: 1 };
+		let (stratum, _solutions) = Stratum::start(&"127.0.0.1:0".parse().unwrap(), verifier, params).unwrap();
+		let mut client = Client::connect(stratum.local_addr());
+		assert_eq!(client.call("mining.subscribe", json!([]))["result"], true);
+
+		// a second connection is closed while the first is served
+		let mut refused = Client::connect(stratum.local_addr());
+		let mut line = String::new();
+		assert_eq!(refused.reader.read_line(&mut line).unwrap(), 0);
+		assert_eq!(stratum.sessions(), 1);
+
+		// an endless line drops the session, then there is room again
+		client.writer.write_all(&vec![b'x'; MAX_LINE + 1]).unwrap();
+		// closed with the line unread, the connection may be reset
+		assert!(client.reader.read_line(&mut line).map_or(true, |read| read == 0));
+		let mut next = Client::connect(stratum.local_addr());
+		assert_eq!(next.call("mining.subscribe", json!([]))["result"], true);
+	}
+}
@@ -0,0 +1,195 @@
+//! Uncles: sealed headers of side chains included by a later block.
+//!
+//! A block lists at most `MAX_UNCLES` uncles in its `Extra`. An uncle is at
+//! most `MAX_UNCLE_AGE` blocks older than the block including it, its parent
+//! is one of the ancestors of that block, it is not an ancestor itself, and
+//! neither the block nor one of its recent ancestors includes it already.
+//! Their authors earn part of the block reward, see `reward`, so miners lose
+//! less to blocks orphaned by propagation delays.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use codec::{Decode, Encode};
+use consensus_engine::{check_parent, ConsensusHeader, Error, Header, Result};
+
+use crate::keccak::H256;
+
+/// Uncles a block includes at most.
+pub const MAX_UNCLES: usize = 2;
+
+/// Blocks an uncle is older than the block including it at most.
+pub const MAX_UNCLE_AGE: u64 = 6;
+
+/// The PoW content of `Fields::extra`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Extra {
+	pub uncles: Vec<Header>,
+}
+
+impl Extra {
+	/// Decode the extra of `header`, empty meaning no uncle.
+	pub fn of(header: &Header) -> Result<Self> {
+		let extra = header.fields()?.extra;
+		if extra.is_empty() {
+			return Ok(Extra::default());
+		}
+		Extra::decode(&mut &extra[..]).map_err(|e| Error::InvalidExtra(e.to_string()))
+	}
+}
+
+/// Hash identifying `header` as a parent, the `parent_hash` of its children.
+pub fn block_hash(header: &Header) -> H256 {
+	header.hash()
+}
+
+/// Headers imported lately, whatever their chain: the ancestors of the next
+/// blocks and their uncles.
+#[derive(Default)]
+pub struct RecentHeaders {
+	/// headers by block hash
+	headers: BTreeMap<H256, Header>,
+}
+
+impl RecentHeaders {
+	pub fn insert(&mut self, header: Header) {
+		let oldest = header.number.saturating_sub(MAX_UNCLE_AGE + 1);
+		self.headers.retain(|_, h| h.number >= oldest);
+		self.headers.insert(block_hash(&header), header);
+	}
+
+	/// `parent` then its known ancestors, the ones the uncles of a child of
+	/// `parent` may descend from.
+	pub fn ancestors<'a>(&'a self, parent: &'a Header) -> Vec<&'a Header> {
+		let mut ancestors = vec![parent];
+		while (ancestors.len() as u64) <= MAX_UNCLE_AGE {
+			let last = ancestors[ancestors.len() - 1];
+			match self.headers.get(&last.parent_hash) {
+				Some(header) if header.number + 1 == last.number => ancestors.push(header),
+				_ => break,
+			}
+		}
+		ancestors
+	}
+
+	/// Uncles a child of `parent` may include, the most recent first.
+	pub fn candidates(&self, parent: &Header) -> Vec<Header> {
+		let ancestors = self.ancestors(parent);
+		let number = parent.number + 1;
+		let mut candidates: Vec<&Header> = self
+			.headers
+			.values()
+			.filter(|uncle| check_uncle(number, uncle, &ancestors).is_ok())
+			.collect();
+		candidates.sort_by_key(|uncle| std::cmp::Reverse(uncle.number));
+		candidates.into_iter().take(MAX_UNCLES).cloned().collect()
+	}
+}
+
+/// Check the uncles of the block `number` against its `ancestors`, as
+/// `RecentHeaders::ancestors` lists them, and return the parent of each.
+/// Their seals and difficulty are left to the engine.
+pub fn check_uncles<'a>(number: u64, uncles: &[Header], ancestors: &[&'a Header]) -> Result<Vec<&'a Header>> {
+	if uncles.len() > MAX_UNCLES {
+		return Err(Error::InvalidExtra(format!("{} uncles, at most {} allowed", uncles.len(), MAX_UNCLES)));
+	}
+	let mut seen = BTreeSet::new();
+	uncles
+		.iter()
+		.map(|uncle| {
+			if !seen.insert(block_hash(uncle)) {
+				return Err(Error::InvalidExtra(format!("uncle {} included twice", uncle.number)));
+			}
+			check_uncle(number, uncle, ancestors)
+		})
+		.collect()
+}
+
+fn check_uncle<'a>(number: u64, uncle: &Header, ancestors: &[&'a Header]) -> Result<&'a Header> {
+	if uncle.number >= number || number - uncle.number > MAX_UNCLE_AGE {
+		return Err(Error::InvalidExtra(format!("uncle {} out of range of block {}", uncle.number, number)));
+	}
+	let hash = block_hash(uncle);
+	if ancestors.iter().any(|ancestor| block_hash(ancestor) == hash) {
+		return Err(Error::InvalidExtra(format!("uncle {} is an ancestor", uncle.number)));
+	}
+	for ancestor in ancestors {
+		if Extra::of(ancestor)?.uncles.iter().any(|included| block_hash(included) == hash) {
+			return Err(Error::InvalidExtra(format!("uncle {} already included", uncle.number)));
+		}
+	}
+	let parent = ancestors
+		.iter()
+		.find(|ancestor| block_hash(ancestor) == uncle.parent_hash)
+		.ok_or_else(|| Error::InvalidExtra(format!("parent of uncle {} is not an ancestor", uncle.number)))?;
+	check_parent(uncle, parent)?;
+	Ok(parent)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use consensus_engine::{new_header, Fields};
+
+	/// A chain of `len` headers after `parent`, a fork when `author` differs.
+	fn chain(parent: &Header, len: u64, author: u8) -> Vec<Header> {
+		let mut headers: Vec<Header> = vec![];
+		for _ in 0..len {
+			let last = headers.last().unwrap_or(parent);
+			let timestamp = last.fields().unwrap().timestamp + 10;
+			let fields = Fields { timestamp, author: vec![author], ..Fields::default() };
+			headers.push(new_header(block_hash(last), last.number + 1, fields));
+		}
+		headers
+	}
+
+	#[test]
+	fn test_check_uncles() {
+		let genesis = new_header([0; 32], 0, Fields::default());
+		let main = chain(&genesis, 10, 1);
+		let mut recent = RecentHeaders::default();
+		for header in main.iter() {
+			recent.insert(header.clone());
+		}
+		let parent = &main[9];
+		let ancestors = recent.ancestors(parent);
+		assert_eq!(ancestors.len() as u64, MAX_UNCLE_AGE + 1);
+
+		// siblings of blocks 5 to 10
+		let uncle = |number: u64| chain(&main[number as usize - 2], 1, 2).remove(0);
+		assert!(check_uncles(11, &[uncle(10), uncle(5)], &ancestors).is_ok());
+		assert_eq!(check_uncles(11, &[uncle(9)], &ancestors).unwrap()[0], &main[7]);
+
+		// too old, too many, twice, an ancestor
+		assert!(check_uncles(11, &[uncle(4)], &ancestors).is_err());
+		assert!(check_uncles(11, &[uncle(10), uncle(9), uncle(8)], &ancestors).is_err());
+		assert!(check_uncles(11, &[uncle(10), uncle(10)], &ancestors).is_err());
+		assert!(check_uncles(11, &[main[8].clone()], &ancestors).is_err());
+
+		// the parent of the uncle is not an ancestor
+		let nephew = chain(&uncle(9), 1, 3).remove(0);
+		assert!(check_uncles(11, &[nephew], &ancestors).is_err());
+	}
+
+	#[test]
+	fn test_included_once() {
+		let genesis = new_header([0; 32], 0, Fields::default());
+		let mut main = chain(&genesis, 4, 1);
+		let uncle = chain(&main[1], 1, 2).remove(0);
+		let mut recent = RecentHeaders::default();
+		for header in main.iter() {
+			recent.insert(header.clone());
+		}
+		recent.insert(uncle.clone());
+		assert_eq!(recent.candidates(&main[3]), vec![uncle.clone()]);
+
+		// once a block includes it, its children may not
+		let mut including = chain(&main[3], 1, 1).remove(0);
+		including.set_fields(Fields { extra: Extra { uncles: vec![uncle.clone()] }.encode(), ..including.fields().unwrap() });
+		assert_eq!(Extra::of(&including).unwrap().uncles, vec![uncle.clone()]);
+		recent.insert(including.clone());
+		main.push(including);
+		assert!(recent.candidates(&main[4]).is_empty());
+		let ancestors = recent.ancestors(&main[4]);
+		assert!(check_uncles(6, &[uncle], &ancestors).is_err());
+	}
+}
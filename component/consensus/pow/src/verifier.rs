@@ -0,0 +1,192 @@
+//! Proof of work verification of sealed headers.
+//!
+//! `Verifier::verify` runs the cheap check first: `quick_get_difficulty`
+//! recovers the value of the seal from its claimed mix hash with two hashes,
+//! which rejects seals below the difficulty. Only then is the mix hash
+//! recomputed from the light cache of the header epoch, which proves the
+//! miner went through the dataset.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{debug, warn};
+use parking_lot::Mutex;
+
+use crate::cache::NodeCacheBuilder;
+use crate::compute::Light;
+use crate::engine::Seal;
+use crate::hashing::{meets_difficulty, quick_get_difficulty};
+use crate::error::{Error, Result};
+use crate::keccak::H256;
+use crate::seeds::{check_epoch, SeedHashCache};
+use crate::shared::{epoch, OptimizeFor};
+
+/// Light caches kept in memory, verification mostly hits the last epochs.
+const LIGHT_CACHES: usize = 3;
+
+pub struct Verifier {
+	builder: NodeCacheBuilder,
+	cache_dir: PathBuf,
+	progpow_transition: u64,
+	lights: Mutex<VecDeque<Arc<Light>>>,
+}
+
+impl Verifier {
+	/// Verifier keeping its light caches in `cache_dir`, hashing with ProgPoW
+	/// from block `progpow_transition` on.
+	pub fn new<P: Into<PathBuf>>(cache_dir: P, progpow_transition: u64) -> Self {
+		let cache_dir = cache_dir.into();
+		let seeds = SeedHashCache::open(&cache_dir).unwrap_or_else(|e| {
+			warn!("cannot open seed hashes in {:?}: {}", cache_dir, e);
+			SeedHashCache::in_memory()
+		});
+		Verifier {
+			builder: NodeCacheBuilder::new(OptimizeFor::Memory, progpow_transition).with_seeds(Arc::new(seeds)),
+			cache_dir,
+			progpow_transition,
+			lights: Mutex::new(VecDeque::with_capacity(LIGHT_CACHES)),
+		}
+	}
+
+	pub fn cache_dir(&self) -> &Path {
+		&self.cache_dir
+	}
+
+	/// Seed hashes of the epochs, persisted in the cache directory.
+	pub fn seeds(&self) -> &Arc<SeedHashCache> {
+		self.builder.seeds()
+	}
+
+	/// Check the seal of the header `header_hash` at block `number`.
+	pub fn verify(&self, header_hash: &H256, number: u64, difficulty: u128, seal: &Seal) -> Result<()> {
+		self.quick_check(header_hash, number, difficulty, seal)?;
+		full_check(&self.light(number), header_hash, number, difficulty, seal)
+	}
+
+	/// Check the value recovered from the claimed mix hash against the
+	/// difficulty, and that the epoch of `number` is supported.
+	pub fn quick_check(&self, header_hash: &H256, number: u64, difficulty: u128, seal: &Seal) -> Result<()> {
+		check_epoch(epoch(number))?;
+		let progpow = number >= self.progpow_transition;
+		let value = quick_get_difficulty(header_hash, seal.nonce, &seal.mix_hash, progpow);
+		if !meets_difficulty(&value, difficulty) {
+			return Err(Error::InsufficientDifficulty { difficulty, value });
+		}
+		Ok(())
+	}
+
+	/// The light cache to verify block `number` with, loaded from the cache
+	/// directory or built.
+	pub fn light(&self, number: u64) -> Arc<Light> {
+		let progpow = number >= self.progpow_transition;
+		let mut lights = self.lights.lock();
+		if let Some(light) = lights.iter().find(|l| l.epoch() == epoch(number) && l.is_progpow() == progpow) {
+			return light.clone();
+		}
+
+		let light = match self.builder.light_from_file(&self.cache_dir, number) {
+			Ok(light) => light,
+			Err(e) => {
+				debug!("build light cache of epoch {}: {}", epoch(number), e);
+				if let Err(e) = fs::create_dir_all(&self.cache_dir) {
+					warn!("cannot create {:?}: {}", self.cache_dir, e);
+				}
+				let mut light = self.builder.light(&self.cache_dir, number);
+				if let Err(e) = light.to_file() {
+					warn!("cannot save light cache of epoch {}: {}", epoch(number), e);
+				}
+				light
+			},
+		};
+		let light = Arc::new(light);
+		if lights.len() == LIGHT_CACHES {
+			lights.pop_front();
+		}
+		lights.push_back(light.clone());
+		light
+	}
+}
+
+/// Recompute the seal of the header `header_hash` at block `number` with `light`.
+pub fn full_check(light: &Light, header_hash: &H256, number: u64, difficulty: u128, seal: &Seal) -> Result<()> {
+	if light.epoch() != epoch(number) {
+		return Err(Error::WrongEpoch { expected: epoch(number), got: light.epoch() });
+	}
+	let pow = light.compute(header_hash, seal.nonce, number);
+	if pow.mix_hash != seal.mix_hash {
+		return Err(Error::MismatchedMixHash { expected: pow.mix_hash, got: seal.mix_hash });
+	}
+	if !meets_difficulty(&pow.value, difficulty) {
+		return Err(Error::InsufficientDifficulty { difficulty, value: pow.value });
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempdir::TempDir;
+
+	const HEADER_HASH: H256 = [
+		0xf5, 0x7e, 0x6f, 0x3a, 0xcf, 0xc0, 0xdd, 0x4b, 0x5b, 0xf2, 0xbe, 0xe4, 0x0a, 0xb3,
+		0x35, 0x8a, 0xa6, 0x87, 0x73, 0xa8, 0xd0, 0x9f, 0x5e, 0x59, 0x5e, 0xab, 0x55, 0x94,
+		0x05, 0x52, 0x7d, 0x72,
+	];
+	const MIX_HASH: H256 = [
+		0x1f, 0xff, 0x04, 0xce, 0xc9, 0x41, 0x73, 0xfd, 0x59, 0x1e, 0x3d, 0x89, 0x60, 0xce,
+		0x6b, 0xdf, 0x8b, 0x19, 0x71, 0x04, 0x8c, 0x71, 0xff, 0x93, 0x7b, 0xb2, 0xd3, 0x2a,
+		0x64, 0x31, 0xab, 0x6d,
+	];
+	const NONCE: u64 = 0xd7b3ac70a301a249;
+	const NUMBER: u64 = 486382;
+	// difficulty of the block, the value of the seal is 0x0000000000013e9b...
+	const DIFFICULTY: u128 = 0x085657254bd9;
+
+	#[test]
+	fn test_verify() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Verifier::new(tempdir.path(), u64::MAX);
+		let seal = Seal { nonce: NONCE, mix_hash: MIX_HASH };
+		verifier.verify(&HEADER_HASH, NUMBER, DIFFICULTY, &seal).unwrap();
+
+		// the value is above the boundary, rejected without the light cache
+		match verifier.quick_check(&HEADER_HASH, NUMBER, DIFFICULTY << 8, &seal) {
+			Err(Error::InsufficientDifficulty { .. }) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+
+		// a forged mix hash passes the quick check at difficulty 1 only
+		let forged = Seal { nonce: NONCE, mix_hash: [0xff; 32] };
+		verifier.quick_check(&HEADER_HASH, NUMBER, 1, &forged).unwrap();
+		match verifier.verify(&HEADER_HASH, NUMBER, 1, &forged) {
+			Err(Error::MismatchedMixHash { expected, .. }) => assert_eq!(expected, MIX_HASH),
+			other => panic!("unexpected {:?}", other),
+		}
+
+		// refused before computing the seed hash of the epoch
+		match verifier.verify(&HEADER_HASH, u64::MAX, 1, &forged) {
+			Err(Error::EpochTooLarge(epoch)) => assert_eq!(epoch, u64::MAX / 30000),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn test_wrong_epoch() {
+		let tempdir = TempDir::new("").unwrap();
+		let verifier = Verifier::new(tempdir.path(), u64::MAX);
+		let light = verifier.light(NUMBER);
+		assert!(Arc::ptr_eq(&light, &verifier.light(NUMBER + 1)));
+
+		let seal = Seal { nonce: NONCE, mix_hash: MIX_HASH };
+		match full_check(&light, &HEADER_HASH, NUMBER + 30000, DIFFICULTY, &seal) {
+			Err(Error::WrongEpoch { expected: 17, got: 16 }) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+
+		// restarted, the light cache is read from the cache directory
+		let verifier = Verifier::new(tempdir.path(), u64::MAX);
+		full_check(&verifier.light(NUMBER), &HEADER_HASH, NUMBER, DIFFICULTY, &seal).unwrap();
+	}
+}
@@ -1,209 +1,419 @@
-use std::error::Error as StdError;
+//! Stratum pool client.
+//!
+//! `Client` is a stream of the `Event`s of the pool: the jobs of
+//! `mining.notify`, the share difficulty of `mining.set_difficulty` and the
+//! results of the shares handed to its `Submitter`. A lost connection is not
+//! fatal: the client moves on to the next pool of its list and reconnects
+//! with an exponential backoff, reset once a pool authorizes the worker.
+//! Pools which do not connect and authorize within the handshake timeout,
+//! stay silent for the idle timeout or send a line longer than `MAX_LINE`
+//! are dropped the same way.
+
+use std::collections::{HashMap, VecDeque};
+use std::mem;
 use std::net::SocketAddr;
-use std::result::Result as StdResult;
+use std::str;
+use std::time::{Duration, Instant};
 
 use bytes::{BufMut, BytesMut};
 use futures::sync::mpsc;
-use futures::{Async, Future, Poll, Stream};
-use serde_json::Value as JsonValue;
+use futures::{try_ready, Async, Future, Poll, Stream};
+use log::{debug, info, warn};
+use serde_json::{json, Value as JsonValue};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::{ConnectFuture, TcpStream};
+use tokio::net::tcp::ConnectFuture;
+use tokio::net::TcpStream;
+use tokio::timer::Delay;
 
-use super::dispatch::{Dispatch, NewDispatch};
 use super::error::Error;
 
-type Tx = mpsc::UnboundedSender<JsonValue>;
-type Rx = mpsc::UnboundedReceiver<JsonValue>;
+pub type H256 = [u8; 32];
+
+const SUBSCRIBE_ID: u64 = 1;
+const AUTHORIZE_ID: u64 = 2;
+/// Error code of the shares of an unknown job.
+const STALE_JOB: i64 = 21;
+/// Jobs shares are still submitted for once a newer job arrived.
+const JOBS: usize = 8;
+/// Longest line read from the pool, in bytes.
+pub const MAX_LINE: usize = 64 * 1024;
+
+/// Work handed out by `mining.notify`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    pub id: String,
+    pub header_hash: H256,
+    pub seed_hash: H256,
+    pub boundary: H256,
+    /// whether the shares of the previous jobs are stale
+    pub clean: bool,
+}
 
+/// Seal found for a job.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Share {
+    pub job_id: String,
+    pub nonce: u64,
+    pub header_hash: H256,
+    pub mix_hash: H256,
+}
 
-#[derive(Debug)]
-pub struct Client<D> {
-    serve: Serve<D>,
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShareResult {
+    pub share: Share,
+    pub accepted: bool,
+    /// the job of the share was replaced
+    pub stale: bool,
+    pub reason: Option<String>,
+    /// time the pool took to answer, none for the shares it never got
+    pub latency: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The pool authorized the worker.
+    Connected(SocketAddr),
+    /// The connection to the pool was lost.
+    Disconnected(SocketAddr),
+    Job(Job),
+    Difficulty(f64),
+    Share(ShareResult),
 }
 
 #[derive(Debug)]
 pub struct Builder {
+    pools: Vec<SocketAddr>,
     id: String,
     pwd: String,
-    connect: Option<ConnectFuture>,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    handshake_timeout: Duration,
+    idle_timeout: Duration,
 }
 
-impl Client<()> {
-    pub fn bind(addr: &SocketAddr, id: String, pwd: String) -> Builder {
-        let connect = TcpStream::connect(addr);
-        Client::builder(id, pwd, connect)
-    }
-
-    fn builder(id: String, pwd: String, connect: ConnectFuture) -> Builder {
-        Builder {
-            id,
-            pwd,
-            connect: Some(connect),
-        }
-    }
+/// Hands shares to the client.
+#[derive(Clone, Debug)]
+pub struct Submitter {
+    tx: mpsc::UnboundedSender<Share>,
 }
 
-impl<D> Client<D> {
-    pub fn execute<F>(&self, fut: F) -> StdResult<(), Error>
-        where
-            F: Future<Item = (), Error = ()> + Send + 'static, {
-        use tokio_executor::Executor;
-        ::tokio_executor::DefaultExecutor::current().spawn(Box::new(fut)).map_err(|_e| Error::new_execute())
-    }
-}
-
-impl<D> Future for Client<D>
-    where
-        D: NewDispatch + Send + 'static,
-        D::Error: Into<Box<StdError + Send + Sync>>,
-        D::Future: Send,
-        <D::Dispatch as Dispatch>::Future: Send + 'static,
-{
-    type Item = ();
-    type Error = Error;
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        loop {
-            if let Some(fut) = try_ready!(self.serve.poll()) {
-                self.execute(fut)?
-            } else {
-                return Ok(Async::Ready(()))
-            }
-        }
+impl Submitter {
+    /// Submit `share`, its result comes as an `Event::Share`. Fails once the client is gone.
+    pub fn submit(&self, share: Share) -> Result<(), Share> {
+        self.tx.unbounded_send(share).map_err(|e| e.into_inner())
     }
 }
 
 #[derive(Debug)]
 enum State {
-    Connecting,
-    Authenticating,
-    Working,
+    /// waiting before connecting
+    Waiting(Delay),
+    /// connecting then authenticating, until the handshake deadline
+    Connecting(ConnectFuture, Delay),
+    Authenticating(Delay),
+    /// until the pool is silent for the idle timeout
+    Working(Delay),
 }
 
 #[derive(Debug)]
-pub struct Serve<D> {
+pub struct Client {
+    pools: Vec<SocketAddr>,
     id: String,
     pwd: String,
-    connect: ConnectFuture,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    handshake_timeout: Duration,
+    idle_timeout: Duration,
+    /// pool connected or connecting to
+    current: usize,
+    /// connections lost or refused since the last authorized one
+    failures: u32,
+    state: State,
     socket: Option<TcpStream>,
     rd: BytesMut,
     wr: BytesMut,
-    rx: Rx,
-    tx: Tx,
-    new_dispatch: D,
-    state: State,
+    shares: mpsc::UnboundedReceiver<Share>,
+    /// shares submitted by request id, with the time they were sent
+    pending: HashMap<u64, (Share, Instant)>,
+    next_id: u64,
+    /// ids of the jobs shares are accepted for, newest last
+    jobs: VecDeque<String>,
+    events: VecDeque<Event>,
+}
+
+impl Client {
+    pub fn bind(addr: &SocketAddr, id: String, pwd: String) -> Builder {
+        Client::builder(vec![*addr], id, pwd)
+    }
+
+    /// Client of the first pool of `pools` that answers, in turn.
+    pub fn builder(pools: Vec<SocketAddr>, id: String, pwd: String) -> Builder {
+        Builder {
+            pools,
+            id,
+            pwd,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            handshake_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
 }
 
 impl Builder {
-    pub fn serve<D>(mut self, new_dispatch: D) -> Client<D> {
-        let connect = self.connect.take().unwrap();
+    /// Wait `min` before the first reconnection, twice as long before every
+    /// further one, up to `max`.
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self
+    }
+
+    /// Drop pools not authorizing the worker within `handshake` of the
+    /// connection attempt, or sending nothing for `idle` once they did.
+    pub fn timeouts(mut self, handshake: Duration, idle: Duration) -> Self {
+        self.handshake_timeout = handshake;
+        self.idle_timeout = idle;
+        self
+    }
+
+    pub fn build(self) -> Result<(Client, Submitter), Error> {
+        if self.pools.is_empty() {
+            return Err(Error::new_no_pools());
+        }
         let (tx, rx) = mpsc::unbounded();
-        let serve = Serve {
+        let client = Client {
+            pools: self.pools,
             id: self.id,
             pwd: self.pwd,
-            connect,
+            min_backoff: self.min_backoff,
+            max_backoff: self.max_backoff,
+            handshake_timeout: self.handshake_timeout,
+            idle_timeout: self.idle_timeout,
+            current: 0,
+            failures: 0,
+            state: State::Waiting(Delay::new(Instant::now())),
             socket: None,
             rd: BytesMut::new(),
             wr: BytesMut::new(),
-            rx,
-            tx,
-            new_dispatch,
-            state: State::Connecting,
+            shares: rx,
+            pending: HashMap::new(),
+            next_id: AUTHORIZE_ID + 1,
+            jobs: VecDeque::with_capacity(JOBS),
+            events: VecDeque::new(),
         };
 
-        Client {
-            serve,
-        }
+        Ok((client, Submitter { tx }))
     }
 }
 
-impl<D> Serve<D> {
-    fn connected(&mut self) -> StdResult<bool, Error> {
-        if let Async::Ready(s) = self.connect.poll().map_err(|e| Error::new_connect(e))? {
-            self.socket = Some(s);
-            info!("Successfully connected");
-            return Ok(true)
-        }
-
-        Ok(false)
-    }
-
-    fn authenticate(&mut self) -> Poll<(), Error> {
-        let auth_request = json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "mining.authorize",
-            "params": [self.id, self.pwd]
-        });
-        debug!("Send an authentication message");
-        self.write(auth_request)
+impl Client {
+    fn pool(&self) -> SocketAddr {
+        self.pools[self.current]
     }
 
-    fn authenticated(&mut self) -> StdResult<bool, Error> {
-        if let Async::Ready(res) = self.read()? {
-            if res["id"] == 1 && res["result"] == true {
+    /// Move the state machine one step forward.
+    fn step(&mut self) -> Poll<(), Error> {
+        let next = match self.state {
+            State::Waiting(ref mut delay) => {
+                try_ready!(delay.poll().map_err(Error::new_timer));
+                debug!("Connect to {}", self.pools[self.current]);
+                let deadline = Delay::new(Instant::now() + self.handshake_timeout);
+                State::Connecting(TcpStream::connect(&self.pools[self.current]), deadline)
+            }
+            State::Connecting(ref mut connect, ref mut deadline) => {
+                check(deadline)?;
+                let socket = try_ready!(connect.poll().map_err(Error::new_connect));
+                info!("Successfully connected to {}", self.pools[self.current]);
+                self.socket = Some(socket);
+                self.rd.clear();
+                self.wr.clear();
+                self.send(json!({
+                    "jsonrpc": "2.0",
+                    "id": SUBSCRIBE_ID,
+                    "method": "mining.subscribe",
+                    "params": []
+                }));
+                debug!("Send an authentication message");
+                self.send(json!({
+                    "jsonrpc": "2.0",
+                    "id": AUTHORIZE_ID,
+                    "method": "mining.authorize",
+                    "params": [self.id, self.pwd]
+                }));
+                match mem::replace(&mut self.state, State::Waiting(Delay::new(Instant::now()))) {
+                    State::Connecting(_, deadline) => State::Authenticating(deadline),
+                    _ => unreachable!("in the connecting state; qed"),
+                }
+            }
+            State::Authenticating(ref mut deadline) => {
+                check(deadline)?;
+                self.flush()?;
+                let res = try_ready!(self.read());
+                if res["id"] != AUTHORIZE_ID {
+                    self.handle(res);
+                    return Ok(Async::Ready(()))
+                }
+                if res["result"] != true {
+                    return Err(Error::new_authenticate())
+                }
                 info!("Successfully authenticated");
-                return Ok(true)
-            } else {
-                return Err(Error::new_authenticate())
+                self.failures = 0;
+                self.events.push_back(Event::Connected(self.pool()));
+                State::Working(Delay::new(Instant::now() + self.idle_timeout))
             }
-        }
+            State::Working(ref mut deadline) => {
+                check(deadline)?;
+                while let Ok(Async::Ready(Some(share))) = self.shares.poll() {
+                    self.submit(share);
+                }
+                self.flush()?;
+                let res = try_ready!(self.read());
+                if let State::Working(ref mut deadline) = self.state {
+                    deadline.reset(Instant::now() + self.idle_timeout);
+                }
+                self.handle(res);
+                return Ok(Async::Ready(()))
+            }
+        };
+        self.state = next;
 
-        Ok(false)
+        Ok(Async::Ready(()))
     }
 
-    fn working(&mut self) -> Poll<Dispatcher<D::Future>, Error>
-        where
-            D: NewDispatch, {
-        if let Async::Ready(Some(json_value)) = self.rx.poll().unwrap() {
-            debug!("Send a message: {:?}", json_value);
-            self.write(json_value)?;
+    /// Drop the connection and schedule the next one, to the next pool.
+    fn fail(&mut self, err: Error) {
+        let pool = self.pool();
+        warn!("Stratum pool {}: {}", pool, err);
+        if let State::Working(_) = self.state {
+            self.events.push_back(Event::Disconnected(pool));
         }
+        let pending: Vec<_> = self.pending.drain().collect();
+        for (_, (share, _)) in pending {
+            self.events.push_back(Event::Share(ShareResult {
+                share,
+                accepted: false,
+                stale: false,
+                reason: Some("connection lost".into()),
+                latency: None,
+            }));
+        }
+        self.socket = None;
+        self.jobs.clear();
+
+        self.failures = self.failures.saturating_add(1);
+        self.current = (self.current + 1) % self.pools.len();
+        let backoff = self
+            .min_backoff
+            .checked_mul(1 << (self.failures - 1).min(16))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+        debug!("Reconnect to {} in {:?}", self.pool(), backoff);
+        self.state = State::Waiting(Delay::new(Instant::now() + backoff));
+    }
 
-        if let Async::Ready(req) = self.read()? {
-            return Ok(Async::Ready(Dispatcher {
-                future: self.new_dispatch.new_dispatch(),
-                req,
-                tx: self.tx.clone(),
-            }))
+    fn submit(&mut self, share: Share) {
+        if !self.jobs.contains(&share.job_id) {
+            debug!("Drop the share of stale job {}", share.job_id);
+            self.events.push_back(Event::Share(ShareResult {
+                share,
+                accepted: false,
+                stale: true,
+                reason: Some("stale job".into()),
+                latency: None,
+            }));
+            return
         }
 
-        Ok(Async::NotReady)
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "mining.submit",
+            "params": [
+                self.id,
+                share.job_id,
+                format!("0x{:016x}", share.nonce),
+                to_hex(&share.header_hash),
+                to_hex(&share.mix_hash),
+            ]
+        }));
+        self.pending.insert(id, (share, Instant::now()));
     }
 
-    fn read(&mut self) -> Poll<JsonValue, Error> {
-        self.rd.reserve(1024);
-        let socket = self.socket.as_mut().unwrap();
-        let n = try_ready!(socket.read_buf(&mut self.rd).map_err(|e| Error::new_io(e)));
-
-        if n == 0 {
-            return Err(Error::new_closed())
+    /// Handle a notification or a response of the pool.
+    fn handle(&mut self, res: JsonValue) {
+        match res["method"].as_str() {
+            Some("mining.notify") => match parse_job(&res["params"]) {
+                Some(job) => {
+                    debug!("New job {}", job.id);
+                    if job.clean {
+                        self.jobs.clear();
+                    }
+                    if self.jobs.len() == JOBS {
+                        self.jobs.pop_front();
+                    }
+                    self.jobs.push_back(job.id.clone());
+                    self.events.push_back(Event::Job(job));
+                }
+                None => warn!("Invalid job: {}", res),
+            },
+            Some("mining.set_difficulty") => match res["params"][0].as_f64() {
+                Some(difficulty) => self.events.push_back(Event::Difficulty(difficulty)),
+                None => warn!("Invalid difficulty: {}", res),
+            },
+            Some(method) => debug!("Ignore {}", method),
+            None => {
+                let pending = res["id"].as_u64().and_then(|id| self.pending.remove(&id));
+                if let Some((share, sent)) = pending {
+                    self.events.push_back(Event::Share(share_result(share, &res, sent.elapsed())));
+                }
+            }
         }
+    }
 
-        let pos = self.rd.windows(1).enumerate().find(|&(_, bytes)| bytes == b"\n").map(|(i, _)| i);
-        if let Some(pos) = pos {
-            let mut line = self.rd.split_to(pos + 1);
-            line.split_off(pos);
+    /// Read the next message, lines which are not JSON are skipped.
+    fn read(&mut self) -> Poll<JsonValue, Error> {
+        loop {
+            if let Some(pos) = self.rd.iter().position(|b| *b == b'\n') {
+                let line = self.rd.split_to(pos + 1);
+                let line = &line[..pos];
+                match str::from_utf8(line).ok().and_then(|line| serde_json::from_str(line).ok()) {
+                    Some(res) => return Ok(Async::Ready(res)),
+                    None => {
+                        warn!("Invalid message: {:?}", String::from_utf8_lossy(line));
+                        continue
+                    }
+                }
+            }
 
-            let req = String::from_utf8(line.to_vec()).expect("Response should be utf-8");
-            let ret = ::serde_json::from_str(&req).map_err(|_e| Error::new_incomplete())?;
-            return Ok(Async::Ready(ret))
+            if self.rd.len() >= MAX_LINE {
+                return Err(Error::new_too_long())
+            }
+            self.rd.reserve(1024);
+            let socket = self.socket.as_mut().expect("read while connected; qed");
+            let n = try_ready!(socket.read_buf(&mut self.rd).map_err(Error::new_io));
+            if n == 0 {
+                return Err(Error::new_closed())
+            }
         }
-
-        Ok(Async::NotReady)
     }
 
-    fn write(&mut self, value: JsonValue) -> Poll<(), Error> {
-        let mut req = ::serde_json::ser::to_vec(&value).map_err(|_e| Error::new_incomplete())?;
-        req.extend(b"\n");
-
-        self.wr.reserve(1024);
+    /// Queue `value`, written by the next `flush`.
+    fn send(&mut self, value: JsonValue) {
+        let mut req = serde_json::ser::to_vec(&value).expect("a JSON value serializes; qed");
+        req.push(b'\n');
+        self.wr.reserve(req.len());
         self.wr.put(req);
+    }
 
-        let socket = self.socket.as_mut().unwrap();
+    fn flush(&mut self) -> Poll<(), Error> {
+        let socket = self.socket.as_mut().expect("flush while connected; qed");
         while !self.wr.is_empty() {
-            let n = try_ready!(socket.poll_write(&self.wr).map_err(|e| Error::new_io(e)));
-            assert!(n > 0);
+            let n = try_ready!(socket.poll_write(&self.wr).map_err(Error::new_io));
+            if n == 0 {
+                return Err(Error::new_closed())
+            }
             let _ = self.wr.split_to(n);
         }
 
@@ -211,68 +421,301 @@ impl<D> Serve<D> {
     }
 }
 
-impl<D> Stream for Serve<D>
-    where
-        D: NewDispatch,
-{
-    type Item = Dispatcher<D::Future>;
+/// Fail once `deadline` passed, otherwise wake the task up then.
+fn check(deadline: &mut Delay) -> Result<(), Error> {
+    match deadline.poll().map_err(Error::new_timer)? {
+        Async::Ready(()) => Err(Error::new_timeout()),
+        Async::NotReady => Ok(()),
+    }
+}
+
+impl Stream for Client {
+    type Item = Event;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
-            let next = match self.state {
-                State::Connecting => {
-                    if !self.connected()? {
-                        return Ok(Async::NotReady)
-                    }
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)))
+            }
 
-                    self.authenticate()?;
-                    State::Authenticating
-                }
-                State::Authenticating => {
-                    if !self.authenticated()? {
+            match self.step() {
+                Ok(Async::Ready(())) => (),
+                Ok(Async::NotReady) => {
+                    if self.events.is_empty() {
                         return Ok(Async::NotReady)
                     }
-
-                    State::Working
                 }
-                State::Working => {
-                    if let Async::Ready(fut) = self.working()? {
-                        return Ok(Async::Ready(Some(fut)))
-                    }
-
-                    return Ok(Async::NotReady)
-                }
-            };
-            self.state = next;
+                Err(err) => self.fail(err),
+            }
         }
     }
 }
 
-pub struct Dispatcher<F> {
-    future: F,
-    req: JsonValue,
-    tx: Tx,
+fn share_result(share: Share, res: &JsonValue, latency: Duration) -> ShareResult {
+    let err = &res["error"];
+    // pools answer `{"code", "message"}` or `[code, message, traceback]`
+    let code = err["code"].as_i64().or_else(|| err[0].as_i64());
+    let message = err["message"].as_str().or_else(|| err[1].as_str());
+    let accepted = res["result"] == true && err.is_null();
+    let stale = code == Some(STALE_JOB) ||
+        message.map_or(false, |m| {
+            let m = m.to_lowercase();
+            m.contains("stale") || m.contains("job not found")
+        });
+    let reason = if accepted {
+        None
+    } else {
+        Some(message.unwrap_or("rejected").to_owned())
+    };
+
+    ShareResult {
+        share,
+        accepted,
+        stale,
+        reason,
+        latency: Some(latency),
+    }
+}
+
+fn parse_job(params: &JsonValue) -> Option<Job> {
+    Some(Job {
+        id: params[0].as_str()?.to_owned(),
+        header_hash: from_hex(&params[1])?,
+        seed_hash: from_hex(&params[2])?,
+        boundary: from_hex(&params[3])?,
+        clean: params[4].as_bool().unwrap_or(true),
+    })
 }
 
-impl<F, D> Future for Dispatcher<F>
-    where
-        F: Future<Item = D>,
-        D: Dispatch,
-        D::Future: Send + 'static,
-        D::Error: Into<Box<StdError + Send + Sync>>,
-        D::Future: Future<Item = Option<JsonValue>, Error = D::Error> + Send + 'static,
-{
-    type Item = ();
-    type Error = ();
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        if let Ok(Async::Ready(ref mut dispatcher)) = self.future.poll() {
-            let mut fut = dispatcher.call(self.req.clone());
-            if let Ok(Async::Ready(Some(res))) = fut.poll() {
-                let _ = self.tx.unbounded_send(res);
+fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + bytes.len() * 2);
+    s.push_str("0x");
+    for byte in bytes {
+        s.push_str(&format!("{:02x}", byte));
+    }
+    s
+}
+
+fn from_hex(value: &JsonValue) -> Option<H256> {
+    let s = value.as_str()?;
+    let s = s.trim_start_matches("0x");
+    if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream as StdTcpStream};
+    use std::thread;
+    use tokio::runtime::Runtime;
+
+    struct Pool {
+        reader: BufReader<StdTcpStream>,
+        writer: StdTcpStream,
+    }
+
+    impl Pool {
+        fn accept(listener: &TcpListener) -> Pool {
+            let (writer, _) = listener.accept().unwrap();
+            Pool { reader: BufReader::new(writer.try_clone().unwrap()), writer }
+        }
+
+        fn read(&mut self) -> JsonValue {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        fn write(&mut self, value: JsonValue) {
+            self.write_raw(&serde_json::to_vec(&value).unwrap());
+        }
+
+        fn write_raw(&mut self, bytes: &[u8]) {
+            self.writer.write_all(bytes).unwrap();
+            self.writer.write_all(b"\n").unwrap();
+        }
+
+        /// Answer the subscription and the authorization.
+        fn authorize(&mut self) {
+            assert_eq!(self.read()["method"], "mining.subscribe");
+            self.write(json!({ "id": SUBSCRIBE_ID, "result": true }));
+            let req = self.read();
+            assert_eq!(req["params"], json!(["worker", "x"]));
+            self.write(json!({ "id": AUTHORIZE_ID, "result": true }));
+        }
+
+        fn notify(&mut self, id: &str, clean: bool) {
+            let hash = to_hex(&[id.as_bytes()[0]; 32]);
+            self.write(json!({ "id": null, "method": "mining.notify", "params": [id, hash, hash, hash, clean] }));
+        }
+    }
+
+    fn next(rt: &mut Runtime, client: Client) -> (Event, Client) {
+        match rt.block_on(client.into_future()) {
+            Ok((Some(event), client)) => (event, client),
+            _ => panic!("client stream ended"),
+        }
+    }
+
+    fn share(job_id: &str, nonce: u64) -> Share {
+        Share { job_id: job_id.into(), nonce, header_hash: [job_id.as_bytes()[0]; 32], mix_hash: [7; 32] }
+    }
+
+    fn share_event(event: Event) -> ShareResult {
+        match event {
+            Event::Share(result) => result,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let live = listener.local_addr().unwrap();
+        let pool = thread::spawn(move || {
+            let mut pool = Pool::accept(&listener);
+            pool.authorize();
+            pool.notify("1", true);
+
+            let req = pool.read();
+            assert_eq!(req["method"], "mining.submit");
+            assert_eq!(req["params"][1], "1");
+            assert_eq!(req["params"][2], "0x000000000000002a");
+            pool.write(json!({ "id": req["id"], "result": true, "error": null }));
+
+            pool.write_raw(b"\xff\xfe not json");
+            pool.write(json!({ "id": null, "method": "mining.set_difficulty", "params": [2.5] }));
+            pool.notify("2", true);
+            let req = pool.read();
+            assert_eq!(req["params"][1], "2");
+            pool.write(json!({ "id": req["id"], "result": null, "error": [21, "Stale share", null] }));
+            drop(pool);
+
+            // back after the failover to the dead pool
+            Pool::accept(&listener).authorize();
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let (client, submitter) = Client::builder(vec![dead, live], "worker".into(), "x".into())
+            .backoff(Duration::from_millis(10), Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let (event, client) = next(&mut rt, client);
+        assert_eq!(event, Event::Connected(live));
+        let (event, client) = next(&mut rt, client);
+        match event {
+            Event::Job(job) => {
+                assert_eq!(job.id, "1");
+                assert_eq!(job.boundary, [b'1'; 32]);
+                assert!(job.clean);
             }
+            other => panic!("unexpected {:?}", other),
         }
-        Ok(Async::Ready(()))
+
+        submitter.submit(share("1", 42)).unwrap();
+        let (event, client) = next(&mut rt, client);
+        let result = share_event(event);
+        assert!(result.accepted);
+        assert!(result.latency.is_some());
+
+        // the garbage line is skipped
+        let (event, client) = next(&mut rt, client);
+        assert_eq!(event, Event::Difficulty(2.5));
+        let (event, client) = next(&mut rt, client);
+        assert!(matches!(event, Event::Job(ref job) if job.id == "2"));
+
+        // the clean job made job 1 stale, not sent
+        submitter.submit(share("1", 43)).unwrap();
+        let (event, client) = next(&mut rt, client);
+        let result = share_event(event);
+        assert!(!result.accepted && result.stale && result.latency.is_none());
+
+        submitter.submit(share("2", 44)).unwrap();
+        let (event, client) = next(&mut rt, client);
+        let result = share_event(event);
+        assert!(!result.accepted && result.stale && result.latency.is_some());
+        assert_eq!(result.reason, Some("Stale share".into()));
+
+        let (event, client) = next(&mut rt, client);
+        assert_eq!(event, Event::Disconnected(live));
+        let (event, _client) = next(&mut rt, client);
+        assert_eq!(event, Event::Connected(live));
+        pool.join().unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = thread::spawn(move || {
+            let mut pool = Pool::accept(&listener);
+            pool.read();
+            pool.read();
+            pool.write(json!({ "id": AUTHORIZE_ID, "result": false }));
+            // tried again after the backoff
+            Pool::accept(&listener).authorize();
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let (client, _submitter) = Client::bind(&addr, "worker".into(), "x".into())
+            .backoff(Duration::from_millis(10), Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let (event, _client) = next(&mut rt, client);
+        assert_eq!(event, Event::Connected(addr));
+        pool.join().unwrap();
+
+        assert!(Client::builder(vec![], "worker".into(), "x".into()).build().is_err());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = thread::spawn(move || {
+            // the connections stay open, the client gives up on them
+            let mut silent = Pool::accept(&listener);
+            silent.read();
+            silent.read();
+            let mut idle = Pool::accept(&listener);
+            idle.authorize();
+            let mut chatty = Pool::accept(&listener);
+            chatty.authorize();
+            chatty.writer.write_all(&vec![b'x'; MAX_LINE + 1]).unwrap();
+            Pool::accept(&listener).authorize();
+            (silent, idle, chatty)
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let (client, _submitter) = Client::bind(&addr, "worker".into(), "x".into())
+            .backoff(Duration::from_millis(10), Duration::from_millis(10))
+            .timeouts(Duration::from_millis(200), Duration::from_millis(200))
+            .build()
+            .unwrap();
+        // no authorization from the first connection
+        let (event, client) = next(&mut rt, client);
+        assert_eq!(event, Event::Connected(addr));
+        // then nothing at all
+        let (event, client) = next(&mut rt, client);
+        assert_eq!(event, Event::Disconnected(addr));
+        let (event, client) = next(&mut rt, client);
+        assert_eq!(event, Event::Connected(addr));
+        // then a line too long
+        let (event, client) = next(&mut rt, client);
+        assert_eq!(event, Event::Disconnected(addr));
+        let (event, _client) = next(&mut rt, client);
+        assert_eq!(event, Event::Connected(addr));
+        pool.join().unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+type Cause = Box<dyn StdError + Send + Sync>;
+
+/// Error of the stratum client.
+pub struct Error {
+    kind: Kind,
+    cause: Option<Cause>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    /// No pool to connect to.
+    NoPools,
+    /// Connecting to the pool failed.
+    Connect,
+    /// Reading from or writing to the pool failed.
+    Io,
+    /// The pool closed the connection.
+    Closed,
+    /// The pool refused the worker.
+    Authenticate,
+    /// The reconnection timer failed.
+    Timer,
+    /// The pool did not connect, authorize or send anything in time.
+    Timeout,
+    /// The pool sent a line longer than the client accepts.
+    TooLong,
+}
+
+impl Error {
+    fn new(kind: Kind, cause: Option<Cause>) -> Error {
+        Error { kind, cause }
+    }
+
+    pub(crate) fn new_no_pools() -> Error {
+        Error::new(Kind::NoPools, None)
+    }
+
+    pub(crate) fn new_connect<E: Into<Cause>>(cause: E) -> Error {
+        Error::new(Kind::Connect, Some(cause.into()))
+    }
+
+    pub(crate) fn new_io<E: Into<Cause>>(cause: E) -> Error {
+        Error::new(Kind::Io, Some(cause.into()))
+    }
+
+    pub(crate) fn new_closed() -> Error {
+        Error::new(Kind::Closed, None)
+    }
+
+    pub(crate) fn new_authenticate() -> Error {
+        Error::new(Kind::Authenticate, None)
+    }
+
+    pub(crate) fn new_timer<E: Into<Cause>>(cause: E) -> Error {
+        Error::new(Kind::Timer, Some(cause.into()))
+    }
+
+    pub(crate) fn new_timeout() -> Error {
+        Error::new(Kind::Timeout, None)
+    }
+
+    pub(crate) fn new_too_long() -> Error {
+        Error::new(Kind::TooLong, None)
+    }
+
+    /// Whether the pool closed the connection.
+    pub fn is_closed(&self) -> bool {
+        self.kind == Kind::Closed
+    }
+
+    /// Whether the pool refused the worker.
+    pub fn is_authenticate(&self) -> bool {
+        self.kind == Kind::Authenticate
+    }
+
+    /// Whether the pool did not answer in time.
+    pub fn is_timeout(&self) -> bool {
+        self.kind == Kind::Timeout
+    }
+
+    fn description(&self) -> &str {
+        match self.kind {
+            Kind::NoPools => "no pool configured",
+            Kind::Connect => "error trying to connect",
+            Kind::Io => "connection error",
+            Kind::Closed => "connection closed by the pool",
+            Kind::Authenticate => "worker refused by the pool",
+            Kind::Timer => "timer error",
+            Kind::Timeout => "no answer from the pool in time",
+            Kind::TooLong => "message too long",
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cause {
+            Some(ref cause) => write!(f, "{}: {}", self.description(), cause),
+            None => f.write_str(self.description()),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.cause.as_ref().map(|cause| &**cause as &(dyn StdError + 'static))
+    }
+}
+
+/// Error of the futures that cannot fail.
+#[derive(Debug)]
+pub enum Never {}
+
+impl fmt::Display for Never {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl StdError for Never {}
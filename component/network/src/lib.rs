@@ -2,6 +2,9 @@ pub use serde_json;
 pub use libp2p::{Multiaddr, PeerId};
 pub use libp2p::multiaddr;
 
+pub mod client;
+pub mod error;
+
 
 use libp2p::core::ConnectedPoint;
 use serde::{Deserialize, Serialize};
@@ -1,13 +1,96 @@
-pub use traits::Blockly;
-pub use trais::Headerly;
+use alloc::vec::Vec;
 
-#[derive(PartialEq, Eq, Clone, Encode, Decode, RuntimeDebug)]
-pub struct Block<Header, BlockBody> {
+use codec::{Codec, Decode, Encode};
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+
+use crate::traits::{Blockly, Headerly, MaybeSerialize, Member};
+
+/// A block: a header and the extrinsics its extrinsics root commits to.
+#[derive(PartialEq, Eq, Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase", deny_unknown_fields))]
+pub struct Block<Header, Extrinsic> {
+    /// The block header.
     pub header: Header,
-    pub body: BlockBody
+    /// The extrinsics, in the order they are executed.
+    pub extrinsics: Vec<Extrinsic>,
 }
 
-impl Blockly for Block<Header, BlockBody>{
+impl<Header, Extrinsic> Blockly for Block<Header, Extrinsic>
+where
+    Header: Headerly,
+    Extrinsic: Member + Codec + MaybeSerialize,
+{
+    type Extrinsic = Extrinsic;
+    type Header = Header;
+    type Hash = <Header as Headerly>::Hash;
+
+    fn header(&self) -> &Self::Header {
+        &self.header
+    }
+
+    fn extrinsics(&self) -> &[Self::Extrinsic] {
+        &self.extrinsics
+    }
 
+    fn deconstruct(self) -> (Self::Header, Vec<Self::Extrinsic>) {
+        (self.header, self.extrinsics)
+    }
+
+    fn new(header: Self::Header, extrinsics: Vec<Self::Extrinsic>) -> Self {
+        Block { header, extrinsics }
+    }
+
+    fn encode_from(header: &Self::Header, extrinsics: &[Self::Extrinsic]) -> Vec<u8> {
+        (header, extrinsics).encode()
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::Digest;
+    use crate::hash::BlakeTwo256;
+    use crate::header::Header;
+    use crate::traits::Hash;
+
+    type TestBlock = Block<Header<u32, BlakeTwo256>, Vec<u8>>;
+
+    fn block() -> TestBlock {
+        let header = Header::new(3, [1; 32], [2; 32], [3; 32], Digest::default());
+        TestBlock::new(header, vec![vec![1, 2], vec![3]])
+    }
+
+    #[test]
+    fn test_codec() {
+        let block = block();
+        let encoded = block.encode();
+        assert_eq!(TestBlock::encode_from(block.header(), block.extrinsics()), encoded);
+        assert_eq!(TestBlock::decode(&mut &encoded[..]).unwrap(), block);
+
+        assert_eq!(Blockly::hash(&block), block.header.hash());
+        let (header, extrinsics) = block.clone().deconstruct();
+        assert_eq!((header, extrinsics), (block.header, block.extrinsics));
+    }
+
+    #[test]
+    fn test_extrinsics_root() {
+        let mut block = block();
+        assert!(!block.check_extrinsics_root());
+        let root = TestBlock::extrinsics_root(&block.extrinsics);
+        assert_eq!(root, BlakeTwo256::ordered_trie_root(vec![vec![0x08, 1, 2], vec![0x04, 3]]));
+        block.header.extrinsics_root = root;
+        assert!(block.check_extrinsics_root());
+        block.extrinsics.swap(0, 1);
+        assert!(!block.check_extrinsics_root());
+    }
+
+    #[test]
+    fn test_serde() {
+        let block = block();
+        let json = serde_json::to_string(&block).unwrap();
+        assert!(json.contains("\"extrinsics\":[[1,2],[3]]"));
+        assert_eq!(serde_json::from_str::<TestBlock>(&json).unwrap(), block);
+    }
+}
@@ -0,0 +1,115 @@
+//! Header digest: the items of a header the runtime and the consensus
+//! engines leave for light clients, such as seals.
+
+use alloc::vec::Vec;
+
+use codec::{Decode, Encode};
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+
+/// Consensus engine an item of the digest belongs to.
+pub type ConsensusEngineId = [u8; 4];
+
+/// An item of the digest.
+#[derive(PartialEq, Eq, Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub enum DigestItem<Hash> {
+	/// Root of the changes trie of the block.
+	ChangesTrieRoot(Hash),
+	/// Left by an engine before the runtime executes the block, a slot or an author.
+	PreRuntime(ConsensusEngineId, Vec<u8>),
+	/// Left by the runtime for an engine, a change of validators.
+	Consensus(ConsensusEngineId, Vec<u8>),
+	/// Seal of an engine, last in the digest and not part of the hash it seals.
+	Seal(ConsensusEngineId, Vec<u8>),
+	/// Anything else.
+	Other(Vec<u8>),
+}
+
+impl<Hash> DigestItem<Hash> {
+	/// The engine and the data of a seal.
+	pub fn as_seal(&self) -> Option<(&ConsensusEngineId, &[u8])> {
+		match self {
+			DigestItem::Seal(id, data) => Some((id, data)),
+			_ => None,
+		}
+	}
+
+	/// The engine and the data of a pre-runtime item.
+	pub fn as_pre_runtime(&self) -> Option<(&ConsensusEngineId, &[u8])> {
+		match self {
+			DigestItem::PreRuntime(id, data) => Some((id, data)),
+			_ => None,
+		}
+	}
+
+	/// The engine and the data of a consensus item.
+	pub fn as_consensus(&self) -> Option<(&ConsensusEngineId, &[u8])> {
+		match self {
+			DigestItem::Consensus(id, data) => Some((id, data)),
+			_ => None,
+		}
+	}
+}
+
+/// The digest of a header.
+#[derive(PartialEq, Eq, Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct Digest<Hash> {
+	/// The items, in the order they were added.
+	pub logs: Vec<DigestItem<Hash>>,
+}
+
+impl<Hash> Default for Digest<Hash> {
+	fn default() -> Self {
+		Digest { logs: Vec::new() }
+	}
+}
+
+impl<Hash> Digest<Hash> {
+	pub fn logs(&self) -> &[DigestItem<Hash>] {
+		&self.logs
+	}
+
+	pub fn push(&mut self, item: DigestItem<Hash>) {
+		self.logs.push(item);
+	}
+
+	pub fn pop(&mut self) -> Option<DigestItem<Hash>> {
+		self.logs.pop()
+	}
+
+	/// The first item `f` maps to something.
+	pub fn log<T: ?Sized, F: Fn(&DigestItem<Hash>) -> Option<&T>>(&self, f: F) -> Option<&T> {
+		self.logs.iter().find_map(f)
+	}
+
+	/// The seal of `engine`, the last item when there is one.
+	pub fn seal(&self, engine: &ConsensusEngineId) -> Option<&[u8]> {
+		match self.logs.last().and_then(DigestItem::as_seal) {
+			Some((id, data)) if id == engine => Some(data),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_seal() {
+		let mut digest = Digest::<[u8; 32]>::default();
+		digest.push(DigestItem::PreRuntime(*b"pow_", vec![1]));
+		assert_eq!(digest.seal(b"pow_"), None);
+		digest.push(DigestItem::Seal(*b"pow_", vec![2, 3]));
+		assert_eq!(digest.seal(b"pow_"), Some(&[2u8, 3][..]));
+		assert_eq!(digest.seal(b"aura"), None);
+		assert_eq!(digest.log(|item| item.as_pre_runtime().map(|(_, data)| data)), Some(&[1u8][..]));
+
+		let encoded = digest.encode();
+		assert_eq!(Digest::decode(&mut &encoded[..]).unwrap(), digest);
+		assert_eq!(digest.pop(), Some(DigestItem::Seal(*b"pow_", vec![2, 3])));
+		assert_eq!(digest.logs().len(), 1);
+	}
+}
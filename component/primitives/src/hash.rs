@@ -0,0 +1,104 @@
+//! The hashers of the model, both 256 bit.
+
+use plain_hasher::PlainHasher;
+use tiny_keccak::{Hasher, Keccak};
+
+use crate::traits::Hash;
+
+/// A 256 bit hash.
+pub type H256 = [u8; 32];
+
+/// Blake2b with a 256 bit output.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlakeTwo256;
+
+impl Hash for BlakeTwo256 {
+	type Output = H256;
+	type Hasher = Blake2Hasher;
+
+	fn hash(s: &[u8]) -> H256 {
+		let mut hash = [0u8; 32];
+		hash.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], s).as_bytes());
+		hash
+	}
+}
+
+/// Trie hasher of `BlakeTwo256`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Blake2Hasher;
+
+impl hash_db::Hasher for Blake2Hasher {
+	type Out = H256;
+	type StdHasher = PlainHasher;
+	const LENGTH: usize = 32;
+
+	fn hash(x: &[u8]) -> H256 {
+		BlakeTwo256::hash(x)
+	}
+}
+
+/// Keccak-256, as Ethereum hashes.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Keccak256;
+
+impl Hash for Keccak256 {
+	type Output = H256;
+	type Hasher = Keccak256Hasher;
+
+	fn hash(s: &[u8]) -> H256 {
+		let mut hash = [0u8; 32];
+		let mut keccak = Keccak::v256();
+		keccak.update(s);
+		keccak.finalize(&mut hash);
+		hash
+	}
+}
+
+/// Trie hasher of `Keccak256`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Keccak256Hasher;
+
+impl hash_db::Hasher for Keccak256Hasher {
+	type Out = H256;
+	type StdHasher = PlainHasher;
+	const LENGTH: usize = 32;
+
+	fn hash(x: &[u8]) -> H256 {
+		Keccak256::hash(x)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex(hash: H256) -> String {
+		hash.iter().map(|b| format!("{:02x}", b)).collect()
+	}
+
+	#[test]
+	fn test_hashes() {
+		assert_eq!(
+			hex(BlakeTwo256::hash(b"")),
+			"0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+		);
+		assert_eq!(
+			hex(Keccak256::hash(b"")),
+			"c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+		);
+		// the hash of a value is the hash of its encoding
+		assert_eq!(Keccak256::hash_of(&7u32), Keccak256::hash(&[7, 0, 0, 0]));
+		// the empty trie hashes the RLP of the empty string
+		assert_eq!(BlakeTwo256::trie_root(Vec::new()), BlakeTwo256::hash(&[0x80]));
+		assert_eq!(
+			hex(Keccak256::ordered_trie_root(Vec::new())),
+			"56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+		);
+		// the roots of the Ethereum hasher
+		let input = vec![(b"doe".to_vec(), b"reindeer".to_vec()), (b"dog".to_vec(), b"puppy".to_vec())];
+		assert_eq!(
+			&Keccak256::trie_root(input.clone())[..],
+			crate::trie::trie_root::<keccak::KeccakHasher, _, _, _>(input).as_bytes()
+		);
+	}
+}
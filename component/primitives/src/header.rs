@@ -1,26 +1,19 @@
 #[cfg(feature = "std")]
 use serde::{Deserialize, Serialize};
-use crate::codec::{Decode, Encode, Codec, Input, Output, HasCompact, EncodeAsRef, Error};
-use crate::traits::{
-	self, Member, SimpleArithmetic, SimpleBitOps, Hash as HashT,
-	MaybeSerializeDeserialize, MaybeSerialize, MaybeDisplay,
-};
-use crate::generic::Digest;
-use primitives::U256;
-use rstd::{
-	convert::TryFrom,
-	fmt::Debug,
-};
-
-/// Abstraction over a block header for a substrate chain.
-#[derive(PartialEq, Eq, Clone, primitives::RuntimeDebug)]
-pub struct Header<Number: Copy + Into<U256> + TryFrom<U256>, Hash: HashT> {
+use codec::{Decode, Encode, Input, Output, HasCompact, EncodeAsRef, Error};
+
+use crate::digest::Digest;
+use crate::traits::{self, BlockNumber, Hash as HashT};
+
+/// Abstraction over a block header, generic over the block number and the
+/// hasher of the chain.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase", deny_unknown_fields))]
+pub struct Header<Number, Hash: HashT> {
 	/// The parent hash.
 	pub parent_hash: Hash::Output,
 	/// The block number.
-	#[cfg_attr(feature = "std", serde(
-		serialize_with = "serialize_number",
-		deserialize_with = "deserialize_number"))]
 	pub number: Number,
 	/// The state trie merkle root
 	pub state_root: Hash::Output,
@@ -30,26 +23,9 @@ pub struct Header<Number: Copy + Into<U256> + TryFrom<U256>, Hash: HashT> {
 	pub digest: Digest<Hash::Output>,
 }
 
-#[cfg(feature = "std")]
-pub fn serialize_number<S, T: Copy + Into<U256> + TryFrom<U256>>(
-	val: &T, s: S,
-) -> Result<S::Ok, S::Error> where S: serde::Serializer {
-	let u256: U256 = (*val).into();
-	serde::Serialize::serialize(&u256, s)
-}
-
-#[cfg(feature = "std")]
-pub fn deserialize_number<'a, D, T: Copy + Into<U256> + TryFrom<U256>>(
-	d: D,
-) -> Result<T, D::Error> where D: serde::Deserializer<'a> {
-	let u256: U256 = serde::Deserialize::deserialize(d)?;
-	TryFrom::try_from(u256).map_err(|_| serde::de::Error::custom("Try from failed"))
-}
-
 impl<Number, Hash> Decode for Header<Number, Hash> where
-	Number: HasCompact + Copy + Into<U256> + TryFrom<U256>,
+	Number: HasCompact,
 	Hash: HashT,
-	Hash::Output: Decode,
 {
 	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
 		Ok(Header {
@@ -63,50 +39,26 @@ impl<Number, Hash> Decode for Header<Number, Hash> where
 }
 
 impl<Number, Hash> Encode for Header<Number, Hash> where
-	Number: HasCompact + Copy + Into<U256> + TryFrom<U256>,
+	Number: HasCompact,
 	Hash: HashT,
-	Hash::Output: Encode,
 {
-	fn encode_to<T: Output>(&self, dest: &mut T) {
-		dest.push(&self.parent_hash);
-		dest.push(&<<<Number as HasCompact>::Type as EncodeAsRef<_>>::RefType>::from(&self.number));
-		dest.push(&self.state_root);
-		dest.push(&self.extrinsics_root);
-		dest.push(&self.digest);
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.parent_hash.encode_to(dest);
+		<<<Number as HasCompact>::Type as EncodeAsRef<_>>::RefType>::from(&self.number).encode_to(dest);
+		self.state_root.encode_to(dest);
+		self.extrinsics_root.encode_to(dest);
+		self.digest.encode_to(dest);
 	}
 }
 
 impl<Number, Hash> traits::Headerly for Header<Number, Hash> where
-	Number: Member + MaybeSerializeDeserialize + Debug + rstd::hash::Hash + MaybeDisplay +
-		SimpleArithmetic + Codec + Copy + Into<U256> + TryFrom<U256>,
+	Number: BlockNumber,
 	Hash: HashT,
-	Hash::Output: Default + rstd::hash::Hash + Copy + Member +
-		MaybeSerialize + Debug + MaybeDisplay + SimpleBitOps + Codec,
 {
 	type Number = Number;
 	type Hash = <Hash as HashT>::Output;
 	type Hashing = Hash;
 
-	fn number(&self) -> &Self::Number { &self.number }
-	fn set_number(&mut self, num: Self::Number) { self.number = num }
-
-	fn extrinsics_root(&self) -> &Self::Hash { &self.extrinsics_root }
-	fn set_extrinsics_root(&mut self, root: Self::Hash) { self.extrinsics_root = root }
-
-	fn state_root(&self) -> &Self::Hash { &self.state_root }
-	fn set_state_root(&mut self, root: Self::Hash) { self.state_root = root }
-
-	fn parent_hash(&self) -> &Self::Hash { &self.parent_hash }
-	fn set_parent_hash(&mut self, hash: Self::Hash) { self.parent_hash = hash }
-
-	fn digest(&self) -> &Digest<Self::Hash> { &self.digest }
-
-	fn digest_mut(&mut self) -> &mut Digest<Self::Hash> {
-		#[cfg(feature = "std")]
-		log::debug!(target: "header", "Retrieving mutable reference to digest");
-		&mut self.digest
-	}
-
 	fn new(
 		number: Self::Number,
 		extrinsics_root: Self::Hash,
@@ -122,16 +74,80 @@ impl<Number, Hash> traits::Headerly for Header<Number, Hash> where
 			digest,
 		}
 	}
+
+	fn number(&self) -> &Self::Number { &self.number }
+	fn set_number(&mut self, num: Self::Number) { self.number = num }
+
+	fn extrinsics_root(&self) -> &Self::Hash { &self.extrinsics_root }
+	fn set_extrinsics_root(&mut self, root: Self::Hash) { self.extrinsics_root = root }
+
+	fn state_root(&self) -> &Self::Hash { &self.state_root }
+	fn set_state_root(&mut self, root: Self::Hash) { self.state_root = root }
+
+	fn parent_hash(&self) -> &Self::Hash { &self.parent_hash }
+	fn set_parent_hash(&mut self, hash: Self::Hash) { self.parent_hash = hash }
+
+	fn digest(&self) -> &Digest<Self::Hash> { &self.digest }
+	fn digest_mut(&mut self) -> &mut Digest<Self::Hash> { &mut self.digest }
 }
 
 impl<Number, Hash> Header<Number, Hash> where
-	Number: Member + rstd::hash::Hash + Copy + MaybeDisplay + SimpleArithmetic + Codec + Into<U256> + TryFrom<U256>,
+	Number: HasCompact,
 	Hash: HashT,
-	Hash::Output: Default + rstd::hash::Hash + Copy + Member + MaybeDisplay + SimpleBitOps + Codec,
- {
+{
 	/// Convenience helper for computing the hash of the header without having
 	/// to import the trait.
 	pub fn hash(&self) -> Hash::Output {
 		Hash::hash_of(self)
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::digest::DigestItem;
+	use crate::hash::{BlakeTwo256, Keccak256};
+	use crate::traits::Headerly;
+
+	type TestHeader = Header<u64, BlakeTwo256>;
+
+	fn header(number: u64) -> TestHeader {
+		let mut digest = Digest::default();
+		digest.push(DigestItem::Seal(*b"pow_", vec![1, 2, 3]));
+		TestHeader::new(number, [1; 32], [2; 32], [3; 32], digest)
+	}
+
+	#[test]
+	fn test_codec() {
+		let header = header(1000);
+		let encoded = header.encode();
+		// the number is compact, two bytes for 1000
+		assert_eq!(&encoded[32..34], &codec::Compact(1000u64).encode()[..]);
+		assert_eq!(encoded.len(), 32 + 2 + 32 + 32 + 1 + (1 + 4 + 1 + 3));
+		assert_eq!(TestHeader::decode(&mut &encoded[..]).unwrap(), header);
+		assert!(TestHeader::decode(&mut &encoded[..encoded.len() - 1]).is_err());
+	}
+
+	#[test]
+	fn test_hash() {
+		let mut header = header(1);
+		assert_eq!(header.hash(), BlakeTwo256::hash_of(&header));
+		assert_eq!(Headerly::hash(&header), header.hash());
+		let hash = header.hash();
+		header.set_number(2);
+		assert_ne!(header.hash(), hash);
+
+		let keccak = Header::<u32, Keccak256>::new(1, [1; 32], [2; 32], [3; 32], Digest::default());
+		assert_eq!(keccak.hash(), Keccak256::hash(&keccak.encode()));
+	}
+
+	#[test]
+	fn test_serde() {
+		let header = header(7);
+		let json = serde_json::to_string(&header).unwrap();
+		assert!(json.contains("\"parentHash\""));
+		assert!(json.contains("\"number\":7"));
+		assert_eq!(serde_json::from_str::<TestHeader>(&json).unwrap(), header);
+		assert!(serde_json::from_str::<TestHeader>(&json.replace("number", "height")).is_err());
+	}
+}
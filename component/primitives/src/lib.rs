@@ -1,7 +1,19 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+//! Block and header model shared by the runtime, the consensus engines and
+//! the network. Builds without `std` for the runtime, `std` adds serde.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod traits;
+mod block;
+mod digest;
+mod hash;
+mod header;
+pub mod trie;
+
+pub use block::Block;
+pub use digest::{ConsensusEngineId, Digest, DigestItem};
+pub use hash::{Blake2Hasher, BlakeTwo256, Keccak256, Keccak256Hasher, H256};
+pub use header::Header;
+pub use traits::{Blockly, Headerly};
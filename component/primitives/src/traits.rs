@@ -1,14 +1,73 @@
-pub mod traits;
+//! Traits of the block model: the hashers, headers and blocks the rest of the
+//! stack is generic over.
 
-// Stupid bug in the Rust compiler believes derived
-// traits must be fulfilled by all type parameters.
-pub trait Hash: 'static + MaybeSerializeDeserialize + Debug + Clone + Eq + PartialEq {
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display};
+
+use codec::{Codec, Encode, HasCompact};
+
+use crate::digest::Digest;
+use crate::trie;
+
+/// Members of the model, whatever the thread they are used from.
+pub trait Member: Send + Sync + Sized + Debug + Eq + PartialEq + Clone + 'static {}
+impl<T: Send + Sync + Sized + Debug + Eq + PartialEq + Clone + 'static> Member for T {}
+
+/// `Serialize` with `std`, nothing without.
+#[cfg(feature = "std")]
+pub trait MaybeSerialize: serde::Serialize {}
+#[cfg(feature = "std")]
+impl<T: serde::Serialize> MaybeSerialize for T {}
+
+/// `Serialize` with `std`, nothing without.
+#[cfg(not(feature = "std"))]
+pub trait MaybeSerialize {}
+#[cfg(not(feature = "std"))]
+impl<T> MaybeSerialize for T {}
+
+/// `Serialize` and `DeserializeOwned` with `std`, nothing without.
+#[cfg(feature = "std")]
+pub trait MaybeSerializeDeserialize: serde::Serialize + serde::de::DeserializeOwned {}
+#[cfg(feature = "std")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> MaybeSerializeDeserialize for T {}
+
+/// `Serialize` and `DeserializeOwned` with `std`, nothing without.
+#[cfg(not(feature = "std"))]
+pub trait MaybeSerializeDeserialize {}
+#[cfg(not(feature = "std"))]
+impl<T> MaybeSerializeDeserialize for T {}
+
+/// Block numbers, compact encoded in headers.
+pub trait BlockNumber:
+	Member + MaybeSerializeDeserialize + Copy + Default + Ord + core::hash::Hash + Display
+	+ Codec + HasCompact + From<u32>
+{
+}
+impl<T> BlockNumber for T where
+	T: Member + MaybeSerializeDeserialize + Copy + Default + Ord + core::hash::Hash + Display
+		+ Codec + HasCompact + From<u32>
+{
+}
+
+/// Hashes of the model: block hashes, trie roots.
+pub trait HashOutput:
+	Member + MaybeSerializeDeserialize + Copy + Default + Ord + core::hash::Hash
+	+ AsRef<[u8]> + AsMut<[u8]> + Codec
+{
+}
+impl<T> HashOutput for T where
+	T: Member + MaybeSerializeDeserialize + Copy + Default + Ord + core::hash::Hash
+		+ AsRef<[u8]> + AsMut<[u8]> + Codec
+{
+}
+
+/// A hasher, the type headers name to hash themselves and their contents.
+pub trait Hash: 'static + Send + Sync + Debug + Clone + Eq + PartialEq {
 	/// The hash type produced.
-	type Output: Member + MaybeSerializeDeserialize + Debug + rstd::hash::Hash
-		+ AsRef<[u8]> + AsMut<[u8]> + Copy + Default + Encode + Decode;
+	type Output: HashOutput;
 
-	/// The associated hash_db Hasher type.
-	type Hasher: Hasher<Out=Self::Output>;
+	/// The hasher of the tries, producing the same hashes as `Output`.
+	type Hasher: hash_db::Hasher<Out = Self::Output>;
 
 	/// Produce the hash of some byte-slice.
 	fn hash(s: &[u8]) -> Self::Output;
@@ -19,25 +78,43 @@ pub trait Hash: 'static + MaybeSerializeDeserialize + Debug + Clone + Eq + Parti
 	}
 
 	/// The ordered Patricia tree root of the given `input`.
-	fn ordered_trie_root(input: Vec<Vec<u8>>) -> Self::Output;
+	fn ordered_trie_root(input: Vec<Vec<u8>>) -> Self::Output {
+		trie::ordered_trie_root::<Self::Hasher, _, _>(input)
+	}
 
 	/// The Patricia tree root of the given mapping.
-	fn trie_root(input: Vec<(Vec<u8>, Vec<u8>)>) -> Self::Output;
+	fn trie_root(input: Vec<(Vec<u8>, Vec<u8>)>) -> Self::Output {
+		trie::trie_root::<Self::Hasher, _, _, _>(input)
+	}
 }
 
-pub trait Headerly: Clone + Eq + MaybeSerialize + Debug + 'static {
-    type Number: Member + MaybeSerializeDeserialize + Debug + MaybeDisplay + Copy Codec;
-    type Hash: Member + MaybeSerializeDeserialize + Debug + rstd::hash::Hash
-    + Copy + MaybeDisplay + Default + SimpleBitOps + Codec + AsRef<[u8]> + AsMut<[u8]>;
-    type Hashing: Hash<Output = Self::Hash>;
-    
-    fn numbers(&self)  -> &Self::Number;
-    fn set_numer(&mut self, number: Self::Number);
+/// A block header.
+pub trait Headerly: Clone + Eq + Debug + Codec + MaybeSerialize + Send + Sync + 'static {
+	/// The block number.
+	type Number: BlockNumber;
+	/// The block hash, and the hash of the roots and the digest.
+	type Hash: HashOutput;
+	/// The hasher producing `Hash`.
+	type Hashing: Hash<Output = Self::Hash>;
+
+	/// Creates a new header.
+	fn new(
+		number: Self::Number,
+		extrinsics_root: Self::Hash,
+		state_root: Self::Hash,
+		parent_hash: Self::Hash,
+		digest: Digest<Self::Hash>,
+	) -> Self;
+
+	/// Returns a reference to the block number.
+	fn number(&self) -> &Self::Number;
+	/// Sets the block number.
+	fn set_number(&mut self, number: Self::Number);
 
-    /// Returns a reference to the transaction root.
-	fn tx_root(&self) -> &Self::Hash;
-	/// Sets the transaction root.
-	fn set_tx_root(&mut self, root: Self::Hash);
+	/// Returns a reference to the extrinsics root.
+	fn extrinsics_root(&self) -> &Self::Hash;
+	/// Sets the extrinsics root.
+	fn set_extrinsics_root(&mut self, root: Self::Hash);
 
 	/// Returns a reference to the state root.
 	fn state_root(&self) -> &Self::Hash;
@@ -57,12 +134,19 @@ pub trait Headerly: Clone + Eq + MaybeSerialize + Debug + 'static {
 	/// Returns the hash of the header.
 	fn hash(&self) -> Self::Hash {
 		<Self::Hashing as Hash>::hash_of(self)
-    }
-    
+	}
 }
 
-pub trait Blockly: Clone + Eq + MaybeSerialize + Debug + 'static {
-    /// Returns a reference to the header.
+/// A block: a header and the extrinsics it commits to.
+pub trait Blockly: Clone + Eq + Debug + Codec + MaybeSerialize + Send + Sync + 'static {
+	/// The extrinsics of the block.
+	type Extrinsic: Member + Codec + MaybeSerialize;
+	/// The header of the block.
+	type Header: Headerly<Hash = Self::Hash>;
+	/// The block hash, the hash of its header.
+	type Hash: HashOutput;
+
+	/// Returns a reference to the header.
 	fn header(&self) -> &Self::Header;
 	/// Returns a reference to the list of extrinsics.
 	fn extrinsics(&self) -> &[Self::Extrinsic];
@@ -72,9 +156,18 @@ pub trait Blockly: Clone + Eq + MaybeSerialize + Debug + 'static {
 	fn new(header: Self::Header, extrinsics: Vec<Self::Extrinsic>) -> Self;
 	/// Returns the hash of the block.
 	fn hash(&self) -> Self::Hash {
-		<<Self::Header as Header>::Hashing as Hash>::hash_of(self.header())
+		<<Self::Header as Headerly>::Hashing as Hash>::hash_of(self.header())
+	}
+	/// The extrinsics root of `extrinsics`, the ordered trie root of their encodings.
+	fn extrinsics_root(extrinsics: &[Self::Extrinsic]) -> Self::Hash {
+		<<Self::Header as Headerly>::Hashing as Hash>::ordered_trie_root(
+			extrinsics.iter().map(Encode::encode).collect(),
+		)
+	}
+	/// Whether the extrinsics root of the header is the one of the extrinsics.
+	fn check_extrinsics_root(&self) -> bool {
+		Self::extrinsics_root(self.extrinsics()) == *self.header().extrinsics_root()
 	}
 	/// Create an encoded block from the given `header` and `extrinsics` without requiring to create an instance.
 	fn encode_from(header: &Self::Header, extrinsics: &[Self::Extrinsic]) -> Vec<u8>;
-
-}
\ No newline at end of file
+}
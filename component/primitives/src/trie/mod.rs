@@ -0,0 +1,299 @@
+//! Merkle Patricia trie, Ethereum's, generic over the hasher.
+//!
+//! `TrieMap` holds a whole trie in memory, to insert, get and remove values
+//! and compute the root at any point. `TrieStream` and `OrderedTrieStream`
+//! compute the root of keys appended in ascending order, or of values indexed
+//! by their position, hashing the subtries they are done with on the way.
+//! Empty values are absent values, as in Ethereum.
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::{fmt, marker::PhantomData, mem};
+
+use hash_db::Hasher;
+
+mod node;
+mod stream;
+
+use node::{common_prefix, nibbles, Node};
+
+pub use stream::{OrderedTrieStream, TrieStream};
+
+/// A trie held in memory.
+pub struct TrieMap<H> {
+	root: Node,
+	_hasher: PhantomData<H>,
+}
+
+impl<H> Default for TrieMap<H> {
+	fn default() -> Self {
+		TrieMap { root: Node::Empty, _hasher: PhantomData }
+	}
+}
+
+impl<H> Clone for TrieMap<H> {
+	fn clone(&self) -> Self {
+		TrieMap { root: self.root.clone(), _hasher: PhantomData }
+	}
+}
+
+impl<H> fmt::Debug for TrieMap<H> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("TrieMap").field("root", &self.root).finish()
+	}
+}
+
+impl<H: Hasher> TrieMap<H> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.root == Node::Empty
+	}
+
+	pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+		get(&self.root, &nibbles(key))
+	}
+
+	/// Set the value of `key`, removing it when `value` is empty, and return
+	/// the previous one.
+	pub fn insert(&mut self, key: &[u8], value: Vec<u8>) -> Option<Vec<u8>> {
+		if value.is_empty() {
+			return self.remove(key);
+		}
+		let (root, old) = insert(mem::take(&mut self.root), &nibbles(key), value);
+		self.root = root;
+		old
+	}
+
+	pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+		let (root, old) = remove(mem::take(&mut self.root), &nibbles(key));
+		self.root = root;
+		old
+	}
+
+	pub fn root(&self) -> H::Out {
+		H::hash(&self.root.encode::<H>())
+	}
+}
+
+fn get<'a>(node: &'a Node, key: &[u8]) -> Option<&'a [u8]> {
+	match node {
+		Node::Empty => None,
+		Node::Leaf(path, value) => Some(&value[..]).filter(|_| path[..] == *key),
+		Node::Extension(path, child) if key.starts_with(path) => get(child, &key[path.len()..]),
+		Node::Extension(..) => None,
+		Node::Branch(_, value) if key.is_empty() => value.as_deref(),
+		Node::Branch(children, _) => get(&children[key[0] as usize], &key[1..]),
+		Node::Ref(_) => unreachable!("a map holds whole subtries; qed"),
+	}
+}
+
+/// Put `value` in a new branch, at the end of `key`.
+fn put(children: &mut [Node; 16], branch_value: &mut Option<Vec<u8>>, key: &[u8], value: Vec<u8>) {
+	match key.split_first() {
+		None => *branch_value = Some(value),
+		Some((nibble, rest)) => children[*nibble as usize] = Node::Leaf(rest.to_vec(), value),
+	}
+}
+
+fn insert(node: Node, key: &[u8], value: Vec<u8>) -> (Node, Option<Vec<u8>>) {
+	match node {
+		Node::Empty => (Node::Leaf(key.to_vec(), value), None),
+		Node::Leaf(path, old) => {
+			if path[..] == *key {
+				return (Node::Leaf(path, value), Some(old));
+			}
+			let shared = common_prefix(&path, key);
+			let (mut children, mut branch_value) = (<[Node; 16]>::default(), None);
+			put(&mut children, &mut branch_value, &path[shared..], old);
+			put(&mut children, &mut branch_value, &key[shared..], value);
+			(Node::extend(&key[..shared], Node::branch(children, branch_value)), None)
+		}
+		Node::Extension(path, child) => {
+			let shared = common_prefix(&path, key);
+			if shared == path.len() {
+				let (child, old) = insert(*child, &key[shared..], value);
+				return (Node::Extension(path, Box::new(child)), old);
+			}
+			let (mut children, mut branch_value) = (<[Node; 16]>::default(), None);
+			children[path[shared] as usize] = Node::extend(&path[shared + 1..], *child);
+			put(&mut children, &mut branch_value, &key[shared..], value);
+			(Node::extend(&path[..shared], Node::branch(children, branch_value)), None)
+		}
+		Node::Branch(mut children, mut branch_value) => {
+			let old = match key.split_first() {
+				None => branch_value.replace(value),
+				Some((nibble, rest)) => {
+					let child = &mut children[*nibble as usize];
+					let (node, old) = insert(mem::take(child), rest, value);
+					*child = node;
+					old
+				}
+			};
+			(Node::Branch(children, branch_value), old)
+		}
+		Node::Ref(_) => unreachable!("a map holds whole subtries; qed"),
+	}
+}
+
+fn remove(node: Node, key: &[u8]) -> (Node, Option<Vec<u8>>) {
+	match node {
+		Node::Leaf(path, value) if path[..] == *key => (Node::Empty, Some(value)),
+		Node::Extension(path, child) if key.starts_with(&path) => {
+			let (child, old) = remove(*child, &key[path.len()..]);
+			(Node::extend(&path, child), old)
+		}
+		Node::Branch(mut children, mut branch_value) => {
+			let old = match key.split_first() {
+				None => branch_value.take(),
+				Some((nibble, rest)) => {
+					let child = &mut children[*nibble as usize];
+					let (node, old) = remove(mem::take(child), rest);
+					*child = node;
+					old
+				}
+			};
+			if old.is_none() {
+				return (Node::Branch(children, branch_value), None);
+			}
+			(collapse(children, branch_value), old)
+		}
+		node => (node, None),
+	}
+}
+
+/// The branch of `children` and `value`, or what it shrinks to when it has
+/// one entry left.
+fn collapse(mut children: Box<[Node; 16]>, value: Option<Vec<u8>>) -> Node {
+	let mut used = children.iter().enumerate().filter(|(_, child)| **child != Node::Empty).map(|(i, _)| i);
+	match (used.next(), used.next(), value) {
+		(None, _, None) => Node::Empty,
+		(None, _, Some(value)) => Node::Leaf(Vec::new(), value),
+		(Some(i), None, None) => Node::extend(&[i as u8], mem::take(&mut children[i])),
+		(_, _, value) => Node::Branch(children, value),
+	}
+}
+
+/// Root of the trie of `input`, the last value of a key repeated winning.
+pub fn trie_root<H, I, K, V>(input: I) -> H::Out
+where
+	H: Hasher,
+	I: IntoIterator<Item = (K, V)>,
+	K: AsRef<[u8]> + Ord,
+	V: AsRef<[u8]>,
+{
+	let sorted: BTreeMap<K, V> = input.into_iter().collect();
+	let mut stream = TrieStream::<H>::new();
+	for (key, value) in sorted.iter() {
+		stream.append(key.as_ref(), value.as_ref());
+	}
+	stream.root()
+}
+
+/// Root of the trie of `input` keyed by the RLP of their index.
+pub fn ordered_trie_root<H, I, V>(input: I) -> H::Out
+where
+	H: Hasher,
+	I: IntoIterator<Item = V>,
+	V: AsRef<[u8]>,
+{
+	let mut stream = OrderedTrieStream::<H>::new();
+	for value in input {
+		stream.push(value.as_ref());
+	}
+	stream.root()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::hash::Blake2Hasher;
+	use keccak::KeccakHasher;
+
+	/// Keys sharing prefixes, some prefixes of others, and values long enough
+	/// to hash some nodes.
+	fn input() -> Vec<(Vec<u8>, Vec<u8>)> {
+		let mut input: Vec<(Vec<u8>, Vec<u8>)> = vec![
+			(b"doe".to_vec(), b"reindeer".to_vec()),
+			(b"dog".to_vec(), b"puppy".to_vec()),
+			(b"dogglesworth".to_vec(), b"cat".to_vec()),
+			(b"do".to_vec(), b"verb".to_vec()),
+			(b"horse".to_vec(), b"stallion".to_vec()),
+			(vec![0x01], vec![0x02; 40]),
+			(vec![0x01, 0x10], vec![0x03; 40]),
+			(vec![0x01, 0x11], vec![0x04]),
+		];
+		for i in 0..200u32 {
+			let key = (i.wrapping_mul(0x9e37_79b9) >> 8).to_be_bytes().to_vec();
+			input.push((key, i.to_le_bytes().repeat(i as usize % 12 + 1)));
+		}
+		input
+	}
+
+	#[test]
+	fn test_known_root() {
+		let input = vec![("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")];
+		assert_eq!(
+			trie_root::<KeccakHasher, _, _, _>(input).as_bytes(),
+			&[
+				0x8a, 0xad, 0x78, 0x9d, 0xff, 0x2f, 0x53, 0x8b, 0xca, 0x5d, 0x8e, 0xa5, 0x6e, 0x8a,
+				0xbe, 0x10, 0xf4, 0xc7, 0xba, 0x3a, 0x5d, 0xea, 0x95, 0xfe, 0xa4, 0xcd, 0x6e, 0x7c,
+				0x3a, 0x11, 0x68, 0xd3,
+			]
+		);
+		// the root of the empty trie, keccak of the RLP of the empty string
+		assert_eq!(TrieMap::<KeccakHasher>::new().root(), KeccakHasher::hash(&[0x80]));
+	}
+
+	#[test]
+	fn test_triehash() {
+		let input = input();
+		assert_eq!(
+			trie_root::<KeccakHasher, _, _, _>(input.clone()),
+			triehash::trie_root::<KeccakHasher, _, _, _>(input.clone())
+		);
+		for len in [0, 1, 2, 127, 128, 129, 300].iter() {
+			let values: Vec<Vec<u8>> = input.iter().cycle().take(*len).map(|(_, value)| value.clone()).collect();
+			assert_eq!(
+				ordered_trie_root::<KeccakHasher, _, _>(values.iter()),
+				triehash::ordered_trie_root::<KeccakHasher, _>(values.iter()),
+				"{} values",
+				len
+			);
+		}
+	}
+
+	#[test]
+	fn test_map() {
+		let input = input();
+		let mut map = TrieMap::<Blake2Hasher>::new();
+		for (key, value) in input.iter() {
+			assert_eq!(map.insert(key, value.clone()), None);
+		}
+		assert_eq!(map.root(), trie_root::<Blake2Hasher, _, _, _>(input.clone()));
+		for (key, value) in input.iter() {
+			assert_eq!(map.get(key), Some(&value[..]));
+		}
+		assert_eq!(map.get(b"d"), None);
+		assert_eq!(map.get(b"dogs"), None);
+		assert_eq!(map.insert(b"dog", b"hound".to_vec()), Some(b"puppy".to_vec()));
+		assert_eq!(map.get(b"dog"), Some(&b"hound"[..]));
+		assert_eq!(map.insert(b"dog", b"puppy".to_vec()), Some(b"hound".to_vec()));
+
+		// removing keys in any order leaves the trie of the others
+		let mut removed = input.clone();
+		removed.sort_by_key(|(_, value)| value.clone());
+		while let Some((key, value)) = removed.pop() {
+			assert_eq!(map.remove(&key), Some(value));
+			assert_eq!(map.remove(&key), None);
+			assert_eq!(map.get(&key), None);
+			assert_eq!(map.root(), trie_root::<Blake2Hasher, _, _, _>(removed.clone()));
+		}
+		assert!(map.is_empty());
+
+		map.insert(b"dog", b"puppy".to_vec());
+		assert_eq!(map.insert(b"dog", Vec::new()), Some(b"puppy".to_vec()));
+		assert!(map.is_empty());
+	}
+}
@@ -0,0 +1,199 @@
+//! Nodes of the trie and their encoding, Ethereum's: RLP lists, hex prefixed
+//! paths, and children encoded in less than 32 bytes inlined in their parent
+//! instead of referenced by hash.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use hash_db::Hasher;
+
+/// Path in the trie, one nibble per byte.
+pub type Nibbles = Vec<u8>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+	Empty,
+	/// the value of the key ending with the path
+	Leaf(Nibbles, Vec<u8>),
+	/// a path shared by the keys below, to a branch
+	Extension(Nibbles, Box<Node>),
+	/// the subtries of the 16 next nibbles, and the value of the key ending here
+	Branch(Box<[Node; 16]>, Option<Vec<u8>>),
+	/// a complete subtrie, by the reference its parent includes
+	Ref(Vec<u8>),
+}
+
+impl Default for Node {
+	fn default() -> Self {
+		Node::Empty
+	}
+}
+
+impl Node {
+	pub fn branch(children: [Node; 16], value: Option<Vec<u8>>) -> Node {
+		Node::Branch(Box::new(children), value)
+	}
+
+	/// `node` below `path`, a path of the trie taking nothing but `path` to it.
+	pub fn extend(path: &[u8], node: Node) -> Node {
+		if path.is_empty() {
+			return node;
+		}
+		let join = |rest: Nibbles| {
+			let mut full = path.to_vec();
+			full.extend_from_slice(&rest);
+			full
+		};
+		match node {
+			Node::Empty => Node::Empty,
+			Node::Leaf(rest, value) => Node::Leaf(join(rest), value),
+			Node::Extension(rest, child) => Node::Extension(join(rest), child),
+			node => Node::Extension(path.to_vec(), Box::new(node)),
+		}
+	}
+
+	/// Encoding of the node.
+	pub fn encode<H: Hasher>(&self) -> Vec<u8> {
+		let mut payload = Vec::new();
+		match self {
+			Node::Empty => return vec![EMPTY],
+			Node::Leaf(path, value) => {
+				rlp_bytes(&mut payload, &hex_prefix(path, true));
+				rlp_bytes(&mut payload, value);
+			}
+			Node::Extension(path, child) => {
+				rlp_bytes(&mut payload, &hex_prefix(path, false));
+				child.encode_ref::<H>(&mut payload);
+			}
+			Node::Branch(children, value) => {
+				for child in children.iter() {
+					child.encode_ref::<H>(&mut payload);
+				}
+				rlp_bytes(&mut payload, value.as_deref().unwrap_or(&[]));
+			}
+			Node::Ref(_) => unreachable!("only the children of a branch are references; qed"),
+		}
+		let mut out = Vec::with_capacity(payload.len() + 9);
+		rlp_length(&mut out, payload.len(), 0xc0);
+		out.extend_from_slice(&payload);
+		out
+	}
+
+	/// Reference of the node in its parent.
+	pub fn reference<H: Hasher>(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		self.encode_ref::<H>(&mut out);
+		out
+	}
+
+	fn encode_ref<H: Hasher>(&self, out: &mut Vec<u8>) {
+		match self {
+			Node::Empty => out.push(EMPTY),
+			Node::Ref(reference) => out.extend_from_slice(reference),
+			node => {
+				let encoded = node.encode::<H>();
+				if encoded.len() < 32 {
+					out.extend_from_slice(&encoded);
+				} else {
+					rlp_bytes(out, H::hash(&encoded).as_ref());
+				}
+			}
+		}
+	}
+}
+
+/// RLP of the empty string, the empty trie and the absent children.
+const EMPTY: u8 = 0x80;
+
+pub fn nibbles(key: &[u8]) -> Nibbles {
+	key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+}
+
+pub fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+	a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count()
+}
+
+/// `path` packed two nibbles a byte, after a nibble flagging leaves and odd lengths.
+fn hex_prefix(path: &[u8], leaf: bool) -> Vec<u8> {
+	let flag = if leaf { 2 } else { 0 };
+	let mut out = Vec::with_capacity(path.len() / 2 + 1);
+	let pairs = if path.len() % 2 == 1 {
+		out.push((flag + 1) << 4 | path[0]);
+		&path[1..]
+	} else {
+		out.push(flag << 4);
+		path
+	};
+	out.extend(pairs.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+	out
+}
+
+fn rlp_length(out: &mut Vec<u8>, len: usize, offset: u8) {
+	if len < 56 {
+		out.push(offset + len as u8);
+	} else {
+		let bytes = (len as u64).to_be_bytes();
+		let skip = bytes.iter().take_while(|b| **b == 0).count();
+		out.push(offset + 55 + (8 - skip) as u8);
+		out.extend_from_slice(&bytes[skip..]);
+	}
+}
+
+fn rlp_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+	if bytes.len() != 1 || bytes[0] >= 0x80 {
+		rlp_length(out, bytes.len(), 0x80);
+	}
+	out.extend_from_slice(bytes);
+}
+
+/// RLP of `index`, its key in an ordered trie.
+pub fn index_key(index: usize) -> Vec<u8> {
+	let mut out = Vec::with_capacity(9);
+	let bytes = (index as u64).to_be_bytes();
+	let skip = bytes.iter().take_while(|b| **b == 0).count();
+	rlp_bytes(&mut out, &bytes[skip..]);
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_hex_prefix() {
+		assert_eq!(hex_prefix(&[1, 2, 3, 4, 5], false), vec![0x11, 0x23, 0x45]);
+		assert_eq!(hex_prefix(&[0, 1, 2, 3, 4, 5], false), vec![0x00, 0x01, 0x23, 0x45]);
+		assert_eq!(hex_prefix(&[0, 15, 1, 12, 11, 8], true), vec![0x20, 0x0f, 0x1c, 0xb8]);
+		assert_eq!(hex_prefix(&[15, 1, 12, 11, 8], true), vec![0x3f, 0x1c, 0xb8]);
+		assert_eq!(hex_prefix(&[], true), vec![0x20]);
+	}
+
+	#[test]
+	fn test_rlp() {
+		let rlp = |bytes: &[u8]| {
+			let mut out = Vec::new();
+			rlp_bytes(&mut out, bytes);
+			out
+		};
+		assert_eq!(rlp(&[]), vec![0x80]);
+		assert_eq!(rlp(&[0x7f]), vec![0x7f]);
+		assert_eq!(rlp(&[0x80]), vec![0x81, 0x80]);
+		assert_eq!(rlp(b"dog"), b"\x83dog".to_vec());
+		let long = [0xaa; 1024];
+		assert_eq!(rlp(&long)[..3], [0xb9, 0x04, 0x00]);
+
+		assert_eq!(index_key(0), vec![0x80]);
+		assert_eq!(index_key(1), vec![0x01]);
+		assert_eq!(index_key(127), vec![0x7f]);
+		assert_eq!(index_key(128), vec![0x81, 0x80]);
+		assert_eq!(index_key(256), vec![0x82, 0x01, 0x00]);
+	}
+
+	#[test]
+	fn test_extend() {
+		let leaf = Node::Leaf(vec![3], vec![1]);
+		assert_eq!(Node::extend(&[1, 2], leaf.clone()), Node::Leaf(vec![1, 2, 3], vec![1]));
+		assert_eq!(Node::extend(&[], leaf.clone()), leaf);
+		let branch = Node::branch(Default::default(), Some(vec![1]));
+		assert_eq!(Node::extend(&[1], branch.clone()), Node::Extension(vec![1], Box::new(branch)));
+	}
+}
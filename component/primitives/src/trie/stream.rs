@@ -0,0 +1,206 @@
+//! Roots computed as the values come.
+//!
+//! Keys appended in ascending order leave every subtrie before the path of the
+//! last key complete: the stream replaces them by their reference as soon as
+//! it is done with them, and only holds that path.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{fmt, marker::PhantomData, mem};
+
+use hash_db::Hasher;
+
+use super::node::{common_prefix, index_key, nibbles, Node};
+
+/// Root of the trie of keys appended in ascending order.
+pub struct TrieStream<H> {
+	root: Node,
+	last: Option<Vec<u8>>,
+	_hasher: PhantomData<H>,
+}
+
+impl<H> Default for TrieStream<H> {
+	fn default() -> Self {
+		TrieStream { root: Node::Empty, last: None, _hasher: PhantomData }
+	}
+}
+
+impl<H> fmt::Debug for TrieStream<H> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("TrieStream").field("root", &self.root).field("last", &self.last).finish()
+	}
+}
+
+impl<H: Hasher> TrieStream<H> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Append `value` at `key`, greater than the keys appended before. Empty
+	/// values are skipped.
+	///
+	/// # Panics
+	///
+	/// If `key` is not greater than the last key.
+	pub fn append(&mut self, key: &[u8], value: &[u8]) {
+		if let Some(last) = &self.last {
+			assert!(key > &last[..], "keys are appended in ascending order");
+		}
+		self.last = Some(key.to_vec());
+		if value.is_empty() {
+			return;
+		}
+		self.root = append::<H>(mem::take(&mut self.root), &nibbles(key), value.to_vec());
+	}
+
+	pub fn root(&self) -> H::Out {
+		H::hash(&self.root.encode::<H>())
+	}
+}
+
+/// A new branch, holding the complete subtrie `done` at `nibble`, then `value` at the end of `key`.
+fn fork<H: Hasher>(nibble: u8, done: Node, key: &[u8], value: Vec<u8>) -> Node {
+	let mut children = <[Node; 16]>::default();
+	children[nibble as usize] = Node::Ref(done.reference::<H>());
+	children[key[0] as usize] = Node::Leaf(key[1..].to_vec(), value);
+	Node::branch(children, None)
+}
+
+fn append<H: Hasher>(node: Node, key: &[u8], value: Vec<u8>) -> Node {
+	// `key` is greater than any key below `node`: it is not a prefix of one,
+	// and it goes right of them where they fork
+	match node {
+		Node::Empty => Node::Leaf(key.to_vec(), value),
+		Node::Leaf(path, old) => {
+			let shared = common_prefix(&path, key);
+			let branch = if shared == path.len() {
+				let mut children = <[Node; 16]>::default();
+				children[key[shared] as usize] = Node::Leaf(key[shared + 1..].to_vec(), value);
+				Node::branch(children, Some(old))
+			} else {
+				fork::<H>(path[shared], Node::Leaf(path[shared + 1..].to_vec(), old), &key[shared..], value)
+			};
+			Node::extend(&key[..shared], branch)
+		}
+		Node::Extension(path, child) => {
+			let shared = common_prefix(&path, key);
+			if shared == path.len() {
+				return Node::Extension(path, Box::new(append::<H>(*child, &key[shared..], value)));
+			}
+			let done = Node::extend(&path[shared + 1..], *child);
+			Node::extend(&path[..shared], fork::<H>(path[shared], done, &key[shared..], value))
+		}
+		Node::Branch(mut children, branch_value) => {
+			let nibble = key[0] as usize;
+			for child in children[..nibble].iter_mut() {
+				if let Node::Leaf(..) | Node::Extension(..) | Node::Branch(..) = child {
+					*child = Node::Ref(child.reference::<H>());
+				}
+			}
+			children[nibble] = append::<H>(mem::take(&mut children[nibble]), &key[1..], value);
+			Node::Branch(children, branch_value)
+		}
+		Node::Ref(_) => unreachable!("keys are appended in ascending order, right of the complete subtries; qed"),
+	}
+}
+
+/// Root of the trie of values keyed by the RLP of their index, pushed in the
+/// order of their index.
+///
+/// The RLP of 0 sorts after the one of 1 to 127, the stream holds the first
+/// value back until then.
+pub struct OrderedTrieStream<H> {
+	stream: TrieStream<H>,
+	first: Option<Vec<u8>>,
+	count: usize,
+}
+
+impl<H> Default for OrderedTrieStream<H> {
+	fn default() -> Self {
+		OrderedTrieStream { stream: TrieStream::default(), first: None, count: 0 }
+	}
+}
+
+impl<H> fmt::Debug for OrderedTrieStream<H> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("OrderedTrieStream").field("stream", &self.stream).field("count", &self.count).finish()
+	}
+}
+
+impl<H: Hasher> OrderedTrieStream<H> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn push(&mut self, value: &[u8]) {
+		if self.count == 0 {
+			self.first = Some(value.to_vec());
+		} else {
+			if self.count == 128 {
+				self.flush_first();
+			}
+			self.stream.append(&index_key(self.count), value);
+		}
+		self.count += 1;
+	}
+
+	pub fn root(mut self) -> H::Out {
+		self.flush_first();
+		self.stream.root()
+	}
+
+	fn flush_first(&mut self) {
+		if let Some(first) = self.first.take() {
+			self.stream.append(&index_key(0), &first);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::trie::TrieMap;
+	use keccak::KeccakHasher;
+
+	#[test]
+	fn test_stream() {
+		let mut map = TrieMap::<KeccakHasher>::new();
+		let mut stream = TrieStream::<KeccakHasher>::new();
+		let keys = [vec![0x00], vec![0x00, 0x01], vec![0x01, 0x23], vec![0x01, 0x24, 0x00], vec![0x01, 0x30], vec![0xf0]];
+		for (i, key) in keys.iter().enumerate() {
+			let value = vec![i as u8 + 1; i * 8];
+			map.insert(key, value.clone());
+			stream.append(key, &value);
+			assert_eq!(stream.root(), map.root());
+		}
+		assert!(std::panic::catch_unwind(move || stream.append(&[0x01], b"late")).is_err());
+
+		let ordered = OrderedTrieStream::<KeccakHasher>::new();
+		assert_eq!(ordered.root(), TrieMap::<KeccakHasher>::new().root());
+		let mut ordered = OrderedTrieStream::<KeccakHasher>::new();
+		let mut map = TrieMap::<KeccakHasher>::new();
+		for i in 0..130usize {
+			let value = vec![i as u8; 33];
+			ordered.push(&value);
+			map.insert(&index_key(i), value);
+		}
+		assert_eq!(ordered.root(), map.root());
+	}
+
+	#[test]
+	fn test_bounded() {
+		// the stream holds the path of the last key, the rest are references
+		fn live(node: &Node) -> usize {
+			match node {
+				Node::Empty | Node::Ref(_) => 0,
+				Node::Leaf(..) => 1,
+				Node::Extension(_, child) => 1 + live(child),
+				Node::Branch(children, _) => 1 + children.iter().map(live).sum::<usize>(),
+			}
+		}
+		let mut stream = TrieStream::<KeccakHasher>::new();
+		for i in 0..4096u32 {
+			stream.append(&i.to_be_bytes(), &[0xaa; 40]);
+			assert!(live(&stream.root) <= 8);
+		}
+	}
+}
@@ -188,6 +188,8 @@ impl sudo::Trait for Runtime {
 /// Used for the module validator in `./validator.rs`
 impl validator::Trait for Runtime {
 	type Event = Event;
+	type SessionKey = AuthorityId;
+	type ValidatorKey = validator::Ed25519Key;
 }
 
 construct_runtime!(
@@ -204,7 +206,7 @@ construct_runtime!(
 		Balances: balances,
 		Sudo: sudo,
 		// Used for the module validator in `./validator.rs`
-		ValidatorModule: validator::{Module, Call, Storage, Event<T>},
+		ValidatorModule: validator::{Module, Call, Storage, Config<T>, Event<T>},
 	}
 );
 
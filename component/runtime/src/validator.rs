@@ -1,5 +1,9 @@
-use support::{decl_module, decl_storage, decl_event, StorageValue, dispatch::Result};
-use system::ensure_signed;
+use rstd::prelude::*;
+use parity_codec::{Encode, Decode};
+use support::{decl_module, decl_storage, decl_event, StorageValue, StorageMap, Parameter, dispatch::Result, ensure};
+use system::{ensure_signed, ensure_root};
+use runtime_primitives::traits::{As, MaybeSerializeDebug};
+use primitives::ed25519;
 
 /// The module's configuration trait.
 pub trait Trait: system::Trait {
@@ -7,6 +11,103 @@ pub trait Trait: system::Trait {
 
 	/// The overarching event type.
 	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+
+	/// Key a validator signs its GBFT messages with, registered for its
+	/// account through `set_session_key`.
+	type SessionKey: Parameter + MaybeSerializeDebug;
+
+	/// Signature scheme of the session keys.
+	type ValidatorKey: ValidatorKey<Self::SessionKey>;
+}
+
+/// Signature scheme of the GBFT validators, to check equivocation evidence.
+pub trait ValidatorKey<Key> {
+	/// GBFT address of the validator signing with `key`.
+	fn address(key: &Key) -> Vec<u8>;
+	/// Whether `signature` over `payload` is one of `key`.
+	fn verify(key: &Key, payload: &[u8], signature: &[u8]) -> bool;
+}
+
+/// The ed25519 authority key of the GBFT nodes: they sign the blake2 hash of
+/// a message and are addressed by the raw public key.
+pub struct Ed25519Key;
+
+impl ValidatorKey<ed25519::Public> for Ed25519Key {
+	fn address(key: &ed25519::Public) -> Vec<u8> {
+		key.0.to_vec()
+	}
+
+	fn verify(key: &ed25519::Public, payload: &[u8], signature: &[u8]) -> bool {
+		if signature.len() != 64 {
+			return false;
+		}
+		let mut raw = [0u8; 64];
+		raw.copy_from_slice(signature);
+		runtime_io::ed25519_verify(&raw, &runtime_io::blake2_256(payload), &key.0[..])
+	}
+}
+
+// SCALE layout of `gbft::Evidence` and the messages it carries, the runtime
+// does not depend on the GBFT crate. GBFT signs the SCALE encoding of
+// proposals and votes, so the runtime checks the signatures of these.
+
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum VoteType {
+	Prepare,
+	Commit,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Vote {
+	pub height: u64,
+	pub round: u64,
+	pub vote_type: VoteType,
+	pub block_hash: Vec<u8>,
+	pub voter: Vec<u8>,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct SignedVote {
+	pub vote: Vote,
+	pub signature: Vec<u8>,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct PreparedCertificate {
+	pub height: u64,
+	pub round: u64,
+	pub block_hash: Vec<u8>,
+	pub votes: Vec<SignedVote>,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Proposal {
+	pub height: u64,
+	pub round: u64,
+	pub block_hash: Vec<u8>,
+	pub content: Vec<u8>,
+	pub proposer: Vec<u8>,
+	pub lock: Option<PreparedCertificate>,
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct SignedProposal {
+	pub proposal: Proposal,
+	pub signature: Vec<u8>,
+}
+
+/// Two conflicting messages signed by the same validator.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum Evidence {
+	DoubleProposal { first: SignedProposal, second: SignedProposal },
+	DoubleVote { first: SignedVote, second: SignedVote },
 }
 
 /// This module's storage items.
@@ -16,6 +117,30 @@ decl_storage! {
 		// Here we are declaring a StorageValue, `Something` as a Option<u32>
 		// `get(something)` is the default getter which returns either the stored `u32` or `None` if nothing stored
 		Something get(something): Option<u32>;
+
+		// SCALE encoded `gbft::Evidence` reported against a validator, by height.
+		// `report_equivocation` checks it before recording it.
+		Equivocations get(equivocation): map (T::AccountId, u64) => Option<Vec<u8>>;
+		// Number of equivocations of a validator, read when punishing it.
+		Offences get(offences): map T::AccountId => u32;
+		// GBFT key of each validator account, evidence is checked against it.
+		SessionKeys get(session_key) build(|config: &GenesisConfig<T>| config.session_keys.clone()): map T::AccountId => Option<T::SessionKey>;
+
+		// Number of blocks of a GBFT epoch, epoch `e` starts at block `e * EpochLength + 1`.
+		EpochLength get(epoch_length): u64 = 100;
+		// Current epoch and its validators with their voting power.
+		CurrentEpoch get(current_epoch): u64;
+		// First block of the current epoch, evidence of earlier heights was
+		// signed under another validator set.
+		EpochStart get(epoch_start): u64;
+		Validators get(validators) config(): Vec<(T::AccountId, u64)>;
+		// Validators of the next epoch and the block they take over at. GBFT
+		// nodes read it after every block and schedule it in their state.
+		PendingValidators get(pending_validators): Option<(u64, u64, Vec<(T::AccountId, u64)>)>;
+	}
+	add_extra_genesis {
+		// Session keys of the genesis validators.
+		config(session_keys): Vec<(T::AccountId, T::SessionKey)>;
 	}
 }
 
@@ -41,6 +166,97 @@ decl_module! {
 			Self::deposit_event(RawEvent::SomethingStored(something, who));
 			Ok(())
 		}
+
+		// Record that `offender` signed two conflicting GBFT messages at `height`,
+		// once `evidence` proves it. Only the first report of a height counts.
+		pub fn report_equivocation(origin, offender: T::AccountId, height: u64, evidence: Vec<u8>) -> Result {
+			let _reporter = ensure_signed(origin)?;
+			ensure!(!<Equivocations<T>>::exists((offender.clone(), height)), "Equivocation already reported");
+			let decoded = Evidence::decode(&mut &evidence[..]).ok_or("Undecodable evidence")?;
+			Self::check_evidence(&offender, height, &decoded)?;
+
+			<Equivocations<T>>::insert((offender.clone(), height), evidence);
+			<Offences<T>>::mutate(&offender, |count| *count += 1);
+
+			Self::deposit_event(RawEvent::EquivocationReported(offender, height));
+			Ok(())
+		}
+
+		// Sign GBFT messages with `key` from now on, the authority key of the
+		// node of the validator `origin`.
+		pub fn set_session_key(origin, key: T::SessionKey) -> Result {
+			let who = ensure_signed(origin)?;
+			<SessionKeys<T>>::insert(&who, key);
+
+			Self::deposit_event(RawEvent::SessionKeySet(who));
+			Ok(())
+		}
+
+		// Replace the validators from the start of the next epoch that begins
+		// at least two blocks from now, so that every node learns about the
+		// change before it happens.
+		pub fn schedule_validators(origin, validators: Vec<(T::AccountId, u64)>) -> Result {
+			ensure_root(origin)?;
+			ensure!(!validators.is_empty(), "Empty validator set");
+			ensure!(validators.iter().all(|(_, power)| *power > 0), "Validator without voting power");
+			ensure!(<PendingValidators<T>>::get().is_none(), "Validator change already pending");
+
+			let now = <system::Module<T>>::block_number().as_();
+			let length = Self::epoch_length();
+			let epoch = now / length + 1;
+			let start = epoch * length + 1;
+			<PendingValidators<T>>::put((epoch, start, validators));
+
+			Self::deposit_event(RawEvent::ValidatorsScheduled(epoch, start));
+			Ok(())
+		}
+
+		fn on_finalize(n: T::BlockNumber) {
+			if let Some((epoch, start, validators)) = <PendingValidators<T>>::get() {
+				if n.as_() + 1 == start {
+					<Validators<T>>::put(validators);
+					<CurrentEpoch<T>>::put(epoch);
+					<EpochStart<T>>::put(start);
+					<PendingValidators<T>>::kill();
+					Self::deposit_event(RawEvent::ValidatorsChanged(epoch));
+				}
+			}
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// Check that `evidence` holds two messages signed with the session key
+	/// of `offender`, a validator, for different blocks in the same slot of
+	/// `height`. Only the current validator set is known, so `height` must
+	/// belong to its epoch.
+	fn check_evidence(offender: &T::AccountId, height: u64, evidence: &Evidence) -> Result {
+		ensure!(height >= Self::epoch_start(), "Evidence from before the current validators");
+		ensure!(Self::validators().iter().any(|(who, _)| who == offender), "Offender is not a validator");
+		let key = Self::session_key(offender).ok_or("Offender has no session key")?;
+		let address = T::ValidatorKey::address(&key);
+		let signed = match evidence {
+			Evidence::DoubleProposal { first, second } => {
+				let (a, b) = (&first.proposal, &second.proposal);
+				ensure!((a.height, a.round, &a.proposer) == (b.height, b.round, &b.proposer), "Proposals of different slots");
+				ensure!(a.block_hash != b.block_hash, "Proposals of the same block");
+				ensure!(a.proposer == address, "Evidence of another validator");
+				ensure!(a.height == height, "Evidence of another height");
+				[(a.encode(), &first.signature), (b.encode(), &second.signature)]
+			}
+			Evidence::DoubleVote { first, second } => {
+				let (a, b) = (&first.vote, &second.vote);
+				ensure!((a.height, a.round, a.vote_type, &a.voter) == (b.height, b.round, b.vote_type, &b.voter), "Votes of different slots");
+				ensure!(a.block_hash != b.block_hash, "Votes for the same block");
+				ensure!(a.voter == address, "Evidence of another validator");
+				ensure!(a.height == height, "Evidence of another height");
+				[(a.encode(), &first.signature), (b.encode(), &second.signature)]
+			}
+		};
+		for (payload, signature) in signed.iter() {
+			ensure!(T::ValidatorKey::verify(&key, payload, signature), "Invalid evidence signature");
+		}
+		Ok(())
 	}
 }
 
@@ -50,6 +266,14 @@ decl_event!(
 		// Event `Something` is declared with a parameter of the type `u32` and `AccountId`
 		// To emit this event, we call the deposit funtion, from our runtime funtions
 		SomethingStored(u32, AccountId),
+		// A validator equivocated at the given height.
+		EquivocationReported(AccountId, u64),
+		// A validator changed its GBFT session key.
+		SessionKeySet(AccountId),
+		// New validators take over at the start of the epoch, at the given block.
+		ValidatorsScheduled(u64, u64),
+		// The validators of the epoch are active.
+		ValidatorsChanged(u64),
 	}
 );
 
@@ -59,11 +283,11 @@ mod tests {
 	use super::*;
 
 	use runtime_io::with_externalities;
-	use primitives::{H256, Blake2Hasher};
+	use primitives::{H256, Blake2Hasher, blake2_256, Pair};
 	use support::{impl_outer_origin, assert_ok};
 	use runtime_primitives::{
 		BuildStorage,
-		traits::{BlakeTwo256, IdentityLookup},
+		traits::{BlakeTwo256, IdentityLookup, OnFinalize},
 		testing::{Digest, DigestItem, Header}
 	};
 
@@ -91,8 +315,27 @@ mod tests {
 	}
 	impl Trait for Test {
 		type Event = ();
+		type SessionKey = ed25519::Public;
+		type ValidatorKey = Ed25519Key;
+	}
+
+	/// Authority key of the node of validator `who`.
+	fn pair(who: u64) -> ed25519::Pair {
+		ed25519::Pair::from_string(&format!("//{}", who), None).expect("static values are valid; qed")
+	}
+
+	/// A vote signed like GBFT nodes do, with the authority key of `voter`.
+	fn vote(voter: u64, height: u64, block_hash: u8) -> SignedVote {
+		let key = pair(voter);
+		let vote = Vote { height, round: 0, vote_type: VoteType::Commit, block_hash: vec![block_hash], voter: key.public().0.to_vec() };
+		SignedVote { signature: key.sign(&blake2_256(&vote.encode())).0.to_vec(), vote }
+	}
+
+	fn double_vote(voter: u64, height: u64) -> Vec<u8> {
+		Evidence::DoubleVote { first: vote(voter, height, 1), second: vote(voter, height, 2) }.encode()
 	}
 	type ValidatorModule = Module<Test>;
+	type System = system::Module<Test>;
 
 	// This function basically just builds a genesis storage key/value store according to
 	// our desired mockup.
@@ -100,6 +343,23 @@ mod tests {
 		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
 	}
 
+	fn genesis_ext(validators: &[u64]) -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(GenesisConfig::<Test> {
+			validators: validators.iter().map(|who| (*who, 1)).collect(),
+			session_keys: validators.iter().map(|who| (*who, pair(*who).public())).collect(),
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	/// Make `validators` the current set, each registering its authority key.
+	fn set_validators(validators: &[u64]) {
+		<Validators<Test>>::put(validators.iter().map(|who| (*who, 1)).collect::<Vec<_>>());
+		for who in validators {
+			assert_ok!(ValidatorModule::set_session_key(Origin::signed(*who), pair(*who).public()));
+		}
+	}
+
 	#[test]
 	fn it_works_for_default_value() {
 		with_externalities(&mut new_test_ext(), || {
@@ -110,4 +370,110 @@ mod tests {
 			assert_eq!(ValidatorModule::something(), Some(42));
 		});
 	}
+
+	#[test]
+	fn validators_change_at_epoch_start() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(150);
+			assert!(ValidatorModule::schedule_validators(Origin::signed(1), vec![(1, 1)]).is_err());
+			assert_ok!(ValidatorModule::schedule_validators(Origin::ROOT, vec![(1, 1), (2, 3)]));
+			assert_eq!(ValidatorModule::pending_validators(), Some((2, 201, vec![(1, 1), (2, 3)])));
+			assert!(ValidatorModule::schedule_validators(Origin::ROOT, vec![(3, 1)]).is_err());
+
+			<ValidatorModule as OnFinalize<u64>>::on_finalize(199);
+			assert_eq!(ValidatorModule::current_epoch(), 0);
+			<ValidatorModule as OnFinalize<u64>>::on_finalize(200);
+			assert_eq!(ValidatorModule::current_epoch(), 2);
+			assert_eq!(ValidatorModule::validators(), vec![(1, 1), (2, 3)]);
+			assert_eq!(ValidatorModule::pending_validators(), None);
+		});
+	}
+
+	#[test]
+	fn equivocation_is_recorded_once() {
+		with_externalities(&mut new_test_ext(), || {
+			set_validators(&[2, 3]);
+			assert_ok!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, double_vote(2, 10)));
+			assert_eq!(ValidatorModule::equivocation((2, 10)), Some(double_vote(2, 10)));
+			assert_eq!(ValidatorModule::offences(2), 1);
+
+			assert!(ValidatorModule::report_equivocation(Origin::signed(3), 2, 10, double_vote(2, 10)).is_err());
+			assert_ok!(ValidatorModule::report_equivocation(Origin::signed(3), 2, 11, double_vote(2, 11)));
+			assert_eq!(ValidatorModule::offences(2), 2);
+		});
+	}
+
+	#[test]
+	fn forged_evidence_is_rejected() {
+		with_externalities(&mut new_test_ext(), || {
+			set_validators(&[2, 3]);
+			// made up bytes
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, vec![1, 2, 3]).is_err());
+			// a signature of someone else
+			let mut second = vote(2, 10, 2);
+			second.signature = pair(3).sign(&blake2_256(&second.vote.encode())).0.to_vec();
+			let forged = Evidence::DoubleVote { first: vote(2, 10, 1), second }.encode();
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, forged).is_err());
+			// the same block twice
+			let same = Evidence::DoubleVote { first: vote(2, 10, 1), second: vote(2, 10, 1) }.encode();
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, same).is_err());
+			// not a validator
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 4, 10, double_vote(4, 10)).is_err());
+			assert_eq!(ValidatorModule::offences(2), 0);
+			assert_eq!(ValidatorModule::offences(4), 0);
+		});
+	}
+
+	#[test]
+	fn mismatched_evidence_is_rejected() {
+		with_externalities(&mut new_test_ext(), || {
+			set_validators(&[2, 3]);
+			// evidence of 2 reported against 3, of height 10 reported at 11
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 3, 10, double_vote(2, 10)).is_err());
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 11, double_vote(2, 10)).is_err());
+			// votes of different heights
+			let apart = Evidence::DoubleVote { first: vote(2, 10, 1), second: vote(2, 11, 2) }.encode();
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, apart).is_err());
+			assert_eq!(ValidatorModule::offences(2), 0);
+			assert_eq!(ValidatorModule::offences(3), 0);
+		});
+	}
+	#[test]
+	fn evidence_is_checked_against_the_session_key() {
+		with_externalities(&mut new_test_ext(), || {
+			<Validators<Test>>::put(vec![(2, 1), (3, 1)]);
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, double_vote(2, 10)).is_err());
+
+			// the node of 2 signs with the key of 5
+			assert_ok!(ValidatorModule::set_session_key(Origin::signed(2), pair(5).public()));
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, double_vote(2, 10)).is_err());
+			assert_ok!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 10, double_vote(5, 10)));
+			assert_eq!(ValidatorModule::offences(2), 1);
+			assert_eq!(ValidatorModule::offences(5), 0);
+		});
+	}
+	#[test]
+	fn genesis_validators_are_checked() {
+		with_externalities(&mut genesis_ext(&[2, 3]), || {
+			assert_eq!(ValidatorModule::validators(), vec![(2, 1), (3, 1)]);
+			assert_eq!(ValidatorModule::session_key(3), Some(pair(3).public()));
+			assert_ok!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 1, double_vote(2, 1)));
+			assert_eq!(ValidatorModule::offences(2), 1);
+		});
+	}
+
+	#[test]
+	fn evidence_of_a_previous_epoch_is_rejected() {
+		with_externalities(&mut genesis_ext(&[2, 3]), || {
+			System::set_block_number(150);
+			assert_ok!(ValidatorModule::schedule_validators(Origin::ROOT, vec![(2, 1), (4, 1)]));
+			<ValidatorModule as OnFinalize<u64>>::on_finalize(200);
+			assert_eq!(ValidatorModule::epoch_start(), 201);
+
+			// 2 still validates, but height 150 was checked by the previous set
+			assert!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 150, double_vote(2, 150)).is_err());
+			assert_ok!(ValidatorModule::report_equivocation(Origin::signed(1), 2, 201, double_vote(2, 201)));
+			assert_eq!(ValidatorModule::offences(2), 1);
+		});
+	}
 }
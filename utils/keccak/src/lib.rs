@@ -1,6 +1,7 @@
 //! Hasher implementation for the Keccak-256 hash
 
 #![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 use hash_db::Hasher;
 use ethereum_types::H256;